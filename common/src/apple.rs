@@ -0,0 +1,178 @@
+//! Apple's `container` CLI as a runtime backend (macOS 15 and later).
+//!
+//! Each container runs in its own lightweight VM, so there is no shared
+//! kernel to harden: `--cap-drop`, `--security-opt`, `--init` and the other
+//! kernel-level flags have no equivalent and are left out of the container
+//! options. Mounts, environment, resource limits and the network mode are
+//! passed through. Runtime classes and remote endpoints are not supported.
+
+use crate::probe::last_line;
+use crate::runtime::{AdaptedOptions, ContainerRuntime, RuntimeFuture};
+use anyhow::{bail, Context};
+use std::time::Duration;
+use tokio::process::Command as AsyncCommand;
+
+pub const BINARY: &str = "container";
+
+/// Container options without a counterpart, each taking a value.
+const DROPPED_WITH_VALUE: &[&str] = &[
+    "--security-opt",
+    "--cap-drop",
+    "--cap-add",
+    "--pids-limit",
+    "--cgroup-parent",
+    "--runtime",
+    "--userns",
+];
+
+/// Container options without a counterpart that stand alone.
+const DROPPED: &[&str] = &["--init"];
+
+/// Container options without the flags the VM boundary replaces.
+pub fn adapt(options: &[String]) -> AdaptedOptions {
+    let mut adapted = AdaptedOptions::default();
+    let mut iter = options.iter();
+    while let Some(arg) = iter.next() {
+        let flag = arg.split('=').next().unwrap_or(arg);
+        if DROPPED.contains(&arg.as_str()) {
+            adapted
+                .downgraded
+                .push(format!("{} left out: the VM has its own init", arg));
+            continue;
+        }
+        if DROPPED_WITH_VALUE.contains(&flag) {
+            let value = match arg.split_once('=') {
+                Some((_, value)) => value.to_string(),
+                None => iter.next().cloned().unwrap_or_default(),
+            };
+            adapted.downgraded.push(format!(
+                "{} {} left out: every container is its own VM",
+                flag, value
+            ));
+            continue;
+        }
+        adapted.options.push(arg.clone());
+    }
+    adapted
+}
+
+#[derive(Debug, Default)]
+pub struct AppleRuntime;
+
+impl AppleRuntime {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Whether the CLI is installed; `container` is only looked for on macOS
+    /// since the name is too generic elsewhere.
+    pub fn is_installed() -> bool {
+        cfg!(target_os = "macos") && which::which(BINARY).is_ok()
+    }
+}
+
+impl ContainerRuntime for AppleRuntime {
+    fn name(&self) -> &str {
+        BINARY
+    }
+
+    fn is_available(&self) -> bool {
+        // the CLI works only while its system service runs
+        which::which(BINARY).is_ok()
+            && std::process::Command::new(BINARY)
+                .args(["system", "status"])
+                .output()
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+    }
+
+    fn command(&self) -> AsyncCommand {
+        AsyncCommand::new(BINARY)
+    }
+
+    fn adapt_options(&self, options: Vec<String>) -> AdaptedOptions {
+        adapt(&options)
+    }
+
+    fn stop<'a>(&'a self, container: &'a str, grace: Duration) -> RuntimeFuture<'a, ()> {
+        Box::pin(async move {
+            self.command()
+                .args(["stop", "--time", &grace.as_secs().to_string(), container])
+                .output()
+                .await
+                .context("Failed to execute container stop")?;
+            Ok(())
+        })
+    }
+
+    fn inspect_image<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, bool> {
+        Box::pin(async move {
+            let output = self
+                .command()
+                .args(["image", "inspect", image])
+                .output()
+                .await
+                .context("Failed to execute container image inspect")?;
+            Ok(output.status.success())
+        })
+    }
+
+    fn pull<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, ()> {
+        Box::pin(async move {
+            let output = self
+                .command()
+                .args(["image", "pull", image])
+                .output()
+                .await
+                .context("Failed to execute container image pull")?;
+            if !output.status.success() {
+                bail!("{}", last_line(&output.stderr));
+            }
+            Ok(())
+        })
+    }
+
+    fn has_oci_runtime<'a>(&'a self, _name: &'a str) -> RuntimeFuture<'a, bool> {
+        Box::pin(async { Ok(false) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_kernel_hardening_flags_are_left_out() {
+        let args = strings(&[
+            "--init",
+            "--security-opt",
+            "no-new-privileges",
+            "--cap-drop",
+            "ALL",
+            "--pids-limit=100",
+            "-v",
+            "/Users/me/docs:/Users/me/docs:ro",
+            "-e",
+            "TZ=UTC",
+            "--memory",
+            "512m",
+        ]);
+        let adapted = adapt(&args);
+        assert_eq!(adapted.downgraded.len(), 4);
+        assert_eq!(
+            adapted.options,
+            strings(&[
+                "-v",
+                "/Users/me/docs:/Users/me/docs:ro",
+                "-e",
+                "TZ=UTC",
+                "--memory",
+                "512m",
+            ])
+        );
+    }
+}
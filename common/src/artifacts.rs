@@ -0,0 +1,568 @@
+//! Policies and rule bundles distributed as OCI artifacts.
+//!
+//! A reference like `oci://ghcr.io/acme/policies/base@sha256:...` names an
+//! artifact pushed with `oras push`. Artifacts are pulled with the `oras` CLI
+//! and cached under `$XDG_CACHE_HOME/semcp/artifacts/<digest>`, so a
+//! digest-pinned reference is read from the registry once and from the cache
+//! afterwards, also offline. A tag is resolved to its digest on every load,
+//! which needs the registry to be reachable. `oras` checks the pulled
+//! content against the digest.
+//!
+//! Policies can also be fetched over HTTPS with `curl`. A URL ending in
+//! `#sha256=<hex>` is pinned: it is checked against the digest, cached and
+//! read from the cache afterwards. Any other URL is revalidated on every
+//! load with the `ETag` of the cached copy, so an unchanged policy is not
+//! downloaded again. A download must be a YAML mapping to replace the
+//! cached copy, so an error page does not.
+//!
+//! `snpx policy push` publishes a policy with the artifact type
+//! [`POLICY_ARTIFACT_TYPE`], after checking it, along with its signature if
+//! it has one. The signature of an `https://` policy is fetched from the
+//! policy's URL with the signature's extension, see [`crate::signing`].
+//!
+//! Offline, only the cache is read: pinned artifacts and URLs fetched
+//! before are available, tags are not.
+
+use crate::signing::{self, BUNDLE_EXTENSION, SIGNATURE_EXTENSION};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const OCI_PREFIX: &str = "oci://";
+pub const HTTPS_PREFIX: &str = "https://";
+/// Artifact type of the policies `snpx policy push` publishes.
+pub const POLICY_ARTIFACT_TYPE: &str = "application/vnd.semcp.policy.v1";
+/// Media type of the policy file in such an artifact.
+pub const POLICY_MEDIA_TYPE: &str = "application/vnd.semcp.policy.v1+yaml";
+/// Media type of a sigstore bundle of the policy.
+pub const BUNDLE_MEDIA_TYPE: &str = "application/vnd.dev.sigstore.bundle.v0.3+json";
+/// Media type of a detached signature of the policy.
+pub const SIGNATURE_MEDIA_TYPE: &str = "application/vnd.semcp.policy.signature.v1";
+
+/// Signatures of a policy, by extension, with their media types.
+const SIGNATURES: [(&str, &str); 2] = [
+    (BUNDLE_EXTENSION, BUNDLE_MEDIA_TYPE),
+    (SIGNATURE_EXTENSION, SIGNATURE_MEDIA_TYPE),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactRef {
+    /// Registry and repository, e.g. `ghcr.io/acme/policies/base`.
+    pub repository: String,
+    pub tag: Option<String>,
+    /// A pinned digest, e.g. `sha256:...`.
+    pub digest: Option<String>,
+}
+
+pub fn is_artifact(source: &str) -> bool {
+    source.starts_with(OCI_PREFIX)
+}
+
+pub fn is_url(source: &str) -> bool {
+    source.starts_with(HTTPS_PREFIX) || source.starts_with("http://")
+}
+
+/// Whether `source` is fetched rather than read from a local path.
+pub fn is_remote(source: &str) -> bool {
+    is_artifact(source) || is_url(source)
+}
+
+/// `relative`, named by the policy at `url`, as a URL of its own.
+pub fn join_url(url: &str, relative: &str) -> String {
+    let url = url.split('#').next().unwrap_or_default();
+    let origin_end = url
+        .find("://")
+        .and_then(|scheme| url[scheme + 3..].find('/').map(|slash| scheme + 3 + slash))
+        .unwrap_or(url.len());
+    if relative.starts_with('/') {
+        return format!("{}{}", &url[..origin_end], relative);
+    }
+    let dir_end = url[origin_end..]
+        .rfind('/')
+        .map_or(url.len(), |slash| origin_end + slash);
+    format!("{}/{}", &url[..dir_end], relative)
+}
+
+fn valid_digest(digest: &str) -> bool {
+    digest.strip_prefix("sha256:").is_some_and(|hex| {
+        hex.len() == 64
+            && hex
+                .chars()
+                .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+    })
+}
+
+impl ArtifactRef {
+    pub fn parse(source: &str) -> Result<Self> {
+        let Some(rest) = source.strip_prefix(OCI_PREFIX) else {
+            bail!("{} is not an {} reference", source, OCI_PREFIX);
+        };
+        let (name, digest) = match rest.split_once('@') {
+            Some((name, digest)) => {
+                if !valid_digest(digest) {
+                    bail!(
+                        "Invalid digest in {}: expected sha256: and 64 lowercase hex digits",
+                        source
+                    );
+                }
+                (name, Some(digest.to_string()))
+            }
+            None => (rest, None),
+        };
+        // a colon after the last slash starts the tag, one before it is a port
+        let (repository, tag) = match name.rsplit_once(':') {
+            Some((repository, tag)) if !tag.contains('/') => (repository, Some(tag.to_string())),
+            _ => (name, None),
+        };
+        if repository.is_empty() || !repository.contains('/') {
+            bail!(
+                "Invalid artifact reference {}: expected registry/repository",
+                source
+            );
+        }
+        Ok(Self {
+            repository: repository.to_string(),
+            tag: if tag.is_none() && digest.is_none() {
+                Some("latest".to_string())
+            } else {
+                tag
+            },
+            digest,
+        })
+    }
+
+    /// The reference as `oras` expects it.
+    fn oras_reference(&self) -> String {
+        match (&self.digest, &self.tag) {
+            (Some(digest), _) => format!("{}@{}", self.repository, digest),
+            (None, Some(tag)) => format!("{}:{}", self.repository, tag),
+            (None, None) => self.repository.clone(),
+        }
+    }
+}
+
+/// Directory of the artifact cache.
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache.join("semcp").join("artifacts"))
+}
+
+fn oras(args: &[&str]) -> Result<String> {
+    which::which("oras").context("oras is required to fetch oci:// artifacts")?;
+    let output = Command::new("oras")
+        .args(args)
+        .output()
+        .context("Failed to execute oras")?;
+    if !output.status.success() {
+        bail!(
+            "oras {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The directory holding the artifact's files, pulled into the cache first
+/// unless it is already there or `offline`.
+pub fn fetch(reference: &ArtifactRef, offline: bool) -> Result<PathBuf> {
+    let digest = match reference.digest {
+        Some(ref digest) => digest.clone(),
+        None if offline => bail!(
+            "{} is a tag, which cannot be resolved offline; pin it by digest",
+            reference.oras_reference()
+        ),
+        None => {
+            let digest = oras(&["resolve", &reference.oras_reference()])?;
+            if !valid_digest(&digest) {
+                bail!(
+                    "oras resolved {} to an unexpected digest {}",
+                    reference.oras_reference(),
+                    digest
+                );
+            }
+            digest
+        }
+    };
+    let cache = cache_dir().context("Cannot locate the artifact cache: HOME is not set")?;
+    let dir = cache.join(digest.replace(':', "-"));
+    if dir.is_dir() {
+        return Ok(dir);
+    }
+    if offline {
+        bail!(
+            "{} is not cached, it cannot be pulled offline",
+            pinned_reference(reference, &digest)
+        );
+    }
+    let partial = cache.join(format!(
+        "{}.partial-{}",
+        digest.replace(':', "-"),
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&partial)
+        .with_context(|| format!("Failed to create {}", partial.display()))?;
+    let pinned = pinned_reference(reference, &digest);
+    let pulled = oras(&["pull", &pinned, "--output", &partial.to_string_lossy()]);
+    if let Err(e) = pulled {
+        let _ = std::fs::remove_dir_all(&partial);
+        return Err(e);
+    }
+    // another process may have finished the same pull first
+    if std::fs::rename(&partial, &dir).is_err() {
+        let _ = std::fs::remove_dir_all(&partial);
+    }
+    Ok(dir)
+}
+
+/// The `oras push` arguments that publish `file_name` as a policy, with the
+/// signatures of it that are in `dir`.
+fn push_args(reference: &ArtifactRef, file_name: &str, dir: &Path) -> Vec<String> {
+    let mut args = vec![
+        "push".to_string(),
+        reference.oras_reference(),
+        "--artifact-type".to_string(),
+        POLICY_ARTIFACT_TYPE.to_string(),
+        format!("{}:{}", file_name, POLICY_MEDIA_TYPE),
+    ];
+    for (extension, media_type) in SIGNATURES {
+        let signature = format!("{}.{}", file_name, extension);
+        if dir.join(&signature).is_file() {
+            args.push(format!("{}:{}", signature, media_type));
+        }
+    }
+    args
+}
+
+/// Pushes the policy `file` to the tag of `reference` and returns the
+/// digest it got, to pin it by.
+pub fn push_policy(file: &Path, reference: &ArtifactRef) -> Result<String> {
+    if reference.digest.is_some() {
+        bail!("Cannot push to a digest, name a tag");
+    }
+    which::which("oras").context("oras is required to push oci:// artifacts")?;
+    let file_name = file
+        .file_name()
+        .with_context(|| format!("{} is not a file", file.display()))?
+        .to_string_lossy()
+        .into_owned();
+    let dir = file
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    // oras stores the path as given, and pulls write the file under it
+    let output = Command::new("oras")
+        .args(push_args(reference, &file_name, dir))
+        .current_dir(dir)
+        .output()
+        .context("Failed to execute oras")?;
+    if !output.status.success() {
+        bail!(
+            "oras push {} failed: {}",
+            reference.oras_reference(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Digest:"))
+        .map(|digest| digest.trim().to_string())
+        .filter(|digest| valid_digest(digest))
+        .context("oras push did not report the digest of the artifact")
+}
+
+/// The single file with one of `extensions` in `dir`.
+pub fn single_file(dir: &Path, extensions: &[&str]) -> Result<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| extensions.iter().any(|wanted| ext == *wanted))
+        })
+        .collect();
+    match files.len() {
+        1 => Ok(files.remove(0)),
+        0 => bail!(
+            "Artifact {} has no {} file",
+            dir.display(),
+            extensions.join("/")
+        ),
+        _ => bail!(
+            "Artifact {} has more than one {} file",
+            dir.display(),
+            extensions.join("/")
+        ),
+    }
+}
+
+fn pinned_reference(reference: &ArtifactRef, digest: &str) -> String {
+    format!("{}@{}", reference.repository, digest)
+}
+
+/// Entity tags of a download: the file with the tag of the cached copy, if
+/// any, and the file to save the new tag to.
+struct Etags<'a> {
+    cached: Option<&'a Path>,
+    save: &'a Path,
+}
+
+/// Downloads `url` with `curl` to `path`. Returns `false` when the server
+/// answered that the cached copy whose tag `etags` names is current, in
+/// which case nothing was downloaded.
+fn download(url: &str, path: &Path, etags: Option<Etags>) -> Result<bool> {
+    which::which("curl").context("curl is required to fetch https:// policies")?;
+    let mut command = Command::new("curl");
+    command
+        .args(["-fsSL", "--proto", "=https", "--max-time", "30"])
+        .args(["-w", "%{http_code}", "-o"])
+        .arg(path);
+    if let Some(etags) = etags {
+        if let Some(cached) = etags.cached {
+            command.arg("--etag-compare").arg(cached);
+        }
+        command.arg("--etag-save").arg(etags.save);
+    }
+    let output = command
+        .arg(url)
+        .output()
+        .context("Failed to execute curl")?;
+    if !output.status.success() {
+        bail!(
+            "curl {} failed: {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim() != "304")
+}
+
+/// Checks a downloaded policy before it replaces the cached copy.
+fn check_download(location: &str, content: &[u8], pin: Option<&str>) -> Result<()> {
+    if let Some(hex) = pin {
+        let digest = format!("{:x}", Sha256::digest(content));
+        if digest != hex {
+            bail!(
+                "{} has digest sha256:{}, not the pinned sha256:{}",
+                location,
+                digest,
+                hex
+            );
+        }
+    }
+    let document: serde_yaml::Value = serde_yaml::from_slice(content)
+        .with_context(|| format!("{} is not a YAML policy", location))?;
+    if !document.is_mapping() {
+        bail!("{} is not a YAML policy", location);
+    }
+    Ok(())
+}
+
+/// The file of the policy at `url`, from the cache when it is pinned and
+/// was fetched before, when the server says it has not changed or when
+/// `offline`.
+fn url_file(url: &str, offline: bool) -> Result<PathBuf> {
+    if url.starts_with("http://") {
+        bail!(
+            "Refusing to fetch policy {} over plain http, use https://",
+            url
+        );
+    }
+    let (location, pin) = match url.split_once('#') {
+        Some((location, fragment)) => {
+            let Some(hex) = fragment.strip_prefix("sha256=") else {
+                bail!("Invalid pin in {}: expected #sha256=<hex>", url);
+            };
+            if !valid_digest(&format!("sha256:{}", hex)) {
+                bail!(
+                    "Invalid digest in {}: expected 64 lowercase hex digits",
+                    url
+                );
+            }
+            (location, Some(hex))
+        }
+        None => (url, None),
+    };
+    let cache = cache_dir().context("Cannot locate the artifact cache: HOME is not set")?;
+    std::fs::create_dir_all(&cache)
+        .with_context(|| format!("Failed to create {}", cache.display()))?;
+    let name = match pin {
+        Some(hex) => format!("url-sha256-{}.yaml", hex),
+        None => format!(
+            "url-{}.yaml",
+            &format!("{:x}", Sha256::digest(location))[..16]
+        ),
+    };
+    let path = cache.join(name);
+    if path.is_file() && (pin.is_some() || offline) {
+        return Ok(path);
+    }
+    if offline {
+        bail!("{} is not cached, it cannot be fetched offline", location);
+    }
+    let partial = path.with_extension(format!("partial-{}", std::process::id()));
+    let etag = path.with_extension("etag");
+    let partial_etag = etag.with_extension(format!("etag-partial-{}", std::process::id()));
+    // only unpinned URLs change, a pinned one is never fetched again
+    let etags = pin.is_none().then(|| Etags {
+        cached: Some(etag.as_path()).filter(|etag| etag.is_file() && path.is_file()),
+        save: &partial_etag,
+    });
+    let downloaded = download(location, &partial, etags).and_then(|modified| {
+        if !modified {
+            return Ok(false);
+        }
+        let content = std::fs::read(&partial)
+            .with_context(|| format!("Failed to read {}", partial.display()))?;
+        check_download(location, &content, pin)?;
+        Ok(true)
+    });
+    let modified = match downloaded {
+        Ok(modified) => modified,
+        Err(e) => {
+            let _ = std::fs::remove_file(&partial);
+            let _ = std::fs::remove_file(&partial_etag);
+            if path.is_file() {
+                return Err(e.context("A cached copy exists, pass --offline to use it"));
+            }
+            return Err(e);
+        }
+    };
+    if !modified {
+        let _ = std::fs::remove_file(&partial);
+        let _ = std::fs::remove_file(&partial_etag);
+        return Ok(path);
+    }
+    std::fs::rename(&partial, &path)
+        .with_context(|| format!("Failed to move policy to {}", path.display()))?;
+    if partial_etag.is_file() {
+        let _ = std::fs::rename(&partial_etag, &etag);
+    } else {
+        let _ = std::fs::remove_file(&etag);
+    }
+    fetch_signatures(location, &path);
+    Ok(path)
+}
+
+/// Fetches the signatures published next to the policy at `location` to
+/// sit next to its cached copy at `path`, and drops those it no longer has.
+fn fetch_signatures(location: &str, path: &Path) {
+    for (extension, _) in SIGNATURES {
+        let signature = signing::sibling(path, extension);
+        let partial = signing::sibling(
+            path,
+            &format!("{}.partial-{}", extension, std::process::id()),
+        );
+        let url = format!("{}.{}", location, extension);
+        if download(&url, &partial, None).is_ok() {
+            let _ = std::fs::rename(&partial, &signature);
+        } else {
+            let _ = std::fs::remove_file(&partial);
+            let _ = std::fs::remove_file(&signature);
+        }
+    }
+}
+
+/// The policy file of the artifact or URL at `source`, from the cache only
+/// when `offline`.
+pub fn policy_file(source: &str, offline: bool) -> Result<PathBuf> {
+    if is_url(source) {
+        return url_file(source, offline)
+            .with_context(|| format!("Failed to fetch policy {}", source));
+    }
+    let reference = ArtifactRef::parse(source)?;
+    let dir =
+        fetch(&reference, offline).with_context(|| format!("Failed to fetch policy {}", source))?;
+    single_file(&dir, &["yaml", "yml"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_references() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let pinned = ArtifactRef::parse(&format!("oci://ghcr.io/acme/base@{}", digest)).unwrap();
+        assert_eq!(pinned.repository, "ghcr.io/acme/base");
+        assert_eq!(pinned.digest, Some(digest));
+
+        let tagged = ArtifactRef::parse("oci://localhost:5000/acme/base:v2").unwrap();
+        assert_eq!(tagged.repository, "localhost:5000/acme/base");
+        assert_eq!(tagged.tag.as_deref(), Some("v2"));
+
+        let latest = ArtifactRef::parse("oci://localhost:5000/acme/base").unwrap();
+        assert_eq!(latest.repository, "localhost:5000/acme/base");
+        assert_eq!(latest.tag.as_deref(), Some("latest"));
+    }
+
+    #[test]
+    fn test_join_url() {
+        let base = "https://example.com/policies/org/base.yaml#sha256=abc";
+        assert_eq!(
+            join_url(base, "team.yaml"),
+            "https://example.com/policies/org/team.yaml"
+        );
+        assert_eq!(
+            join_url(base, "../shared.yaml"),
+            "https://example.com/policies/org/../shared.yaml"
+        );
+        assert_eq!(
+            join_url(base, "/root.yaml"),
+            "https://example.com/root.yaml"
+        );
+        assert_eq!(
+            join_url("https://example.com", "base.yaml"),
+            "https://example.com/base.yaml"
+        );
+        assert!(url_file("http://example.com/base.yaml", false).is_err());
+        assert!(url_file("https://example.com/base.yaml#md5=abc", false).is_err());
+    }
+
+    #[test]
+    fn test_push_args() {
+        let reference = ArtifactRef::parse("oci://ghcr.io/acme/policies/mcp:prod").unwrap();
+        assert_eq!(
+            push_args(&reference, "mcp.yaml", Path::new("testdata")),
+            [
+                "push",
+                "ghcr.io/acme/policies/mcp:prod",
+                "--artifact-type",
+                "application/vnd.semcp.policy.v1",
+                "mcp.yaml:application/vnd.semcp.policy.v1+yaml",
+            ]
+        );
+        let pinned = ArtifactRef::parse(&format!(
+            "oci://ghcr.io/acme/policies/mcp@sha256:{}",
+            "0".repeat(64)
+        ))
+        .unwrap();
+        assert!(push_policy(Path::new("mcp.yaml"), &pinned).is_err());
+    }
+
+    #[test]
+    fn test_offline_and_checked_downloads() {
+        let tagged = ArtifactRef::parse("oci://ghcr.io/acme/policies/base:v1").unwrap();
+        let error = fetch(&tagged, true).unwrap_err();
+        assert!(error.to_string().contains("cannot be resolved offline"));
+
+        let pin = "0".repeat(64);
+        let error = check_download(
+            "https://example.com/base.yaml",
+            b"version: '1.0'\n",
+            Some(&pin),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("not the pinned"));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_references() {
+        assert!(ArtifactRef::parse("ghcr.io/acme/base").is_err());
+        assert!(ArtifactRef::parse("oci://base").is_err());
+        assert!(ArtifactRef::parse("oci://ghcr.io/acme/base@sha256:abc").is_err());
+    }
+}
@@ -0,0 +1,372 @@
+//! Audit log.
+//!
+//! Every run appends one JSON line to `$XDG_STATE_HOME/semcp/audit.jsonl`
+//! (`~/.local/state/semcp/audit.jsonl`), or to the file named by
+//! `SEMCP_AUDIT_LOG`; `SEMCP_AUDIT_LOG=off` turns the log off. A run
+//! record names the container, image, package and policies, and carries a
+//! fingerprint of the execution environment: engine and version, storage
+//! driver, kernel, seccomp default and cgroup version. That is what an
+//! incident responder needs to tell which protections actually applied.
+//!
+//! Failing to write the log never fails the run.
+//!
+//! `permissions.runtime.audit` decides what is recorded for runs under a
+//! policy, and may name another file. A run record then also carries the
+//! command line, the mounts and the network settings, each behind a flag,
+//! and a `stop` record follows it. `SEMCP_AUDIT_LOG` still wins over the
+//! policy's file, so an administrator can collect every run in one place.
+
+use crate::runtime::ContainerRuntime;
+use crate::ContainerExecutor;
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const AUDIT_LOG_ENV: &str = "SEMCP_AUDIT_LOG";
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AuditSpec {
+    pub log_level: AuditLevel,
+    /// Record the command line of runs and the tool calls.
+    pub log_commands: bool,
+    /// Record the network settings of runs and the egress the firewall
+    /// blocks.
+    pub log_network_access: bool,
+    /// Record the mounts of runs. Touched canaries are violations and
+    /// recorded regardless.
+    pub log_file_access: bool,
+    /// File to append to instead of `audit.jsonl` in the state directory.
+    pub log_file: Option<String>,
+}
+
+impl Default for AuditSpec {
+    fn default() -> Self {
+        Self {
+            log_level: AuditLevel::Info,
+            log_commands: true,
+            log_network_access: true,
+            log_file_access: true,
+            log_file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditLevel {
+    /// Nothing.
+    Off,
+    /// Only what the policy stopped: blocked egress, touched canaries and
+    /// crash loops.
+    Violations,
+    /// Runs, stops and tool calls too.
+    Info,
+}
+
+impl AuditSpec {
+    /// Whether an event is recorded at this level, `violation` for events
+    /// of something the policy stopped.
+    pub fn records(&self, violation: bool) -> bool {
+        match self.log_level {
+            AuditLevel::Off => false,
+            AuditLevel::Violations => violation,
+            AuditLevel::Info => true,
+        }
+    }
+}
+
+/// Where records go, `None` when the log is off.
+pub fn log_path() -> Option<PathBuf> {
+    log_path_for(&AuditSpec::default())
+}
+
+/// Where records of runs under `spec` go.
+pub fn log_path_for(spec: &AuditSpec) -> Option<PathBuf> {
+    match std::env::var(AUDIT_LOG_ENV) {
+        Ok(value) if value == "off" => None,
+        Ok(value) if !value.is_empty() => Some(PathBuf::from(value)),
+        _ if spec.log_level == AuditLevel::Off => None,
+        _ => match spec.log_file {
+            Some(ref file) => Some(PathBuf::from(file)),
+            None => Some(crate::state::state_home()?.join("audit.jsonl")),
+        },
+    }
+}
+
+/// Appends a record of `event` with `fields`, stamped with the time and the
+/// user.
+pub fn record(event: &str, fields: Value) -> Result<()> {
+    record_for(&AuditSpec::default(), event, fields)
+}
+
+/// Like [`record`], to the log of `spec`.
+pub fn record_for(spec: &AuditSpec, event: &str, fields: Value) -> Result<()> {
+    let Some(path) = log_path_for(spec) else {
+        return Ok(());
+    };
+    let mut entry = Map::new();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or_default();
+    entry.insert("time".to_string(), json!(time));
+    entry.insert("event".to_string(), json!(event));
+    entry.insert("user".to_string(), json!(crate::state::current_user()));
+    if let Value::Object(fields) = fields {
+        entry.extend(fields);
+    }
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open the audit log {}", path.display()))?;
+    writeln!(file, "{}", Value::Object(entry))
+        .with_context(|| format!("Failed to write the audit log {}", path.display()))
+}
+
+/// Reads the environment fingerprint out of `info --format '{{json .}}'`
+/// output, from Docker or Podman.
+pub fn fingerprint_from_info(engine: &str, info: &Value) -> Value {
+    let text = |pointers: &[&str]| {
+        pointers
+            .iter()
+            .find_map(|pointer| info.pointer(pointer))
+            .map(|value| match value {
+                Value::String(text) => json!(text),
+                other => json!(other.to_string()),
+            })
+            .unwrap_or(Value::Null)
+    };
+    let seccomp = match info.get("SecurityOptions").and_then(Value::as_array) {
+        // docker: "name=seccomp,profile=builtin"
+        Some(options) => options
+            .iter()
+            .filter_map(Value::as_str)
+            .find(|option| option.starts_with("name=seccomp"))
+            .map(|option| {
+                json!(option
+                    .split(',')
+                    .find_map(|part| part.strip_prefix("profile="))
+                    .unwrap_or("enabled"))
+            })
+            .unwrap_or(json!("disabled")),
+        None => match info.pointer("/host/security/seccompEnabled") {
+            Some(Value::Bool(true)) => text(&["/host/security/seccompProfilePath"]),
+            Some(Value::Bool(false)) => json!("disabled"),
+            _ => Value::Null,
+        },
+    };
+    json!({
+        "engine": engine,
+        "engine_version": text(&["/ServerVersion", "/version/Version"]),
+        "storage_driver": text(&["/Driver", "/store/graphDriverName"]),
+        "kernel": text(&["/KernelVersion", "/host/kernel"]),
+        "os": text(&["/OperatingSystem", "/host/distribution/distribution"]),
+        "seccomp": seccomp,
+        "cgroup_version": text(&["/CgroupVersion", "/host/cgroupVersion"]),
+        "default_runtime": text(&["/DefaultRuntime", "/host/ociRuntime/name"]),
+    })
+}
+
+/// Asks the runtime for the environment fingerprint. Fields it cannot tell
+/// are null.
+pub async fn fingerprint(runtime: &dyn ContainerRuntime) -> Value {
+    let info = runtime
+        .command()
+        .args(["info", "--format", "{{json .}}"])
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| serde_json::from_slice::<Value>(&output.stdout).ok())
+        .unwrap_or(Value::Null);
+    fingerprint_from_info(runtime.name(), &info)
+}
+
+/// The digest the runtime has for `image`, or its ID when it was not
+/// pulled from a registry.
+pub async fn image_digest(runtime: &dyn ContainerRuntime, image: &str) -> Option<String> {
+    let output = runtime
+        .command()
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "{{if .RepoDigests}}{{index .RepoDigests 0}}{{else}}{{.Id}}{{end}}",
+            image,
+        ])
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+    let digest = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!digest.is_empty()).then_some(digest)
+}
+
+/// Records a run started on `runtime`, with the environment fingerprint
+/// and the image digest added to `fields`.
+pub async fn record_run(runtime: &dyn ContainerRuntime, fields: Value, verbose: bool) {
+    record_run_for(runtime, &AuditSpec::default(), fields, verbose).await
+}
+
+/// Like [`record_run`], for a run under `spec`.
+pub async fn record_run_for(
+    runtime: &dyn ContainerRuntime,
+    spec: &AuditSpec,
+    mut fields: Value,
+    verbose: bool,
+) {
+    if log_path_for(spec).is_none() || !spec.records(false) {
+        return;
+    }
+    fields["environment"] = fingerprint(runtime).await;
+    if let Some(image) = fields["image"].as_str() {
+        fields["image_digest"] = json!(image_digest(runtime, image).await);
+    }
+    if let Err(e) = record_for(spec, "run", fields) {
+        if verbose {
+            eprintln!("Warning: {:#}", e);
+        }
+    }
+}
+
+/// The `-v`, `--mount` and `--tmpfs` values among run options.
+fn mounts(options: &[String]) -> Vec<&str> {
+    options
+        .windows(2)
+        .filter(|pair| ["-v", "--volume", "--mount", "--tmpfs"].contains(&pair[0].as_str()))
+        .map(|pair| pair[1].as_str())
+        .collect()
+}
+
+impl ContainerExecutor {
+    fn audit_spec(&self) -> &AuditSpec {
+        &self.policy_config.runtime.audit
+    }
+
+    /// Records `event` under the policy's audit settings, a `violation`
+    /// when the policy stopped something.
+    pub(crate) fn audit(&self, event: &str, violation: bool, mut fields: Value) {
+        let spec = self.audit_spec();
+        if !spec.records(violation) {
+            return;
+        }
+        fields["container"] = json!(self.container_name);
+        fields["package"] = json!(self.package);
+        if let Err(e) = record_for(spec, event, fields) {
+            if self.verbose {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
+    }
+
+    /// Records the run about to start with `run_args`.
+    pub(crate) async fn audit_run(&self, run_args: &[String]) {
+        let spec = self.audit_spec();
+        let mut fields = json!({
+            "container": self.container_name,
+            "image": self.docker_image,
+            "package": self.package,
+            "runtime_class": self.runtime_class().name(),
+            "policies": self.policy_config.sources(),
+        });
+        if spec.log_commands {
+            fields["command"] = json!(run_args);
+        }
+        if spec.log_file_access {
+            fields["mounts"] = json!(mounts(run_args));
+        }
+        if spec.log_network_access {
+            fields["network"] = json!({
+                "mode": self.policy_config.network_mode(),
+                "allow": self.policy_config.allowed_network_hosts(),
+                "filter_dns": self.policy_config.filter_dns(),
+                "blocked_ports": self
+                    .policy_config
+                    .blocked_ports()
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            });
+        }
+        record_run_for(self.runtime.as_ref(), spec, fields, self.verbose).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_audit_levels() {
+        let spec = AuditSpec::default();
+        assert!(spec.records(false) && spec.records(true));
+        let violations = AuditSpec {
+            log_level: AuditLevel::Violations,
+            ..AuditSpec::default()
+        };
+        assert!(!violations.records(false) && violations.records(true));
+        let off = AuditSpec {
+            log_level: AuditLevel::Off,
+            ..AuditSpec::default()
+        };
+        assert!(!off.records(true));
+        let options: Vec<String> = [
+            "--rm", "-v", "/a:/a:ro", "--tmpfs", "/tmp", "--memory", "1g",
+        ]
+        .map(String::from)
+        .to_vec();
+        assert_eq!(mounts(&options), ["/a:/a:ro", "/tmp"]);
+    }
+
+    #[test]
+    fn test_docker_fingerprint() {
+        let info = json!({
+            "ServerVersion": "27.3.1",
+            "Driver": "overlay2",
+            "KernelVersion": "6.8.0-45-generic",
+            "OperatingSystem": "Ubuntu 24.04.1 LTS",
+            "SecurityOptions": ["name=apparmor", "name=seccomp,profile=builtin", "name=cgroupns"],
+            "CgroupVersion": "2",
+            "DefaultRuntime": "runc"
+        });
+        let fingerprint = fingerprint_from_info("docker", &info);
+        assert_eq!(fingerprint["engine_version"], "27.3.1");
+        assert_eq!(fingerprint["storage_driver"], "overlay2");
+        assert_eq!(fingerprint["seccomp"], "builtin");
+        assert_eq!(fingerprint["cgroup_version"], "2");
+    }
+
+    #[test]
+    fn test_podman_fingerprint() {
+        let info = json!({
+            "host": {
+                "kernel": "6.11.4-301.fc41.x86_64",
+                "cgroupVersion": "v2",
+                "ociRuntime": { "name": "crun" },
+                "security": { "seccompEnabled": true, "seccompProfilePath": "/usr/share/containers/seccomp.json" }
+            },
+            "store": { "graphDriverName": "overlay" },
+            "version": { "Version": "5.2.5" }
+        });
+        let fingerprint = fingerprint_from_info("podman", &info);
+        assert_eq!(fingerprint["engine_version"], "5.2.5");
+        assert_eq!(fingerprint["kernel"], "6.11.4-301.fc41.x86_64");
+        assert_eq!(fingerprint["seccomp"], "/usr/share/containers/seccomp.json");
+        assert_eq!(fingerprint["default_runtime"], "crun");
+        assert_eq!(
+            fingerprint_from_info("docker", &Value::Null)["kernel"],
+            Value::Null
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Per-session tool budgets.
+//!
+//! An agent stuck in a loop can call an expensive tool (a paid search API, a
+//! browser) hundreds of times before anyone notices. With `budget` under
+//! `permissions.runtime`, every `tools/call` costs its tool's weight, and the
+//! proxy answers calls the session can no longer pay for with an error
+//! instead of passing them on. The budget works as a token bucket: it starts
+//! full and, when `refill_per_minute` is set, slowly fills up again.
+
+use crate::refusal::Refusal;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct BudgetSpec {
+    /// What a session may spend.
+    pub per_session: u64,
+    /// Budget regained per minute, up to `per_session`.
+    pub refill_per_minute: u64,
+    /// Cost of tools without a weight of their own.
+    pub default_cost: u64,
+    /// Cost by tool name.
+    pub tools: BTreeMap<String, u64>,
+}
+
+impl Default for BudgetSpec {
+    fn default() -> Self {
+        Self {
+            per_session: 0,
+            refill_per_minute: 0,
+            default_cost: 1,
+            tools: BTreeMap::new(),
+        }
+    }
+}
+
+impl BudgetSpec {
+    pub fn cost(&self, tool: &str) -> u64 {
+        self.tools.get(tool).copied().unwrap_or(self.default_cost)
+    }
+}
+
+/// A call the budget cannot pay for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exhausted {
+    pub tool: String,
+    pub cost: u64,
+    pub left: u64,
+}
+
+impl std::fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tool budget exhausted: {} costs {}, {} left (runtime.budget)",
+            self.tool, self.cost, self.left
+        )
+    }
+}
+
+impl From<Exhausted> for Refusal {
+    fn from(exhausted: Exhausted) -> Self {
+        Refusal {
+            reason: "budget_exhausted",
+            rule: "permissions.runtime.budget",
+            message: exhausted.to_string(),
+            details: json!({
+                "tool": exhausted.tool,
+                "cost": exhausted.cost,
+                "left": exhausted.left,
+            }),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Budget {
+    spec: BudgetSpec,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl Budget {
+    pub fn new(spec: BudgetSpec, now: Instant) -> Self {
+        Self {
+            tokens: spec.per_session as f64,
+            spec,
+            refilled_at: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let minutes = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64()
+            / 60.0;
+        self.tokens = (self.tokens + minutes * self.spec.refill_per_minute as f64)
+            .min(self.spec.per_session as f64);
+        self.refilled_at = now;
+    }
+
+    /// Takes the cost of calling `tool` from the budget.
+    pub fn charge(&mut self, tool: &str, now: Instant) -> Result<(), Exhausted> {
+        self.refill(now);
+        let cost = self.spec.cost(tool);
+        if (cost as f64) > self.tokens {
+            return Err(Exhausted {
+                tool: tool.to_string(),
+                cost,
+                left: self.tokens as u64,
+            });
+        }
+        self.tokens -= cost as f64;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn spec(yaml: &str) -> BudgetSpec {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_weighted_calls_exhaust_the_budget() {
+        let now = Instant::now();
+        let mut budget = Budget::new(spec("{per_session: 10, tools: {search: 4}}"), now);
+        assert!(budget.charge("search", now).is_ok());
+        assert!(budget.charge("search", now).is_ok());
+        let exhausted = budget.charge("search", now).unwrap_err();
+        assert_eq!((exhausted.cost, exhausted.left), (4, 2));
+        assert!(budget.charge("echo", now).is_ok());
+        assert!(budget.charge("echo", now).is_ok());
+        assert!(budget.charge("echo", now).is_err());
+    }
+
+    #[test]
+    fn test_budget_refills_up_to_capacity() {
+        let now = Instant::now();
+        let mut budget = Budget::new(spec("{per_session: 3, refill_per_minute: 2}"), now);
+        for _ in 0..3 {
+            budget.charge("echo", now).unwrap();
+        }
+        assert!(budget.charge("echo", now).is_err());
+
+        let later = now + Duration::from_secs(30);
+        assert!(budget.charge("echo", later).is_ok());
+        assert!(budget.charge("echo", later).is_err());
+
+        let much_later = later + Duration::from_secs(3600);
+        for _ in 0..3 {
+            budget.charge("echo", much_later).unwrap();
+        }
+        assert!(budget.charge("echo", much_later).is_err());
+    }
+}
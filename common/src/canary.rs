@@ -0,0 +1,198 @@
+//! Canary files next to mounts.
+//!
+//! A container should only ever see what is mounted into it. With
+//! `--canaries`, a decoy file is planted in the host directory around each
+//! mount and watched with `inotifywait`. The server has no legitimate way
+//! to reach those files, so any access means the mount setup leaks more of
+//! the host than intended: the container is stopped and the run fails.
+//!
+//! The files are named `.semcp-canary-*`, so Falco or auditd rules can
+//! watch the same names instead of, or in addition to, inotify.
+
+use crate::overrides::path_within;
+use crate::ContainerExecutor;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdout, Command as AsyncCommand};
+
+pub const CANARY_PREFIX: &str = ".semcp-canary-";
+
+/// Host directories that hold a mount but are not mounted themselves.
+pub fn canary_dirs(host_paths: &[String]) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    for path in host_paths {
+        let Some(parent) = Path::new(path).parent() else {
+            continue;
+        };
+        let parent_str = parent.to_string_lossy();
+        // a canary inside another mount would be reachable legitimately
+        if host_paths
+            .iter()
+            .any(|mount| path_within(&parent_str, mount))
+        {
+            continue;
+        }
+        if !dirs.iter().any(|dir| dir == parent) {
+            dirs.push(parent.to_path_buf());
+        }
+    }
+    dirs
+}
+
+/// Planted canary files, removed again on drop.
+pub struct Canaries {
+    files: Vec<PathBuf>,
+}
+
+impl Canaries {
+    pub fn plant(dirs: &[PathBuf], tag: &str, verbose: bool) -> Self {
+        let mut files = Vec::new();
+        for dir in dirs {
+            let file = dir.join(format!("{}{}", CANARY_PREFIX, tag));
+            let token = format!("SEMCP-CANARY-{}\n", tag);
+            match std::fs::write(&file, token) {
+                Ok(()) => files.push(file),
+                // unwritable directories are skipped rather than failing the run
+                Err(e) if verbose => {
+                    eprintln!("Not planting a canary in {}: {}", dir.display(), e)
+                }
+                Err(_) => {}
+            }
+        }
+        Self { files }
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// Starts watching the canaries for any access.
+    pub fn watch(&self) -> Result<Tripwire> {
+        if which::which("inotifywait").is_err() {
+            bail!("--canaries needs inotifywait (install inotify-tools)");
+        }
+        let mut child = AsyncCommand::new("inotifywait")
+            .args([
+                "-m",
+                "-q",
+                "--format",
+                "%w",
+                "-e",
+                "open,access,modify,attrib,move_self,delete_self",
+            ])
+            .args(&self.files)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to spawn inotifywait")?;
+        let stdout = child.stdout.take().context("inotifywait has no stdout")?;
+        Ok(Tripwire {
+            _child: child,
+            events: BufReader::new(stdout).lines(),
+        })
+    }
+}
+
+impl Drop for Canaries {
+    fn drop(&mut self) {
+        for file in &self.files {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+pub struct Tripwire {
+    _child: Child,
+    events: Lines<BufReader<ChildStdout>>,
+}
+
+impl Tripwire {
+    /// Resolves with the path of the first canary that is touched.
+    pub async fn triggered(&mut self) -> Result<String> {
+        match self
+            .events
+            .next_line()
+            .await
+            .context("Failed to read inotifywait output")?
+        {
+            Some(path) => Ok(path),
+            None => bail!("inotifywait exited, canaries are no longer watched"),
+        }
+    }
+}
+
+/// Like [`Tripwire::triggered`], but never resolves without a tripwire.
+pub async fn tripped(tripwire: &mut Option<Tripwire>) -> Result<String> {
+    match tripwire {
+        Some(tripwire) => tripwire.triggered().await,
+        None => std::future::pending().await,
+    }
+}
+
+impl ContainerExecutor {
+    /// Host paths bind mounted into the container.
+    fn host_mount_paths(&self) -> Vec<String> {
+        let mut paths = self.policy_config.mount_host_paths();
+        for pair in self.extra_args.windows(2) {
+            if pair[0] == "-v" {
+                if let Some(host) = pair[1].split(':').next().filter(|h| h.starts_with('/')) {
+                    paths.push(host.to_string());
+                }
+            }
+        }
+        paths
+    }
+
+    /// Plants canaries around the mounts when enabled.
+    pub(crate) fn plant_canaries(&self) -> Option<Canaries> {
+        if !self.canaries {
+            return None;
+        }
+        let dirs = canary_dirs(&self.host_mount_paths());
+        let canaries = Canaries::plant(&dirs, &self.container_name, self.verbose);
+        if self.verbose {
+            for file in canaries.files() {
+                eprintln!("Planted canary: {}", file.display());
+            }
+        }
+        Some(canaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_canary_dirs_skip_mounted_parents() {
+        let dirs = canary_dirs(&strings(&[
+            "/home/me/project",
+            "/home/me/project/docs",
+            "/srv/data",
+        ]));
+        assert_eq!(dirs, [PathBuf::from("/home/me"), PathBuf::from("/srv")]);
+    }
+
+    #[test]
+    fn test_plant_and_remove() {
+        let dir = std::env::temp_dir().join(format!("semcp-canary-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = {
+            let canaries = Canaries::plant(std::slice::from_ref(&dir), "t1", false);
+            let file = canaries.files()[0].clone();
+            assert!(std::fs::read_to_string(&file)
+                .unwrap()
+                .contains("SEMCP-CANARY-t1"));
+            file
+        };
+        assert!(!file.exists());
+        std::fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,327 @@
+//! The Cedar policy engine.
+//!
+//! Decisions are made by the `cedar` CLI against the policies in the
+//! engine's `data`, with entities in the `Semcp` namespace:
+//!
+//! - `run`: `Client::"cli"` on `Server::"<package>"`, with `network`,
+//!   `cap_add` and the `env` names in the context
+//! - `mount`: `Client::"cli"` on `Path::"<host path>"`, with `read_only`
+//! - `connect`: `Client::"cli"` on `Domain::"<host>"`
+//! - `call_tool`: `Client::"<client>"` on `Tool::"<tool>"`
+//!
+//! `run` and `mount` cover the command line, `connect` the hosts the YAML
+//! policy allows, and `call_tool` every tool call, with the client named as
+//! in its `initialize` request. [`schema`] describes these entities for
+//! `cedar validate`.
+
+use crate::cli::ContainerArgs;
+use crate::dnsfilter::domain_of;
+use crate::engine::{EngineSpec, PolicyEngine};
+use crate::overrides::Denial;
+use crate::refusal::Refusal;
+use crate::PolicyConfig;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const NAMESPACE: &str = "Semcp";
+/// The principal of decisions about the command line.
+pub const CLI_CLIENT: &str = "cli";
+
+/// The Cedar schema, in JSON format, of the entities and actions semcp
+/// asks about.
+pub fn schema() -> Value {
+    let strings = json!({"type": "Set", "element": {"type": "String"}});
+    json!({
+        NAMESPACE: {
+            "entityTypes": {
+                "Client": {},
+                "Server": {},
+                "Path": {},
+                "Domain": {},
+                "Tool": {},
+            },
+            "actions": {
+                "run": {"appliesTo": {
+                    "principalTypes": ["Client"],
+                    "resourceTypes": ["Server"],
+                    "context": {"type": "Record", "attributes": {
+                        "network": {"type": "String"},
+                        "cap_add": strings,
+                        "env": strings,
+                    }},
+                }},
+                "mount": {"appliesTo": {
+                    "principalTypes": ["Client"],
+                    "resourceTypes": ["Path"],
+                    "context": {"type": "Record", "attributes": {
+                        "read_only": {"type": "Boolean"},
+                    }},
+                }},
+                "connect": {"appliesTo": {
+                    "principalTypes": ["Client"],
+                    "resourceTypes": ["Domain"],
+                }},
+                "call_tool": {"appliesTo": {
+                    "principalTypes": ["Client"],
+                    "resourceTypes": ["Tool"],
+                }},
+            },
+        }
+    })
+}
+
+/// The Cedar uid of entity `id` of `kind`, e.g. `Semcp::Tool::"search"`.
+pub fn entity_uid(kind: &str, id: &str) -> String {
+    format!("{}::{}::{}", NAMESPACE, kind, Value::from(id))
+}
+
+/// Host path and access of a `--volume` argument.
+fn volume_mount(volume: &str) -> (String, bool) {
+    let mut parts = volume.split(':');
+    let host = parts.next().unwrap_or_default().to_string();
+    let read_only = parts
+        .nth(1)
+        .is_some_and(|mode| mode.split(',').any(|m| m == "ro"));
+    (host, read_only)
+}
+
+/// A file for one `cedar` invocation, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new(contents: &Value) -> Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "semcp-cedar-{}-{}.json",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, contents.to_string())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(Self(path))
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// The `cedar` CLI.
+pub fn binary() -> Result<PathBuf> {
+    which::which("cedar").context("cedar is not installed")
+}
+
+/// Checks `policies` against [`schema`] with `cedar validate`.
+pub fn validate(binary: &Path, policies: &Path) -> Result<()> {
+    let schema = Scratch::new(&schema())?;
+    let output = Command::new(binary)
+        .args(["validate", "--schema-format", "json", "--schema"])
+        .arg(&schema.0)
+        .arg("--policies")
+        .arg(policies)
+        .output()
+        .context("Failed to execute cedar validate")?;
+    if !output.status.success() {
+        bail!(
+            "{} does not validate against the semcp schema:\n{}{}",
+            policies.display(),
+            String::from_utf8_lossy(&output.stdout).trim(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct CedarEngine {
+    binary: PathBuf,
+    policies: PathBuf,
+    allowed_hosts: Vec<String>,
+    docker_args: Vec<String>,
+}
+
+impl CedarEngine {
+    /// Loads the engine, validating its policies first.
+    pub fn new(spec: &EngineSpec, policy: &PolicyConfig) -> Result<Self> {
+        let Some(ref data) = spec.data else {
+            bail!("engine: the cedar engine needs `data`");
+        };
+        let binary = binary().context("engine")?;
+        let policies = PathBuf::from(data);
+        validate(&binary, &policies)?;
+        Ok(Self {
+            binary,
+            policies,
+            allowed_hosts: policy.allowed_network_hosts(),
+            docker_args: policy.get_all_docker_args(),
+        })
+    }
+
+    /// Whether `principal` may take `action` on `resource`.
+    fn is_authorized(
+        &self,
+        principal: &str,
+        action: &str,
+        resource: &str,
+        context: &Value,
+    ) -> Result<bool> {
+        let context = Scratch::new(context)?;
+        let output = Command::new(&self.binary)
+            .arg("authorize")
+            .arg("--policies")
+            .arg(&self.policies)
+            .args(["--principal", principal])
+            .args(["--action", &entity_uid("Action", action)])
+            .args(["--resource", resource])
+            .arg("--context")
+            .arg(&context.0)
+            .output()
+            .context("Failed to execute cedar authorize")?;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout.split_whitespace().next() {
+            Some("ALLOW") => Ok(true),
+            Some("DENY") => Ok(false),
+            _ => bail!(
+                "cedar authorize {} failed: {}",
+                action,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        }
+    }
+
+    /// A denial unless the command line may take `action` on `resource`.
+    fn check(
+        &self,
+        requested: String,
+        action: &str,
+        resource: String,
+        context: Value,
+    ) -> Option<Denial> {
+        let principal = entity_uid("Client", CLI_CLIENT);
+        let reason = match self.is_authorized(&principal, action, &resource, &context) {
+            Ok(true) => return None,
+            Ok(false) => format!("no Cedar policy permits {} on {}", action, resource),
+            Err(e) => format!("{:#}", e),
+        };
+        Some(Denial {
+            requested,
+            clause: format!("{} ({})", action, self.policies.display()),
+            reason,
+        })
+    }
+}
+
+impl PolicyEngine for CedarEngine {
+    fn name(&self) -> &'static str {
+        "cedar"
+    }
+
+    fn check_overrides(&self, args: &ContainerArgs, package: Option<&str>) -> Vec<Denial> {
+        let mut denials = Vec::new();
+        let env: Vec<&str> = args
+            .env
+            .iter()
+            .map(|env| env.split('=').next().unwrap_or(env))
+            .collect();
+        let package = package.unwrap_or_default();
+        denials.extend(self.check(
+            format!("running {}", package),
+            "run",
+            entity_uid("Server", package),
+            json!({
+                "network": args.network.clone().unwrap_or_default(),
+                "cap_add": args.cap_add,
+                "env": env,
+            }),
+        ));
+        for volume in &args.volumes {
+            let (host, read_only) = volume_mount(volume);
+            denials.extend(self.check(
+                format!("--volume {}", volume),
+                "mount",
+                entity_uid("Path", &host),
+                json!({"read_only": read_only}),
+            ));
+        }
+        for host in &self.allowed_hosts {
+            let Some(domain) = domain_of(host) else {
+                continue;
+            };
+            denials.extend(self.check(
+                format!("permissions.network.allow {}", host),
+                "connect",
+                entity_uid("Domain", &domain),
+                json!({}),
+            ));
+        }
+        denials
+    }
+
+    fn docker_args(&self) -> Vec<String> {
+        self.docker_args.clone()
+    }
+
+    fn decides_tools(&self) -> bool {
+        true
+    }
+
+    fn authorize_tool(
+        &self,
+        client: Option<&str>,
+        tool: &str,
+        _arguments: &Value,
+    ) -> Result<(), Refusal> {
+        let client = client.unwrap_or("unknown");
+        let principal = entity_uid("Client", client);
+        // an engine that cannot answer refuses
+        let message = match self.is_authorized(
+            &principal,
+            "call_tool",
+            &entity_uid("Tool", tool),
+            &json!({}),
+        ) {
+            Ok(true) => return Ok(()),
+            Ok(false) => format!("no Cedar policy permits {} to call {}", client, tool),
+            Err(e) => format!("{:#}", e),
+        };
+        Err(Refusal {
+            reason: "tool_denied",
+            rule: "engine",
+            message,
+            details: json!({"tool": tool, "client": client, "engine": self.name()}),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entity_uids_are_quoted() {
+        assert_eq!(entity_uid("Tool", "search"), r#"Semcp::Tool::"search""#);
+        assert_eq!(
+            entity_uid("Path", r#"/data/"x""#),
+            r#"Semcp::Path::"/data/\"x\"""#
+        );
+        assert_eq!(volume_mount("/data:/data:ro"), ("/data".to_string(), true));
+        assert_eq!(volume_mount("/data:/data"), ("/data".to_string(), false));
+    }
+
+    #[test]
+    fn test_schema_covers_every_action() {
+        let schema = schema();
+        let actions = schema[NAMESPACE]["actions"].as_object().unwrap();
+        for action in ["run", "mount", "connect", "call_tool"] {
+            let applies = &actions[action]["appliesTo"];
+            assert_eq!(applies["principalTypes"], json!(["Client"]));
+            let resource = applies["resourceTypes"][0].as_str().unwrap();
+            assert!(schema[NAMESPACE]["entityTypes"].get(resource).is_some());
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Cleanup sets: everything a run creates on the runtime.
+//!
+//! A run registers each container, network and volume it creates, and
+//! [`ContainerExecutor::cleanup`] tears all of them down, newest first, so
+//! sidecars and per-run networks go away with the main container. The set is
+//! also written to `$XDG_STATE_HOME/semcp/runs/<container>.json` while the
+//! run lasts. If snpx is killed before it can clean up (SIGKILL, a crash),
+//! `semcp gc` finds sets whose process is gone and finishes the job.
+
+use crate::runtime::ContainerRuntime;
+use crate::spec::FORCE_KILL_MARGIN;
+use crate::ContainerExecutor;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "name", rename_all = "lowercase")]
+pub enum Resource {
+    Container(String),
+    Network(String),
+    Volume(String),
+}
+
+impl Resource {
+    /// Removes the resource, giving a container `grace` to exit. Missing
+    /// resources are not an error.
+    pub async fn remove(&self, runtime: &dyn ContainerRuntime, grace: Duration) {
+        match self {
+            Resource::Container(name) => {
+                stop_container(runtime, name, grace, grace + FORCE_KILL_MARGIN).await;
+                let _ = runtime.command().args(["rm", "-f", name]).output().await;
+            }
+            Resource::Network(name) => {
+                let _ = runtime
+                    .command()
+                    .args(["network", "rm", name])
+                    .output()
+                    .await;
+            }
+            Resource::Volume(name) => {
+                let _ = runtime
+                    .command()
+                    .args(["volume", "rm", "-f", name])
+                    .output()
+                    .await;
+            }
+        }
+    }
+}
+
+/// Stops `name` with `grace` to exit, and kills it when stopping has not
+/// finished within `force_kill`, e.g. because the daemon does not answer.
+async fn stop_container(
+    runtime: &dyn ContainerRuntime,
+    name: &str,
+    grace: Duration,
+    force_kill: Duration,
+) {
+    let stopped = tokio::time::timeout(force_kill, runtime.stop(name, grace)).await;
+    if !matches!(stopped, Ok(Ok(()))) {
+        let _ = runtime.command().args(["kill", name]).output().await;
+    }
+}
+
+impl std::fmt::Display for Resource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::Container(name) => write!(f, "container {}", name),
+            Resource::Network(name) => write!(f, "network {}", name),
+            Resource::Volume(name) => write!(f, "volume {}", name),
+        }
+    }
+}
+
+/// The resources of one run, with the process that owns them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CleanupSet {
+    pub pid: u32,
+    pub runtime: String,
+    pub resources: Vec<Resource>,
+}
+
+impl CleanupSet {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let content = serde_json::to_string(self)?;
+        std::fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Whether the owning process has exited without cleaning up.
+    pub fn is_orphaned(&self) -> bool {
+        !process_alive(self.pid)
+    }
+
+    /// Removes the resources, newest first.
+    pub async fn remove_all(&self, runtime: &dyn ContainerRuntime, grace: Duration) {
+        for resource in self.resources.iter().rev() {
+            resource.remove(runtime, grace).await;
+        }
+    }
+}
+
+fn process_alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        return Path::new(&format!("/proc/{}", pid)).exists();
+    }
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Directory of the cleanup set files.
+pub fn runs_dir() -> Option<PathBuf> {
+    Some(crate::state::state_home()?.join("runs"))
+}
+
+/// Cleanup sets left behind by runs whose process is gone, with their files.
+pub fn orphaned_sets() -> Result<Vec<(PathBuf, CleanupSet)>> {
+    let Some(dir) = runs_dir() else {
+        return Ok(vec![]);
+    };
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return Ok(vec![]);
+    };
+    let mut sets = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let set = CleanupSet::load(&path)?;
+            if set.is_orphaned() {
+                sets.push((path, set));
+            }
+        }
+    }
+    sets.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(sets)
+}
+
+impl ContainerExecutor {
+    fn cleanup_set_path(&self) -> Option<PathBuf> {
+        Some(runs_dir()?.join(format!("{}.json", self.container_name)))
+    }
+
+    /// Adds `resource` to the run's cleanup set. Failing to persist the set
+    /// only loses the `semcp gc` safety net, so it does not fail the run.
+    pub fn register(&self, resource: Resource) {
+        let set = {
+            let mut resources = self.resources.lock().unwrap();
+            if resources.contains(&resource) {
+                return;
+            }
+            resources.push(resource);
+            CleanupSet {
+                pid: std::process::id(),
+                runtime: self.runtime.name().to_string(),
+                resources: resources.clone(),
+            }
+        };
+        if let Some(path) = self.cleanup_set_path() {
+            if let Err(e) = set.save(&path) {
+                if self.verbose {
+                    eprintln!("Warning: {:#}", e);
+                }
+            }
+        }
+    }
+
+    /// Stops the main container, giving it the policy's
+    /// `graceful_shutdown_timeout` to exit, and kills it once
+    /// `force_kill_timeout` has passed.
+    pub async fn terminate(&self) {
+        let runtime = &self.policy_config.runtime;
+        stop_container(
+            self.runtime.as_ref(),
+            &self.container_name,
+            runtime.graceful_shutdown_timeout(),
+            runtime.force_kill_timeout(),
+        )
+        .await;
+    }
+
+    /// Tears down the main container and everything registered for the run,
+    /// then forgets the cleanup set.
+    pub async fn cleanup(&self) -> Result<()> {
+        self.audit_blocked_egress().await;
+        let mut set = CleanupSet {
+            resources: std::mem::take(&mut *self.resources.lock().unwrap()),
+            ..CleanupSet::default()
+        };
+        let main = Resource::Container(self.container_name.clone());
+        if !set.resources.contains(&main) {
+            // the container may already be gone
+            set.resources.insert(0, main);
+        }
+        // only the server gets to shut down gracefully, sidecars keep no state
+        self.terminate().await;
+        set.remove_all(self.runtime.as_ref(), Duration::ZERO).await;
+        if let Some(path) = self.cleanup_set_path() {
+            let _ = std::fs::remove_file(path);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup_set_round_trip() {
+        let dir = std::env::temp_dir().join(format!("semcp-cleanup-{}", std::process::id()));
+        let path = dir.join("run.json");
+        let set = CleanupSet {
+            pid: 1,
+            runtime: "docker".to_string(),
+            resources: vec![
+                Resource::Container("snpx-1".to_string()),
+                Resource::Network("snpx-1-net".to_string()),
+                Resource::Volume("semcp-cache-npx-snpx-1".to_string()),
+            ],
+        };
+        set.save(&path).unwrap();
+        assert_eq!(CleanupSet::load(&path).unwrap(), set);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_live_process_is_not_orphaned() {
+        let set = CleanupSet {
+            pid: std::process::id(),
+            ..CleanupSet::default()
+        };
+        assert!(!set.is_orphaned());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::presets::Preset;
+use crate::runtime::{self, Endpoint, RuntimeKind};
+use crate::spec::RuntimeClass;
+use crate::{ContainerExecutor, LocaleSettings, ProxyOptions, StateVolume};
+use clap::Args;
+use std::time::Duration;
+
+/// Container options shared by the runner binaries. Options that can grant
+/// the container more privilege than the policy does are checked against
+/// the loaded policy before being applied.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ContainerArgs {
+    #[arg(
+        long = "runtime",
+        value_enum,
+        help = "Container runtime (default: the first installed of docker, podman, nerdctl, then apple on macOS)"
+    )]
+    pub runtime: Option<RuntimeKind>,
+
+    #[arg(
+        long = "context",
+        help = "Docker context (or Podman connection) of the daemon to run on"
+    )]
+    pub context: Option<String>,
+
+    #[arg(
+        long = "docker-host",
+        help = "Daemon to run on, e.g. ssh://user@host or tcp://host:2376 (bind mounts are refused when it is remote)"
+    )]
+    pub docker_host: Option<String>,
+
+    #[arg(
+        long = "tls-cert-path",
+        help = "Directory with ca.pem, cert.pem and key.pem for a TLS docker daemon"
+    )]
+    pub tls_cert_path: Option<String>,
+
+    #[arg(
+        long = "runtime-class",
+        value_enum,
+        help = "Isolation of the container: runc, gvisor for a user space kernel (needs runsc), kata for a VM (needs kata-runtime) or firecracker for a microVM (needs kata-fc)"
+    )]
+    pub runtime_class: Option<RuntimeClass>,
+
+    #[arg(long = "network", help = "Network mode for the container")]
+    pub network: Option<String>,
+
+    #[arg(
+        short = 'e',
+        long = "env",
+        help = "Set an environment variable in the container (KEY or KEY=VALUE)"
+    )]
+    pub env: Vec<String>,
+
+    #[arg(
+        short = 'v',
+        long = "volume",
+        help = "Bind mount a host path (HOST:CONTAINER[:ro|rw])"
+    )]
+    pub volumes: Vec<String>,
+
+    #[arg(long = "cap-add", help = "Add a Linux capability to the container")]
+    pub cap_add: Vec<String>,
+
+    #[arg(
+        long = "explain-denial",
+        help = "Show which policy clause blocked a requested privilege"
+    )]
+    pub explain_denial: bool,
+
+    #[arg(
+        long = "split-phases",
+        help = "Install the package with network access, then serve it offline from a read-only cache"
+    )]
+    pub split_phases: bool,
+
+    #[arg(
+        long = "no-state",
+        help = "Do not mount the persistent per-package state volume"
+    )]
+    pub no_state: bool,
+
+    #[arg(
+        long = "no-init",
+        help = "Do not run an init process as PID 1 in the container"
+    )]
+    pub no_init: bool,
+
+    #[arg(
+        long = "tz",
+        value_parser = parse_timezone,
+        help = "Timezone inside the container (e.g. Europe/Berlin, or host)"
+    )]
+    pub timezone: Option<String>,
+
+    #[arg(
+        long = "locale",
+        help = "Locale inside the container (e.g. en_US.UTF-8)"
+    )]
+    pub locale: Option<String>,
+
+    #[arg(
+        long = "server-json",
+        help = "Registry server.json for the package; its required environment variables are checked before starting"
+    )]
+    pub server_json: Option<String>,
+
+    #[arg(
+        long = "canaries",
+        help = "Plant canary files next to the mounts and stop the container if one is accessed (needs inotifywait)"
+    )]
+    pub canaries: bool,
+
+    #[arg(
+        long = "egress-summary",
+        help = "Print the network destinations the container contacted when it exits"
+    )]
+    pub egress_summary: bool,
+
+    #[arg(
+        long = "sandbox-fallback",
+        help = "Without a container runtime, run the package on the host in a bubblewrap (Linux) or sandbox-exec (macOS) sandbox"
+    )]
+    pub sandbox_fallback: bool,
+
+    #[arg(
+        long = "native-sandbox",
+        help = "Run the package on the host under Landlock and seccomp instead of in a container (Linux)"
+    )]
+    pub native_sandbox: bool,
+
+    #[arg(
+        long = "preset",
+        value_enum,
+        help = "Run under a built-in policy: strict (no network, split phases), balanced or permissive; layered under --policy files"
+    )]
+    pub preset: Option<Preset>,
+
+    #[arg(
+        long = "insecure-defaults",
+        visible_alias = "no-default-policy",
+        help = "Without --policy, run the container without the built-in hardening (dropped capabilities, limits, read-only root)"
+    )]
+    pub insecure_defaults: bool,
+
+    #[arg(
+        long = "lenient-policy",
+        help = "Ignore unknown keys in policy files instead of failing on them"
+    )]
+    pub lenient_policy: bool,
+
+    #[arg(
+        long = "strict-env",
+        help = "Fail on ${VAR} references in policy files to variables that are not set instead of expanding them to nothing"
+    )]
+    pub strict_env: bool,
+
+    #[arg(
+        long = "offline",
+        help = "Read oci:// and https:// policies from the cache instead of fetching or revalidating them"
+    )]
+    pub offline: bool,
+
+    #[arg(
+        long = "require-signed-policy",
+        help = "Refuse policy files without a signature that verifies against policy_signing in the semcp config"
+    )]
+    pub require_signed_policy: bool,
+
+    #[arg(
+        long = "translate-roots",
+        help = "Mount host paths under /roots and translate paths and MCP roots between host and container"
+    )]
+    pub translate_roots: bool,
+
+    #[arg(
+        long = "translate-paths",
+        conflicts_with = "translate_roots",
+        help = "Rewrite paths in the server's messages between container and host according to the mounts"
+    )]
+    pub translate_paths: bool,
+
+    #[arg(
+        long = "skip-arg-check",
+        help = "Do not check the package arguments against the known servers database"
+    )]
+    pub skip_arg_check: bool,
+}
+
+fn parse_timezone(value: &str) -> Result<String, String> {
+    if crate::locale::is_valid_timezone(value) {
+        Ok(value.to_string())
+    } else {
+        Err(format!("invalid timezone `{}`", value))
+    }
+}
+
+impl ContainerArgs {
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(ref network) = self.network {
+            args.push("--network".to_string());
+            args.push(network.clone());
+        }
+        for env in &self.env {
+            args.push("-e".to_string());
+            args.push(env.clone());
+        }
+        for volume in &self.volumes {
+            args.push("-v".to_string());
+            args.push(volume.clone());
+        }
+        for cap in &self.cap_add {
+            args.push("--cap-add".to_string());
+            args.push(cap.clone());
+        }
+        args
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint {
+            context: self.context.clone(),
+            host: self.docker_host.clone(),
+            tls_cert_path: self.tls_cert_path.clone(),
+        }
+    }
+
+    /// Selects the runtime and daemon for this process. Call it before
+    /// creating the executor.
+    pub fn select_runtime(&self) -> anyhow::Result<()> {
+        let endpoint = self.endpoint();
+        if self.runtime.is_none() && endpoint.is_default() {
+            return Ok(());
+        }
+        runtime::set_current(self.runtime, endpoint)
+    }
+
+    /// Applies these options to `executor`. `package` is the package being
+    /// run, which keys the state volume.
+    pub fn configure(
+        &self,
+        executor: ContainerExecutor,
+        package: Option<&String>,
+    ) -> ContainerExecutor {
+        let state_volume = package
+            .filter(|_| !self.no_state)
+            .map(|package| StateVolume::for_package(package));
+        executor
+            .with_extra_args(self.docker_args())
+            // the strict preset has no network to install with
+            .with_split_phases(self.split_phases || self.preset == Some(Preset::Strict))
+            .with_init(!self.no_init)
+            .with_canaries(self.canaries)
+            .with_runtime_class(self.runtime_class)
+            .with_translate_roots(self.translate_roots)
+            .with_translate_paths(self.translate_paths)
+            .with_egress_summary(self.egress_summary)
+            .with_state_volume(state_volume)
+            .with_package(package.cloned())
+            .with_locale(LocaleSettings {
+                timezone: self.timezone.clone(),
+                locale: self.locale.clone(),
+            })
+    }
+}
+
+/// Options for the stdio proxy between the MCP client and the container.
+#[derive(Args, Debug, Clone)]
+pub struct ProxyArgs {
+    #[arg(
+        long = "eager-init",
+        help = "Answer the client's initialize request while the package is still installing"
+    )]
+    pub eager_init: bool,
+
+    #[arg(
+        long = "keepalive-interval",
+        default_value_t = 10,
+        help = "Seconds between progress notifications while the server starts"
+    )]
+    pub keepalive_interval: u64,
+
+    #[arg(
+        long = "trace",
+        help = "Give every request a W3C traceparent in its _meta and audit tool calls with its ids"
+    )]
+    pub trace: bool,
+}
+
+impl ProxyArgs {
+    /// Returns the proxy options, or `None` when stdio can be inherited.
+    pub fn proxy_options(&self) -> Option<ProxyOptions> {
+        if !self.eager_init && !self.trace {
+            return None;
+        }
+        Some(ProxyOptions {
+            eager_initialize: self.eager_init,
+            trace: self.trace,
+            keepalive_interval: Duration::from_secs(self.keepalive_interval.max(1)),
+            ..ProxyOptions::default()
+        })
+    }
+
+    pub fn configure(&self, executor: ContainerExecutor) -> ContainerExecutor {
+        executor.with_proxy(self.proxy_options())
+    }
+}
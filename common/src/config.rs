@@ -0,0 +1,106 @@
+//! The user's semcp settings, in `$XDG_CONFIG_HOME/semcp/config.yaml`.
+//!
+//! ```yaml
+//! # the organization's policy, layered under any --policy
+//! policy_url: https://policies.example.com/mcp/base.yaml
+//! # where to pull images from when their registry fails, see crate::pull
+//! registry_mirrors:
+//!   docker.io: mirror.gcr.io
+//! # who signs policies, see crate::signing
+//! policy_signing:
+//!   key: /etc/semcp/policy.pub
+//! ```
+
+use crate::artifacts;
+use crate::signing::SigningSpec;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UserConfig {
+    /// Policy every run starts from, before the policies given with
+    /// `--policy`, so a security team can publish one canonical policy.
+    pub policy_url: Option<String>,
+    /// Registry to pull from, by the registry it stands in for, when a pull
+    /// fails, e.g. for Docker Hub's rate limit.
+    pub registry_mirrors: BTreeMap<String, String>,
+    /// Who signs policies, see [`crate::signing`].
+    pub policy_signing: SigningSpec,
+}
+
+/// Where the user's settings are read from.
+pub fn user_file() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("semcp").join("config.yaml"))
+}
+
+impl UserConfig {
+    /// The user's settings, or the defaults when there is no file.
+    pub fn load() -> Result<Self> {
+        match user_file().filter(|path| path.exists()) {
+            Some(path) => {
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Self::parse(&content).with_context(|| format!("Invalid {}", path.display()))
+            }
+            None => Ok(Self::default()),
+        }
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let config: Self = serde_yaml::from_str(content)?;
+        if let Some(ref url) = config.policy_url {
+            if !artifacts::is_remote(url) {
+                bail!(
+                    "policy_url must be an https:// or oci:// reference, got {}",
+                    url
+                );
+            }
+        }
+        config.policy_signing.validate()?;
+        for (registry, mirror) in &config.registry_mirrors {
+            if mirror.is_empty() || mirror.contains("://") {
+                bail!(
+                    "The mirror of {} must be a registry and path like mirror.gcr.io, got {:?}",
+                    registry,
+                    mirror
+                );
+            }
+        }
+        Ok(config)
+    }
+
+    /// `policies` with the configured policy in front.
+    pub fn policies(&self, policies: &[String]) -> Vec<String> {
+        self.policy_url.iter().chain(policies).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_url_comes_first() {
+        let config =
+            UserConfig::parse("policy_url: https://policies.example.com/base.yaml\n").unwrap();
+        assert_eq!(
+            config.policies(&["project.yaml".to_string()]),
+            ["https://policies.example.com/base.yaml", "project.yaml"]
+        );
+        assert_eq!(
+            UserConfig::default().policies(&["project.yaml".to_string()]),
+            ["project.yaml"]
+        );
+        assert!(UserConfig::parse("policy_url: base.yaml\n").is_err());
+        assert!(UserConfig::parse("policy: https://example.com/base.yaml\n").is_err());
+        assert!(
+            UserConfig::parse("registry_mirrors:\n  docker.io: https://mirror.gcr.io\n").is_err()
+        );
+    }
+}
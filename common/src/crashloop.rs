@@ -0,0 +1,275 @@
+//! Crash-loop detection.
+//!
+//! A server that dies on startup is restarted until it has failed more
+//! than `max_restart_attempts` times in a row. At that point retrying is
+//! pointless, so the tracker produces one report with the exit code, the
+//! last lines the server wrote to stderr and the likely causes, instead of
+//! another round of restarts.
+
+use anyhow::{Context, Result};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::task::JoinHandle;
+
+pub const DEFAULT_MAX_RESTART_ATTEMPTS: u32 = 3;
+pub const STDERR_TAIL_LINES: usize = 20;
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Forwards a server's stderr to ours while keeping the last lines.
+#[derive(Clone, Default)]
+pub struct StderrTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl StderrTail {
+    pub fn capture<R>(&self, stderr: R) -> JoinHandle<Result<()>>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let lines = Arc::clone(&self.lines);
+        tokio::spawn(async move {
+            let mut reader = BufReader::new(stderr).lines();
+            let mut out = tokio::io::stderr();
+            while let Some(line) = reader
+                .next_line()
+                .await
+                .context("Failed to read server stderr")?
+            {
+                out.write_all(line.as_bytes()).await?;
+                out.write_all(b"\n").await?;
+                let mut lines = lines.lock().unwrap();
+                if lines.len() == STDERR_TAIL_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back(line);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Crash {
+    pub exit_code: Option<i32>,
+    pub stderr: Vec<String>,
+}
+
+/// Guesses why a server failed from what it printed.
+pub fn likely_causes(stderr: &[String]) -> Vec<&'static str> {
+    let text = stderr.join("\n").to_lowercase();
+    let rules: [(&[&str], &str); 6] = [
+        (
+            &["environment variable", "env var", "api_key", "token is required", "not set"],
+            "a required environment variable is missing; pass it with -e and allow it in permissions.environment",
+        ),
+        (
+            &["unknown option", "unknown argument", "unrecognized", "usage:", "invalid argument"],
+            "the server was started with an argument it does not accept",
+        ),
+        (
+            &["enotfound", "eai_again", "getaddrinfo", "econnrefused", "network is unreachable"],
+            "the server needs network access the policy does not grant",
+        ),
+        (
+            &["eacces", "permission denied", "read-only file system", "erofs"],
+            "the server writes to a path that is not mounted writable",
+        ),
+        (
+            &["enoent", "no such file or directory"],
+            "a file or directory the server expects is not mounted",
+        ),
+        (
+            &["404 not found", "e404", "module_not_found", "no matching distribution", "not found in the package registry"],
+            "the package name or version does not exist",
+        ),
+    ];
+    rules
+        .iter()
+        .filter(|(needles, _)| needles.iter().any(|needle| text.contains(needle)))
+        .map(|(_, cause)| *cause)
+        .collect()
+}
+
+/// Consolidated diagnostic for a server stuck in a crash loop.
+#[derive(Debug, Clone)]
+pub struct CrashLoopReport {
+    pub server: String,
+    pub crashes: usize,
+    pub max_restart_attempts: u32,
+    pub last: Crash,
+}
+
+impl fmt::Display for CrashLoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} crashed on {} consecutive starts (max_restart_attempts: {}), not restarting it",
+            self.server, self.crashes, self.max_restart_attempts
+        )?;
+        match self.last.exit_code {
+            Some(code) => writeln!(f, "last exit code: {}", code)?,
+            None => writeln!(f, "last exit: killed by a signal")?,
+        }
+        if self.last.stderr.is_empty() {
+            writeln!(f, "the server wrote nothing to stderr")?;
+        } else {
+            writeln!(f, "last {} lines of stderr:", self.last.stderr.len())?;
+            for line in &self.last.stderr {
+                writeln!(f, "  {}", line)?;
+            }
+        }
+        let causes = likely_causes(&self.last.stderr);
+        if causes.is_empty() {
+            write!(f, "run with --verbose and check the server's documentation")
+        } else {
+            write!(f, "likely causes:")?;
+            for cause in causes {
+                write!(f, "\n  - {}", cause)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CrashVerdict {
+    /// Restart after waiting this long.
+    Restart {
+        attempt: u32,
+        backoff: Duration,
+    },
+    CrashLoop,
+}
+
+/// Delay before restart `attempt`, counted from 1: doubling from one
+/// second up to a minute.
+pub fn backoff(attempt: u32) -> Duration {
+    BASE_BACKOFF
+        .saturating_mul(1 << attempt.saturating_sub(1).min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Counts consecutive crashes of one server.
+#[derive(Debug, Clone)]
+pub struct CrashTracker {
+    server: String,
+    max_restart_attempts: u32,
+    consecutive: u32,
+    last: Option<Crash>,
+}
+
+impl CrashTracker {
+    pub fn new(server: &str, max_restart_attempts: u32) -> Self {
+        Self {
+            server: server.to_string(),
+            max_restart_attempts,
+            consecutive: 0,
+            last: None,
+        }
+    }
+
+    pub fn max_restart_attempts(&self) -> u32 {
+        self.max_restart_attempts
+    }
+
+    pub fn record_crash(&mut self, crash: Crash) -> CrashVerdict {
+        self.consecutive += 1;
+        self.last = Some(crash);
+        if self.consecutive > self.max_restart_attempts {
+            return CrashVerdict::CrashLoop;
+        }
+        CrashVerdict::Restart {
+            attempt: self.consecutive,
+            backoff: backoff(self.consecutive),
+        }
+    }
+
+    /// A clean exit or a session the client ended breaks the loop.
+    pub fn record_success(&mut self) {
+        self.consecutive = 0;
+        self.last = None;
+    }
+
+    pub fn is_crash_looping(&self) -> bool {
+        self.consecutive > self.max_restart_attempts
+    }
+
+    pub fn report(&self) -> Option<CrashLoopReport> {
+        let last = self.last.clone()?;
+        Some(CrashLoopReport {
+            server: self.server.clone(),
+            crashes: self.consecutive as usize,
+            max_restart_attempts: self.max_restart_attempts,
+            last,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crash(stderr: &[&str]) -> Crash {
+        Crash {
+            exit_code: Some(1),
+            stderr: stderr.iter().map(|line| line.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_then_crash_loop() {
+        let mut tracker = CrashTracker::new("memory", 2);
+        assert_eq!(
+            tracker.record_crash(crash(&[])),
+            CrashVerdict::Restart {
+                attempt: 1,
+                backoff: Duration::from_secs(1)
+            }
+        );
+        assert_eq!(
+            tracker.record_crash(crash(&[])),
+            CrashVerdict::Restart {
+                attempt: 2,
+                backoff: Duration::from_secs(2)
+            }
+        );
+        assert_eq!(tracker.record_crash(crash(&[])), CrashVerdict::CrashLoop);
+        assert!(tracker.is_crash_looping());
+
+        tracker.record_success();
+        assert!(!tracker.is_crash_looping());
+        assert!(tracker.report().is_none());
+    }
+
+    #[test]
+    fn test_report_includes_stderr_and_causes() {
+        let mut tracker = CrashTracker::new("github", 0);
+        tracker.record_crash(crash(&[
+            "Error: GITHUB_PERSONAL_ACCESS_TOKEN environment variable is not set",
+        ]));
+
+        let report = tracker.report().unwrap().to_string();
+        assert!(report.contains("github crashed on 1 consecutive starts"));
+        assert!(report.contains("  Error: GITHUB_PERSONAL_ACCESS_TOKEN"));
+        assert!(report.contains("environment variable is missing"));
+    }
+
+    #[test]
+    fn test_likely_causes() {
+        let lines = vec!["error: unrecognized arguments: --port".to_string()];
+        assert_eq!(
+            likely_causes(&lines),
+            ["the server was started with an argument it does not accept"]
+        );
+        assert!(likely_causes(&["Listening on stdio".to_string()]).is_empty());
+    }
+}
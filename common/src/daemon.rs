@@ -0,0 +1,284 @@
+//! Checks of the container daemon's own hardening.
+//!
+//! A policy can require a minimum bar of the daemon a package runs on:
+//!
+//! ```yaml
+//! permissions:
+//!   runtime:
+//!     daemon:
+//!       userns_remap: true           # or a rootless daemon
+//!       live_restore: true
+//!       seccomp: true                # a default seccomp profile
+//!       no_insecure_registries: true
+//!       on_failure: refuse           # or warn
+//! ```
+//!
+//! The daemon is asked with `info`, so remote daemons are checked too.
+//! Podman has no daemon whose restart would stop containers, so it meets
+//! `live_restore`. A requirement that cannot be told from the answer counts
+//! as unmet.
+
+use crate::runtime::ContainerRuntime;
+use crate::ContainerExecutor;
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct DaemonSpec {
+    /// Containers run in a remapped user namespace, or the daemon is
+    /// rootless, so root in a container is not root on the host.
+    pub userns_remap: bool,
+    /// Containers keep running while the daemon restarts.
+    pub live_restore: bool,
+    /// Containers get a seccomp profile by default.
+    pub seccomp: bool,
+    /// The daemon pulls from no registry over plain HTTP or without
+    /// verifying its certificate.
+    pub no_insecure_registries: bool,
+    pub on_failure: OnFailure,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Do not start the package.
+    #[default]
+    Refuse,
+    /// Print a warning and start it.
+    Warn,
+}
+
+/// What the daemon says about its hardening, `None` where it does not tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DaemonFacts {
+    pub userns_remap: Option<bool>,
+    pub live_restore: Option<bool>,
+    pub seccomp: Option<bool>,
+    /// Registries trusted without TLS, loopback addresses left out.
+    pub insecure_registries: Option<Vec<String>>,
+}
+
+fn is_loopback(registry: &str) -> bool {
+    let host = registry.split('/').next().unwrap_or_default();
+    host.starts_with("127.") || host == "::1" || host.starts_with("localhost")
+}
+
+impl DaemonFacts {
+    /// The facts in `info --format '{{json .}}'` output, from Docker or
+    /// Podman.
+    pub fn from_info(info: &Value) -> Self {
+        if let Some(host) = info.get("host") {
+            // podman
+            let security = host.get("security");
+            let rootless = security
+                .and_then(|security| security.get("rootless"))
+                .and_then(Value::as_bool);
+            let remapped = host
+                .pointer("/idMappings/uidmap")
+                .and_then(Value::as_array)
+                .is_some_and(|map| !map.is_empty());
+            let insecure = info
+                .get("registries")
+                .and_then(Value::as_object)
+                .map(|registries| {
+                    registries
+                        .iter()
+                        .filter(|(_, config)| {
+                            config.get("Insecure").and_then(Value::as_bool) == Some(true)
+                        })
+                        .map(|(name, _)| name.clone())
+                        .filter(|name| !is_loopback(name))
+                        .collect()
+                });
+            return Self {
+                userns_remap: rootless.map(|rootless| rootless || remapped),
+                live_restore: Some(true),
+                seccomp: security
+                    .and_then(|security| security.get("seccompEnabled"))
+                    .and_then(Value::as_bool),
+                insecure_registries: insecure.or(Some(vec![])),
+            };
+        }
+        let options: Option<Vec<&str>> = info
+            .get("SecurityOptions")
+            .and_then(Value::as_array)
+            .map(|options| options.iter().filter_map(Value::as_str).collect());
+        let insecure = info.get("RegistryConfig").map(|config| {
+            let cidrs = config
+                .get("InsecureRegistryCIDRs")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .map(str::to_string);
+            let indexes = config
+                .get("IndexConfigs")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter(|(_, index)| index.get("Secure").and_then(Value::as_bool) == Some(false))
+                .map(|(name, _)| name.clone());
+            let mut insecure: Vec<String> = cidrs
+                .chain(indexes)
+                .filter(|registry| !is_loopback(registry))
+                .collect();
+            insecure.sort();
+            insecure.dedup();
+            insecure
+        });
+        Self {
+            userns_remap: options.as_ref().map(|options| {
+                options
+                    .iter()
+                    .any(|option| *option == "name=userns" || *option == "name=rootless")
+            }),
+            live_restore: info.get("LiveRestoreEnabled").and_then(Value::as_bool),
+            seccomp: options.as_ref().map(|options| {
+                options.iter().any(|option| {
+                    option.starts_with("name=seccomp") && !option.contains("profile=unconfined")
+                })
+            }),
+            insecure_registries: insecure,
+        }
+    }
+}
+
+impl DaemonSpec {
+    pub fn is_empty(&self) -> bool {
+        !(self.userns_remap || self.live_restore || self.seccomp || self.no_insecure_registries)
+    }
+
+    /// The requirements `facts` do not meet, described.
+    pub fn unmet(&self, facts: &DaemonFacts) -> Vec<String> {
+        let mut unmet = Vec::new();
+        let mut require = |required: bool, fact: Option<bool>, what: &str| match (required, fact) {
+            (false, _) | (true, Some(true)) => {}
+            (true, Some(false)) => unmet.push(format!("{} is off", what)),
+            (true, None) => unmet.push(format!("{} cannot be checked", what)),
+        };
+        require(
+            self.userns_remap,
+            facts.userns_remap,
+            "user namespace remapping",
+        );
+        require(self.live_restore, facts.live_restore, "live restore");
+        require(self.seccomp, facts.seccomp, "the default seccomp profile");
+        if self.no_insecure_registries {
+            match facts.insecure_registries {
+                Some(ref registries) if registries.is_empty() => {}
+                Some(ref registries) => unmet.push(format!(
+                    "insecure registries are allowed: {}",
+                    registries.join(", ")
+                )),
+                None => unmet.push("insecure registries cannot be checked".to_string()),
+            }
+        }
+        unmet
+    }
+}
+
+/// Asks `runtime` how its daemon is set up and applies `spec`: fails when
+/// it falls short and `spec` refuses, otherwise warns.
+pub async fn check(runtime: &dyn ContainerRuntime, spec: &DaemonSpec) -> Result<()> {
+    if spec.is_empty() {
+        return Ok(());
+    }
+    let info = runtime
+        .command()
+        .args(["info", "--format", "{{json .}}"])
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| serde_json::from_slice::<Value>(&output.stdout).ok())
+        .unwrap_or(Value::Null);
+    let unmet = spec.unmet(&DaemonFacts::from_info(&info));
+    if unmet.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "The {} daemon does not meet permissions.runtime.daemon: {}",
+        runtime.name(),
+        unmet.join("; ")
+    );
+    match spec.on_failure {
+        OnFailure::Refuse => bail!(message),
+        OnFailure::Warn => {
+            eprintln!("Warning: {}", message);
+            Ok(())
+        }
+    }
+}
+
+impl ContainerExecutor {
+    /// Checks the daemon against the policy's `runtime.daemon`.
+    pub async fn check_daemon(&self) -> Result<()> {
+        check(self.runtime.as_ref(), &self.policy_config.runtime.daemon).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn all() -> DaemonSpec {
+        DaemonSpec {
+            userns_remap: true,
+            live_restore: true,
+            seccomp: true,
+            no_insecure_registries: true,
+            on_failure: OnFailure::Refuse,
+        }
+    }
+
+    #[test]
+    fn test_docker_facts() {
+        let info = json!({
+            "SecurityOptions": ["name=apparmor", "name=seccomp,profile=builtin", "name=userns"],
+            "LiveRestoreEnabled": false,
+            "RegistryConfig": {
+                "InsecureRegistryCIDRs": ["127.0.0.0/8", "::1/128"],
+                "IndexConfigs": {
+                    "docker.io": {"Secure": true},
+                    "registry.internal:5000": {"Secure": false}
+                }
+            }
+        });
+        let facts = DaemonFacts::from_info(&info);
+        assert_eq!(facts.userns_remap, Some(true));
+        assert_eq!(facts.seccomp, Some(true));
+        assert_eq!(
+            all().unmet(&facts),
+            [
+                "live restore is off",
+                "insecure registries are allowed: registry.internal:5000",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_podman_facts() {
+        let info = json!({
+            "host": {"security": {"rootless": true, "seccompEnabled": true}},
+            "registries": {"search": ["docker.io"]}
+        });
+        let facts = DaemonFacts::from_info(&info);
+        assert!(all().unmet(&facts).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_facts_are_unmet() {
+        let unmet = all().unmet(&DaemonFacts::from_info(&Value::Null));
+        assert_eq!(unmet.len(), 4);
+        assert!(unmet
+            .iter()
+            .all(|reason| reason.contains("cannot be checked")));
+        assert!(DaemonSpec::default()
+            .unmet(&DaemonFacts::default())
+            .is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+//! Enforcing the network allow-list at the resolver.
+//!
+//! With `filter_dns: true` under `permissions.network`, the container gets a
+//! network of its own, `<container>-net`, shared with a dnsmasq sidecar,
+//! `<container>-dns`, that is its only resolver. The sidecar forwards the
+//! hosts in `permissions.network.allow` and their subdomains, and answers
+//! everything else with NXDOMAIN. It does not stop connections to IP
+//! addresses. Both are in the run's cleanup set.
+
+use crate::cleanup::Resource;
+use crate::{ContainerExecutor, PolicyConfig};
+use anyhow::{bail, Context, Result};
+
+/// Built from `data/sidecars/dns-filter.Dockerfile`, see [`crate::sidecar`].
+pub const DEFAULT_IMAGE: &str = crate::sidecar::DNS_FILTER.tag;
+/// The runtime's embedded resolver, reachable from user-defined networks.
+pub const EMBEDDED_RESOLVER: &str = "127.0.0.11";
+
+/// The domain an allow-list host stands for: `https://*.github.com:443`
+/// becomes `github.com`. IP addresses need no resolving and give `None`.
+pub fn domain_of(host: &str) -> Option<String> {
+    let host = host.split_once("://").map_or(host, |(_, rest)| rest);
+    let host = host.split('/').next().unwrap_or_default();
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let host = host.trim_start_matches("*.").trim_end_matches('.');
+    if host.is_empty() || host.parse::<std::net::IpAddr>().is_ok() {
+        return None;
+    }
+    Some(host.to_ascii_lowercase())
+}
+
+/// dnsmasq arguments forwarding `domains` to `upstreams` and refusing the
+/// rest.
+pub fn dnsmasq_args(domains: &[String], upstreams: &[String]) -> Vec<String> {
+    let mut args = vec![
+        "--keep-in-foreground".to_string(),
+        "--no-resolv".to_string(),
+        "--no-hosts".to_string(),
+        "--log-facility=-".to_string(),
+        // more specific domains below take precedence
+        "--address=/#/".to_string(),
+    ];
+    for domain in domains {
+        for upstream in upstreams {
+            args.push(format!("--server=/{}/{}", domain, upstream));
+        }
+    }
+    args
+}
+
+/// Domains the filter forwards for `policy`.
+pub fn domains(policy: &PolicyConfig) -> Vec<String> {
+    policy
+        .allowed_network_hosts()
+        .iter()
+        .filter_map(|host| domain_of(host))
+        .collect()
+}
+
+/// Resolvers the filter forwards to: the policy's, or the runtime's own.
+pub fn upstreams(policy: &PolicyConfig) -> Vec<String> {
+    let mut upstreams = policy.network.dns_servers.clone();
+    if upstreams.is_empty() {
+        upstreams.push(EMBEDDED_RESOLVER.to_string());
+    }
+    upstreams
+}
+
+impl ContainerExecutor {
+    /// The per-run network of the filter and firewall sidecars.
+    pub(crate) fn run_network(&self) -> String {
+        format!("{}-net", self.container_name)
+    }
+
+    /// Creates the per-run network unless this run already has.
+    pub(crate) async fn ensure_run_network(&self) -> Result<String> {
+        let network = self.run_network();
+        let resource = Resource::Network(network.clone());
+        if self.resources.lock().unwrap().contains(&resource) {
+            return Ok(network);
+        }
+        self.register(resource);
+        let label_args = self.label_args();
+        let mut create_args = vec!["network", "create", "--label", "semcp.managed=true"];
+        create_args.extend(label_args.iter().map(String::as_str));
+        create_args.push(&network);
+        self.runtime_step(&create_args).await?;
+        Ok(network)
+    }
+
+    /// `--network` and `--dns` for a container behind the filter, once
+    /// [`ContainerExecutor::start_dns_filter`] has started it.
+    pub(crate) fn dns_filter_args(&self) -> Option<Vec<String>> {
+        let address = self.dns_filter.lock().unwrap().clone()?;
+        Some(vec![
+            "--network".to_string(),
+            self.run_network(),
+            "--dns".to_string(),
+            address,
+        ])
+    }
+
+    /// Creates the per-session network and the resolver sidecar when the
+    /// policy asks for DNS filtering.
+    pub(crate) async fn start_dns_filter(&self) -> Result<()> {
+        if !self.policy_config.filter_dns() {
+            return Ok(());
+        }
+        let requested_network = self.extra_args.iter().any(|arg| arg == "--network");
+        if requested_network && self.policy_config.is_baseline() {
+            // the baseline grants any network asked for, but not silently
+            // without the filter
+            eprintln!(
+                "Warning: --network takes the container off the baseline's DNS filter; \
+                 it can resolve any host"
+            );
+            return Ok(());
+        }
+        if requested_network || self.policy_config.network_mode().is_some() {
+            bail!(
+                "permissions.network.filter_dns puts the container on a network of its own; \
+                 drop permissions.network.policy and --network"
+            );
+        }
+
+        let network = self.ensure_run_network().await?;
+
+        let sidecar = format!("{}-dns", self.container_name);
+        let domains = domains(&self.policy_config);
+        let upstreams = upstreams(&self.policy_config);
+        let image = self.policy_config.dns_filter_image();
+        let filter_args = dnsmasq_args(&domains, &upstreams);
+        self.ensure_sidecar_image(&image).await?;
+        let label_args = self.label_args();
+        let mut run_args = vec![
+            "run",
+            "-d",
+            "--rm",
+            "--name",
+            &sidecar,
+            "--network",
+            &network,
+            "--label",
+            "semcp.managed=true",
+        ];
+        run_args.extend(label_args.iter().map(String::as_str));
+        run_args.extend(["--entrypoint", "dnsmasq", &image]);
+        run_args.extend(filter_args.iter().map(String::as_str));
+        self.register(Resource::Container(sidecar.clone()));
+        self.runtime_step(&run_args).await?;
+
+        let inspect = self
+            .runtime_step(&[
+                "inspect",
+                "-f",
+                "{{range .NetworkSettings.Networks}}{{.IPAddress}}{{end}}",
+                &sidecar,
+            ])
+            .await?;
+        let address = inspect.trim().to_string();
+        if address.parse::<std::net::IpAddr>().is_err() {
+            bail!("DNS filter {} has no address on {}", sidecar, network);
+        }
+        if self.verbose {
+            eprintln!(
+                "DNS filter {} at {} allows: {}",
+                sidecar,
+                address,
+                domains.join(", ")
+            );
+        }
+        *self.dns_filter.lock().unwrap() = Some(address);
+        Ok(())
+    }
+
+    /// Runs one runtime command to completion and returns its stdout.
+    pub(crate) async fn runtime_step(&self, args: &[&str]) -> Result<String> {
+        if self.verbose {
+            eprintln!("Running: {} {}", self.runtime, args.join(" "));
+        }
+        let output = self
+            .runtime
+            .command()
+            .args(args)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {} {}", self.runtime, args[0]))?;
+        if !output.status.success() {
+            bail!(
+                "{} {} failed: {}",
+                self.runtime,
+                args[..2.min(args.len())].join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_domain_of_allow_list_hosts() {
+        assert_eq!(domain_of("api.github.com"), Some("api.github.com".into()));
+        assert_eq!(
+            domain_of("https://*.GitHub.com:443/repos"),
+            Some("github.com".into())
+        );
+        assert_eq!(domain_of("10.0.0.1"), None);
+        assert_eq!(domain_of("*."), None);
+    }
+
+    #[test]
+    fn test_dnsmasq_args_refuse_everything_else() {
+        let args = dnsmasq_args(
+            &["github.com".to_string()],
+            &["10.0.0.53".to_string(), "10.0.1.53".to_string()],
+        );
+        assert!(args.contains(&"--address=/#/".to_string()));
+        assert!(args.contains(&"--server=/github.com/10.0.0.53".to_string()));
+        assert!(args.contains(&"--server=/github.com/10.0.1.53".to_string()));
+        assert_eq!(dnsmasq_args(&[], &["10.0.0.53".to_string()]).len(), 5);
+    }
+}
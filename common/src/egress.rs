@@ -0,0 +1,306 @@
+//! Network egress summary.
+//!
+//! With `--egress-summary`, the container's socket tables are sampled from
+//! the host (`/proc/<pid>/net/{tcp,tcp6,udp,udp6}`) while it runs, and a
+//! summary of the destinations it contacted is printed when it exits.
+//! Connections that never got past SYN_SENT are reported separately, as
+//! those are attempts that were blocked or unreachable.
+//!
+//! Sampling sees sockets, not packets: byte counts are per container (from
+//! `/proc/<pid>/net/dev`), and a connection opened and closed between two
+//! samples is missed. Per-destination bytes and domain names need a
+//! filtering egress proxy, which semcp does not have yet.
+
+use crate::runtime::ContainerRuntime;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+const TCP_SYN_SENT: u8 = 0x02;
+const TCP_LISTEN: u8 = 0x0A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Socket {
+    pub protocol: Protocol,
+    pub remote: SocketAddr,
+    pub state: u8,
+}
+
+fn parse_ipv4(hex: &str) -> Option<Ipv4Addr> {
+    let raw = u32::from_str_radix(hex, 16).ok()?;
+    Some(Ipv4Addr::from(raw.to_ne_bytes()))
+}
+
+fn parse_ipv6(hex: &str) -> Option<Ipv6Addr> {
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut bytes = [0u8; 16];
+    for (i, chunk) in bytes.chunks_mut(4).enumerate() {
+        let raw = u32::from_str_radix(&hex[i * 8..i * 8 + 8], 16).ok()?;
+        chunk.copy_from_slice(&raw.to_ne_bytes());
+    }
+    Some(Ipv6Addr::from(bytes))
+}
+
+fn parse_endpoint(endpoint: &str) -> Option<SocketAddr> {
+    let (ip, port) = endpoint.split_once(':')?;
+    let ip = match ip.len() {
+        8 => IpAddr::V4(parse_ipv4(ip)?),
+        _ => IpAddr::V6(parse_ipv6(ip)?),
+    };
+    Some(SocketAddr::new(ip, u16::from_str_radix(port, 16).ok()?))
+}
+
+/// Sockets with a remote end from a `/proc/net/{tcp,udp}[6]` table.
+pub fn parse_sockets(content: &str, protocol: Protocol) -> Vec<Socket> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let remote = parse_endpoint(fields.get(2)?)?;
+            let state = u8::from_str_radix(fields.get(3)?, 16).ok()?;
+            Some(Socket {
+                protocol,
+                remote,
+                state,
+            })
+        })
+        .filter(|socket| {
+            let ip = socket.remote.ip();
+            let listening = socket.protocol == Protocol::Tcp && socket.state == TCP_LISTEN;
+            !(ip.is_unspecified() || ip.is_loopback() || listening)
+        })
+        .collect()
+}
+
+/// Received and sent bytes over all interfaces but loopback, from a
+/// `/proc/net/dev` table.
+pub fn parse_net_dev(content: &str) -> (u64, u64) {
+    content
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, counters) = line.split_once(':')?;
+            if interface.trim() == "lo" {
+                return None;
+            }
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .filter_map(|value| value.parse().ok())
+                .collect();
+            Some((*counters.first()?, *counters.get(8)?))
+        })
+        .fold((0, 0), |(rx, tx), (r, t)| (rx + r, tx + t))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Destination {
+    pub connected: bool,
+    pub samples: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EgressSummary {
+    pub destinations: BTreeMap<(Protocol, SocketAddr), Destination>,
+    pub received_bytes: u64,
+    pub sent_bytes: u64,
+    pub sampled: bool,
+}
+
+impl EgressSummary {
+    pub fn record(&mut self, sockets: &[Socket]) {
+        self.sampled = true;
+        for socket in sockets {
+            let destination = self
+                .destinations
+                .entry((socket.protocol, socket.remote))
+                .or_default();
+            destination.samples += 1;
+            if socket.protocol == Protocol::Udp || socket.state != TCP_SYN_SENT {
+                destination.connected = true;
+            }
+        }
+    }
+
+    /// Destinations that only ever showed up as pending connects.
+    pub fn failed_attempts(&self) -> impl Iterator<Item = &(Protocol, SocketAddr)> {
+        self.destinations
+            .iter()
+            .filter(|(_, destination)| !destination.connected)
+            .map(|(key, _)| key)
+    }
+}
+
+fn protocol_name(protocol: Protocol) -> &'static str {
+    match protocol {
+        Protocol::Tcp => "tcp",
+        Protocol::Udp => "udp",
+    }
+}
+
+impl fmt::Display for EgressSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.sampled {
+            return write!(
+                f,
+                "Egress summary: the container's sockets could not be sampled"
+            );
+        }
+        writeln!(
+            f,
+            "Egress summary: {} destination(s), {} bytes received, {} bytes sent",
+            self.destinations.len(),
+            self.received_bytes,
+            self.sent_bytes
+        )?;
+        for ((protocol, remote), destination) in &self.destinations {
+            if destination.connected {
+                writeln!(f, "  {} {}", protocol_name(*protocol), remote)?;
+            }
+        }
+        for (protocol, remote) in self.failed_attempts() {
+            writeln!(
+                f,
+                "  {} {} (never connected: blocked or unreachable)",
+                protocol_name(*protocol),
+                remote
+            )?;
+        }
+        write!(f, "(sampled every {}ms)", SAMPLE_INTERVAL.as_millis())
+    }
+}
+
+async fn container_pid(runtime: &dyn ContainerRuntime, container: &str) -> Option<u32> {
+    let output = runtime
+        .command()
+        .args(["inspect", "--format", "{{.State.Pid}}", container])
+        .output()
+        .await
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .ok()
+        .filter(|pid| *pid != 0)
+}
+
+fn sample(pid: u32, summary: &Mutex<EgressSummary>) -> bool {
+    let base = format!("/proc/{}/net", pid);
+    let tables = [
+        ("tcp", Protocol::Tcp),
+        ("tcp6", Protocol::Tcp),
+        ("udp", Protocol::Udp),
+        ("udp6", Protocol::Udp),
+    ];
+    let mut sockets = Vec::new();
+    let mut readable = false;
+    for (table, protocol) in tables {
+        if let Ok(content) = std::fs::read_to_string(format!("{}/{}", base, table)) {
+            readable = true;
+            sockets.extend(parse_sockets(&content, protocol));
+        }
+    }
+    if !readable {
+        return false;
+    }
+    let mut summary = summary.lock().unwrap();
+    summary.record(&sockets);
+    // counters vanish with the container, so keep the latest reading
+    if let Ok(content) = std::fs::read_to_string(format!("{}/dev", base)) {
+        let (received, sent) = parse_net_dev(&content);
+        summary.received_bytes = received;
+        summary.sent_bytes = sent;
+    }
+    true
+}
+
+/// Samples a running container until [`EgressMonitor::finish`].
+pub struct EgressMonitor {
+    summary: Arc<Mutex<EgressSummary>>,
+    task: JoinHandle<()>,
+}
+
+impl EgressMonitor {
+    pub fn start(runtime: Arc<dyn ContainerRuntime>, container: String) -> Self {
+        let summary = Arc::new(Mutex::new(EgressSummary::default()));
+        let shared = Arc::clone(&summary);
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            let mut pid = None;
+            loop {
+                interval.tick().await;
+                if pid.is_none() {
+                    pid = container_pid(runtime.as_ref(), &container).await;
+                }
+                if let Some(current) = pid {
+                    if !sample(current, &shared) {
+                        pid = None;
+                    }
+                }
+            }
+        });
+        Self { summary, task }
+    }
+
+    pub fn finish(self) -> EgressSummary {
+        self.task.abort();
+        let summary = self.summary.lock().unwrap().clone();
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TCP: &str = "  sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode
+   0: 00000000:0BB8 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 1 1 0000000000000000 100 0 0 10 0
+   1: 0200000A:A1B2 2204C68C:01BB 01 00000000:00000000 00:00000000 00000000     0        0 2 1 0000000000000000 20 4 30 10 -1
+   2: 0200000A:A1B4 08080808:0050 02 00000000:00000000 00:00000000 00000000     0        0 3 1 0000000000000000 20 4 30 10 -1
+   3: 0100007F:A1B6 0100007F:1F90 01 00000000:00000000 00:00000000 00000000     0        0 4 1 0000000000000000 20 4 30 10 -1";
+
+    #[test]
+    fn test_parse_sockets() {
+        let sockets = parse_sockets(TCP, Protocol::Tcp);
+        assert_eq!(sockets.len(), 2);
+        if cfg!(target_endian = "little") {
+            assert_eq!(sockets[0].remote, "140.198.4.34:443".parse().unwrap());
+            assert_eq!(sockets[1].remote, "8.8.8.8:80".parse().unwrap());
+        }
+        assert_eq!(sockets[0].state, 0x01);
+    }
+
+    #[test]
+    fn test_summary_separates_failed_attempts() {
+        let mut summary = EgressSummary::default();
+        summary.record(&parse_sockets(TCP, Protocol::Tcp));
+        assert_eq!(summary.destinations.len(), 2);
+        assert_eq!(summary.failed_attempts().count(), 1);
+
+        let text = summary.to_string();
+        assert!(text.contains("2 destination(s)"));
+        assert!(text.contains(":80 (never connected"));
+    }
+
+    #[test]
+    fn test_parse_net_dev() {
+        let dev = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:     100       1    0    0    0     0          0         0      100       1    0    0    0     0       0          0
+  eth0:    5000      10    0    0    0     0          0         0     1200       8    0    0    0     0       0          0";
+        assert_eq!(parse_net_dev(dev), (5000, 1200));
+    }
+}
@@ -0,0 +1,419 @@
+//! Policy engines.
+//!
+//! Docker argument generation, the check of command line overrides and the
+//! proxy ask a [`PolicyEngine`] rather than reading the policy themselves.
+//! The built-in engine evaluates the YAML policy. With an `engine` section
+//! in the policy, the decisions can come from elsewhere:
+//!
+//! ```yaml
+//! engine:
+//!   kind: opa            # yaml (default), opa, rego or cedar
+//!   data: policy/        # Rego files, a bundle or Cedar policies
+//!   package: semcp
+//! ```
+//!
+//! The OPA engine runs `opa eval` against `data.<package>.deny`, with
+//! `input.action` set to `run` for the command line and `tools/call` for
+//! tool calls; every message in `deny` refuses the action. When
+//! `data.<package>.docker_args` is defined it replaces the container
+//! arguments of the YAML policy. The `rego` engine makes the same decisions
+//! in-process with [regorus](https://github.com/microsoft/regorus), so `opa`
+//! need not be installed; it takes `.rego` and `.json` files, bundles still
+//! need `opa`. It is built with the `regorus` feature. For Cedar, see
+//! [`crate::cedar`].
+
+use crate::cedar::CedarEngine;
+use crate::cli::ContainerArgs;
+use crate::overrides::Denial;
+use crate::refusal::Refusal;
+use crate::PolicyConfig;
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    #[default]
+    Yaml,
+    Opa,
+    Rego,
+    Cedar,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct EngineSpec {
+    pub kind: EngineKind,
+    /// Policy data for the engine, absolute or relative to the working
+    /// directory.
+    pub data: Option<String>,
+    pub package: String,
+}
+
+impl EngineKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Yaml => "yaml",
+            Self::Opa => "opa",
+            Self::Rego => "rego",
+            Self::Cedar => "cedar",
+        }
+    }
+}
+
+impl Default for EngineSpec {
+    fn default() -> Self {
+        Self {
+            kind: EngineKind::Yaml,
+            data: None,
+            package: "semcp".to_string(),
+        }
+    }
+}
+
+pub trait PolicyEngine: fmt::Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Privileges requested on the command line for `package` that the
+    /// policy does not grant.
+    fn check_overrides(&self, args: &ContainerArgs, package: Option<&str>) -> Vec<Denial>;
+
+    /// Container arguments the policy calls for.
+    fn docker_args(&self) -> Vec<String>;
+
+    /// Whether [`PolicyEngine::authorize_tool`] can refuse anything, so the
+    /// proxy has to see the traffic.
+    fn decides_tools(&self) -> bool {
+        false
+    }
+
+    /// Decides whether a `tools/call` of `client`, named as in its
+    /// `initialize` request, may reach the server.
+    fn authorize_tool(
+        &self,
+        _client: Option<&str>,
+        _tool: &str,
+        _arguments: &Value,
+    ) -> Result<(), Refusal> {
+        Ok(())
+    }
+}
+
+/// The built-in engine, evaluating the YAML policy.
+#[derive(Debug, Clone)]
+pub struct YamlEngine {
+    policy: PolicyConfig,
+}
+
+impl YamlEngine {
+    pub fn new(policy: PolicyConfig) -> Self {
+        Self { policy }
+    }
+}
+
+impl PolicyEngine for YamlEngine {
+    fn name(&self) -> &'static str {
+        "yaml"
+    }
+
+    fn check_overrides(&self, args: &ContainerArgs, _package: Option<&str>) -> Vec<Denial> {
+        self.policy.check_overrides(args)
+    }
+
+    fn docker_args(&self) -> Vec<String> {
+        self.policy.get_all_docker_args()
+    }
+}
+
+/// Where Rego is evaluated.
+#[derive(Debug)]
+enum Evaluator {
+    /// By `opa eval`, against the files or bundle at `data`.
+    Cli { binary: PathBuf, data: PathBuf },
+    /// In-process, by a copy of this engine per decision.
+    #[cfg(feature = "regorus")]
+    Embedded(std::sync::Mutex<regorus::Engine>),
+}
+
+impl Evaluator {
+    fn new(kind: EngineKind, data: &str) -> Result<Self> {
+        match kind {
+            EngineKind::Rego => Self::embedded(Path::new(data)),
+            _ => Ok(Self::Cli {
+                binary: which::which("opa").context("engine: opa is not installed")?,
+                data: PathBuf::from(data),
+            }),
+        }
+    }
+
+    #[cfg(feature = "regorus")]
+    fn embedded(data: &Path) -> Result<Self> {
+        let mut engine = regorus::Engine::new();
+        load_rego(&mut engine, data)?;
+        Ok(Self::Embedded(std::sync::Mutex::new(engine)))
+    }
+
+    #[cfg(not(feature = "regorus"))]
+    fn embedded(_data: &Path) -> Result<Self> {
+        bail!("engine: the rego engine needs a build with the `regorus` feature, or use kind: opa")
+    }
+}
+
+/// Adds the `.rego` policies and `.json` documents at `path`, a file or a
+/// directory, to `engine`.
+#[cfg(feature = "regorus")]
+fn load_rego(engine: &mut regorus::Engine, path: &Path) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            load_rego(engine, &entry)?;
+        }
+        return Ok(());
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("rego") => {
+            engine
+                .add_policy_from_file(path)
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+        }
+        Some("json") => {
+            let data = regorus::Value::from_json_file(path)
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+            engine.add_data(data)?;
+        }
+        Some("gz") | Some("tar") => {
+            bail!(
+                "engine: {} is a bundle, which needs kind: opa",
+                path.display()
+            )
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// The Rego engines, `opa` and `rego`, which differ only in where the
+/// policy is evaluated.
+#[derive(Debug)]
+pub struct OpaEngine {
+    kind: EngineKind,
+    evaluator: Evaluator,
+    package: String,
+    docker_args: Vec<String>,
+}
+
+/// The messages of a `deny` set.
+fn deny_messages(value: Option<Value>) -> Vec<String> {
+    match value {
+        Some(Value::Array(messages)) => messages
+            .into_iter()
+            .map(|message| match message {
+                Value::String(text) => text,
+                other => other.to_string(),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+impl OpaEngine {
+    pub fn new(spec: &EngineSpec, policy: &PolicyConfig) -> Result<Self> {
+        let Some(ref data) = spec.data else {
+            bail!("engine: the {} engine needs `data`", spec.kind.name());
+        };
+        let mut engine = Self {
+            kind: spec.kind,
+            evaluator: Evaluator::new(spec.kind, data)?,
+            package: spec.package.clone(),
+            docker_args: Vec::new(),
+        };
+        engine.docker_args = match engine.eval("docker_args", &json!({"action": "run"}))? {
+            Some(Value::Array(args)) => args
+                .iter()
+                .map(|arg| arg.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .context("engine: docker_args must be a list of strings")?,
+            Some(_) => bail!("engine: docker_args must be a list of strings"),
+            None => policy.get_all_docker_args(),
+        };
+        Ok(engine)
+    }
+
+    /// Evaluates `data.<package>.<rule>`; `None` when it is undefined.
+    fn eval(&self, rule: &str, input: &Value) -> Result<Option<Value>> {
+        let query = format!("data.{}.{}", self.package, rule);
+        match self.evaluator {
+            Evaluator::Cli {
+                ref binary,
+                ref data,
+            } => eval_cli(binary, data, &query, input),
+            #[cfg(feature = "regorus")]
+            Evaluator::Embedded(ref engine) => {
+                let mut engine = engine.lock().unwrap().clone();
+                engine.set_input(regorus::Value::from_json_str(&input.to_string())?);
+                let value = engine
+                    .eval_rule(query.clone())
+                    .with_context(|| format!("Failed to evaluate {}", query))?;
+                if value == regorus::Value::Undefined {
+                    return Ok(None);
+                }
+                Ok(Some(serde_json::from_str(&value.to_json_str()?)?))
+            }
+        }
+    }
+
+    fn deny(&self, input: &Value) -> Result<Vec<String>> {
+        Ok(deny_messages(self.eval("deny", input)?))
+    }
+}
+
+/// Evaluates `query` with `opa eval`; `None` when it is undefined.
+fn eval_cli(binary: &Path, data: &Path, query: &str, input: &Value) -> Result<Option<Value>> {
+    let mut child = Command::new(binary)
+        .args(["eval", "--format", "json", "--stdin-input", "--data"])
+        .arg(data)
+        .arg(query)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute opa eval")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.to_string().as_bytes())?;
+    }
+    let output = child
+        .wait_with_output()
+        .context("Failed to wait for opa eval")?;
+    if !output.status.success() {
+        bail!(
+            "opa eval {} failed: {}",
+            query,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let result: Value =
+        serde_json::from_slice(&output.stdout).context("Failed to parse opa eval output")?;
+    Ok(result.pointer("/result/0/expressions/0/value").cloned())
+}
+
+impl PolicyEngine for OpaEngine {
+    fn name(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    fn check_overrides(&self, args: &ContainerArgs, package: Option<&str>) -> Vec<Denial> {
+        // environment values may be secrets, only their names are shown
+        let env: Vec<&str> = args
+            .env
+            .iter()
+            .map(|env| env.split('=').next().unwrap_or(env))
+            .collect();
+        let input = json!({
+            "action": "run",
+            "package": package,
+            "network": args.network,
+            "env": env,
+            "volumes": args.volumes,
+            "cap_add": args.cap_add,
+        });
+        let clause = format!("data.{}.deny", self.package);
+        match self.deny(&input) {
+            Ok(messages) => messages
+                .into_iter()
+                .map(|reason| Denial {
+                    requested: "the command line".to_string(),
+                    clause: clause.clone(),
+                    reason,
+                })
+                .collect(),
+            Err(e) => vec![Denial {
+                requested: "the command line".to_string(),
+                clause,
+                reason: format!("{:#}", e),
+            }],
+        }
+    }
+
+    fn docker_args(&self) -> Vec<String> {
+        self.docker_args.clone()
+    }
+
+    fn decides_tools(&self) -> bool {
+        true
+    }
+
+    fn authorize_tool(
+        &self,
+        client: Option<&str>,
+        tool: &str,
+        arguments: &Value,
+    ) -> Result<(), Refusal> {
+        let input = json!({
+            "action": "tools/call",
+            "client": client,
+            "tool": tool,
+            "arguments": arguments,
+        });
+        // an engine that cannot answer refuses
+        let messages = self
+            .deny(&input)
+            .unwrap_or_else(|e| vec![format!("{:#}", e)]);
+        if messages.is_empty() {
+            return Ok(());
+        }
+        Err(Refusal {
+            reason: "tool_denied",
+            rule: "engine",
+            message: format!("{} denied: {}", tool, messages.join("; ")),
+            details: json!({"tool": tool, "engine": self.name(), "deny": messages}),
+        })
+    }
+}
+
+/// The engine the policy selects.
+pub fn from_policy(policy: &PolicyConfig) -> Result<Arc<dyn PolicyEngine>> {
+    let spec = policy.engine_spec()?;
+    Ok(match spec.kind {
+        EngineKind::Yaml => Arc::new(YamlEngine::new(policy.clone())),
+        EngineKind::Opa | EngineKind::Rego => Arc::new(OpaEngine::new(&spec, policy)?),
+        EngineKind::Cedar => Arc::new(CedarEngine::new(&spec, policy)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_spec_defaults_to_yaml() {
+        let policy = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+        let engine = from_policy(&policy).unwrap();
+        assert_eq!(engine.name(), "yaml");
+        assert!(!engine.decides_tools());
+        assert_eq!(engine.docker_args(), policy.get_all_docker_args());
+
+        let spec: EngineSpec = serde_yaml::from_str("{kind: opa, data: policy.rego}").unwrap();
+        assert_eq!(spec.kind, EngineKind::Opa);
+        assert_eq!(spec.package, "semcp");
+    }
+
+    #[test]
+    fn test_deny_messages() {
+        assert!(deny_messages(None).is_empty());
+        assert_eq!(
+            deny_messages(Some(json!(["no network", {"rule": 1}]))),
+            vec!["no network".to_string(), r#"{"rule":1}"#.to_string()]
+        );
+    }
+}
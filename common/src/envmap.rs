@@ -0,0 +1,212 @@
+//! Environment mapping.
+//!
+//! Every MCP server expects its own variable names. `env_mapping` under
+//! `permissions.runtime` renames host variables on the way into the
+//! container and transforms their values:
+//!
+//! ```yaml
+//! env_mapping:
+//!   - {from: GITHUB_TOKEN, to: GITHUB_PERSONAL_ACCESS_TOKEN}
+//!   - {from: "ACME_*", strip_prefix: ACME_}
+//!   - {from: API_KEY_B64, to: API_KEY, transform: [base64-decode, trim]}
+//! ```
+//!
+//! Mapped variables are passed as `-e NAME` with their values in the
+//! environment of the runtime CLI, so the values never show up in a command
+//! line.
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+pub struct EnvMapping {
+    /// Host variable, or every variable with a prefix ending in `*`.
+    pub from: String,
+    /// Name in the container, for a single variable.
+    #[serde(default)]
+    pub to: Option<String>,
+    /// Removed from the front of the name.
+    #[serde(default)]
+    pub strip_prefix: Option<String>,
+    /// Applied to the value, in order.
+    #[serde(default)]
+    pub transform: Vec<Transform>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    Base64Decode,
+    Trim,
+    Lowercase,
+    Uppercase,
+}
+
+impl Transform {
+    fn apply(self, value: String) -> Result<String> {
+        Ok(match self {
+            Transform::Base64Decode => String::from_utf8(base64_decode(&value)?)
+                .context("the decoded value is not UTF-8")?,
+            Transform::Trim => value.trim().to_string(),
+            Transform::Lowercase => value.to_lowercase(),
+            Transform::Uppercase => value.to_uppercase(),
+        })
+    }
+}
+
+/// Decodes standard or URL-safe base64, padded or not.
+fn base64_decode(value: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in value.trim().trim_end_matches('=').chars() {
+        let digit = match c {
+            'A'..='Z' => c as u32 - 'A' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 26,
+            '0'..='9' => c as u32 - '0' as u32 + 52,
+            '+' | '-' => 62,
+            '/' | '_' => 63,
+            _ => bail!("`{}` is not a base64 digit", c),
+        };
+        buffer = (buffer << 6) | digit;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(bytes)
+}
+
+impl EnvMapping {
+    fn prefix(&self) -> Option<&str> {
+        self.from.strip_suffix('*')
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let name = self.prefix().unwrap_or(&self.from);
+        if name.is_empty() || name.contains(['*', '=']) {
+            bail!(
+                "env_mapping: invalid `from` `{}`, use a variable name or a prefix ending in *",
+                self.from
+            );
+        }
+        if self.prefix().is_some() && self.to.is_some() {
+            bail!(
+                "env_mapping: `{}` maps several variables, rename them with strip_prefix instead of `to`",
+                self.from
+            );
+        }
+        if let Some(ref to) = self.to {
+            if to.is_empty() || to.contains('=') {
+                bail!("env_mapping: invalid `to` `{}`", to);
+            }
+        }
+        Ok(())
+    }
+
+    /// The container name of host variable `key`, if this mapping takes it.
+    fn target(&self, key: &str) -> Option<String> {
+        let matches = match self.prefix() {
+            Some(prefix) => key.starts_with(prefix),
+            None => key == self.from,
+        };
+        if !matches {
+            return None;
+        }
+        let name = match (&self.to, &self.strip_prefix) {
+            (Some(to), _) => to.clone(),
+            (None, Some(strip)) => key.strip_prefix(strip.as_str()).unwrap_or(key).to_string(),
+            (None, None) => key.to_string(),
+        };
+        (!name.is_empty()).then_some(name)
+    }
+}
+
+/// The container variables `mappings` make of the `host` environment. A
+/// later mapping wins over an earlier one for the same name.
+pub fn map(
+    mappings: &[EnvMapping],
+    host: impl IntoIterator<Item = (String, String)>,
+) -> Result<Vec<(String, String)>> {
+    let host: BTreeMap<String, String> = host.into_iter().collect();
+    let mut mapped = BTreeMap::new();
+    for mapping in mappings {
+        for (key, value) in &host {
+            let Some(name) = mapping.target(key) else {
+                continue;
+            };
+            let value = mapping
+                .transform
+                .iter()
+                .try_fold(value.clone(), |value, transform| transform.apply(value))
+                .with_context(|| format!("env_mapping: cannot map {} to {}", key, name))?;
+            mapped.insert(name, value);
+        }
+    }
+    Ok(mapped.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_renames_and_prefixes() {
+        let mappings: Vec<EnvMapping> = serde_yaml::from_str(
+            "[{from: GITHUB_TOKEN, to: GITHUB_PERSONAL_ACCESS_TOKEN}, {from: 'ACME_*', strip_prefix: ACME_}]",
+        )
+        .unwrap();
+        let mapped = map(
+            &mappings,
+            host(&[
+                ("GITHUB_TOKEN", "ghp_x"),
+                ("ACME_REGION", "eu"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            mapped,
+            host(&[("GITHUB_PERSONAL_ACCESS_TOKEN", "ghp_x"), ("REGION", "eu")])
+        );
+    }
+
+    #[test]
+    fn test_transforms() {
+        let mappings: Vec<EnvMapping> =
+            serde_yaml::from_str("[{from: KEY_B64, to: KEY, transform: [base64-decode, trim]}]")
+                .unwrap();
+        let mapped = map(&mappings, host(&[("KEY_B64", "c2VjcmV0Cg==")])).unwrap();
+        assert_eq!(mapped, host(&[("KEY", "secret")]));
+        assert!(map(&mappings, host(&[("KEY_B64", "not base64!")])).is_err());
+        assert_eq!(base64_decode("aGk").unwrap(), b"hi");
+    }
+
+    #[test]
+    fn test_validate() {
+        let prefix_with_to = EnvMapping {
+            from: "ACME_*".to_string(),
+            to: Some("X".to_string()),
+            strip_prefix: None,
+            transform: vec![],
+        };
+        assert!(prefix_with_to.validate().is_err());
+        let star = EnvMapping {
+            from: "*".to_string(),
+            to: None,
+            strip_prefix: None,
+            transform: vec![],
+        };
+        assert!(star.validate().is_err());
+    }
+}
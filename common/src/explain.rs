@@ -0,0 +1,450 @@
+//! What a policy turns into, for `snpx policy explain`, and how two
+//! policies differ in it, for `snpx policy diff`.
+//!
+//! The run options come from the code that starts containers, adapted to
+//! the detected runtime, so they are what a run gets before command line
+//! flags are added. Settings that are parsed but do not restrict the server
+//! are listed apart, so "enforced" can be checked before anything runs.
+//! Comparing explanations rather than files shows what a change does, not
+//! how it is written: moving a key between layers changes nothing.
+
+use crate::dnsfilter::domain_of;
+use crate::engine::PolicyEngine;
+use crate::spec::{DurationSpec, MountOption, RuntimeSpec};
+use crate::{ContainerExecutor, PolicyConfig};
+use serde_yaml::Value;
+use std::fmt;
+use std::sync::Arc;
+
+/// Run options listed under limits rather than options.
+const LIMIT_FLAGS: &[&str] = &["--memory", "--cpus", "--pids-limit", "--ulimit"];
+
+#[derive(Debug, Default)]
+pub struct Explanation {
+    /// The policy files, empty for the baseline.
+    pub sources: Vec<String>,
+    pub runtime: String,
+    pub options: Vec<String>,
+    pub mounts: Vec<String>,
+    /// Hosts `permissions.network` allows.
+    pub hosts: Vec<String>,
+    pub limits: Vec<String>,
+    pub sidecars: Vec<String>,
+    pub unenforced: Vec<String>,
+}
+
+pub fn explain(policy: &PolicyConfig, engine: Arc<dyn PolicyEngine>) -> Explanation {
+    let executor =
+        ContainerExecutor::with_policy(String::new(), false, policy.clone()).with_engine(engine);
+    let runtime = executor.runtime().name().to_string();
+    let adapted = executor.adapted_options();
+    let mut explanation = Explanation {
+        sources: policy.sources().to_vec(),
+        hosts: policy.allowed_network_hosts(),
+        sidecars: sidecars(policy),
+        unenforced: unenforced(policy),
+        ..Explanation::default()
+    };
+    for option in pair_options(&adapted.options) {
+        let flag = option.split(' ').next().unwrap_or_default();
+        if flag == "-v" || flag == "--mount" {
+            explanation.mounts.push(option);
+        } else if LIMIT_FLAGS.contains(&flag) {
+            explanation.limits.push(option);
+        } else {
+            explanation.options.push(option);
+        }
+    }
+    explanation.limits.extend(runtime_limits(&policy.runtime));
+    explanation.unenforced.extend(
+        adapted
+            .downgraded
+            .iter()
+            .map(|reason| format!("{}: {}", runtime, reason)),
+    );
+    explanation.runtime = runtime;
+    explanation
+}
+
+/// Joins each flag with its value, e.g. `--memory 512m`.
+fn pair_options(options: &[String]) -> Vec<String> {
+    let mut paired = Vec::new();
+    let mut iter = options.iter().peekable();
+    while let Some(option) = iter.next() {
+        match iter.next_if(|value| !value.starts_with('-')) {
+            Some(value) => paired.push(format!("{} {}", option, value)),
+            None => paired.push(option.clone()),
+        }
+    }
+    paired
+}
+
+fn duration_text(duration: &DurationSpec) -> String {
+    match duration {
+        DurationSpec::Seconds(seconds) => format!("{}s", seconds),
+        DurationSpec::Text(text) => text.trim().to_string(),
+    }
+}
+
+/// The limits the proxy and supervisor apply rather than the runtime.
+fn runtime_limits(runtime: &RuntimeSpec) -> Vec<String> {
+    let mut limits = Vec::new();
+    for (name, duration) in [
+        ("idle timeout", &runtime.idle_timeout),
+        ("timeout", &runtime.timeout),
+        ("graceful shutdown", &runtime.graceful_shutdown_timeout),
+        ("force kill after", &runtime.force_kill_timeout),
+    ] {
+        if let Some(duration) = duration {
+            limits.push(format!("{} {}", name, duration_text(duration)));
+        }
+    }
+    if let Some(attempts) = runtime.max_restart_attempts {
+        limits.push(format!("at most {} restarts", attempts));
+    }
+    if let Some(ref budget) = runtime.budget {
+        limits.push(format!(
+            "tool budget {} per session, {} back per minute",
+            budget.per_session, budget.refill_per_minute
+        ));
+    }
+    limits
+}
+
+fn sidecars(policy: &PolicyConfig) -> Vec<String> {
+    let mut sidecars = Vec::new();
+    if policy.filter_dns() {
+        let domains: Vec<String> = policy
+            .allowed_network_hosts()
+            .iter()
+            .filter_map(|host| domain_of(host))
+            .collect();
+        sidecars.push(format!(
+            "DNS filter ({}) resolving only {}",
+            policy.dns_filter_image(),
+            if domains.is_empty() {
+                "nothing".to_string()
+            } else {
+                domains.join(", ")
+            }
+        ));
+    }
+    let ports = policy.blocked_ports();
+    if !ports.is_empty() {
+        let ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
+        sidecars.push(format!(
+            "firewall ({}) blocking outgoing ports {}",
+            policy.firewall_image(),
+            ports.join(", ")
+        ));
+    }
+    sidecars
+}
+
+fn field<'a>(document: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(document, |value, key| value.get(*key))
+}
+
+/// Settings that are accepted but do not restrict the server, or that make
+/// every run fail.
+fn unenforced(policy: &PolicyConfig) -> Vec<String> {
+    let mut findings = Vec::new();
+    let network_mode = policy.network_mode();
+
+    let hosts = policy.allowed_network_hosts();
+    if !hosts.is_empty() && !policy.filter_dns() {
+        let reach = match network_mode {
+            Some(ref mode) => format!("the `{}` network decides what the container reaches", mode),
+            None => "the container can reach any host".to_string(),
+        };
+        findings.push(format!(
+            "permissions.network.allow: without filter_dns {}; the hosts only limit --network on the command line",
+            reach
+        ));
+    }
+    if let Some(ref mode) = network_mode {
+        if policy.filter_dns() {
+            findings.push(format!(
+                "permissions.network.filter_dns: runs fail, the DNS filter cannot put the container on `{}`",
+                mode
+            ));
+        }
+        if !policy.blocked_ports().is_empty() {
+            findings.push(format!(
+                "permissions.network.blocked_ports: runs fail, the firewall cannot put the container on `{}`",
+                mode
+            ));
+        }
+        let shared = mode == "host" || mode.starts_with("container:");
+        if shared && !policy.map_dns_args().is_empty() {
+            findings.push(format!(
+                "permissions.network.dns_servers: ignored, the `{}` network has its own resolvers",
+                mode
+            ));
+        }
+    }
+
+    let bind_options: Vec<&str> = policy
+        .runtime
+        .docker
+        .mount_options
+        .iter()
+        .filter(|option| **option != MountOption::Ro)
+        .map(MountOption::as_str)
+        .collect();
+    if !bind_options.is_empty() && !policy.storage_grants().is_empty() {
+        findings.push(format!(
+            "permissions.runtime.docker.mount_options: {} apply to tmpfs mounts only, bind mounts keep the mount flags of the host",
+            bind_options.join(", ")
+        ));
+    }
+
+    for permission in &policy.storage.allow {
+        if !permission.uri.starts_with("fs://") {
+            findings.push(format!(
+                "permissions.storage.allow: `{}` is not an fs:// path and is ignored",
+                permission.uri
+            ));
+        }
+    }
+
+    if let Some(document) = policy.document() {
+        let security = field(document, &["permissions", "runtime", "docker", "security"]);
+        let no_new_privileges = security
+            .and_then(|security| security.get("no_new_privileges"))
+            .and_then(Value::as_bool);
+        let privileged = security
+            .and_then(|security| security.get("privileged"))
+            .and_then(Value::as_bool);
+        if no_new_privileges == Some(true) && privileged != Some(false) {
+            findings.push(
+                "permissions.runtime.docker.security.no_new_privileges: not read, `privileged: false` sets no-new-privileges"
+                    .to_string(),
+            );
+        }
+    }
+    findings
+}
+
+fn section(f: &mut fmt::Formatter<'_>, title: &str, lines: &[String]) -> fmt::Result {
+    writeln!(f, "{}:", title)?;
+    if lines.is_empty() {
+        writeln!(f, "  none")?;
+    }
+    for line in lines {
+        writeln!(f, "  {}", line)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.sources.is_empty() {
+            writeln!(f, "Policy: the built-in baseline (no --policy)")?;
+        } else {
+            writeln!(f, "Policy: {}", self.sources.join(", "))?;
+        }
+        writeln!(f, "Runtime: {}", self.runtime)?;
+        section(f, "Options", &self.options)?;
+        section(f, "Mounts", &self.mounts)?;
+        section(f, "Hosts", &self.hosts)?;
+        section(f, "Limits", &self.limits)?;
+        section(f, "Sidecars", &self.sidecars)?;
+        section(f, "Not enforced", &self.unenforced)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+    Removed(String),
+    /// A setting with another value, e.g. a limit.
+    Changed {
+        from: String,
+        to: String,
+    },
+}
+
+/// How one explanation differs from another, by section.
+#[derive(Debug, Default)]
+pub struct PolicyDiff {
+    pub sections: Vec<(&'static str, Vec<Change>)>,
+}
+
+/// What a limit or option sets, so a new value reads as a change: the flag
+/// of `--memory 1g`, the flag and name of `--ulimit nofile=64`, or the text
+/// before the value of `idle timeout 10m`.
+fn setting_key(line: &str) -> Option<&str> {
+    if let Some(rest) = line.strip_prefix("--ulimit ") {
+        let name = rest.split('=').next().unwrap_or_default();
+        return Some(&line[..line.len() - rest.len() + name.len()]);
+    }
+    let (key, _) = line.rsplit_once(' ')?;
+    (line.starts_with("--") || !key.contains(char::is_numeric)).then_some(key)
+}
+
+/// The changes from `old` to `new`, lines keyed by [`setting_key`] when
+/// `keyed`.
+fn diff_lines(old: &[String], new: &[String], keyed: bool) -> Vec<Change> {
+    let key = |line: &String| {
+        if keyed {
+            setting_key(line).map(str::to_string)
+        } else {
+            None
+        }
+    };
+    let mut changes = Vec::new();
+    for line in old.iter().filter(|line| !new.contains(line)) {
+        let replaced = key(line).and_then(|wanted| {
+            new.iter()
+                .find(|other| !old.contains(other) && key(other).as_ref() == Some(&wanted))
+        });
+        match replaced {
+            Some(to) => changes.push(Change::Changed {
+                from: line.clone(),
+                to: to.clone(),
+            }),
+            None => changes.push(Change::Removed(line.clone())),
+        }
+    }
+    for line in new.iter().filter(|line| !old.contains(line)) {
+        let replacing = changes
+            .iter()
+            .any(|change| matches!(change, Change::Changed { to, .. } if to == line));
+        if !replacing {
+            changes.push(Change::Added(line.clone()));
+        }
+    }
+    changes
+}
+
+pub fn diff(old: &Explanation, new: &Explanation) -> PolicyDiff {
+    let sections = [
+        ("Options", &old.options, &new.options, true),
+        ("Mounts", &old.mounts, &new.mounts, false),
+        ("Hosts", &old.hosts, &new.hosts, false),
+        ("Limits", &old.limits, &new.limits, true),
+        ("Sidecars", &old.sidecars, &new.sidecars, false),
+        ("Not enforced", &old.unenforced, &new.unenforced, false),
+    ];
+    PolicyDiff {
+        sections: sections
+            .into_iter()
+            .map(|(title, old, new, keyed)| (title, diff_lines(old, new, keyed)))
+            .filter(|(_, changes)| !changes.is_empty())
+            .collect(),
+    }
+}
+
+impl PolicyDiff {
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+}
+
+impl fmt::Display for PolicyDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No differences");
+        }
+        for (title, changes) in &self.sections {
+            writeln!(f, "{}:", title)?;
+            for change in changes {
+                match change {
+                    Change::Added(line) => writeln!(f, "  + {}", line)?,
+                    Change::Removed(line) => writeln!(f, "  - {}", line)?,
+                    Change::Changed { from, to } => writeln!(f, "  ~ {} -> {}", from, to)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pair_options() {
+        assert_eq!(
+            pair_options(&strings(&[
+                "--cap-drop",
+                "ALL",
+                "--read-only",
+                "-v",
+                "/data:/data:ro"
+            ])),
+            ["--cap-drop ALL", "--read-only", "-v /data:/data:ro"]
+        );
+    }
+
+    #[test]
+    fn test_unenforced_hosts_without_dns_filter() {
+        let config = PolicyConfig::from_file("../samples/github/policy.yaml").unwrap();
+        let findings = unenforced(&config);
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].contains("can reach any host"));
+
+        let config = PolicyConfig::from_file("testdata/dns-filter-policy.yaml").unwrap();
+        assert!(unenforced(&config).is_empty());
+        assert!(unenforced(&PolicyConfig::from_file("testdata/policy.yaml").unwrap()).is_empty());
+    }
+
+    #[test]
+    fn test_sidecars() {
+        let config = PolicyConfig::from_file("testdata/dns-filter-policy.yaml").unwrap();
+        let found = sidecars(&config);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].contains("resolving only api.github.com"));
+        assert!(found[1].ends_with("blocking outgoing ports 25, 6379/tcp"));
+        assert!(sidecars(&PolicyConfig::baseline()).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Explanation {
+            options: strings(&["--cap-drop ALL", "--read-only"]),
+            hosts: strings(&["registry.npmjs.org"]),
+            limits: strings(&[
+                "--memory 1073741824",
+                "--ulimit nofile=64",
+                "idle timeout 10m",
+            ]),
+            ..Explanation::default()
+        };
+        let new = Explanation {
+            options: strings(&["--cap-drop ALL", "--cap-add NET_RAW"]),
+            hosts: strings(&["registry.npmjs.org", "api.github.com"]),
+            limits: strings(&[
+                "--memory 2147483648",
+                "--ulimit nofile=64",
+                "--ulimit nproc=32",
+                "idle timeout 30m",
+            ]),
+            ..Explanation::default()
+        };
+        let diff = diff(&old, &new);
+        assert_eq!(
+            diff.to_string(),
+            "Options:\n  - --read-only\n  + --cap-add NET_RAW\nHosts:\n  + api.github.com\nLimits:\n  ~ --memory 1073741824 -> --memory 2147483648\n  ~ idle timeout 10m -> idle timeout 30m\n  + --ulimit nproc=32\n"
+        );
+        assert!(super::diff(&old, &old).is_empty());
+        assert_eq!(super::diff(&old, &old).to_string(), "No differences\n");
+    }
+
+    #[test]
+    fn test_runtime_limits() {
+        let runtime: RuntimeSpec =
+            serde_yaml::from_str("{idle_timeout: 10m, timeout: 600, max_restart_attempts: 3}")
+                .unwrap();
+        assert_eq!(
+            runtime_limits(&runtime),
+            ["idle timeout 10m", "timeout 600s", "at most 3 restarts"]
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! Policies as manifests of other platforms, for `snpx policy export`, so a
+//! server hardened locally runs under the same restrictions elsewhere.
+//!
+//! Settings the platform cannot express are not dropped silently: they are
+//! returned as findings, in the words of `snpx policy explain`, and written
+//! as comments above the manifests.
+
+use crate::policy::PolicyConfig;
+use crate::spec::{parse_memory, LimitSpec, VolumeMode};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A Kubernetes Pod and the NetworkPolicy isolating it.
+    K8s,
+}
+
+/// Manifests for a policy and what they leave out.
+#[derive(Debug, Default)]
+pub struct Export {
+    pub documents: Vec<Value>,
+    pub unenforced: Vec<String>,
+}
+
+impl Export {
+    /// The manifests as one YAML stream, the findings as comments on top.
+    pub fn to_yaml(&self) -> Result<String> {
+        let mut out = String::new();
+        if !self.unenforced.is_empty() {
+            out.push_str("# Not enforced:\n");
+            for finding in &self.unenforced {
+                out.push_str(&format!("#   {}\n", finding));
+            }
+        }
+        for document in &self.documents {
+            out.push_str("---\n");
+            out.push_str(&serde_yaml::to_string(document)?);
+        }
+        Ok(out)
+    }
+}
+
+/// What runs under the exported policy.
+#[derive(Debug, Clone)]
+pub struct Workload {
+    /// Name of the Pod, and of the policies selecting it.
+    pub name: String,
+    pub image: String,
+    /// Command of the container, the image's own without any.
+    pub command: Vec<String>,
+}
+
+pub fn export(format: ExportFormat, policy: &PolicyConfig, workload: &Workload) -> Result<Export> {
+    match format {
+        ExportFormat::K8s => kubernetes(policy, workload),
+    }
+}
+
+/// `NET_BIND_SERVICE` for `NetBindService`, as the policy parser names
+/// capabilities.
+fn capability_name(cap: &str) -> String {
+    let mut name = String::new();
+    let mut lower = false;
+    for c in cap.chars() {
+        if c.is_ascii_uppercase() && lower {
+            name.push('_');
+        }
+        lower = c.is_ascii_lowercase();
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+/// `runAsUser` and `runAsGroup` of `docker.user`, which Kubernetes only
+/// takes as numbers.
+fn numeric_user(user: &str) -> Option<(u32, Option<u32>)> {
+    match user.split_once(':') {
+        Some((uid, gid)) => Some((uid.parse().ok()?, Some(gid.parse().ok()?))),
+        None => Some((user.parse().ok()?, None)),
+    }
+}
+
+/// The path of a `--tmpfs` entry, without its options.
+fn tmpfs_path(tmpfs: &str) -> &str {
+    tmpfs.split(':').next().unwrap_or(tmpfs)
+}
+
+/// Whether `host` is an address or a CIDR block, which a NetworkPolicy can
+/// allow, rather than a name, which it cannot.
+fn ip_block(host: &str) -> Option<String> {
+    let (address, prefix) = match host.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix.parse::<u8>().ok()?)),
+        None => (host, None),
+    };
+    let ip = address.parse::<std::net::IpAddr>().ok()?;
+    let bits = if ip.is_ipv4() { 32 } else { 128 };
+    Some(format!("{}/{}", ip, prefix.unwrap_or(bits)))
+}
+
+fn kubernetes(policy: &PolicyConfig, workload: &Workload) -> Result<Export> {
+    let mut unenforced = Vec::new();
+    let docker = &policy.runtime.docker;
+    let labels = json!({
+        "app.kubernetes.io/name": workload.name,
+        "app.kubernetes.io/managed-by": "snpx",
+    });
+
+    let mut security = json!({"privileged": false});
+    let (mut drop, mut add) = (Vec::new(), Vec::new());
+    let args = policy.map_docker_security_args();
+    for pair in args.chunks(2) {
+        match (pair[0].as_str(), pair.get(1).map(String::as_str)) {
+            ("--security-opt", Some("no-new-privileges")) => {
+                security["allowPrivilegeEscalation"] = json!(false);
+            }
+            ("--cap-drop", Some(cap)) => drop.push(capability_name(cap)),
+            ("--cap-add", Some(cap)) => add.push(capability_name(cap)),
+            _ => {}
+        }
+    }
+    if !drop.is_empty() || !add.is_empty() {
+        security["capabilities"] = json!({"drop": drop, "add": add});
+    }
+    if docker.read_only_root_filesystem {
+        security["readOnlyRootFilesystem"] = json!(true);
+    }
+    if let Some(ref user) = docker.user {
+        match numeric_user(user) {
+            Some((uid, gid)) => {
+                security["runAsUser"] = json!(uid);
+                security["runAsNonRoot"] = json!(uid != 0);
+                if let Some(gid) = gid {
+                    security["runAsGroup"] = json!(gid);
+                }
+            }
+            None => unenforced.push(format!(
+                "runtime.docker.user: `{}` is a name, Kubernetes takes a numeric uid",
+                user
+            )),
+        }
+    }
+    if let Some(ref selinux) = docker.selinux {
+        let options = json!({
+            "user": selinux.user,
+            "role": selinux.role,
+            "type": selinux.process_type,
+            "level": selinux.level,
+        });
+        security["seLinuxOptions"] = Value::Object(
+            options
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        );
+    }
+    if docker.seccomp.is_some() {
+        unenforced.push(
+            "runtime.docker.seccomp: the generated profile must be installed on the nodes, the Pod uses RuntimeDefault".to_string(),
+        );
+    }
+
+    let mut limits = serde_json::Map::new();
+    if let Some(ref memory) = docker.memory_limit {
+        let bytes = match memory {
+            LimitSpec::Number(number) => *number as u64,
+            LimitSpec::Text(text) => parse_memory(text)?,
+        };
+        limits.insert("memory".to_string(), json!(bytes.to_string()));
+    }
+    if let Some(ref cpus) = docker.cpu_limit {
+        limits.insert("cpu".to_string(), json!(cpus.to_string()));
+    }
+    if docker.pids_limit.is_some() {
+        unenforced.push(
+            "runtime.docker.pids_limit: Kubernetes limits processes per node, with the kubelet's podPidsLimit".to_string(),
+        );
+    }
+    if !docker.ulimits.is_empty() {
+        unenforced.push("runtime.docker.ulimits: Kubernetes has no per-Pod ulimits".to_string());
+    }
+
+    let (mut volumes, mut mounts) = (Vec::new(), Vec::new());
+    for (i, tmpfs) in docker.tmpfs.iter().enumerate() {
+        let name = format!("tmpfs-{}", i);
+        volumes.push(json!({"name": name, "emptyDir": {"medium": "Memory"}}));
+        mounts.push(json!({"name": name, "mountPath": tmpfs_path(tmpfs)}));
+    }
+    for (i, (path, writable)) in policy.storage_grants().into_iter().enumerate() {
+        let name = format!("host-{}", i);
+        volumes.push(json!({"name": name, "hostPath": {"path": path}}));
+        mounts.push(json!({"name": name, "mountPath": path, "readOnly": !writable}));
+    }
+    for volume in &docker.volumes {
+        volumes.push(json!({
+            "name": volume.name,
+            "persistentVolumeClaim": {"claimName": volume.name},
+        }));
+        mounts.push(json!({
+            "name": volume.name,
+            "mountPath": volume.mount,
+            "readOnly": volume.mode == VolumeMode::Ro,
+        }));
+    }
+
+    // the values stay out of the manifest, in a Secret named after the Pod
+    let env: Vec<Value> = policy
+        .allowed_env_keys()
+        .into_iter()
+        .chain(policy.runtime.environment_whitelist.iter().cloned())
+        .map(|key| {
+            json!({
+                "name": key,
+                "valueFrom": {"secretKeyRef": {"name": workload.name, "key": key, "optional": true}},
+            })
+        })
+        .collect();
+
+    let mut container = json!({
+        "name": workload.name,
+        "image": workload.image,
+        "stdin": true,
+        "securityContext": security,
+        "resources": {"limits": limits},
+        "volumeMounts": mounts,
+        "env": env,
+    });
+    if !workload.command.is_empty() {
+        container["command"] = json!(workload.command);
+    }
+    let mut spec = json!({
+        "automountServiceAccountToken": false,
+        "restartPolicy": "Never",
+        "securityContext": {"seccompProfile": {"type": "RuntimeDefault"}},
+        "containers": [container],
+        "volumes": volumes,
+    });
+    if let Some(class) = docker.runtime_class {
+        if class.oci_runtime().is_some() {
+            // the RuntimeClass objects are set up by the cluster, by these names
+            spec["runtimeClassName"] = json!(class.name());
+        }
+    }
+    let pod = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": {"name": workload.name, "labels": labels},
+        "spec": spec,
+    });
+
+    let egress = match policy.network_mode().as_deref() {
+        Some("none") => vec![],
+        Some("host") => bail!("permissions.network.policy: a Pod on the host network cannot be isolated by a NetworkPolicy"),
+        _ => egress_rules(policy, &mut unenforced),
+    };
+    let network_policy = json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": {"name": workload.name, "labels": labels},
+        "spec": {
+            "podSelector": {"matchLabels": labels},
+            "policyTypes": ["Ingress", "Egress"],
+            "ingress": [],
+            "egress": egress,
+        },
+    });
+
+    Ok(Export {
+        documents: vec![pod, network_policy],
+        unenforced,
+    })
+}
+
+/// Egress for a Pod on a network of its own: anywhere without allowed hosts,
+/// as with Docker, else DNS and the addresses among the hosts. Names are
+/// left out, so they fail closed.
+fn egress_rules(policy: &PolicyConfig, unenforced: &mut Vec<String>) -> Vec<Value> {
+    let hosts = policy.allowed_network_hosts();
+    if !policy.blocked_ports().is_empty() {
+        unenforced.push(
+            "permissions.network.blocked_ports: a NetworkPolicy only allows, so allowed traffic reaches these ports".to_string(),
+        );
+    }
+    if hosts.is_empty() {
+        return vec![json!({})];
+    }
+    let (blocks, names): (Vec<String>, Vec<String>) =
+        hosts.into_iter().partition(|host| ip_block(host).is_some());
+    if !names.is_empty() {
+        unenforced.push(format!(
+            "permissions.network.allow: a NetworkPolicy cannot allow {}, add an egress proxy or a CNI with FQDN policies; until then they are blocked",
+            names.join(", ")
+        ));
+    }
+    let mut rules = vec![json!({
+        "ports": [{"protocol": "UDP", "port": 53}, {"protocol": "TCP", "port": 53}],
+    })];
+    if !blocks.is_empty() {
+        let to: Vec<Value> = blocks
+            .iter()
+            .filter_map(|host| ip_block(host))
+            .map(|cidr| json!({"ipBlock": {"cidr": cidr}}))
+            .collect();
+        rules.push(json!({ "to": to }));
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_name() {
+        assert_eq!(capability_name("All"), "ALL");
+        assert_eq!(capability_name("NetBindService"), "NET_BIND_SERVICE");
+        assert_eq!(capability_name("ALL"), "ALL");
+        assert_eq!(capability_name("NET_RAW"), "NET_RAW");
+    }
+
+    #[test]
+    fn test_numeric_user() {
+        assert_eq!(numeric_user("1000"), Some((1000, None)));
+        assert_eq!(numeric_user("1000:100"), Some((1000, Some(100))));
+        assert_eq!(numeric_user("node"), None);
+        assert_eq!(numeric_user("1000:staff"), None);
+    }
+
+    #[test]
+    fn test_ip_block() {
+        assert_eq!(ip_block("10.0.0.0/8").as_deref(), Some("10.0.0.0/8"));
+        assert_eq!(ip_block("192.168.1.5").as_deref(), Some("192.168.1.5/32"));
+        assert_eq!(ip_block("::1").as_deref(), Some("::1/128"));
+        assert_eq!(ip_block("api.github.com"), None);
+        assert_eq!(tmpfs_path("/tmp:exec"), "/tmp");
+    }
+}
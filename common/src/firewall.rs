@@ -0,0 +1,256 @@
+//! Blocking egress ports.
+//!
+//! With `blocked_ports` under `permissions.network`, a firewall sidecar,
+//! `<container>-fw`, joins the per-run network `<container>-net` and the
+//! container shares its network stack. The sidecar holds iptables rules that
+//! reject outgoing traffic to those ports, over IPv4 and IPv6; if the stack
+//! has IPv6 and its rules cannot be installed, the run fails. When the run
+//! ends, the rules' packet counters are read back and blocked attempts are
+//! written to the audit log.
+
+use crate::cleanup::Resource;
+use crate::ContainerExecutor;
+use anyhow::{bail, Result};
+use serde_json::json;
+use std::fmt;
+
+/// Built from `data/sidecars/firewall.Dockerfile`, see [`crate::sidecar`].
+pub const DEFAULT_IMAGE: &str = crate::sidecar::FIREWALL.tag;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockedPort {
+    pub port: u16,
+    /// `tcp` or `udp`; both when unset.
+    pub protocol: Option<&'static str>,
+}
+
+impl BlockedPort {
+    /// Parses `25`, `53/udp` or `5432/tcp`.
+    pub fn parse(value: &str) -> Result<Self> {
+        let (port, protocol) = match value.split_once('/') {
+            Some((port, "tcp")) => (port, Some("tcp")),
+            Some((port, "udp")) => (port, Some("udp")),
+            Some((_, other)) => bail!("unknown protocol `{}`, use tcp or udp", other),
+            None => (value, None),
+        };
+        match port.trim().parse::<u16>() {
+            Ok(port) if port > 0 => Ok(Self { port, protocol }),
+            _ => bail!("`{}` is not a port", value),
+        }
+    }
+
+    fn protocols(&self) -> Vec<&'static str> {
+        match self.protocol {
+            Some(protocol) => vec![protocol],
+            None => vec!["tcp", "udp"],
+        }
+    }
+}
+
+impl fmt::Display for BlockedPort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.protocol {
+            Some(protocol) => write!(f, "{}/{}", self.port, protocol),
+            None => write!(f, "{}", self.port),
+        }
+    }
+}
+
+/// The shell script installing the rules for `ports`. IPv6 rules are
+/// skipped only when the stack has no IPv6 at all; failing to install them
+/// otherwise fails the script, rather than leave the ports open over IPv6.
+pub fn rules_script(ports: &[BlockedPort]) -> String {
+    let mut lines = vec!["set -e".to_string()];
+    let mut ipv6 = Vec::new();
+    for blocked in ports {
+        for protocol in blocked.protocols() {
+            let rule = format!("OUTPUT -p {} --dport {} -j REJECT", protocol, blocked.port);
+            lines.push(format!("iptables -w -A {}", rule));
+            ipv6.push(format!("  ip6tables -w -A {}", rule));
+        }
+    }
+    if !ipv6.is_empty() {
+        lines.push("if [ -e /proc/net/if_inet6 ]; then".to_string());
+        lines.extend(ipv6);
+        lines.push("fi".to_string());
+    }
+    lines.join("\n")
+}
+
+/// Rejected packets per protocol and port in an `iptables -L OUTPUT -n -v -x`
+/// listing.
+pub fn rejected_packets(listing: &str) -> Vec<(String, u16, u64)> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.get(2) != Some(&"REJECT") {
+                return None;
+            }
+            let packets = fields[0].parse().ok()?;
+            let port = fields
+                .iter()
+                .find_map(|field| field.strip_prefix("dpt:"))?
+                .parse()
+                .ok()?;
+            Some((fields.get(3)?.to_string(), port, packets))
+        })
+        .collect()
+}
+
+impl ContainerExecutor {
+    fn firewall_name(&self) -> String {
+        format!("{}-fw", self.container_name)
+    }
+
+    /// `--network` for a container behind the firewall, once
+    /// [`ContainerExecutor::start_firewall`] has started it.
+    pub(crate) fn firewall_args(&self) -> Option<Vec<String>> {
+        if !*self.firewall.lock().unwrap() {
+            return None;
+        }
+        Some(vec![
+            "--network".to_string(),
+            format!("container:{}", self.firewall_name()),
+        ])
+    }
+
+    /// Starts the firewall sidecar when the policy blocks ports.
+    pub(crate) async fn start_firewall(&self) -> Result<()> {
+        let ports = self.policy_config.blocked_ports();
+        if ports.is_empty() {
+            return Ok(());
+        }
+        let requested_network = self.extra_args.iter().any(|arg| arg == "--network");
+        if requested_network || self.policy_config.network_mode().is_some() {
+            bail!(
+                "permissions.network.blocked_ports puts the container behind a firewall; \
+                 drop permissions.network.policy and --network"
+            );
+        }
+
+        let network = self.ensure_run_network().await?;
+        let sidecar = self.firewall_name();
+        let image = self.policy_config.firewall_image();
+        let dns = self.dns_filter.lock().unwrap().clone();
+        let label_args = self.label_args();
+        let mut run_args = vec![
+            "run",
+            "-d",
+            "--rm",
+            "--name",
+            &sidecar,
+            "--network",
+            &network,
+            "--label",
+            "semcp.managed=true",
+            "--cap-drop",
+            "ALL",
+            "--cap-add",
+            "NET_ADMIN",
+        ];
+        run_args.extend(label_args.iter().map(String::as_str));
+        // the container shares the sidecar's resolver, so it goes through the
+        // DNS filter too
+        if let Some(ref address) = dns {
+            run_args.extend(["--dns", address]);
+        }
+        run_args.extend(["--entrypoint", "tail", &image, "-f", "/dev/null"]);
+        self.ensure_sidecar_image(&image).await?;
+        self.register(Resource::Container(sidecar.clone()));
+        self.runtime_step(&run_args).await?;
+
+        let script = rules_script(&ports);
+        self.runtime_step(&["exec", &sidecar, "sh", "-c", &script])
+            .await?;
+        if self.verbose {
+            let ports: Vec<String> = ports.iter().map(BlockedPort::to_string).collect();
+            eprintln!("Firewall {} blocks ports: {}", sidecar, ports.join(", "));
+        }
+        *self.firewall.lock().unwrap() = true;
+        Ok(())
+    }
+
+    /// Writes the attempts the firewall blocked to the audit log.
+    pub(crate) async fn audit_blocked_egress(&self) {
+        if !*self.firewall.lock().unwrap() {
+            return;
+        }
+        let sidecar = self.firewall_name();
+        let mut blocked = Vec::new();
+        for tool in ["iptables", "ip6tables"] {
+            let listing = self
+                .runtime_step(&[
+                    "exec", &sidecar, tool, "-w", "-L", "OUTPUT", "-n", "-v", "-x",
+                ])
+                .await
+                .unwrap_or_default();
+            blocked.extend(
+                rejected_packets(&listing)
+                    .into_iter()
+                    .filter(|(_, _, packets)| *packets > 0),
+            );
+        }
+        if blocked.is_empty() {
+            return;
+        }
+        if self.verbose {
+            for (protocol, port, packets) in &blocked {
+                eprintln!(
+                    "Firewall blocked {} packets to {}/{}",
+                    packets, port, protocol
+                );
+            }
+        }
+        let blocked: Vec<_> = blocked
+            .into_iter()
+            .map(|(protocol, port, packets)| {
+                json!({"protocol": protocol, "port": port, "packets": packets})
+            })
+            .collect();
+        if self.policy_config.runtime.audit.log_network_access {
+            self.audit("egress_blocked", true, json!({ "blocked": blocked }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_blocked_ports() {
+        assert_eq!(
+            BlockedPort::parse("25").unwrap(),
+            BlockedPort {
+                port: 25,
+                protocol: None
+            }
+        );
+        assert_eq!(BlockedPort::parse("53/udp").unwrap().protocol, Some("udp"));
+        assert!(BlockedPort::parse("0").is_err());
+        assert!(BlockedPort::parse("25/sctp").is_err());
+
+        let script = rules_script(&[BlockedPort::parse("25").unwrap()]);
+        assert!(script.contains("iptables -w -A OUTPUT -p tcp --dport 25 -j REJECT"));
+        assert!(script.contains("iptables -w -A OUTPUT -p udp --dport 25 -j REJECT"));
+        assert!(script.contains(
+            "if [ -e /proc/net/if_inet6 ]; then\n  ip6tables -w -A OUTPUT -p tcp --dport 25 -j REJECT"
+        ));
+        assert!(!script.contains("|| true"));
+    }
+
+    #[test]
+    fn test_rejected_packets() {
+        let listing = "\
+Chain OUTPUT (policy ACCEPT 12 packets, 720 bytes)
+    pkts      bytes target     prot opt in     out     source               destination
+       3      180 REJECT     tcp  --  *      *       0.0.0.0/0            0.0.0.0/0            tcp dpt:25 reject-with icmp-port-unreachable
+       0        0 REJECT     udp  --  *      *       0.0.0.0/0            0.0.0.0/0            udp dpt:25 reject-with icmp-port-unreachable
+";
+        assert_eq!(
+            rejected_packets(listing),
+            vec![("tcp".to_string(), 25, 3), ("udp".to_string(), 25, 0)]
+        );
+    }
+}
@@ -0,0 +1,595 @@
+//! Stdio gateway for a bundle of MCP servers.
+//!
+//! `--multi` starts one container per package and puts this gateway between
+//! them and the client, which sees a single MCP server:
+//!
+//! - `initialize` and the list requests go to every server and the answers
+//!   are merged. Tool and prompt names get the server's name as a prefix,
+//!   `memory__create_entities`, so they stay unique.
+//! - `tools/call`, `prompts/get` and completions go to the server the prefix
+//!   names; resource requests go to the server that listed the URI.
+//! - Requests the servers make (sampling, roots) reach the client under a
+//!   gateway id and the answer goes back to the server that asked.
+//!
+//! The servers share one lifetime: when the client goes away or any server
+//! exits, all containers are stopped. Only the first page of paginated lists
+//! is merged.
+
+use crate::{ContainerExecutor, Runner};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::process::ExitStatus;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Child;
+use tokio::sync::mpsc;
+
+/// Separates the server name from a tool or prompt name.
+pub const SEPARATOR: &str = "__";
+
+/// Requests answered by every server, with the answers merged.
+const FANOUT_METHODS: &[&str] = &[
+    "initialize",
+    "tools/list",
+    "prompts/list",
+    "resources/list",
+    "resources/templates/list",
+    "logging/setLevel",
+];
+
+/// Server names for a bundle of packages: the package name without scope
+/// or version, made unique.
+pub fn server_names(packages: &[String]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for package in packages {
+        let identity = crate::state::package_identity(package);
+        let base: String = identity
+            .rsplit('/')
+            .next()
+            .unwrap_or(&identity)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        let mut name = base.clone();
+        let mut n = 2;
+        while names.contains(&name) {
+            name = format!("{}-{}", base, n);
+            n += 1;
+        }
+        names.push(name);
+    }
+    names
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Routed {
+    to_client: Vec<String>,
+    to_servers: Vec<(usize, String)>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    client_id: Value,
+    method: String,
+    /// Servers that have not answered yet.
+    remaining: usize,
+    answers: Vec<(usize, Value)>,
+}
+
+/// Message-level state of the gateway. Like the proxy session it performs
+/// no IO.
+#[derive(Debug)]
+struct Gateway {
+    names: Vec<String>,
+    next_id: u64,
+    /// Client requests by the id they carry towards the servers.
+    pending: HashMap<u64, Pending>,
+    /// Server requests by the id they carry towards the client.
+    server_requests: HashMap<String, (usize, Value)>,
+    /// Which server listed each resource URI.
+    resources: HashMap<String, usize>,
+    /// Fixed prefixes of resource templates, with their server.
+    templates: Vec<(String, usize)>,
+}
+
+fn error_response(id: &Value, code: i64, message: &str) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message }
+    })
+    .to_string()
+}
+
+fn with_id(message: &Value, id: Value) -> String {
+    let mut message = message.clone();
+    message["id"] = id;
+    message.to_string()
+}
+
+impl Gateway {
+    fn new(names: Vec<String>) -> Self {
+        Self {
+            names,
+            next_id: 1,
+            pending: HashMap::new(),
+            server_requests: HashMap::new(),
+            resources: HashMap::new(),
+            templates: Vec::new(),
+        }
+    }
+
+    /// The server a prefixed name belongs to, and the name without prefix.
+    fn route_name(&self, name: &str) -> Option<(usize, String)> {
+        let (prefix, rest) = name.split_once(SEPARATOR)?;
+        let server = self.names.iter().position(|n| n == prefix)?;
+        Some((server, rest.to_string()))
+    }
+
+    fn route_uri(&self, uri: &str) -> Option<usize> {
+        self.resources.get(uri).copied().or_else(|| {
+            self.templates
+                .iter()
+                .filter(|(prefix, _)| uri.starts_with(prefix.as_str()))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(_, server)| *server)
+        })
+    }
+
+    /// Sends a client request to `servers` under a gateway id.
+    fn forward(&mut self, message: &Value, method: &str, servers: Vec<usize>) -> Routed {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(
+            id,
+            Pending {
+                client_id: message["id"].clone(),
+                method: method.to_string(),
+                remaining: servers.len(),
+                answers: Vec::new(),
+            },
+        );
+        let line = with_id(message, json!(id));
+        Routed {
+            to_client: vec![],
+            to_servers: servers
+                .into_iter()
+                .map(|server| (server, line.clone()))
+                .collect(),
+        }
+    }
+
+    fn on_client_line(&mut self, line: &str) -> Routed {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            return Routed::default();
+        };
+        let id = message.get("id").cloned();
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            // an answer to a server request
+            let Some((server, original)) = id
+                .and_then(|id| id.as_str().map(str::to_string))
+                .and_then(|id| self.server_requests.remove(&id))
+            else {
+                return Routed::default();
+            };
+            return Routed {
+                to_client: vec![],
+                to_servers: vec![(server, with_id(&message, original))],
+            };
+        };
+        let method = method.to_string();
+        let Some(id) = id else {
+            return self.on_client_notification(&message, &method);
+        };
+
+        let all: Vec<usize> = (0..self.names.len()).collect();
+        if method == "ping" {
+            return Routed {
+                to_client: vec![json!({"jsonrpc": "2.0", "id": id, "result": {}}).to_string()],
+                to_servers: vec![],
+            };
+        }
+        if FANOUT_METHODS.contains(&method.as_str()) {
+            return self.forward(&message, &method, all);
+        }
+
+        let name_pointer = match method.as_str() {
+            "tools/call" | "prompts/get" => Some("/params/name"),
+            "completion/complete" => Some("/params/ref/name"),
+            _ => None,
+        };
+        if let Some(pointer) = name_pointer.filter(|p| message.pointer(p).is_some()) {
+            let name = message.pointer(pointer).and_then(Value::as_str);
+            let Some((server, name)) = name.and_then(|name| self.route_name(name)) else {
+                return Routed {
+                    to_client: vec![error_response(&id, -32602, "Unknown tool or prompt")],
+                    to_servers: vec![],
+                };
+            };
+            let mut message = message.clone();
+            if let Some(slot) = message.pointer_mut(pointer) {
+                *slot = json!(name);
+            }
+            return self.forward(&message, &method, vec![server]);
+        }
+
+        let uri = message
+            .pointer("/params/uri")
+            .or_else(|| message.pointer("/params/ref/uri"))
+            .and_then(Value::as_str);
+        if let Some(uri) = uri {
+            return match self.route_uri(uri) {
+                Some(server) => self.forward(&message, &method, vec![server]),
+                None => Routed {
+                    to_client: vec![error_response(&id, -32002, "Resource not found")],
+                    to_servers: vec![],
+                },
+            };
+        }
+        Routed {
+            to_client: vec![error_response(&id, -32601, "Method not found")],
+            to_servers: vec![],
+        }
+    }
+
+    fn on_client_notification(&mut self, message: &Value, method: &str) -> Routed {
+        let mut routed = Routed::default();
+        if method == "notifications/cancelled" {
+            let cancelled = message.pointer("/params/requestId");
+            let found = self
+                .pending
+                .iter()
+                .find(|(_, pending)| Some(&pending.client_id) == cancelled);
+            if let Some((&gateway_id, _)) = found {
+                let mut message = message.clone();
+                message["params"]["requestId"] = json!(gateway_id);
+                let line = message.to_string();
+                routed.to_servers = (0..self.names.len())
+                    .map(|server| (server, line.clone()))
+                    .collect();
+            }
+            return routed;
+        }
+        routed.to_servers = (0..self.names.len())
+            .map(|server| (server, message.to_string()))
+            .collect();
+        routed
+    }
+
+    fn on_server_line(&mut self, server: usize, line: &str) -> Routed {
+        let mut routed = Routed::default();
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            return routed;
+        };
+        let id = message.get("id").cloned();
+        if message.get("method").is_some() {
+            match id {
+                // a server request, e.g. sampling or roots
+                Some(id) => {
+                    let gateway_id = format!("{}-{}", self.names[server], self.next_id);
+                    self.next_id += 1;
+                    self.server_requests
+                        .insert(gateway_id.clone(), (server, id));
+                    routed.to_client.push(with_id(&message, json!(gateway_id)));
+                }
+                None => routed.to_client.push(line.to_string()),
+            }
+            return routed;
+        }
+
+        let Some(gateway_id) = id.as_ref().and_then(Value::as_u64) else {
+            return routed;
+        };
+        let Some(pending) = self.pending.get_mut(&gateway_id) else {
+            return routed;
+        };
+        pending.answers.push((server, message));
+        pending.remaining = pending.remaining.saturating_sub(1);
+        if pending.remaining > 0 {
+            return routed;
+        }
+        let Some(pending) = self.pending.remove(&gateway_id) else {
+            return routed;
+        };
+        routed.to_client.push(self.answer(pending));
+        routed
+    }
+
+    /// The client's answer once every server involved has replied.
+    fn answer(&mut self, pending: Pending) -> String {
+        if let [(_, answer)] = pending.answers.as_slice() {
+            if !FANOUT_METHODS.contains(&pending.method.as_str()) {
+                return with_id(answer, pending.client_id);
+            }
+        }
+        let results: Vec<(usize, &Value)> = pending
+            .answers
+            .iter()
+            .filter_map(|(server, answer)| Some((*server, answer.get("result")?)))
+            .collect();
+        if results.is_empty() {
+            let (_, first) = &pending.answers[0];
+            return with_id(first, pending.client_id);
+        }
+        let result = match pending.method.as_str() {
+            "initialize" => merge_initialize(&results),
+            "tools/list" => self.merge_named(&results, "tools"),
+            "prompts/list" => self.merge_named(&results, "prompts"),
+            "resources/list" => {
+                for (server, result) in &results {
+                    for uri in list(result, "resources")
+                        .iter()
+                        .filter_map(|resource| resource.get("uri")?.as_str())
+                    {
+                        self.resources.insert(uri.to_string(), *server);
+                    }
+                }
+                merge_lists(&results, "resources")
+            }
+            "resources/templates/list" => {
+                for (server, result) in &results {
+                    for template in list(result, "resourceTemplates")
+                        .iter()
+                        .filter_map(|template| template.get("uriTemplate")?.as_str())
+                    {
+                        let prefix = template.split('{').next().unwrap_or_default();
+                        self.templates.push((prefix.to_string(), *server));
+                    }
+                }
+                merge_lists(&results, "resourceTemplates")
+            }
+            _ => json!({}),
+        };
+        json!({"jsonrpc": "2.0", "id": pending.client_id, "result": result}).to_string()
+    }
+
+    fn merge_named(&self, results: &[(usize, &Value)], key: &str) -> Value {
+        let items: Vec<Value> = results
+            .iter()
+            .flat_map(|(server, result)| {
+                list(result, key).into_iter().map(move |mut item| {
+                    if let Some(name) = item.get("name").and_then(Value::as_str) {
+                        item["name"] =
+                            json!(format!("{}{}{}", self.names[*server], SEPARATOR, name));
+                    }
+                    item
+                })
+            })
+            .collect();
+        json!({ key: items })
+    }
+}
+
+fn list(result: &Value, key: &str) -> Vec<Value> {
+    result
+        .get(key)
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn merge_lists(results: &[(usize, &Value)], key: &str) -> Value {
+    let items: Vec<Value> = results
+        .iter()
+        .flat_map(|(_, result)| list(result, key))
+        .collect();
+    json!({ key: items })
+}
+
+fn merge_initialize(results: &[(usize, &Value)]) -> Value {
+    let mut capabilities = Map::new();
+    for (_, result) in results {
+        if let Some(Value::Object(caps)) = result.get("capabilities") {
+            for (key, value) in caps {
+                let merged = capabilities.entry(key.clone()).or_insert(json!({}));
+                if let (Value::Object(merged), Value::Object(value)) = (merged, value) {
+                    for (flag, enabled) in value {
+                        merged.insert(flag.clone(), enabled.clone());
+                    }
+                }
+            }
+        }
+    }
+    json!({
+        "protocolVersion": results[0].1.get("protocolVersion").cloned().unwrap_or(json!("2025-03-26")),
+        "capabilities": capabilities,
+        "serverInfo": { "name": "semcp-gateway", "version": env!("CARGO_PKG_VERSION") }
+    })
+}
+
+/// Relays between this process's stdio and the servers until the client
+/// goes away or a server closes its stdout. Returns the server that closed,
+/// if one did.
+async fn relay(children: &mut [Child], names: Vec<String>) -> Result<Option<usize>> {
+    let (from_servers, mut server_rx) = mpsc::channel::<(usize, Option<String>)>(64);
+    let mut server_inputs = Vec::new();
+    for (server, child) in children.iter_mut().enumerate() {
+        server_inputs.push(child.stdin.take().context("Container stdin is not piped")?);
+        let stdout = child
+            .stdout
+            .take()
+            .context("Container stdout is not piped")?;
+        let tx = from_servers.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if tx.send((server, Some(line))).await.is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send((server, None)).await;
+        });
+    }
+    drop(from_servers);
+
+    let mut gateway = Gateway::new(names);
+    let mut client = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+    loop {
+        let routed = tokio::select! {
+            line = client.next_line() => match line.context("Failed to read from the client")? {
+                Some(line) => gateway.on_client_line(&line),
+                None => return Ok(None),
+            },
+            message = server_rx.recv() => match message {
+                Some((server, Some(line))) => gateway.on_server_line(server, &line),
+                Some((server, None)) => return Ok(Some(server)),
+                None => return Ok(None),
+            },
+        };
+        for line in routed.to_client {
+            stdout.write_all(line.as_bytes()).await?;
+            stdout.write_all(b"\n").await?;
+        }
+        stdout.flush().await?;
+        for (server, line) in routed.to_servers {
+            let input = &mut server_inputs[server];
+            input.write_all(line.as_bytes()).await?;
+            input.write_all(b"\n").await?;
+            input.flush().await?;
+        }
+    }
+}
+
+/// Runs each package in its own container behind one gateway. `servers`
+/// pairs each package with the executor configured for it.
+pub async fn run_bundle<R: Runner>(
+    runner: &R,
+    flags: &[String],
+    servers: Vec<(String, ContainerExecutor)>,
+) -> Result<ExitStatus> {
+    if servers.len() < 2 {
+        bail!("--multi needs at least two packages");
+    }
+    let packages: Vec<String> = servers.iter().map(|(package, _)| package.clone()).collect();
+    let names = server_names(&packages);
+    let executors: Vec<&ContainerExecutor> = servers.iter().map(|(_, executor)| executor).collect();
+
+    let stop_all = || async {
+        for executor in &executors {
+            let _ = executor.cleanup().await;
+        }
+    };
+    let mut children = Vec::new();
+    for ((package, executor), name) in servers.iter().zip(&names) {
+        if executor.verbose() {
+            eprintln!("Starting {} as {}", package, name);
+        }
+        match executor
+            .spawn_piped(runner, flags, std::slice::from_ref(package))
+            .await
+        {
+            Ok(child) => children.push(child),
+            Err(e) => {
+                stop_all().await;
+                return Err(e.context(format!("Failed to start {}", package)));
+            }
+        }
+    }
+
+    let relayed = tokio::select! {
+        relayed = relay(&mut children, names.clone()) => relayed,
+        _ = tokio::signal::ctrl_c() => {
+            stop_all().await;
+            std::process::exit(130);
+        }
+    };
+    stop_all().await;
+    let mut status = None;
+    for (server, child) in children.iter_mut().enumerate() {
+        let exit = child.wait().await.context("Failed to wait for a server")?;
+        if relayed.as_ref().ok() == Some(&Some(server)) {
+            eprintln!("{} exited ({}), stopped the bundle", names[server], exit);
+            status = Some(exit);
+        }
+        status.get_or_insert(exit);
+    }
+    relayed?;
+    status.context("No server ran")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gateway() -> Gateway {
+        Gateway::new(vec!["memory".to_string(), "time".to_string()])
+    }
+
+    fn parse(line: &str) -> Value {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_server_names() {
+        let packages = [
+            "@modelcontextprotocol/server-memory@1.0.0".to_string(),
+            "mcp-server-time".to_string(),
+            "@other/server-memory".to_string(),
+        ];
+        assert_eq!(
+            server_names(&packages),
+            ["server-memory", "mcp-server-time", "server-memory-2"]
+        );
+    }
+
+    #[test]
+    fn test_lists_are_merged_and_calls_routed() {
+        let mut gateway = gateway();
+        let routed = gateway.on_client_line(r#"{"jsonrpc":"2.0","id":"a","method":"tools/list"}"#);
+        assert_eq!(routed.to_servers.len(), 2);
+        let id = parse(&routed.to_servers[0].1)["id"].clone();
+
+        let reply = |tools: &str| {
+            format!(
+                r#"{{"jsonrpc":"2.0","id":{},"result":{{"tools":[{}]}}}}"#,
+                id, tools
+            )
+        };
+        assert!(gateway
+            .on_server_line(0, &reply(r#"{"name":"create_entities"}"#))
+            .to_client
+            .is_empty());
+        let routed = gateway.on_server_line(1, &reply(r#"{"name":"get_time"}"#));
+        let merged = parse(&routed.to_client[0]);
+        assert_eq!(merged["id"], "a");
+        assert_eq!(
+            merged["result"]["tools"],
+            json!([{ "name": "memory__create_entities" }, { "name": "time__get_time" }])
+        );
+
+        let routed = gateway.on_client_line(
+            r#"{"jsonrpc":"2.0","id":7,"method":"tools/call","params":{"name":"time__get_time","arguments":{}}}"#,
+        );
+        let (server, line) = &routed.to_servers[0];
+        assert_eq!(*server, 1);
+        assert_eq!(parse(line)["params"]["name"], "get_time");
+        let answer = format!(
+            r#"{{"jsonrpc":"2.0","id":{},"result":{{"content":[]}}}}"#,
+            parse(line)["id"]
+        );
+        assert_eq!(
+            parse(&gateway.on_server_line(1, &answer).to_client[0])["id"],
+            json!(7)
+        );
+    }
+
+    #[test]
+    fn test_server_requests_reach_the_client() {
+        let mut gateway = gateway();
+        let routed = gateway.on_server_line(0, r#"{"jsonrpc":"2.0","id":3,"method":"roots/list"}"#);
+        let request = parse(&routed.to_client[0]);
+        let answer = format!(
+            r#"{{"jsonrpc":"2.0","id":{},"result":{{"roots":[]}}}}"#,
+            request["id"]
+        );
+        let routed = gateway.on_client_line(&answer);
+        assert_eq!(routed.to_servers[0].0, 0);
+        assert_eq!(parse(&routed.to_servers[0].1)["id"], json!(3));
+
+        let unknown = gateway.on_client_line(
+            r#"{"jsonrpc":"2.0","id":9,"method":"tools/call","params":{"name":"nope"}}"#,
+        );
+        assert!(unknown.to_client[0].contains("Unknown tool"));
+    }
+}
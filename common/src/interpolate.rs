@@ -0,0 +1,165 @@
+//! Environment variable references in policy values.
+//!
+//! String values of a policy may name variables as `${VAR}` or
+//! `${ENV:VAR}`, which are replaced by their values when the policy is
+//! loaded, so one policy can mount `${HOME}/projects` for everyone. `$${`
+//! stands for a literal `${`. Keys are never expanded, and values stay
+//! strings. A variable that is not set expands to nothing, or is an error
+//! when loading requires it.
+
+use anyhow::{bail, Result};
+use serde_yaml::Value;
+
+/// Prefix that names the environment explicitly, as in `${ENV:HOME}`.
+const ENV_PREFIX: &str = "ENV:";
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Expands the references in `text` with `lookup`. Names of variables that
+/// are not set are pushed to `undefined`, and they expand to nothing.
+pub fn expand(
+    text: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    undefined: &mut Vec<String>,
+) -> Result<String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start..];
+        if let Some(escaped) = after.strip_prefix("$${") {
+            expanded.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = after.strip_prefix("${") {
+            let Some(end) = reference.find('}') else {
+                bail!("Unterminated variable reference in `{}`", text);
+            };
+            let name = &reference[..end];
+            let name = name.strip_prefix(ENV_PREFIX).unwrap_or(name);
+            if !is_name(name) {
+                bail!("Invalid variable name `{}` in `{}`", name, text);
+            }
+            match lookup(name) {
+                Some(value) => expanded.push_str(&value),
+                None => undefined.push(name.to_string()),
+            }
+            rest = &reference[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &after[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Expands the references in every string value of `value`, in place, and
+/// returns the names of the variables that are not set, sorted.
+pub fn expand_values(
+    value: &mut Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<String>> {
+    fn walk(
+        value: &mut Value,
+        lookup: &dyn Fn(&str) -> Option<String>,
+        undefined: &mut Vec<String>,
+    ) -> Result<()> {
+        match value {
+            Value::String(text) if text.contains('$') => {
+                *text = expand(text, lookup, undefined)?;
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    walk(item, lookup, undefined)?;
+                }
+            }
+            Value::Mapping(mapping) => {
+                for (_, item) in mapping.iter_mut() {
+                    walk(item, lookup, undefined)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    let mut undefined = Vec::new();
+    walk(value, lookup, &mut undefined)?;
+    undefined.sort();
+    undefined.dedup();
+    Ok(undefined)
+}
+
+/// Looks variables up in the environment of semcp.
+pub fn from_env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/alice".to_string()),
+            "REGISTRY" => Some("registry.example.com".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand() {
+        let mut undefined = Vec::new();
+        let cases = [
+            ("fs://${HOME}/projects", "fs:///home/alice/projects"),
+            ("${ENV:REGISTRY}/mcp", "registry.example.com/mcp"),
+            ("$${HOME} costs $5", "${HOME} costs $5"),
+            ("no references", "no references"),
+            ("${MISSING}/data", "/data"),
+        ];
+        for (text, want) in cases {
+            assert_eq!(expand(text, &lookup, &mut undefined).unwrap(), want);
+        }
+        assert_eq!(undefined, ["MISSING"]);
+
+        for invalid in ["${HOME", "${}", "${1PATH}", "${ENV:}"] {
+            assert!(
+                expand(invalid, &lookup, &mut undefined).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_expand_values() {
+        let mut value: Value = serde_yaml::from_str(
+            r#"
+permissions:
+  storage:
+    allow:
+    - uri: fs://${HOME}/data
+      access: [read]
+  runtime:
+    docker:
+      memory_limit: ${MEMORY}
+      pids_limit: 64
+"#,
+        )
+        .unwrap();
+        let undefined = expand_values(&mut value, &lookup).unwrap();
+        assert_eq!(undefined, ["MEMORY"]);
+        let storage = &value["permissions"]["storage"]["allow"][0];
+        assert_eq!(storage["uri"].as_str(), Some("fs:///home/alice/data"));
+        assert_eq!(
+            value["permissions"]["runtime"]["docker"]["pids_limit"].as_u64(),
+            Some(64)
+        );
+    }
+}
@@ -0,0 +1,306 @@
+//! Argument checks for well-known MCP server packages.
+//!
+//! A small database ships with semcp (`data/known-servers.yaml`) and can be
+//! extended in `~/.config/semcp/known-servers.yaml`; entries there replace
+//! built-in entries for the same package. Arguments are checked before the
+//! container starts, and path arguments must point into something that is
+//! mounted, since the server only sees the container's filesystem.
+
+use crate::overrides::path_within;
+use crate::state::{package_identity, STATE_DIR};
+use crate::ContainerExecutor;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+const BUILTIN: &str = include_str!("../data/known-servers.yaml");
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct KnownServers {
+    #[serde(default)]
+    pub servers: Vec<KnownServer>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct KnownServer {
+    pub package: String,
+    #[serde(default)]
+    pub positional: Option<PositionalSpec>,
+    /// Accepted flags. An empty list means flags are not checked.
+    #[serde(default)]
+    pub flags: Vec<FlagSpec>,
+    #[serde(default)]
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PositionalSpec {
+    pub name: String,
+    #[serde(default)]
+    pub min: usize,
+    #[serde(default)]
+    pub max: Option<usize>,
+    /// Positional arguments are container paths. They must be mounted, and
+    /// when none are given the mounted paths are used.
+    #[serde(default)]
+    pub paths: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlagSpec {
+    pub name: String,
+    #[serde(default)]
+    pub value: Option<ValueKind>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueKind {
+    Path,
+    Text,
+}
+
+fn user_file() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("semcp").join("known-servers.yaml"))
+}
+
+impl KnownServers {
+    pub fn builtin() -> Self {
+        serde_yaml::from_str(BUILTIN).expect("built-in known-servers.yaml is valid")
+    }
+
+    /// Built-in entries extended with the user's file, if any.
+    pub fn load() -> Result<Self> {
+        let mut known = Self::builtin();
+        if let Some(path) = user_file().filter(|path| path.exists()) {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let user: KnownServers = serde_yaml::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            known.extend(user);
+        }
+        Ok(known)
+    }
+
+    pub fn extend(&mut self, other: KnownServers) {
+        for server in other.servers {
+            self.servers.retain(|known| known.package != server.package);
+            self.servers.push(server);
+        }
+    }
+
+    pub fn find(&self, package: &str) -> Option<&KnownServer> {
+        let identity = package_identity(package);
+        self.servers.iter().find(|known| known.package == identity)
+    }
+}
+
+fn check_mounted(path: &str, what: &str, mounts: &[String]) -> Result<()> {
+    if path.starts_with('/') && !mounts.iter().any(|mount| path_within(path, mount)) {
+        bail!(
+            "{} `{}` is not mounted into the container; grant it in permissions.storage or pass -v {}:{}",
+            what,
+            path,
+            path,
+            path
+        );
+    }
+    Ok(())
+}
+
+impl KnownServer {
+    /// Checks `args` (without the package) and returns them, completed with
+    /// `mounts` when a required path list is empty.
+    pub fn check(&self, args: &[String], mounts: &[String]) -> Result<Vec<String>> {
+        let mut positional = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if !arg.starts_with('-') {
+                positional.push(arg.clone());
+                continue;
+            }
+            if self.flags.is_empty() {
+                continue;
+            }
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            let Some(flag) = self.flags.iter().find(|flag| flag.name == name) else {
+                let known: Vec<&str> = self.flags.iter().map(|flag| flag.name.as_str()).collect();
+                bail!(
+                    "{} does not accept `{}` (known flags: {})",
+                    self.package,
+                    name,
+                    known.join(", ")
+                );
+            };
+            if let Some(kind) = flag.value {
+                let value = match inline_value {
+                    Some(value) => value,
+                    None => iter
+                        .next()
+                        .with_context(|| format!("{} expects a value", name))?
+                        .clone(),
+                };
+                if kind == ValueKind::Path {
+                    check_mounted(&value, name, mounts)?;
+                }
+            }
+        }
+
+        let Some(ref spec) = self.positional else {
+            return Ok(args.to_vec());
+        };
+        let mut args = args.to_vec();
+        // the state volume belongs to semcp, not to the user's data
+        let completion: Vec<String> = mounts
+            .iter()
+            .filter(|mount| mount.as_str() != STATE_DIR)
+            .cloned()
+            .collect();
+        if positional.is_empty() && spec.paths && spec.min > 0 && !completion.is_empty() {
+            eprintln!(
+                "No {} given for {}, using the mounted paths: {}",
+                spec.name,
+                self.package,
+                completion.join(" ")
+            );
+            args.extend(completion);
+            return Ok(args);
+        }
+        if positional.len() < spec.min || spec.max.is_some_and(|max| positional.len() > max) {
+            let expected = match spec.max {
+                Some(0) => "no positional arguments".to_string(),
+                Some(max) if max == spec.min => format!("{} {}(s)", max, spec.name),
+                Some(max) => format!("{} to {} {}(s)", spec.min, max, spec.name),
+                None => format!("at least {} {}(s)", spec.min, spec.name),
+            };
+            let mut message = format!(
+                "{} expects {}, got {}",
+                self.package,
+                expected,
+                positional.len()
+            );
+            if let Some(ref hint) = self.hint {
+                message.push_str(&format!("; {}", hint));
+            }
+            bail!(message);
+        }
+        if spec.paths {
+            for path in &positional {
+                check_mounted(path, &spec.name, mounts)?;
+            }
+        }
+        Ok(args)
+    }
+}
+
+impl ContainerExecutor {
+    /// Container paths that are backed by a mount.
+    pub fn mounted_paths(&self) -> Vec<String> {
+        let mut paths = self.policy_config.mount_host_paths();
+        for volume in &self.policy_config.runtime.docker.volumes {
+            paths.push(volume.mount.clone());
+        }
+        if self.state_volume.lock().unwrap().is_some() {
+            paths.push(STATE_DIR.to_string());
+        }
+        for pair in self.extra_args.windows(2) {
+            if pair[0] == "-v" {
+                if let Some(target) = pair[1].split(':').nth(1) {
+                    paths.push(target.to_string());
+                }
+            }
+        }
+        paths
+    }
+
+    /// Validates `package_args` (package first) against the known servers
+    /// database and returns them, possibly completed.
+    pub fn check_server_args(&self, package_args: &[String]) -> Result<Vec<String>> {
+        let Some((package, args)) = package_args.split_first() else {
+            return Ok(package_args.to_vec());
+        };
+        let known = KnownServers::load()?;
+        let Some(server) = known.find(package) else {
+            return Ok(package_args.to_vec());
+        };
+        let mut checked = vec![package.clone()];
+        checked.extend(server.check(args, &self.mounted_paths())?);
+        Ok(checked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_builtin_database_parses() {
+        let known = KnownServers::builtin();
+        assert!(known
+            .find("@modelcontextprotocol/server-filesystem@2025.1.1")
+            .is_some());
+        assert!(known.find("mcp-server-git==0.6.2").is_some());
+        assert!(known.find("unknown-server").is_none());
+    }
+
+    #[test]
+    fn test_filesystem_requires_mounted_directory() {
+        let known = KnownServers::builtin();
+        let server = known
+            .find("@modelcontextprotocol/server-filesystem")
+            .unwrap();
+
+        let error = server.check(&[], &[]).unwrap_err().to_string();
+        assert!(error.contains("at least 1 directory"));
+
+        let completed = server.check(&[], &strings(&["/data"])).unwrap();
+        assert_eq!(completed, ["/data"]);
+
+        assert!(server
+            .check(&strings(&["/data/docs"]), &strings(&["/data"]))
+            .is_ok());
+        assert!(server
+            .check(&strings(&["/etc"]), &strings(&["/data"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_unknown_flag_and_path_value() {
+        let known = KnownServers::builtin();
+        let git = known.find("mcp-server-git").unwrap();
+        let mounts = strings(&["/repo"]);
+
+        assert!(git
+            .check(&strings(&["--repository", "/repo"]), &mounts)
+            .is_ok());
+        assert!(git
+            .check(&strings(&["--repository=/other"]), &mounts)
+            .is_err());
+        assert!(git.check(&strings(&["--repo", "/repo"]), &mounts).is_err());
+        assert!(git.check(&strings(&["extra"]), &mounts).is_err());
+    }
+
+    #[test]
+    fn test_user_entries_replace_builtin() {
+        let mut known = KnownServers::builtin();
+        known.extend(
+            serde_yaml::from_str(
+                "servers: [{package: mcp-server-time, positional: {name: zone, min: 1}}]",
+            )
+            .unwrap(),
+        );
+        let time = known.find("mcp-server-time").unwrap();
+        assert!(time.flags.is_empty());
+        assert!(time.check(&[], &[]).is_err());
+    }
+}
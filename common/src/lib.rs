@@ -1,10 +1,81 @@
+use crate::cleanup::Resource;
+use crate::engine::{PolicyEngine, YamlEngine};
 use anyhow::{Context, Result};
-use std::process::{Command, ExitStatus};
+use std::collections::{BTreeMap, HashMap};
+use std::process::{ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
-use tokio::process::Command as AsyncCommand;
+use tokio::process::{Child, Command as AsyncCommand};
 
+pub mod apple;
+pub mod artifacts;
+pub mod audit;
+pub mod budget;
+pub mod canary;
+pub mod cedar;
+pub mod cleanup;
+pub mod cli;
+pub mod config;
+pub mod crashloop;
+pub mod daemon;
+pub mod dnsfilter;
+pub mod egress;
+pub mod engine;
+pub mod envmap;
+pub mod explain;
+pub mod export;
+pub mod firewall;
+pub mod gateway;
+pub mod interpolate;
+pub mod known;
+pub mod lint;
+pub mod locale;
+pub mod masking;
+pub mod merge;
+pub mod native;
+pub mod overrides;
+pub mod phases;
 pub mod policy;
+pub mod policytest;
+pub mod preflight;
+pub mod presets;
+pub mod probe;
+pub mod proxy;
+pub mod pull;
+pub mod pump;
+pub mod refusal;
+pub mod rootless;
+pub mod roots;
+pub mod runtime;
+pub mod sandbox;
+pub mod schema;
+pub mod scratch;
+pub mod seatbelt;
+pub mod seccomp;
+pub mod sidecar;
+pub mod signing;
+pub mod sockets;
+pub mod spec;
+pub mod state;
+pub mod supervisor;
+pub mod trace;
+pub mod trust;
+pub mod variants;
+pub mod volumes;
+pub use cli::{ContainerArgs, ProxyArgs};
+pub use locale::LocaleSettings;
+pub use overrides::Denial;
 pub use policy::PolicyConfig;
+pub use probe::ProbeReport;
+pub use proxy::ProxyOptions;
+pub use runtime::{AdaptedOptions, CliRuntime, ContainerRuntime};
+pub use spec::RuntimeClass;
+pub use state::StateVolume;
+pub use variants::VariantCapabilities;
+
+/// Exit code of runs stopped for exceeding `runtime.timeout`, as with
+/// `timeout(1)`.
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
 
 #[derive(Debug, Clone)]
 pub enum Transport {
@@ -52,6 +123,27 @@ pub trait Runner {
         cmd_args.extend(args.iter().cloned());
         cmd_args
     }
+    /// Container path of the package manager cache shared between the
+    /// install and serve phases.
+    fn cache_dir(&self) -> Option<&str> {
+        None
+    }
+    /// Command that fetches the package into the cache without running it.
+    fn build_install_args(&self, _flags: &[String], _args: &[String]) -> Option<Vec<String>> {
+        None
+    }
+    /// Environment (`KEY=VALUE`) that keeps the package manager offline
+    /// during the serve phase.
+    fn offline_env(&self) -> Vec<String> {
+        vec![]
+    }
+    /// What the image must provide to run the command with `flags`.
+    fn required_capabilities(&self, _flags: &[String]) -> VariantCapabilities {
+        VariantCapabilities {
+            has_shell: false,
+            has_package_manager: true,
+        }
+    }
 }
 
 pub struct ContainerExecutor {
@@ -59,6 +151,32 @@ pub struct ContainerExecutor {
     verbose: bool,
     container_name: String,
     policy_config: PolicyConfig,
+    engine: Arc<dyn PolicyEngine>,
+    extra_args: Vec<String>,
+    proxy: Option<ProxyOptions>,
+    split_phases: bool,
+    /// The state volume, under its legacy name once
+    /// [`ContainerExecutor::ensure_named_volumes`] found only that.
+    state_volume: Mutex<Option<StateVolume>>,
+    locale: LocaleSettings,
+    runtime: Arc<dyn ContainerRuntime>,
+    canaries: bool,
+    egress_summary: bool,
+    package: Option<String>,
+    runtime_class: Option<RuntimeClass>,
+    translate_roots: bool,
+    translate_paths: bool,
+    init: bool,
+    /// See [`ContainerExecutor::with_test_mode`].
+    test_mode: bool,
+    /// Labels added to the policy's, see [`ContainerExecutor::labels`].
+    labels: BTreeMap<String, String>,
+    /// What this run created, for [`ContainerExecutor::cleanup`].
+    resources: Mutex<Vec<Resource>>,
+    /// Address of the DNS filter sidecar, once started.
+    dns_filter: Mutex<Option<String>>,
+    /// Whether the firewall sidecar runs.
+    firewall: Mutex<bool>,
 }
 
 impl ContainerExecutor {
@@ -76,20 +194,240 @@ impl ContainerExecutor {
             docker_image,
             verbose,
             container_name,
+            engine: Arc::new(YamlEngine::new(policy_config.clone())),
             policy_config,
+            extra_args: Vec::new(),
+            proxy: None,
+            split_phases: false,
+            state_volume: Mutex::new(None),
+            locale: LocaleSettings::default(),
+            runtime: runtime::current(),
+            canaries: false,
+            egress_summary: false,
+            package: None,
+            runtime_class: None,
+            translate_roots: false,
+            translate_paths: false,
+            init: true,
+            test_mode: false,
+            labels: BTreeMap::new(),
+            resources: Mutex::new(Vec::new()),
+            dns_filter: Mutex::new(None),
+            firewall: Mutex::new(false),
+        }
+    }
+
+    /// Lets `engine` make the policy decisions instead of the built-in YAML
+    /// engine, see [`engine::from_policy`].
+    pub fn with_engine(mut self, engine: Arc<dyn PolicyEngine>) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Appends docker arguments requested on the command line. Callers are
+    /// expected to have checked them with `PolicyEngine::check_overrides`.
+    pub fn with_extra_args(mut self, extra_args: Vec<String>) -> Self {
+        self.extra_args = extra_args;
+        self
+    }
+
+    /// Routes the container's stdio through the JSON-RPC proxy instead of
+    /// inheriting it.
+    pub fn with_proxy(mut self, proxy: Option<ProxyOptions>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Installs the package in a separate container before serving it with
+    /// a read-only cache and restricted network.
+    pub fn with_split_phases(mut self, split_phases: bool) -> Self {
+        self.split_phases = split_phases;
+        self
+    }
+
+    /// Runs an init process as PID 1 in the container. On by default, the
+    /// policy can also turn it off.
+    pub fn with_init(mut self, init: bool) -> Self {
+        self.init = init;
+        self
+    }
+
+    /// Labels the containers, volumes and networks of this run get on top
+    /// of the policy's, replacing those with the same key.
+    pub fn with_labels(mut self, labels: BTreeMap<String, String>) -> Self {
+        self.labels = labels;
+        self
+    }
+
+    /// The user-defined labels of what this run creates, as `KEY=VALUE`.
+    pub fn labels(&self) -> Vec<String> {
+        let mut labels = self.policy_config.runtime.docker.labels.clone();
+        labels.extend(self.labels.clone());
+        labels
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect()
+    }
+
+    /// `--label` for every label of [`ContainerExecutor::labels`].
+    pub(crate) fn label_args(&self) -> Vec<String> {
+        self.labels()
+            .into_iter()
+            .flat_map(|label| ["--label".to_string(), label])
+            .collect()
+    }
+
+    /// `--init` when the container gets an init process, so servers that
+    /// spawn children do not leave zombies and stop on SIGTERM.
+    pub fn init_args(&self) -> Vec<String> {
+        let enabled = self.init && self.policy_config.runtime.docker.init.unwrap_or(true);
+        if enabled {
+            self.runtime
+                .adapt_options(vec!["--init".to_string()])
+                .options
+        } else {
+            vec![]
         }
     }
 
+    /// Plants canary files next to the mounts and stops the container when
+    /// one of them is touched.
+    pub fn with_canaries(mut self, canaries: bool) -> Self {
+        self.canaries = canaries;
+        self
+    }
+
+    /// Prints the destinations the container contacted when it exits.
+    pub fn with_egress_summary(mut self, egress_summary: bool) -> Self {
+        self.egress_summary = egress_summary;
+        self
+    }
+
+    /// Overrides the policy's runtime class.
+    pub fn with_runtime_class(mut self, runtime_class: Option<RuntimeClass>) -> Self {
+        self.runtime_class = runtime_class;
+        self
+    }
+
+    /// Mounts host paths under `/roots` and translates paths and MCP roots
+    /// between host and container in the proxy.
+    pub fn with_translate_roots(mut self, translate_roots: bool) -> Self {
+        self.translate_roots = translate_roots;
+        self
+    }
+
+    /// Translates paths in the server's traffic according to the mount
+    /// table, so results name paths that exist on the host.
+    pub fn with_translate_paths(mut self, translate_paths: bool) -> Self {
+        self.translate_paths = translate_paths;
+        self
+    }
+
+    /// Records the package being run, which decides whether it may use
+    /// the shared package cache.
+    pub fn with_package(mut self, package: Option<String>) -> Self {
+        self.package = package;
+        self
+    }
+
+    /// Mounts a persistent per-package state volume into the container.
+    pub fn with_state_volume(mut self, state_volume: Option<StateVolume>) -> Self {
+        self.state_volume = Mutex::new(state_volume);
+        self
+    }
+
+    /// Sets the container timezone and locale. Unset values fall back to
+    /// the policy.
+    pub fn with_locale(mut self, locale: LocaleSettings) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Overrides the detected container runtime.
+    pub fn with_runtime(mut self, runtime: Arc<dyn ContainerRuntime>) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Skips what needs a real daemon and network: the daemon and runtime
+    /// class checks, the DNS filter, the firewall and the egress summary.
+    /// For tests with a fake runtime, like those of `semcp-testkit`; the
+    /// run arguments are built as usual.
+    pub fn with_test_mode(mut self, test_mode: bool) -> Self {
+        self.test_mode = test_mode;
+        self
+    }
+
     pub fn check_docker_available(&self) -> Result<bool> {
-        match which::which("docker") {
-            Ok(_) => {
-                let output = Command::new("docker")
-                    .args(["--version"])
-                    .output()
-                    .context("Failed to execute docker --version")?;
-                Ok(output.status.success())
-            }
-            Err(_) => Ok(false),
+        Ok(self.runtime.is_available())
+    }
+
+    /// Mounts, security options and environment for the container, from the
+    /// policy and the command line.
+    pub fn container_options(&self) -> Vec<String> {
+        self.adapted_options().options
+    }
+
+    /// The container options as the runtime can apply them, with what had
+    /// to be left out.
+    pub fn adapted_options(&self) -> AdaptedOptions {
+        let mut args = self.runtime.run_args();
+        args.extend(self.label_args());
+        if let Some(oci_runtime) = self.runtime_class().oci_runtime() {
+            args.push(format!("--runtime={}", oci_runtime));
+        }
+        args.extend(self.engine.docker_args());
+        // a --network from the command line was checked against the policy
+        let requested_network = self
+            .extra_args
+            .iter()
+            .position(|arg| arg == "--network")
+            .and_then(|i| self.extra_args.get(i + 1).cloned());
+        let policy_network = self.policy_config.network_mode();
+        if let (None, Some(mode)) = (&requested_network, &policy_network) {
+            args.extend(["--network".to_string(), mode.clone()]);
+        }
+        let network = requested_network.or(policy_network);
+        if let Some(firewall_args) = self.firewall_args() {
+            // the firewall's network stack, resolver included
+            args.extend(firewall_args);
+        } else if let Some(filter_args) = self.dns_filter_args() {
+            // the policy's resolvers are the filter's upstreams
+            args.extend(filter_args);
+        } else if !network.is_some_and(|mode| mode == "host" || mode.starts_with("container:")) {
+            // containers sharing another network stack cannot have resolvers
+            // of their own
+            args.extend(self.policy_config.map_dns_args());
+        }
+        args.extend(
+            self.locale
+                .or_policy(&self.policy_config.runtime.docker)
+                .docker_args(),
+        );
+        if let Some(ref state) = *self.state_volume.lock().unwrap() {
+            args.extend(state.docker_args());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        let mut args = match self.path_map() {
+            Some(paths) if self.translate_roots => roots::translate_mounts(args, &paths),
+            _ => args,
+        };
+        args.extend(self.mask_args(&args));
+        self.runtime.adapt_options(args)
+    }
+
+    /// The runner command for `flags` and `args`, with host paths in the
+    /// arguments translated when paths are.
+    fn command_args<R: Runner>(
+        &self,
+        runner: &R,
+        flags: &[String],
+        args: &[String],
+    ) -> Vec<String> {
+        let cmd_args = runner.build_command_args(flags, args);
+        match self.path_map() {
+            Some(paths) => cmd_args.iter().map(|arg| paths.to_container(arg)).collect(),
+            None => cmd_args,
         }
     }
 
@@ -98,20 +436,37 @@ impl ContainerExecutor {
         runner: &R,
         cmd_args: &[String],
         transport: &Transport,
+    ) -> Vec<String> {
+        let mut docker_args = vec!["run".to_string()];
+        docker_args.extend(self.create_run_args(runner, cmd_args, transport));
+        docker_args
+    }
+
+    /// Arguments of the `run` subcommand, without the subcommand itself.
+    pub fn create_run_args<R: Runner>(
+        &self,
+        runner: &R,
+        cmd_args: &[String],
+        transport: &Transport,
     ) -> Vec<String> {
         let mut docker_args = vec![
-            "run".to_string(),
             "--rm".to_string(),
             "-i".to_string(),
             "--name".to_string(),
             self.container_name.clone(),
         ];
+        docker_args.extend(self.init_args());
 
         if runner.requires_tty(transport) {
             docker_args.push("-t".to_string());
         }
 
-        docker_args.extend(self.policy_config.get_all_docker_args());
+        docker_args.extend(self.container_options());
+        if self.splits_phases(runner, transport) {
+            docker_args.extend(self.serve_phase_args(runner, transport));
+        } else if self.serves_offline(transport) {
+            docker_args.extend(["--network".to_string(), "none".to_string()]);
+        }
         docker_args.extend(runner.additional_docker_args());
         docker_args.push(self.docker_image.clone());
         docker_args.extend(cmd_args.iter().cloned());
@@ -119,6 +474,135 @@ impl ContainerExecutor {
         docker_args
     }
 
+    /// Proxy options for this run. A policy idle timeout, a tool budget,
+    /// path translation and session replay need the proxy to see the
+    /// traffic, so they enable the proxy even without `--eager-init`.
+    fn proxy_options(&self) -> Option<ProxyOptions> {
+        let idle_timeout = self.policy_config.runtime.idle_timeout();
+        let budget = self.policy_config.runtime.budget.clone();
+        let paths = self.path_map();
+        let engine = self
+            .engine
+            .decides_tools()
+            .then(|| Arc::clone(&self.engine));
+        let replay = self.policy_config.runtime.replay_on_restart.clone();
+        if self.proxy.is_none()
+            && idle_timeout.is_none()
+            && budget.is_none()
+            && paths.is_none()
+            && engine.is_none()
+            && replay.is_none()
+        {
+            return None;
+        }
+        Some(ProxyOptions {
+            idle_timeout,
+            budget,
+            paths,
+            engine,
+            replay_methods: replay.unwrap_or_default(),
+            policies: self.policy_config.sources().to_vec(),
+            audit: self.policy_config.runtime.audit.clone(),
+            ..self.proxy.clone().unwrap_or_default()
+        })
+    }
+
+    /// `run` with `run_args`, with the values of mapped environment
+    /// variables set for the runtime CLI to copy into the container.
+    pub fn run_command(&self, run_args: &[String]) -> AsyncCommand {
+        let mut command = self.runtime.run(run_args);
+        command.envs(self.policy_config.mapped_env().unwrap_or_default());
+        command
+    }
+
+    /// Host environment as the container sees it for `-e KEY`, mapped
+    /// variables included, for runs without a container runtime.
+    pub(crate) fn env_lookup(&self) -> impl Fn(&str) -> Option<String> {
+        let mapped: HashMap<String, String> = self
+            .policy_config
+            .mapped_env()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        move |key| mapped.get(key).cloned().or_else(|| std::env::var(key).ok())
+    }
+
+    /// Checks that must pass before the container starts, and the install
+    /// phase when phases are split.
+    async fn prepare_run<R: Runner>(
+        &self,
+        runner: &R,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<()> {
+        self.check_variant(runner, flags)?;
+        self.policy_config.mapped_env()?;
+        self.policy_config.seccomp_profile()?;
+        self.check_remote_mounts()?;
+        if self.verbose {
+            for note in self.adapted_options().downgraded {
+                eprintln!("Note: {}: {}", self.runtime, note);
+            }
+        }
+        // independent of each other, so they run at the same time
+        let prepared = if self.test_mode {
+            tokio::try_join!(self.ensure_named_volumes(), self.ensure_image()).map(|_| ())
+        } else {
+            tokio::try_join!(
+                self.check_daemon(),
+                self.check_runtime_class(),
+                self.ensure_named_volumes(),
+                self.ensure_image(),
+                async {
+                    self.start_dns_filter().await?;
+                    self.start_firewall().await
+                },
+            )
+            .map(|_| ())
+        };
+        if let Err(e) = prepared {
+            self.cleanup().await?;
+            return Err(e);
+        }
+        let transport = runner.detect_transport(args.first().map_or("", String::as_str));
+        if self.splits_phases(runner, &transport) {
+            if let Err(e) = self.run_install_phase(runner, flags, args).await {
+                self.cleanup().await?;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts the container with piped stdio and returns without waiting,
+    /// for callers that relay the traffic themselves, like the gateway.
+    pub async fn spawn_piped<R: Runner>(
+        &self,
+        runner: &R,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<Child> {
+        let package_name = args.first().cloned().unwrap_or_default();
+        let transport = runner.detect_transport(&package_name);
+        let cmd_args = self.command_args(runner, flags, args);
+        self.prepare_run(runner, flags, args).await?;
+
+        let run_args = self.create_run_args(runner, &cmd_args, &transport);
+        if self.verbose {
+            eprintln!("Running: {} run {}", self.runtime, run_args.join(" "));
+        }
+        self.register(Resource::Container(self.container_name.clone()));
+        let child = self
+            .run_command(&run_args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to spawn {} command", self.runtime))?;
+        self.audit_run(&run_args).await;
+        Ok(child)
+    }
+
     pub async fn run_containerized<R: Runner>(
         &self,
         runner: &R,
@@ -128,41 +612,74 @@ impl ContainerExecutor {
         let empty_string = String::new();
         let package_name = args.first().unwrap_or(&empty_string);
         let transport = runner.detect_transport(package_name);
-        let cmd_args = runner.build_command_args(flags, args);
-        let docker_args = self.create_docker_args(runner, &cmd_args, &transport);
+        let cmd_args = self.command_args(runner, flags, args);
+
+        self.prepare_run(runner, flags, args).await?;
+        let run_args = self.create_run_args(runner, &cmd_args, &transport);
+        let canaries = self.plant_canaries();
+        let mut tripwire = canaries.as_ref().map(|c| c.watch()).transpose()?;
 
         if self.verbose {
-            let docker_cmd = format!("docker {}", docker_args.join(" "));
+            let docker_cmd = format!("{} run {}", self.runtime, run_args.join(" "));
             eprintln!("Running: {}", docker_cmd);
         }
 
-        let mut child = AsyncCommand::new("docker")
-            .args(docker_args)
-            .spawn()
-            .context("Failed to spawn docker command")?;
+        let proxy_options = self.proxy_options();
+        self.register(Resource::Container(self.container_name.clone()));
+        self.audit_run(&run_args).await;
+        let egress = (self.egress_summary && !self.test_mode).then(|| {
+            egress::EgressMonitor::start(self.runtime.clone(), self.container_name.clone())
+        });
+
+        let timeout = self.policy_config.runtime.timeout();
+        let deadline = async {
+            match timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        let run = self.supervise(&run_args, proxy_options.as_ref());
 
         tokio::select! {
-            result = child.wait() => {
-                result.context("Failed to wait for docker command")
+            result = run => {
+                if let Some(egress) = egress {
+                    eprintln!("{}", egress.finish());
+                }
+                self.cleanup().await?;
+                let code = result.as_ref().ok().and_then(|status| status.code());
+                self.audit("stop", false, serde_json::json!({ "exit_code": code }));
+                result
+            }
+            path = canary::tripped(&mut tripwire) => {
+                let path = path?;
+                self.audit("canary_tripped", true, serde_json::json!({ "path": path }));
+                self.cleanup().await?;
+                anyhow::bail!(
+                    "Canary {} was accessed while the container ran; it was stopped. Check the mounts for paths that expose more of the host than intended",
+                    path
+                );
+            }
+            _ = deadline => {
+                eprintln!(
+                    "Error: the server ran longer than runtime.timeout ({}s) and was stopped",
+                    timeout.unwrap_or_default().as_secs()
+                );
+                self.cleanup().await?;
+                drop(canaries);
+                std::process::exit(TIMEOUT_EXIT_CODE);
             }
             _ = tokio::signal::ctrl_c() => {
                 if self.verbose {
                     eprintln!("Received Ctrl+C, cleaning up container...");
                 }
                 self.cleanup().await?;
+                drop(canaries);
                 std::process::exit(130);
             }
         }
     }
 
-    pub async fn cleanup(&self) -> Result<()> {
-        let _output = AsyncCommand::new("docker")
-            .args(["stop", &self.container_name])
-            .output()
-            .await;
-        Ok(())
-    }
-
     pub fn verbose(&self) -> bool {
         self.verbose
     }
@@ -174,4 +691,259 @@ impl ContainerExecutor {
     pub fn image(&self) -> &str {
         &self.docker_image
     }
+
+    pub fn runtime(&self) -> &Arc<dyn ContainerRuntime> {
+        &self.runtime
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    struct TestRunner;
+
+    impl Runner for TestRunner {
+        fn command(&self) -> &str {
+            "npx"
+        }
+
+        fn default_image(&self) -> &str {
+            ImageVariants::NODE_ALPINE
+        }
+
+        fn default_flags(&self) -> Vec<String> {
+            vec![]
+        }
+
+        fn detect_transport(&self, _package: &str) -> Transport {
+            Transport::Stdio
+        }
+
+        fn requires_tty(&self, _transport: &Transport) -> bool {
+            false
+        }
+
+        fn cache_dir(&self) -> Option<&str> {
+            Some("/root/.npm")
+        }
+
+        fn offline_env(&self) -> Vec<String> {
+            vec!["npm_config_offline=true".to_string()]
+        }
+    }
+
+    fn docker_args(executor: &ContainerExecutor) -> Vec<String> {
+        let cmd_args = vec!["npx".to_string(), "pkg".to_string()];
+        executor.create_docker_args(&TestRunner, &cmd_args, &Transport::Stdio)
+    }
+
+    #[test]
+    fn test_create_docker_args_order() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false)
+            .with_extra_args(vec!["-e".to_string(), "KEY".to_string()]);
+        let args = docker_args(&executor);
+
+        assert_eq!(&args[..3], ["run", "--rm", "-i"]);
+        let image_pos = args.iter().position(|arg| arg == "node:24-alpine").unwrap();
+        assert!(args.iter().position(|arg| arg == "KEY").unwrap() < image_pos);
+        assert_eq!(&args[image_pos + 1..], ["npx", "pkg"]);
+    }
+
+    #[test]
+    fn test_baseline_without_policy() {
+        // as snpx, suvx and semcp exec set up a run without --policy
+        let (policy, engine) = crate::preflight::baseline_policy().unwrap();
+        let executor = ContainerExecutor::with_policy("node:24-alpine".to_string(), false, policy)
+            .with_engine(engine);
+        let args = docker_args(&executor);
+
+        assert!(args.windows(2).any(|pair| pair == ["--cap-drop", "ALL"]));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--security-opt", "no-new-privileges"]));
+        assert!(args.contains(&"--read-only".to_string()));
+        assert!(args.windows(2).any(|pair| pair == ["--pids-limit", "256"]));
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--tmpfs", "/root:exec"]));
+    }
+
+    #[test]
+    fn test_labels_add_to_the_policy() {
+        let mut policy = PolicyConfig::new();
+        policy.runtime.docker.labels = BTreeMap::from([
+            ("environment".to_string(), "dev".to_string()),
+            ("owner".to_string(), "data-platform".to_string()),
+        ]);
+        let executor = ContainerExecutor::with_policy("node:24-alpine".to_string(), false, policy)
+            .with_labels(BTreeMap::from([(
+                "environment".to_string(),
+                "prod".to_string(),
+            )]));
+        assert_eq!(
+            executor.labels(),
+            ["environment=prod", "owner=data-platform"]
+        );
+        let args = docker_args(&executor);
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--label", "owner=data-platform"]));
+    }
+
+    #[test]
+    fn test_init_by_default() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false);
+        assert!(docker_args(&executor).contains(&"--init".to_string()));
+        let executor = executor.with_init(false);
+        assert!(!docker_args(&executor).contains(&"--init".to_string()));
+    }
+
+    #[test]
+    fn test_split_phases_serve_args() {
+        let executor =
+            ContainerExecutor::new("node:24-alpine".to_string(), false).with_split_phases(true);
+        let args = docker_args(&executor);
+
+        assert!(args.contains(&"semcp-cache-npx:/root/.npm:ro".to_string()));
+        assert!(args.windows(2).any(|pair| pair == ["--network", "none"]));
+        assert!(args.contains(&"npm_config_offline=true".to_string()));
+    }
+
+    #[test]
+    fn test_stdio_servers_run_offline_without_split_phases() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false);
+        assert!(executor.splits_phases(&TestRunner, &Transport::Stdio));
+        let args = docker_args(&executor);
+        assert!(args.windows(2).any(|pair| pair == ["--network", "none"]));
+        assert!(args.contains(&"semcp-cache-npx:/root/.npm:ro".to_string()));
+
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false)
+            .with_extra_args(vec!["--network".to_string(), "bridge".to_string()]);
+        assert!(!executor.splits_phases(&TestRunner, &Transport::Stdio));
+        assert!(!docker_args(&executor)
+            .windows(2)
+            .any(|pair| pair == ["--network", "none"]));
+    }
+
+    #[test]
+    fn test_split_phases_respects_requested_network() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false)
+            .with_split_phases(true)
+            .with_extra_args(vec!["--network".to_string(), "bridge".to_string()]);
+        let args = docker_args(&executor);
+
+        assert!(!args.windows(2).any(|pair| pair == ["--network", "none"]));
+    }
+
+    #[test]
+    fn test_dns_filter_replaces_policy_resolvers() {
+        let policy = PolicyConfig::from_file("testdata/dns-filter-policy.yaml").unwrap();
+        let executor = ContainerExecutor::with_policy("node:24-alpine".to_string(), false, policy)
+            .with_split_phases(true);
+        *executor.dns_filter.lock().unwrap() = Some("172.18.0.2".to_string());
+        let args = docker_args(&executor);
+
+        let network = format!("{}-net", executor.container_name);
+        assert!(args
+            .windows(2)
+            .any(|pair| pair == ["--network", network.as_str()]));
+        assert!(args.windows(2).any(|pair| pair == ["--dns", "172.18.0.2"]));
+        assert!(!args.windows(2).any(|pair| pair == ["--dns", "10.0.0.53"]));
+        assert!(!args.windows(2).any(|pair| pair == ["--network", "none"]));
+    }
+
+    #[test]
+    fn test_split_phases_keep_network_for_http_servers() {
+        let executor =
+            ContainerExecutor::new("node:24-alpine".to_string(), false).with_split_phases(true);
+        let cmd_args = vec!["npx".to_string(), "pkg".to_string()];
+        let args = executor.create_docker_args(&TestRunner, &cmd_args, &Transport::Http);
+
+        assert!(!args.windows(2).any(|pair| pair == ["--network", "none"]));
+    }
+
+    #[test]
+    fn test_policy_idle_timeout_enables_proxy() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false);
+        assert!(executor.proxy_options().is_none());
+
+        let mut policy = PolicyConfig::new();
+        policy.runtime.idle_timeout = Some(spec::DurationSpec::Text("5m".to_string()));
+        let executor = ContainerExecutor::with_policy("node:24-alpine".to_string(), false, policy);
+        let options = executor.proxy_options().unwrap();
+        assert!(!options.eager_initialize);
+        assert_eq!(options.idle_timeout, Some(Duration::from_secs(300)));
+    }
+
+    #[test]
+    fn test_translated_roots_hide_host_paths() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false)
+            .with_extra_args(vec![
+                "-v".to_string(),
+                "/home/me/project:/home/me/project".to_string(),
+            ])
+            .with_translate_roots(true);
+        let args = docker_args(&executor);
+        assert!(args.contains(&"/home/me/project:/roots/project".to_string()));
+        assert!(executor.proxy_options().unwrap().paths.is_some());
+        assert_eq!(
+            executor.command_args(&TestRunner, &[], &["/home/me/project/notes".to_string()]),
+            ["npx", "/roots/project/notes"]
+        );
+    }
+
+    #[test]
+    fn test_untrusted_package_gets_its_own_cache() {
+        let executor = |package: &str| {
+            let mut policy = PolicyConfig::new();
+            policy.runtime.cache.isolate_untrusted = true;
+            policy.runtime.cache.trusted_publishers = vec!["@modelcontextprotocol".to_string()];
+            ContainerExecutor::with_policy("node:24-alpine".to_string(), false, policy)
+                .with_split_phases(true)
+                .with_package(Some(package.to_string()))
+        };
+
+        let trusted = executor("@modelcontextprotocol/server-memory");
+        assert!(docker_args(&trusted).contains(&"semcp-cache-npx:/root/.npm:ro".to_string()));
+
+        let untrusted = executor("left-pad-mcp");
+        let isolated = format!(
+            "semcp-cache-npx-{}:/root/.npm:ro",
+            untrusted.container_name()
+        );
+        assert!(docker_args(&untrusted).contains(&isolated));
+    }
+
+    #[test]
+    fn test_sandboxed_runtime_classes() {
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false);
+        assert!(!docker_args(&executor).contains(&"--runtime=runsc".to_string()));
+
+        let mut policy = PolicyConfig::new();
+        policy.runtime.docker.runtime_class = Some(RuntimeClass::Gvisor);
+        let executor = ContainerExecutor::with_policy("node:24-alpine".to_string(), false, policy);
+        assert!(docker_args(&executor).contains(&"--runtime=runsc".to_string()));
+
+        let executor = executor.with_runtime_class(Some(RuntimeClass::Runc));
+        assert_eq!(executor.runtime_class(), RuntimeClass::Runc);
+
+        let executor = executor.with_runtime_class(Some(RuntimeClass::Kata));
+        assert!(docker_args(&executor).contains(&"--runtime=kata-runtime".to_string()));
+
+        let executor = executor.with_runtime_class(Some(RuntimeClass::Firecracker));
+        assert!(docker_args(&executor).contains(&"--runtime=kata-fc".to_string()));
+        assert!(executor.runtime_class().needs_kvm());
+    }
+
+    #[test]
+    fn test_check_variant_rejects_distroless() {
+        let executor = ContainerExecutor::new(ImageVariants::NODE_DISTROLESS.to_string(), false);
+        let error = executor.check_variant(&TestRunner, &[]).unwrap_err();
+        assert!(error.to_string().contains("--image"));
+
+        let executor = ContainerExecutor::new(ImageVariants::NODE_SLIM.to_string(), false);
+        assert!(executor.check_variant(&TestRunner, &[]).is_ok());
+    }
 }
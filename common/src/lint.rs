@@ -0,0 +1,745 @@
+//! Checking policy files before they are used, for `snpx policy validate`.
+//!
+//! Loading a policy stops at its first error and does not say where in the
+//! file it is. Here a file is checked as a whole instead: its YAML, the
+//! formats of the values semcp parses later (memory sizes, durations, ports,
+//! IP addresses and CIDR ranges), and keys semcp does not know, which are
+//! most likely typos and which loading rejects unless it is lenient. Every
+//! finding points at the line and column of its key. The policies a file `extends` are not
+//! checked along with it.
+
+use crate::engine::EngineSpec;
+use crate::firewall::BlockedPort;
+use crate::spec::{parse_memory, DurationSpec, RuntimeSpec};
+use anyhow::{bail, Context, Result};
+use policy_mcp::PolicyDocument;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The policy would fail to load, or be applied differently than
+    /// written.
+    Error,
+    /// The policy loads, but part of it has no effect.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Key the finding is about, e.g. `permissions.runtime.timeout` or
+    /// `permissions.network.allow[2].host`. Empty for syntax errors.
+    pub path: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}: ", self.line, self.column, self.severity)?;
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The keys semcp reads from a policy.
+enum Shape {
+    /// A mapping with these keys.
+    Keys(&'static [(&'static str, Shape)]),
+    /// A list of values of this shape.
+    List(&'static Shape),
+    /// A scalar, or a mapping keyed by names, such as `ulimits`.
+    Any,
+}
+
+const POLICY: Shape = Shape::Keys(&[
+    ("version", Shape::Any),
+    ("description", Shape::Any),
+    ("extends", Shape::Any),
+    ("merge", Shape::Any),
+    (
+        "engine",
+        Shape::Keys(&[
+            ("kind", Shape::Any),
+            ("data", Shape::Any),
+            ("package", Shape::Any),
+        ]),
+    ),
+    ("permissions", PERMISSIONS),
+]);
+
+const PERMISSIONS: Shape = Shape::Keys(&[
+    (
+        "storage",
+        Shape::Keys(&[
+            (
+                "allow",
+                Shape::List(&Shape::Keys(&[("uri", Shape::Any), ("access", Shape::Any)])),
+            ),
+            ("allowed_paths", Shape::Any),
+            ("blocked_paths", Shape::Any),
+        ]),
+    ),
+    (
+        "network",
+        Shape::Keys(&[
+            ("allow", Shape::List(&Shape::Keys(&[("host", Shape::Any)]))),
+            ("policy", Shape::Any),
+            ("dns_servers", Shape::Any),
+            ("dns_search", Shape::Any),
+            ("dns_options", Shape::Any),
+            ("filter_dns", Shape::Any),
+            ("filter_image", Shape::Any),
+            ("firewall_image", Shape::Any),
+            ("blocked_ports", Shape::Any),
+        ]),
+    ),
+    (
+        "environment",
+        Shape::Keys(&[("allow", Shape::List(&Shape::Keys(&[("key", Shape::Any)])))]),
+    ),
+    ("runtime", RUNTIME),
+]);
+
+const RUNTIME: Shape = Shape::Keys(&[
+    ("docker", DOCKER),
+    (
+        "daemon",
+        Shape::Keys(&[
+            ("userns_remap", Shape::Any),
+            ("live_restore", Shape::Any),
+            ("seccomp", Shape::Any),
+            ("no_insecure_registries", Shape::Any),
+            ("on_failure", Shape::Any),
+        ]),
+    ),
+    (
+        "audit",
+        Shape::Keys(&[
+            ("log_level", Shape::Any),
+            ("log_commands", Shape::Any),
+            ("log_network_access", Shape::Any),
+            ("log_file_access", Shape::Any),
+            ("log_file", Shape::Any),
+        ]),
+    ),
+    ("idle_timeout", Shape::Any),
+    ("timeout", Shape::Any),
+    ("graceful_shutdown_timeout", Shape::Any),
+    ("force_kill_timeout", Shape::Any),
+    ("max_restart_attempts", Shape::Any),
+    ("replay_on_restart", Shape::Any),
+    ("environment_whitelist", Shape::Any),
+    (
+        "env_mapping",
+        Shape::List(&Shape::Keys(&[
+            ("from", Shape::Any),
+            ("to", Shape::Any),
+            ("strip_prefix", Shape::Any),
+            ("transform", Shape::Any),
+        ])),
+    ),
+    (
+        "cache",
+        Shape::Keys(&[
+            ("trusted_publishers", Shape::Any),
+            ("isolate_untrusted", Shape::Any),
+        ]),
+    ),
+    (
+        "budget",
+        Shape::Keys(&[
+            ("per_session", Shape::Any),
+            ("refill_per_minute", Shape::Any),
+            ("default_cost", Shape::Any),
+            ("tools", Shape::Any),
+        ]),
+    ),
+]);
+
+const DOCKER: Shape = Shape::Keys(&[
+    (
+        "security",
+        Shape::Keys(&[
+            ("privileged", Shape::Any),
+            ("no_new_privileges", Shape::Any),
+            (
+                "capabilities",
+                Shape::Keys(&[("drop", Shape::Any), ("add", Shape::Any)]),
+            ),
+        ]),
+    ),
+    (
+        "volumes",
+        Shape::List(&Shape::Keys(&[
+            ("name", Shape::Any),
+            ("mount", Shape::Any),
+            ("mode", Shape::Any),
+            ("size", Shape::Any),
+        ])),
+    ),
+    ("timezone", Shape::Any),
+    ("locale", Shape::Any),
+    ("runtime_class", Shape::Any),
+    ("init", Shape::Any),
+    ("memory_limit", Shape::Any),
+    ("cpu_limit", Shape::Any),
+    ("pids_limit", Shape::Any),
+    ("ulimits", Shape::Any),
+    ("user", Shape::Any),
+    ("read_only_root_filesystem", Shape::Any),
+    ("tmpfs", Shape::Any),
+    ("mount_options", Shape::Any),
+    ("security_opts", Shape::Any),
+    ("labels", Shape::Any),
+    (
+        "seccomp",
+        Shape::Keys(&[
+            ("preset", Shape::Any),
+            ("allow", Shape::Any),
+            ("deny", Shape::Any),
+        ]),
+    ),
+    (
+        "selinux",
+        Shape::Keys(&[
+            ("user", Shape::Any),
+            ("role", Shape::Any),
+            ("type", Shape::Any),
+            ("level", Shape::Any),
+            ("disable", Shape::Any),
+            ("relabel", Shape::Any),
+        ]),
+    ),
+]);
+
+/// The sections semcp parses itself, typed, so that type errors come with
+/// their location.
+#[derive(Deserialize)]
+struct Sections {
+    #[serde(default, rename = "engine")]
+    _engine: Option<EngineSpec>,
+    #[serde(default)]
+    permissions: Option<SemcpPermissions>,
+}
+
+#[derive(Deserialize)]
+struct SemcpPermissions {
+    #[serde(default)]
+    runtime: Option<RuntimeSpec>,
+}
+
+/// Line and column, both from 1, of every key and list item of a block
+/// style YAML document, by path. Keys inside flow style values such as
+/// `{soft: 1, hard: 2}` are not indexed.
+fn key_locations(content: &str) -> HashMap<String, (usize, usize)> {
+    let mut locations = HashMap::new();
+    // indentation, path and whether it is a list item, of the open nodes
+    let mut open: Vec<(usize, String, bool)> = Vec::new();
+    let mut items: HashMap<String, usize> = HashMap::new();
+    // indentation of the key whose block scalar (`|`, `>`) is being skipped
+    let mut block_scalar: Option<usize> = None;
+    for (number, line) in content.lines().enumerate() {
+        let rest = line.trim_start();
+        let mut column = line.len() - rest.len();
+        if rest.is_empty() || rest.starts_with('#') {
+            continue;
+        }
+        if let Some(indent) = block_scalar {
+            if column > indent {
+                continue;
+            }
+            block_scalar = None;
+        }
+        if rest.starts_with("---") || rest.starts_with("...") {
+            open.clear();
+            continue;
+        }
+        let mut rest = rest;
+        while let Some(item) = rest
+            .strip_prefix('-')
+            .filter(|item| item.is_empty() || item.starts_with(' '))
+        {
+            while open.last().is_some_and(|(indent, _, is_item)| {
+                *indent > column || (*indent == column && *is_item)
+            }) {
+                open.pop();
+            }
+            let parent = open
+                .last()
+                .map(|(_, path, _)| path.clone())
+                .unwrap_or_default();
+            let index = items.entry(parent.clone()).or_insert(0);
+            let path = format!("{}[{}]", parent, index);
+            *index += 1;
+            let content = item.trim_start();
+            let dash = column;
+            column += 1 + item.len() - content.len();
+            locations.insert(path.clone(), (number + 1, column + 1));
+            open.push((dash, path, true));
+            rest = content;
+        }
+        let Some((key, value)) = split_key(rest) else {
+            continue;
+        };
+        while open.last().is_some_and(|(indent, _, _)| *indent >= column) {
+            open.pop();
+        }
+        let path = match open.last() {
+            Some((_, parent, _)) => format!("{}.{}", parent, key),
+            None => key.to_string(),
+        };
+        items.remove(&path);
+        locations.insert(path.clone(), (number + 1, column + 1));
+        open.push((column, path, false));
+        if value.starts_with('|') || value.starts_with('>') {
+            block_scalar = Some(column);
+        }
+    }
+    locations
+}
+
+/// The key and value of a `key: value` line, if it is one.
+fn split_key(line: &str) -> Option<(&str, &str)> {
+    let end = match line.chars().next()? {
+        quote @ ('"' | '\'') => line[1..].find(quote)? + 2,
+        '{' | '[' | '&' | '*' | '!' | '|' | '>' => return None,
+        _ => 0,
+    };
+    let colon = end
+        + line[end..]
+            .find(": ")
+            .or_else(|| line[end..].strip_suffix(':').map(|key| key.len()))?;
+    let key = line[..colon].trim_end().trim_matches(['"', '\'']);
+    Some((key, line[colon + 1..].trim_start()))
+}
+
+/// Levenshtein distance, to suggest the key a typo was meant to be.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+fn join(parent: &str, key: &str) -> String {
+    if parent.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", parent, key)
+    }
+}
+
+struct Findings {
+    locations: HashMap<String, (usize, usize)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Findings {
+    /// Location of `path`, or of the nearest parent that has one.
+    fn locate(&self, mut path: &str) -> (usize, usize) {
+        loop {
+            if let Some(&location) = self.locations.get(path) {
+                return location;
+            }
+            match path.rfind(['.', '[']) {
+                Some(end) => path = &path[..end],
+                None => return (1, 1),
+            }
+        }
+    }
+
+    fn add(&mut self, severity: Severity, path: String, message: String) {
+        let (line, column) = self.locate(&path);
+        self.diagnostics.push(Diagnostic {
+            severity,
+            path,
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn error(&mut self, path: &str, error: anyhow::Error) {
+        self.add(Severity::Error, path.to_string(), format!("{:#}", error));
+    }
+
+    /// An error of the YAML parser, which knows its location.
+    fn parse_error(&mut self, error: serde_yaml::Error) {
+        let (line, column) = error
+            .location()
+            .map_or((1, 1), |location| (location.line(), location.column()));
+        let message = error.to_string();
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_string(),
+            None => message,
+        };
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            path: String::new(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn unknown_keys(&mut self, value: &Value, shape: &Shape, path: &str) {
+        match (shape, value) {
+            (Shape::Keys(keys), Value::Mapping(mapping)) => {
+                for (key, value) in mapping {
+                    let Some(key) = key.as_str() else {
+                        continue;
+                    };
+                    let path = join(path, key);
+                    match keys.iter().find(|(known, _)| *known == key) {
+                        Some((_, shape)) => self.unknown_keys(value, shape, &path),
+                        None => {
+                            let suggestion = keys
+                                .iter()
+                                .map(|(known, _)| *known)
+                                .filter(|known| distance(key, known) <= 2)
+                                .min_by_key(|known| distance(key, known));
+                            let message = match suggestion {
+                                Some(known) => format!("unknown field (did you mean `{}`?)", known),
+                                None => "unknown field".to_string(),
+                            };
+                            self.add(Severity::Error, path, message);
+                        }
+                    }
+                }
+            }
+            (Shape::List(shape), Value::Sequence(values)) => {
+                for (index, value) in values.iter().enumerate() {
+                    self.unknown_keys(value, shape, &format!("{}[{}]", path, index));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The keys of the policy in `content` that semcp does not know, as
+/// errors. Nothing is reported for content that is not YAML.
+pub fn unknown_fields(content: &str) -> Vec<Diagnostic> {
+    let Ok(document) = serde_yaml::from_str::<Value>(content) else {
+        return Vec::new();
+    };
+    let mut findings = Findings {
+        locations: key_locations(content),
+        diagnostics: Vec::new(),
+    };
+    findings.unknown_keys(&document, &POLICY, "");
+    findings.diagnostics
+}
+
+/// A scalar value as text, numbers included.
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Number(number) => number.to_string(),
+        other => other.as_str().unwrap_or_default().to_string(),
+    }
+}
+
+fn entries<'a>(value: &'a Value, path: &[&str]) -> impl Iterator<Item = (usize, &'a Value)> {
+    let mut value = Some(value);
+    for key in path {
+        value = value.and_then(|value| value.get(*key));
+    }
+    value
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .enumerate()
+}
+
+/// Checks a CIDR range such as `10.0.0.0/8` or `fd00::/8`.
+pub fn check_cidr(range: &str) -> Result<()> {
+    let Some((address, prefix)) = range.split_once('/') else {
+        bail!("`{}` is not a CIDR range", range);
+    };
+    let Ok(address) = address.parse::<IpAddr>() else {
+        bail!("`{}` is not an IP address", address);
+    };
+    let bits = if address.is_ipv4() { 32 } else { 128 };
+    match prefix.parse::<u8>() {
+        Ok(prefix) if prefix <= bits => Ok(()),
+        _ => bail!("`{}` is not a prefix length of 0 to {}", prefix, bits),
+    }
+}
+
+/// Checks an allow-list host: a name or IP address with an optional port,
+/// a URL, or a CIDR range.
+pub fn check_host(host: &str) -> Result<()> {
+    let rest = match host.split_once("://") {
+        Some((_, rest)) => rest,
+        None => {
+            if let Some((address, _)) = host.split_once('/') {
+                let numeric =
+                    address.starts_with(|c: char| c.is_ascii_digit()) || address.contains(':');
+                if numeric
+                    && address
+                        .chars()
+                        .all(|c| c.is_ascii_hexdigit() || c == '.' || c == ':')
+                {
+                    return check_cidr(host);
+                }
+            }
+            host
+        }
+    };
+    let authority = rest.split('/').next().unwrap_or_default();
+    let port = match authority.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once("]:").map(|(_, port)| port),
+        // more than one colon is an IPv6 address without a port
+        None if authority.matches(':').count() == 1 => {
+            authority.split_once(':').map(|(_, port)| port)
+        }
+        None => None,
+    };
+    if authority.is_empty() || authority.starts_with(':') {
+        bail!("`{}` has no host", host);
+    }
+    match port {
+        Some(port) if !matches!(port.parse::<u16>(), Ok(1..=u16::MAX)) => {
+            bail!("`{}` is not a port from 1 to 65535", port)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The value formats loading only checks one at a time, or not at all.
+fn check_values(document: &Value, findings: &mut Findings) {
+    let runtime = document
+        .get("permissions")
+        .and_then(|permissions| permissions.get("runtime"));
+    for key in [
+        "idle_timeout",
+        "timeout",
+        "graceful_shutdown_timeout",
+        "force_kill_timeout",
+    ] {
+        let Some(value) = runtime.and_then(|runtime| runtime.get(key)) else {
+            continue;
+        };
+        let path = format!("permissions.runtime.{}", key);
+        let duration = serde_yaml::from_value::<DurationSpec>(value.clone())
+            .context("Invalid duration")
+            .and_then(|duration| duration.to_duration());
+        match duration {
+            Ok(duration) if duration.is_zero() && matches!(key, "idle_timeout" | "timeout") => {
+                findings.error(&path, anyhow::anyhow!("must be greater than zero"));
+            }
+            Ok(_) => {}
+            Err(e) => findings.error(&path, e),
+        }
+    }
+    let docker = runtime.and_then(|runtime| runtime.get("docker"));
+    if let Some(memory) = docker.and_then(|docker| docker.get("memory_limit")) {
+        let text = scalar(memory);
+        if let Err(e) = parse_memory(&text) {
+            findings.error("permissions.runtime.docker.memory_limit", e);
+        }
+    }
+    if let Some(cpus) = docker.and_then(|docker| docker.get("cpu_limit")) {
+        let cpus = match cpus {
+            Value::Number(number) => number.as_f64(),
+            other => other.as_str().and_then(|text| text.trim().parse().ok()),
+        };
+        if !cpus.is_some_and(|cpus| cpus > 0.0) {
+            findings.error(
+                "permissions.runtime.docker.cpu_limit",
+                anyhow::anyhow!("must be a number of CPUs greater than zero"),
+            );
+        }
+    }
+
+    for (index, port) in entries(document, &["permissions", "network", "blocked_ports"]) {
+        if let Err(e) = BlockedPort::parse(&scalar(port)) {
+            let path = format!("permissions.network.blocked_ports[{}]", index);
+            findings.error(&path, e);
+        }
+    }
+    for (index, server) in entries(document, &["permissions", "network", "dns_servers"]) {
+        let address = scalar(server);
+        if address.parse::<IpAddr>().is_err() {
+            let path = format!("permissions.network.dns_servers[{}]", index);
+            findings.error(&path, anyhow::anyhow!("`{}` is not an IP address", address));
+        }
+    }
+    for (index, entry) in entries(document, &["permissions", "network", "allow"]) {
+        let Some(host) = entry.get("host") else {
+            continue;
+        };
+        let path = format!("permissions.network.allow[{}].host", index);
+        if let Err(e) = check_host(&scalar(host)) {
+            findings.error(&path, e);
+        }
+    }
+}
+
+/// Checks the policy in `content`. The policy loads when none of the
+/// diagnostics is an error.
+pub fn check(content: &str) -> Vec<Diagnostic> {
+    let mut findings = Findings {
+        locations: key_locations(content),
+        diagnostics: Vec::new(),
+    };
+    let document: Value = match serde_yaml::from_str(content) {
+        Ok(document) => document,
+        Err(e) => {
+            findings.parse_error(e);
+            return findings.diagnostics;
+        }
+    };
+    if !document.is_mapping() {
+        findings.error("", anyhow::anyhow!("a policy must be a mapping of keys"));
+        return findings.diagnostics;
+    }
+    findings.unknown_keys(&document, &POLICY, "");
+
+    // a policy that extends others may leave required parts to them
+    if document.get("extends").is_none() {
+        if let Err(e) = serde_yaml::from_str::<PolicyDocument>(content) {
+            findings.parse_error(e);
+        }
+    }
+    let runtime = match serde_yaml::from_str::<Sections>(content) {
+        Ok(sections) => sections
+            .permissions
+            .and_then(|permissions| permissions.runtime),
+        Err(e) => {
+            findings.parse_error(e);
+            None
+        }
+    };
+    let found = findings.diagnostics.len();
+    check_values(&document, &mut findings);
+    let checked = findings.diagnostics[found..]
+        .iter()
+        .any(|diagnostic| diagnostic.path.starts_with("permissions.runtime"));
+    // the remaining rules of the runtime section, reported by the first
+    // broken one like loading does
+    if let (Some(runtime), false) = (runtime, checked) {
+        if let Err(e) = runtime.validate() {
+            findings.error("permissions.runtime", e);
+        }
+    }
+    findings.diagnostics
+}
+
+/// Reads and checks the policy file at `path`.
+pub fn check_file(path: &str) -> Result<Vec<Diagnostic>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read policy file {}", path))?;
+    Ok(check(&content))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY_FILE: &str = "version: '1.0'
+permissions:
+  network:
+    allow:
+    - host: api.github.com
+    - host: 10.0.0.0/33
+    blocked_ports: [25, 70000]
+  runtime:
+    idle_timout: 10m
+    timeout: 5x
+    docker:
+      memory_limit: lots
+      volumes:
+      - name: data
+        mount: /data
+";
+
+    #[test]
+    fn test_key_locations() {
+        let locations = key_locations(POLICY_FILE);
+        assert_eq!(locations["permissions.network.allow[0].host"], (5, 7));
+        assert_eq!(locations["permissions.network.allow[1].host"], (6, 7));
+        assert_eq!(locations["permissions.runtime.timeout"], (10, 5));
+        assert_eq!(
+            locations["permissions.runtime.docker.volumes[0].mount"],
+            (15, 9)
+        );
+    }
+
+    #[test]
+    fn test_check_reports_locations() {
+        let diagnostics: Vec<String> = check(POLICY_FILE).iter().map(ToString::to_string).collect();
+        assert_eq!(
+            diagnostics,
+            [
+                "9:5: error: permissions.runtime.idle_timout: unknown field (did you mean `idle_timeout`?)",
+                "10:5: error: permissions.runtime.timeout: Invalid duration unit in `5x`, use s, m or h",
+                "12:7: error: permissions.runtime.docker.memory_limit: Invalid memory size `lots`",
+                "7:5: error: permissions.network.blocked_ports[1]: `70000` is not a port",
+                "6:7: error: permissions.network.allow[1].host: `33` is not a prefix length of 0 to 32",
+            ]
+        );
+        assert!(check("version: '1.0'\npermissions: {}\n").is_empty());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let content = "permissions:\n  runtime:\n    docker:\n      security:\n        capabilties: {drop: [ALL]}\n        colour: red\n";
+        let found: Vec<String> = unknown_fields(content)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            found,
+            [
+                "5:9: error: permissions.runtime.docker.security.capabilties: unknown field (did you mean `capabilities`?)",
+                "6:9: error: permissions.runtime.docker.security.colour: unknown field",
+            ]
+        );
+        assert!(
+            unknown_fields(POLICY_FILE.replace("idle_timout", "idle_timeout").as_str()).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_check_host() {
+        for host in [
+            "api.github.com",
+            "https://api.github.com:443/v3",
+            "10.0.0.0/8",
+            "fd00::/8",
+            "[::1]:8080",
+            "2001:db8::1",
+        ] {
+            assert!(check_host(host).is_ok(), "{}", host);
+        }
+        for host in ["10.0.0.256/8", "api.github.com:0", "host:http", "https://"] {
+            assert!(check_host(host).is_err(), "{}", host);
+        }
+    }
+}
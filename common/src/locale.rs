@@ -0,0 +1,140 @@
+//! Timezone and locale inside the container.
+//!
+//! Base images default to UTC and the C locale. Servers that print
+//! timestamps or format dates then disagree with the user's clock, so the
+//! timezone and locale can be set from the policy (`permissions.runtime.docker`)
+//! or on the command line, which takes precedence.
+
+use crate::spec::DockerSpec;
+
+/// Timezone value that follows the host's clock settings.
+pub const HOST_TIMEZONE: &str = "host";
+
+const HOST_LOCALTIME: &str = "/etc/localtime";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LocaleSettings {
+    pub timezone: Option<String>,
+    pub locale: Option<String>,
+}
+
+/// Accepts IANA names (`Europe/Berlin`, `Etc/GMT+2`, `UTC`) and `host`.
+pub fn is_valid_timezone(timezone: &str) -> bool {
+    !timezone.is_empty()
+        && !timezone.starts_with('/')
+        && timezone
+            .split('/')
+            .all(|part| !part.is_empty() && part != "..")
+        && timezone
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '/' | '_' | '-' | '+'))
+}
+
+/// The host's timezone name, from `TZ`, `/etc/timezone` or the
+/// `/etc/localtime` symlink.
+pub fn host_timezone() -> Option<String> {
+    if let Ok(tz) = std::env::var("TZ") {
+        let tz = tz.trim_start_matches(':');
+        if is_valid_timezone(tz) {
+            return Some(tz.to_string());
+        }
+    }
+    if let Ok(contents) = std::fs::read_to_string("/etc/timezone") {
+        let tz = contents.trim();
+        if is_valid_timezone(tz) {
+            return Some(tz.to_string());
+        }
+    }
+    let target = std::fs::read_link(HOST_LOCALTIME).ok()?;
+    let target = target.to_string_lossy();
+    let (_, tz) = target.split_once("zoneinfo/")?;
+    is_valid_timezone(tz).then(|| tz.to_string())
+}
+
+impl LocaleSettings {
+    /// Fills unset values from the policy.
+    pub fn or_policy(&self, docker: &DockerSpec) -> Self {
+        Self {
+            timezone: self.timezone.clone().or_else(|| docker.timezone.clone()),
+            locale: self.locale.clone().or_else(|| docker.locale.clone()),
+        }
+    }
+
+    pub fn docker_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        match self.timezone.as_deref() {
+            Some(HOST_TIMEZONE) => {
+                if std::path::Path::new(HOST_LOCALTIME).exists() {
+                    args.push("-v".to_string());
+                    args.push(format!("{}:{}:ro", HOST_LOCALTIME, HOST_LOCALTIME));
+                }
+                if let Some(tz) = host_timezone() {
+                    args.push("-e".to_string());
+                    args.push(format!("TZ={}", tz));
+                }
+            }
+            Some(tz) => {
+                args.push("-e".to_string());
+                args.push(format!("TZ={}", tz));
+            }
+            None => {}
+        }
+        if let Some(ref locale) = self.locale {
+            for key in ["LANG", "LC_ALL"] {
+                args.push("-e".to_string());
+                args.push(format!("{}={}", key, locale));
+            }
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timezone_validation() {
+        assert!(is_valid_timezone("Europe/Berlin"));
+        assert!(is_valid_timezone("America/Argentina/Buenos_Aires"));
+        assert!(is_valid_timezone("Etc/GMT+2"));
+        assert!(is_valid_timezone("host"));
+        assert!(!is_valid_timezone("/etc/localtime"));
+        assert!(!is_valid_timezone("../etc/passwd"));
+        assert!(!is_valid_timezone("UTC; rm -rf"));
+        assert!(!is_valid_timezone(""));
+    }
+
+    #[test]
+    fn test_cli_overrides_policy() {
+        let docker: DockerSpec =
+            serde_yaml::from_str("{timezone: Asia/Tokyo, locale: ja_JP.UTF-8}").unwrap();
+        let cli = LocaleSettings {
+            timezone: Some("Europe/Paris".to_string()),
+            locale: None,
+        };
+        let settings = cli.or_policy(&docker);
+        assert_eq!(settings.timezone.as_deref(), Some("Europe/Paris"));
+        assert_eq!(settings.locale.as_deref(), Some("ja_JP.UTF-8"));
+    }
+
+    #[test]
+    fn test_docker_args() {
+        let settings = LocaleSettings {
+            timezone: Some("Europe/Paris".to_string()),
+            locale: Some("fr_FR.UTF-8".to_string()),
+        };
+        assert_eq!(
+            settings.docker_args(),
+            [
+                "-e",
+                "TZ=Europe/Paris",
+                "-e",
+                "LANG=fr_FR.UTF-8",
+                "-e",
+                "LC_ALL=fr_FR.UTF-8"
+            ]
+        );
+        assert!(LocaleSettings::default().docker_args().is_empty());
+    }
+}
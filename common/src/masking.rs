@@ -0,0 +1,117 @@
+//! Hiding paths inside mounts.
+//!
+//! `permissions.storage.blocked_paths` lists host paths the server must not
+//! read even when a mount contains them, such as `~/.ssh` under a mounted
+//! home directory. Each one inside a bind mount is covered where it shows
+//! up in the container: a directory by an empty read-only tmpfs, a file by
+//! `/dev/null`. Blocked paths outside every mount are already unreadable,
+//! and ones that do not exist have nothing to hide.
+
+use crate::ContainerExecutor;
+
+/// The `(host, container)` pairs of the bind mounts among `-v` arguments.
+fn bind_mounts(args: &[String]) -> Vec<(&str, &str)> {
+    args.windows(2)
+        .filter(|pair| pair[0] == "-v" || pair[0] == "--volume")
+        .filter_map(|pair| {
+            let mut parts = pair[1].split(':');
+            let host = parts.next()?.trim_end_matches('/');
+            let container = parts.next()?.trim_end_matches('/');
+            host.starts_with('/').then_some((host, container))
+        })
+        .collect()
+}
+
+/// Where `path` shows up in the container, through the deepest mount that
+/// contains it.
+fn container_path(mounts: &[(&str, &str)], path: &str) -> Option<String> {
+    mounts
+        .iter()
+        .filter_map(|(host, container)| {
+            let rest = path.strip_prefix(host)?;
+            (rest.is_empty() || rest.starts_with('/')).then_some((host.len(), container, rest))
+        })
+        .max_by_key(|(depth, _, _)| *depth)
+        .map(|(_, container, rest)| format!("{}{}", container, rest))
+}
+
+/// Options covering the `blocked` paths that the mounts in `args` expose.
+/// `is_dir` tells directories from files, `None` for paths that do not
+/// exist.
+pub fn mask_args(
+    args: &[String],
+    blocked: &[String],
+    is_dir: impl Fn(&str) -> Option<bool>,
+) -> Vec<String> {
+    let mounts = bind_mounts(args);
+    let mut masks = Vec::new();
+    for path in blocked {
+        let path = path.trim_end_matches('/');
+        let Some(target) = container_path(&mounts, path) else {
+            continue;
+        };
+        match is_dir(path) {
+            Some(true) => masks.extend(["--tmpfs".to_string(), format!("{}:ro", target)]),
+            Some(false) => masks.extend(["-v".to_string(), format!("/dev/null:{}:ro", target)]),
+            None => {}
+        }
+    }
+    masks
+}
+
+impl ContainerExecutor {
+    /// [`mask_args`] for the policy's blocked paths and the mounts in
+    /// `args`, looked up on the host.
+    pub(crate) fn mask_args(&self, args: &[String]) -> Vec<String> {
+        let blocked = self.policy_config.blocked_paths();
+        if blocked.is_empty() {
+            return Vec::new();
+        }
+        mask_args(args, &blocked, |path| {
+            std::fs::metadata(path).ok().map(|meta| meta.is_dir())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_mask_args() {
+        let args = strings(&[
+            "-v",
+            "/home/me:/home/me:ro",
+            "-v",
+            "/home/me/project:/workspace:rw",
+            "--read-only",
+        ]);
+        let blocked = strings(&[
+            "/home/me/.ssh/",
+            "/home/me/.netrc",
+            "/home/me/project/.env",
+            "/home/me/.aws",
+            "/etc/shadow",
+        ]);
+        let masks = mask_args(&args, &blocked, |path| match path {
+            "/home/me/.ssh" => Some(true),
+            "/home/me/.netrc" | "/home/me/project/.env" => Some(false),
+            _ => None,
+        });
+        assert_eq!(
+            masks,
+            [
+                "--tmpfs",
+                "/home/me/.ssh:ro",
+                "-v",
+                "/dev/null:/home/me/.netrc:ro",
+                "-v",
+                "/dev/null:/workspace/.env:ro",
+            ]
+        );
+    }
+}
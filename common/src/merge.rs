@@ -0,0 +1,159 @@
+//! Merging of policy documents.
+//!
+//! Layers are applied in order, each one on top of the result of the
+//! previous ones:
+//!
+//! - mappings are merged key by key, recursively
+//! - sequences are concatenated, skipping entries already present, so a
+//!   layer can add allow-list entries but not remove them
+//! - any other value in a later layer replaces the earlier one
+//!
+//! A layer can replace what the layers before it set for a key instead,
+//! list or mapping alike, by naming the key in its `merge` section:
+//!
+//! ```yaml
+//! merge:
+//!   permissions.network.allow: replace
+//! ```
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+/// Top-level key of a layer that says how it merges.
+pub const MERGE_KEY: &str = "merge";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// The default: lists are concatenated, mappings merged.
+    Append,
+    /// The layer's value replaces the earlier one.
+    Replace,
+}
+
+/// Removes the `merge` section from `layer` and returns the keys, as dotted
+/// paths, whose values the layer replaces.
+pub fn take_replaced(layer: &mut Value) -> Result<Vec<String>> {
+    let Some(section) = layer
+        .as_mapping_mut()
+        .and_then(|mapping| mapping.remove(MERGE_KEY))
+    else {
+        return Ok(Vec::new());
+    };
+    let modes: BTreeMap<String, MergeMode> = serde_yaml::from_value(section)
+        .context("merge must map keys such as permissions.network.allow to append or replace")?;
+    let mut replaced = Vec::new();
+    for (path, mode) in modes {
+        if path.is_empty() || path.split('.').any(str::is_empty) {
+            bail!("Invalid key `{}` in merge", path);
+        }
+        if mode == MergeMode::Replace {
+            replaced.push(path);
+        }
+    }
+    Ok(replaced)
+}
+
+pub fn merge_values(base: &mut Value, layer: Value) {
+    merge_at(base, layer, "", &[]);
+}
+
+/// Merges `layer` into `base`, both found at `path`, replacing the values
+/// at the paths in `replaced`.
+fn merge_at(base: &mut Value, layer: Value, path: &str, replaced: &[String]) {
+    if replaced.iter().any(|replaced| replaced == path) {
+        *base = layer;
+        return;
+    }
+    match (base, layer) {
+        (Value::Mapping(base_map), Value::Mapping(layer_map)) => {
+            for (key, value) in layer_map {
+                let Some(existing) = base_map.get_mut(&key) else {
+                    base_map.insert(key, value);
+                    continue;
+                };
+                match key.as_str() {
+                    Some(name) if path.is_empty() => merge_at(existing, value, name, replaced),
+                    Some(name) => {
+                        merge_at(existing, value, &format!("{}.{}", path, name), replaced)
+                    }
+                    None => merge_values(existing, value),
+                }
+            }
+        }
+        (Value::Sequence(base_seq), Value::Sequence(layer_seq)) => {
+            for value in layer_seq {
+                if !base_seq.contains(&value) {
+                    base_seq.push(value);
+                }
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// Merges `layers` in order, each according to its `merge` section.
+pub fn merge_layers(layers: Vec<Value>) -> Result<Value> {
+    let mut merged = Value::Null;
+    for mut layer in layers {
+        let replaced = take_replaced(&mut layer)?;
+        if merged.is_null() {
+            merged = layer;
+        } else {
+            merge_at(&mut merged, layer, "", &replaced);
+        }
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(source: &str) -> Value {
+        serde_yaml::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn test_mappings_merge_recursively() {
+        let mut base = yaml("a: {b: 1, c: 2}");
+        merge_values(&mut base, yaml("a: {c: 3, d: 4}"));
+        assert_eq!(base, yaml("a: {b: 1, c: 3, d: 4}"));
+    }
+
+    #[test]
+    fn test_sequences_append_without_duplicates() {
+        let mut base = yaml("drop: [ALL]\nallow: [{host: a}]");
+        merge_values(&mut base, yaml("drop: [ALL]\nallow: [{host: b}]"));
+        assert_eq!(base, yaml("drop: [ALL]\nallow: [{host: a}, {host: b}]"));
+    }
+
+    #[test]
+    fn test_later_scalars_win() {
+        let merged =
+            merge_layers(vec![yaml("privileged: true"), yaml("privileged: false")]).unwrap();
+        assert_eq!(merged, yaml("privileged: false"));
+    }
+
+    #[test]
+    fn test_merge_section_replaces_keys() {
+        let base = yaml("allow: [{host: a}]\ndocker: {tmpfs: [/tmp], ulimits: {nofile: 64}}");
+        let layer = yaml(
+            "merge: {allow: replace, docker.ulimits: replace, docker.tmpfs: append}\n\
+             allow: [{host: b}]\ndocker: {tmpfs: [/run], ulimits: {nproc: 32}}",
+        );
+        assert_eq!(
+            merge_layers(vec![base, layer]).unwrap(),
+            yaml("allow: [{host: b}]\ndocker: {tmpfs: [/tmp, /run], ulimits: {nproc: 32}}")
+        );
+
+        let mut first = yaml("merge: {allow: replace}\nallow: [a]");
+        assert_eq!(take_replaced(&mut first).unwrap(), ["allow"]);
+        assert_eq!(first, yaml("allow: [a]"));
+        assert!(take_replaced(&mut yaml("merge: {allow: drop}")).is_err());
+        assert!(take_replaced(&mut yaml("merge: {allow.: replace}")).is_err());
+    }
+}
@@ -0,0 +1,394 @@
+//! Native sandbox under Landlock and seccomp.
+//!
+//! `--native-sandbox` runs the host's `npx`/`uvx` without a container, for
+//! CI runners and servers where containers are not permitted. Between fork
+//! and exec the child sets `no_new_privs`, restricts itself to a Landlock
+//! ruleset and loads the seccomp filter of [`crate::sandbox`], so the
+//! package and everything it starts inherit both:
+//!
+//! - Landlock: system directories and the runner's install prefix are
+//!   readable, the policy mounts are readable or writable as declared, and a
+//!   scratch `$HOME` is writable. Nothing else on the file system is.
+//! - seccomp: the syscalls denied to the bubblewrap fallback, plus IPv4 and
+//!   IPv6 sockets when the policy turns the network off.
+//!
+//! There is no mount namespace, so mounts cannot be remapped, and the
+//! environment is rebuilt from the `-e` options. The kernel needs Landlock
+//! (5.13 or later, enabled in the LSM list); without it the run fails.
+
+use crate::sandbox::{parse_options, runner_dirs, SandboxOptions, SYSTEM_DIRS};
+use crate::{ContainerExecutor, Runner};
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+
+// see linux/landlock.h
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+const ACCESS_FS_IOCTL_DEV: u64 = 1 << 15;
+
+const READ_ACCESS: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+/// The rights that apply to a file rather than a directory.
+const FILE_ACCESS: u64 = ACCESS_FS_EXECUTE
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_READ_FILE
+    | ACCESS_FS_TRUNCATE
+    | ACCESS_FS_IOCTL_DEV;
+
+/// Directories the native sandbox reads besides [`SYSTEM_DIRS`].
+const EXTRA_DIRS: &[&str] = &["/proc", "/dev"];
+
+/// Devices the native sandbox writes to.
+const WRITABLE_DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/tty"];
+
+/// The file system rights Landlock ABI `abi` knows about. Handling all of
+/// them denies whatever no rule allows.
+pub fn handled_access(abi: u32) -> u64 {
+    match abi {
+        0 => 0,
+        1 => (1 << 13) - 1,
+        2 => (1 << 14) - 1,
+        3 | 4 => (1 << 15) - 1,
+        _ => (1 << 16) - 1,
+    }
+}
+
+/// A path the sandbox may use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub path: PathBuf,
+    pub writable: bool,
+}
+
+impl Rule {
+    fn new(path: impl Into<PathBuf>, writable: bool) -> Self {
+        Self {
+            path: path.into(),
+            writable,
+        }
+    }
+
+    /// The Landlock rights of the rule, within `handled`.
+    pub fn access(&self, handled: u64, is_dir: bool) -> u64 {
+        let access = if self.writable {
+            handled
+        } else {
+            READ_ACCESS & handled
+        };
+        if is_dir {
+            access
+        } else {
+            access & FILE_ACCESS
+        }
+    }
+}
+
+/// The paths the package may use, given the container options.
+pub fn rules(options: &SandboxOptions, runner_path: &Path, home: &Path) -> Result<Vec<Rule>> {
+    let mut rules: Vec<Rule> = SYSTEM_DIRS
+        .iter()
+        .chain(EXTRA_DIRS)
+        .map(|dir| Rule::new(dir, false))
+        .collect();
+    rules.extend(
+        runner_dirs(runner_path, SYSTEM_DIRS)
+            .into_iter()
+            .map(|dir| Rule::new(dir, false)),
+    );
+    rules.extend(
+        WRITABLE_DEVICES
+            .iter()
+            .map(|device| Rule::new(device, true)),
+    );
+    rules.push(Rule::new(home, true));
+    for mount in &options.mounts {
+        if mount.host != mount.target {
+            bail!(
+                "The native sandbox cannot mount {} at {}, only at its own path",
+                mount.host,
+                mount.target
+            );
+        }
+        rules.push(Rule::new(&mount.host, !mount.read_only));
+    }
+    Ok(rules)
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{handled_access, Rule};
+    use anyhow::{bail, Context, Result};
+    use std::io;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::fs::OpenOptionsExt;
+
+    const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+    const LANDLOCK_RULE_PATH_BENEATH: u32 = 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneathAttr {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// The Landlock ABI of the running kernel, 0 without Landlock.
+    pub fn abi() -> u32 {
+        let version = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                std::ptr::null::<libc::c_void>(),
+                0usize,
+                LANDLOCK_CREATE_RULESET_VERSION,
+            )
+        };
+        u32::try_from(version).unwrap_or(0)
+    }
+
+    /// A ruleset allowing `rules` and denying the rest. Paths that do not
+    /// exist are left out.
+    pub fn ruleset(rules: &[Rule], abi: u32) -> Result<OwnedFd> {
+        let handled = handled_access(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+                .context("Failed to create the Landlock ruleset");
+        }
+        let ruleset = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        for rule in rules {
+            let Ok(file) = std::fs::OpenOptions::new()
+                .read(true)
+                .custom_flags(libc::O_PATH | libc::O_CLOEXEC)
+                .open(&rule.path)
+            else {
+                continue;
+            };
+            let is_dir = file.metadata().map(|m| m.is_dir()).unwrap_or(false);
+            let attr = PathBeneathAttr {
+                allowed_access: rule.access(handled, is_dir),
+                parent_fd: file.as_raw_fd(),
+            };
+            let added = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset.as_raw_fd(),
+                    LANDLOCK_RULE_PATH_BENEATH,
+                    &attr as *const PathBeneathAttr,
+                    0u32,
+                )
+            };
+            if added < 0 {
+                bail!(
+                    "Failed to allow {} in the Landlock ruleset: {}",
+                    rule.path.display(),
+                    io::Error::last_os_error()
+                );
+            }
+        }
+        Ok(ruleset)
+    }
+
+    /// Turns seccomp program bytes into the instructions the kernel loads.
+    pub fn instructions(program: &[u8]) -> Vec<libc::sock_filter> {
+        program
+            .chunks_exact(8)
+            .map(|bytes| libc::sock_filter {
+                code: u16::from_ne_bytes([bytes[0], bytes[1]]),
+                jt: bytes[2],
+                jf: bytes[3],
+                k: u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            })
+            .collect()
+    }
+
+    /// Restricts the calling process. Runs between fork and exec, so it
+    /// only makes syscalls.
+    pub fn restrict_self(ruleset: RawFd, filter: &[libc::sock_filter]) -> io::Result<()> {
+        unsafe {
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1 as libc::c_ulong, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0u32) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let program = libc::sock_fprog {
+                len: filter.len() as libc::c_ushort,
+                filter: filter.as_ptr() as *mut libc::sock_filter,
+            };
+            if libc::syscall(
+                libc::SYS_seccomp,
+                libc::SECCOMP_SET_MODE_FILTER,
+                0u32,
+                &program as *const libc::sock_fprog,
+            ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ContainerExecutor {
+    /// Runs the package on the host under Landlock and seccomp.
+    #[cfg(target_os = "linux")]
+    pub async fn run_native<R: Runner>(
+        &self,
+        runner: &R,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<ExitStatus> {
+        use crate::sandbox::{seccomp_filter, Scratch, DENIED_SYSCALLS, SOCKET_SYSCALL};
+        use std::os::fd::AsRawFd;
+        use tokio::process::Command as AsyncCommand;
+
+        if !runner.supports_fallback() {
+            bail!("{} has no native sandbox", runner.command());
+        }
+        let runner_path = which::which(runner.command()).with_context(|| {
+            format!(
+                "The native sandbox runs the host's {}, which is not installed",
+                runner.command()
+            )
+        })?;
+        let abi = linux::abi();
+        if abi == 0 {
+            bail!(
+                "The native sandbox needs Landlock (Linux 5.13 or later, enabled in the LSM list)"
+            );
+        }
+        let Some((arch, denied)) = DENIED_SYSCALLS else {
+            bail!("The native sandbox has no seccomp filter for this architecture");
+        };
+        let options = parse_options(&self.container_options(), self.env_lookup())?;
+
+        let home = std::env::temp_dir().join(format!("{}.home", self.container_name));
+        std::fs::create_dir_all(&home)
+            .with_context(|| format!("Failed to create {}", home.display()))?;
+        let _scratch = Scratch::Dir(home.clone());
+        let rules = rules(&options, &runner_path, &home)?;
+        let ruleset = linux::ruleset(&rules, abi)?;
+        let deny_network = (!options.network).then_some(SOCKET_SYSCALL);
+        let filter = linux::instructions(&seccomp_filter(arch, denied, deny_network));
+
+        let cmd_args = self.command_args(runner, flags, args);
+        let mut command = AsyncCommand::new(&runner_path);
+        command
+            .args(&cmd_args[1..])
+            .env_clear()
+            .env("HOME", &home)
+            .env("TMPDIR", &home)
+            .envs(options.env.iter().map(|(key, val)| (key, val)))
+            .current_dir(&home);
+        if let Ok(path) = std::env::var("PATH") {
+            command.env("PATH", path);
+        }
+        let ruleset_fd = ruleset.as_raw_fd();
+        unsafe {
+            command.pre_exec(move || linux::restrict_self(ruleset_fd, &filter));
+        }
+
+        if self.verbose {
+            eprintln!(
+                "Running {} under Landlock ABI {} and seccomp, allowed paths:",
+                cmd_args.join(" "),
+                abi
+            );
+            for rule in &rules {
+                let mode = if rule.writable { "rw" } else { "ro" };
+                eprintln!("  {} {}", mode, rule.path.display());
+            }
+        }
+        let status = self.run_sandbox(command).await;
+        drop(ruleset);
+        status
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub async fn run_native<R: Runner>(
+        &self,
+        _runner: &R,
+        _flags: &[String],
+        _args: &[String],
+    ) -> Result<ExitStatus> {
+        bail!("The native sandbox needs Linux")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Mount;
+
+    #[test]
+    fn test_rules_follow_the_options() {
+        let options = SandboxOptions {
+            mounts: vec![
+                Mount {
+                    host: "/srv/docs".to_string(),
+                    target: "/srv/docs".to_string(),
+                    read_only: true,
+                },
+                Mount {
+                    host: "/srv/out".to_string(),
+                    target: "/srv/out".to_string(),
+                    read_only: false,
+                },
+            ],
+            env: vec![],
+            network: true,
+        };
+        let rules = rules(
+            &options,
+            Path::new("/usr/bin/npx"),
+            Path::new("/tmp/x.home"),
+        )
+        .unwrap();
+        assert!(rules.contains(&Rule::new("/usr", false)));
+        assert!(rules.contains(&Rule::new("/tmp/x.home", true)));
+        assert!(rules.contains(&Rule::new("/srv/docs", false)));
+        assert!(rules.contains(&Rule::new("/srv/out", true)));
+
+        let remapped = SandboxOptions {
+            mounts: vec![Mount {
+                host: "/srv/out".to_string(),
+                target: "/out".to_string(),
+                read_only: false,
+            }],
+            ..options
+        };
+        assert!(super::rules(&remapped, Path::new("/usr/bin/npx"), Path::new("/tmp")).is_err());
+    }
+
+    #[test]
+    fn test_access_rights() {
+        let handled = handled_access(3);
+        assert_eq!(handled, (1 << 15) - 1);
+        let read = Rule::new("/usr", false);
+        assert_eq!(read.access(handled, true), READ_ACCESS);
+        let write = Rule::new("/dev/null", true);
+        assert_eq!(
+            write.access(handled, false),
+            ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE
+        );
+        assert_eq!(handled_access(0), 0);
+    }
+}
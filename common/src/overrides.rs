@@ -0,0 +1,410 @@
+use crate::cli::ContainerArgs;
+use crate::spec::{RuntimeClass, VolumeMode};
+use crate::PolicyConfig;
+use std::fmt;
+use std::path::{Component, Path};
+
+/// A privilege requested on the command line that the loaded policy does not
+/// grant, along with the policy clause responsible for the denial.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Denial {
+    pub requested: String,
+    pub clause: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Denial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: denied by `{}` ({})",
+            self.requested, self.clause, self.reason
+        )
+    }
+}
+
+pub(crate) fn normalize_capability(cap: &str) -> String {
+    let upper = cap.to_uppercase().replace('_', "");
+    upper.strip_prefix("CAP").unwrap_or(&upper).to_string()
+}
+
+/// Whether `path` has a `..` component, which the runtime resolves after
+/// any comparison of the path as written.
+pub(crate) fn has_parent_dir(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .any(|component| component == Component::ParentDir)
+}
+
+/// Whether `path` is `root` or below it. Paths climbing with `..` are
+/// never within.
+pub(crate) fn path_within(path: &str, root: &str) -> bool {
+    !has_parent_dir(path) && Path::new(path).starts_with(Path::new(root))
+}
+
+impl PolicyConfig {
+    /// Compares privileges requested through CLI flags with the loaded
+    /// policy. Without a policy nothing is restricted.
+    pub fn check_overrides(&self, args: &ContainerArgs) -> Vec<Denial> {
+        let mut denials = Vec::new();
+        if !self.is_loaded() {
+            return denials;
+        }
+
+        if let Some(ref network) = args.network {
+            let requested = format!("--network {}", network);
+            let allowed_hosts = self.allowed_network_hosts();
+            if let Some(mode) = self.network_mode() {
+                if *network != mode {
+                    denials.push(Denial {
+                        requested,
+                        clause: "permissions.network.policy".to_string(),
+                        reason: format!("the policy puts the container on `{}`", mode),
+                    });
+                }
+            } else if network == "none" {
+            } else if allowed_hosts.is_empty() {
+                denials.push(Denial {
+                    requested,
+                    clause: "permissions.network".to_string(),
+                    reason: "the policy grants no network access".to_string(),
+                });
+            } else {
+                // only the policy's own network is limited to the allowed
+                // hosts, any other reaches past them
+                let bypass = if network == "host" {
+                    "host networking".to_string()
+                } else if network.starts_with("container:") {
+                    "joining another container's network".to_string()
+                } else {
+                    format!("the network `{}`", network)
+                };
+                denials.push(Denial {
+                    requested,
+                    clause: "permissions.network.allow".to_string(),
+                    reason: format!(
+                        "{} bypasses the allowed hosts ({})",
+                        bypass,
+                        allowed_hosts.join(", ")
+                    ),
+                });
+            }
+        }
+
+        let allowed_env = self.allowed_env_keys();
+        for env in &args.env {
+            let key = env.split('=').next().unwrap_or(env);
+            if !allowed_env.iter().any(|allowed| allowed == key)
+                && !self.runtime.whitelists_env(key)
+            {
+                denials.push(Denial {
+                    requested: format!("--env {}", key),
+                    clause: "permissions.environment.allow".to_string(),
+                    reason: format!("`{}` is not an allowed environment variable", key),
+                });
+            }
+        }
+
+        for volume in &args.volumes {
+            if let Some(denial) = self.check_volume(volume) {
+                denials.push(denial);
+            }
+        }
+
+        if let (Some(requested), Some(required)) =
+            (args.runtime_class, self.runtime.docker.runtime_class)
+        {
+            if required != RuntimeClass::Runc && requested != required {
+                denials.push(Denial {
+                    requested: format!("--runtime-class {}", requested.name()),
+                    clause: "permissions.runtime.docker.runtime_class".to_string(),
+                    reason: format!("the policy requires {} isolation", required.name()),
+                });
+            }
+        }
+
+        let granted_caps: Vec<String> = self
+            .added_capabilities()
+            .iter()
+            .map(|cap| normalize_capability(cap))
+            .collect();
+        for cap in &args.cap_add {
+            if !granted_caps.contains(&normalize_capability(cap)) {
+                denials.push(Denial {
+                    requested: format!("--cap-add {}", cap),
+                    clause: "permissions.runtime.docker.security.capabilities.add".to_string(),
+                    reason: format!("`{}` is not an added capability", cap),
+                });
+            }
+        }
+
+        denials
+    }
+
+    fn check_volume(&self, volume: &str) -> Option<Denial> {
+        let parts: Vec<&str> = volume.split(':').collect();
+        let host = parts[0];
+        let readonly = parts
+            .get(2)
+            .is_some_and(|mode| mode.split(',').any(|opt| opt == "ro"));
+        let requested = format!("--volume {}", volume);
+        let clause = "permissions.storage.allow".to_string();
+
+        if !host.starts_with('/') {
+            let declared = self.runtime.docker.volumes.iter().find(|v| v.name == host);
+            return match declared {
+                Some(volume) if readonly || volume.mode == VolumeMode::Rw => None,
+                Some(_) => Some(Denial {
+                    requested,
+                    clause: "permissions.runtime.docker.volumes".to_string(),
+                    reason: format!("volume `{}` is declared read-only, mount it with :ro", host),
+                }),
+                None => Some(Denial {
+                    requested,
+                    clause: "permissions.runtime.docker.volumes".to_string(),
+                    reason: format!("volume `{}` is not declared in the policy", host),
+                }),
+            };
+        }
+
+        if has_parent_dir(host) {
+            return Some(Denial {
+                requested,
+                clause,
+                reason: format!("`{}` climbs with `..`, give the path without it", host),
+            });
+        }
+
+        let grants: Vec<(String, bool)> = self
+            .storage_grants()
+            .into_iter()
+            .filter(|(path, _)| path_within(host, path))
+            .collect();
+
+        if grants.is_empty() {
+            Some(Denial {
+                requested,
+                clause,
+                reason: format!("`{}` is outside every allowed fs:// path", host),
+            })
+        } else if !readonly && !grants.iter().any(|(_, writable)| *writable) {
+            Some(Denial {
+                requested,
+                clause,
+                reason: format!("`{}` is only granted read access, mount it with :ro", host),
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// Renders denials for the terminal. Without `explain` only the rejected
+/// flags are listed.
+pub fn format_denials(denials: &[Denial], explain: bool) -> String {
+    let mut out = String::from("Requested privileges exceed the loaded policy:\n");
+    for denial in denials {
+        if explain {
+            out.push_str(&format!("  {}\n", denial));
+        } else {
+            out.push_str(&format!("  {}\n", denial.requested));
+        }
+    }
+    if !explain {
+        out.push_str("Re-run with --explain-denial to see which policy clause blocked each flag");
+    }
+    out.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> ContainerArgs {
+        ContainerArgs::default()
+    }
+
+    #[test]
+    fn test_no_policy_allows_everything() {
+        let config = PolicyConfig::new();
+        let mut requested = args();
+        requested.network = Some("host".to_string());
+        requested.cap_add.push("SYS_ADMIN".to_string());
+
+        assert!(config.check_overrides(&requested).is_empty());
+    }
+
+    #[test]
+    fn test_network_denied_without_network_grant() {
+        let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+        let mut requested = args();
+        requested.network = Some("host".to_string());
+
+        let denials = config.check_overrides(&requested);
+        assert_eq!(denials.len(), 1);
+        assert_eq!(denials[0].clause, "permissions.network");
+
+        requested.network = Some("none".to_string());
+        assert!(config.check_overrides(&requested).is_empty());
+    }
+
+    #[test]
+    fn test_network_limited_to_allowed_hosts() {
+        let mut config = PolicyConfig::from_file("testdata/network-policy.yaml").unwrap();
+        config.network.policy = None;
+        let mut requested = args();
+        for network in ["host", "bridge", "container:other"] {
+            requested.network = Some(network.to_string());
+            let denials = config.check_overrides(&requested);
+            assert_eq!(denials.len(), 1, "{}", network);
+            assert_eq!(denials[0].clause, "permissions.network.allow");
+        }
+        assert!(config
+            .check_overrides(&requested)
+            .remove(0)
+            .reason
+            .contains("another container"));
+
+        requested.network = Some("none".to_string());
+        assert!(config.check_overrides(&requested).is_empty());
+    }
+
+    #[test]
+    fn test_network_must_match_policy_mode() {
+        let config = PolicyConfig::from_file("testdata/network-policy.yaml").unwrap();
+        let mut requested = args();
+        requested.network = Some("bridge".to_string());
+
+        let denials = config.check_overrides(&requested);
+        assert_eq!(denials.len(), 1);
+        assert_eq!(denials[0].clause, "permissions.network.policy");
+
+        requested.network = Some("mcp-egress".to_string());
+        assert!(config.check_overrides(&requested).is_empty());
+    }
+
+    #[test]
+    fn test_env_and_capabilities_denied() {
+        let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+        let mut requested = args();
+        requested.env.push("API_KEY=secret".to_string());
+        requested.cap_add.push("NET_ADMIN".to_string());
+
+        let denials = config.check_overrides(&requested);
+        assert_eq!(denials.len(), 2);
+        assert_eq!(denials[0].requested, "--env API_KEY");
+        assert_eq!(denials[0].clause, "permissions.environment.allow");
+        assert!(!denials[0].to_string().contains("secret"));
+        assert!(denials[1].clause.ends_with("capabilities.add"));
+
+        let mut config = config;
+        config.runtime.environment_whitelist = vec!["API_*".to_string()];
+        let denials = config.check_overrides(&requested);
+        assert_eq!(denials.len(), 1);
+        assert!(denials[0].clause.ends_with("capabilities.add"));
+    }
+
+    #[test]
+    fn test_volume_checked_against_storage() {
+        let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+
+        let mut requested = args();
+        requested
+            .volumes
+            .push("/tmp/mcp-filesystem/data:/data:ro".to_string());
+        assert!(config.check_overrides(&requested).is_empty());
+
+        requested.volumes = vec!["/tmp/mcp-filesystem:/data".to_string()];
+        let denials = config.check_overrides(&requested);
+        assert!(denials[0].reason.contains("read access"));
+
+        requested.volumes = vec!["/etc:/etc:ro".to_string()];
+        let denials = config.check_overrides(&requested);
+        assert!(denials[0].reason.contains("outside"));
+
+        requested.volumes = vec!["/tmp/mcp-filesystem/../../etc:/x:ro".to_string()];
+        let denials = config.check_overrides(&requested);
+        assert!(denials[0].reason.contains("climbs"));
+    }
+
+    #[test]
+    fn test_path_within() {
+        assert!(path_within(
+            "/tmp/mcp-filesystem/data",
+            "/tmp/mcp-filesystem"
+        ));
+        assert!(path_within("/tmp/mcp-filesystem", "/tmp/mcp-filesystem/"));
+        assert!(!path_within(
+            "/tmp/mcp-filesystem-other",
+            "/tmp/mcp-filesystem"
+        ));
+        assert!(!path_within(
+            "/tmp/mcp-filesystem/../../etc",
+            "/tmp/mcp-filesystem"
+        ));
+        assert!(!path_within("/tmp/mcp-filesystem/a/../..", "/tmp"));
+    }
+
+    #[test]
+    fn test_runtime_class_cannot_be_weakened() {
+        let mut config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+        let mut requested = args();
+        requested.runtime_class = Some(RuntimeClass::Runc);
+        assert!(config.check_overrides(&requested).is_empty());
+
+        config.runtime.docker.runtime_class = Some(RuntimeClass::Gvisor);
+        let denials = config.check_overrides(&requested);
+        assert_eq!(
+            denials[0].clause,
+            "permissions.runtime.docker.runtime_class"
+        );
+
+        requested.runtime_class = Some(RuntimeClass::Gvisor);
+        assert!(config.check_overrides(&requested).is_empty());
+
+        config.runtime.docker.runtime_class = Some(RuntimeClass::Kata);
+        let denials = config.check_overrides(&requested);
+        assert_eq!(denials[0].reason, "the policy requires kata isolation");
+    }
+
+    #[test]
+    fn test_named_volume_must_be_declared() {
+        let config = PolicyConfig::from_file("testdata/volumes-policy.yaml").unwrap();
+
+        let mut requested = args();
+        requested.volumes.push("mcp-memory:/data".to_string());
+        assert!(config.check_overrides(&requested).is_empty());
+
+        requested.volumes = vec!["mcp-knowledge:/knowledge".to_string()];
+        assert!(config.check_overrides(&requested)[0]
+            .reason
+            .contains("read-only"));
+
+        requested.volumes = vec!["other:/other".to_string()];
+        let denials = config.check_overrides(&requested);
+        assert_eq!(denials[0].clause, "permissions.runtime.docker.volumes");
+    }
+
+    #[test]
+    fn test_format_denials() {
+        let denials = vec![Denial {
+            requested: "--network host".to_string(),
+            clause: "permissions.network".to_string(),
+            reason: "the policy grants no network access".to_string(),
+        }];
+
+        let short = format_denials(&denials, false);
+        assert!(short.contains("--network host"));
+        assert!(!short.contains("permissions.network"));
+
+        let explained = format_denials(&denials, true);
+        assert!(explained.contains("denied by `permissions.network`"));
+    }
+
+    #[test]
+    fn test_normalize_capability() {
+        assert_eq!(normalize_capability("NET_ADMIN"), "NETADMIN");
+        assert_eq!(normalize_capability("CAP_NET_ADMIN"), "NETADMIN");
+        assert_eq!(normalize_capability("NetAdmin"), "NETADMIN");
+    }
+}
@@ -0,0 +1,138 @@
+use crate::cleanup::Resource;
+use crate::{ContainerExecutor, Runner, Transport};
+use anyhow::{bail, Context, Result};
+use std::process::Stdio;
+
+/// Name of the volume holding the package manager cache for `runner`.
+pub fn cache_volume<R: Runner>(runner: &R) -> String {
+    format!("semcp-cache-{}", runner.command())
+}
+
+impl ContainerExecutor {
+    fn isolated_cache(&self) -> bool {
+        self.policy_config
+            .runtime
+            .cache
+            .is_isolated(self.package.as_deref())
+    }
+
+    /// The shared cache volume, or a per-run one for packages the policy
+    /// does not trust with it.
+    pub(crate) fn cache_volume_for<R: Runner>(&self, runner: &R) -> String {
+        if self.isolated_cache() {
+            format!("{}-{}", cache_volume(runner), self.container_name)
+        } else {
+            cache_volume(runner)
+        }
+    }
+
+    /// Installs the package into the shared cache volume. This phase gets
+    /// network access and a writable cache, but no host mounts.
+    pub(crate) async fn run_install_phase<R: Runner>(
+        &self,
+        runner: &R,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<()> {
+        let (Some(cache_dir), Some(install_args)) =
+            (runner.cache_dir(), runner.build_install_args(flags, args))
+        else {
+            bail!(
+                "{} does not support separate install and serve phases",
+                runner.command()
+            );
+        };
+
+        let install_name = format!("{}-install", self.container_name);
+        if self.isolated_cache() {
+            self.register(Resource::Volume(self.cache_volume_for(runner)));
+        }
+        self.register(Resource::Container(install_name.clone()));
+        let mut docker_args = vec![
+            "--rm".to_string(),
+            "--name".to_string(),
+            install_name,
+            "-v".to_string(),
+            format!("{}:{}", self.cache_volume_for(runner), cache_dir),
+        ];
+        docker_args.extend(self.runtime.run_args());
+        docker_args.extend(self.label_args());
+        docker_args.extend(self.policy_config.map_docker_security_args());
+        docker_args.extend(runner.additional_docker_args());
+        docker_args.push(self.docker_image.clone());
+        docker_args.extend(install_args);
+
+        if self.verbose {
+            eprintln!(
+                "Install phase: {} run {}",
+                self.runtime,
+                docker_args.join(" ")
+            );
+        }
+
+        // stdout belongs to the MCP client, so installer output goes to stderr
+        let status = self
+            .runtime
+            .run(&docker_args)
+            .stdin(Stdio::null())
+            .stdout(std::io::stderr())
+            .status()
+            .await
+            .context("Failed to run install phase")?;
+
+        if !status.success() {
+            bail!("Install phase failed: {}", status);
+        }
+        Ok(())
+    }
+
+    /// Whether the server runs without network: stdio servers do unless the
+    /// policy or the user grants them one. HTTP and SSE servers must stay
+    /// reachable.
+    pub(crate) fn serves_offline(&self, transport: &Transport) -> bool {
+        let network_requested = self.extra_args.iter().any(|arg| arg == "--network")
+            || self.policy_config.network_mode().is_some()
+            || self.policy_config.filter_dns()
+            || !self.policy_config.blocked_ports().is_empty();
+        matches!(transport, Transport::Stdio)
+            && !network_requested
+            && self.policy_config.allowed_network_hosts().is_empty()
+    }
+
+    /// Whether the package is installed in a phase of its own: with
+    /// `--split-phases`, and for servers that run without network, which
+    /// could not fetch their package otherwise.
+    pub(crate) fn splits_phases<R: Runner>(&self, runner: &R, transport: &Transport) -> bool {
+        self.split_phases || (self.serves_offline(transport) && runner.cache_dir().is_some())
+    }
+
+    /// Arguments for the serve phase: a read-only cache, an offline package
+    /// manager and no network for servers that run without.
+    pub(crate) fn serve_phase_args<R: Runner>(
+        &self,
+        runner: &R,
+        transport: &Transport,
+    ) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(cache_dir) = runner.cache_dir() {
+            args.push("-v".to_string());
+            args.push(format!(
+                "{}:{}:ro",
+                self.cache_volume_for(runner),
+                cache_dir
+            ));
+        }
+
+        if self.serves_offline(transport) {
+            args.push("--network".to_string());
+            args.push("none".to_string());
+        }
+
+        for env in runner.offline_env() {
+            args.push("-e".to_string());
+            args.push(env);
+        }
+        args
+    }
+}
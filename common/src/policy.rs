@@ -1,83 +1,693 @@
-use anyhow::{Context, Result};
-use policy_mcp::{AccessType, PolicyDocument, PolicyParser};
+use crate::artifacts;
+use crate::engine::EngineSpec;
+use crate::envmap;
+use crate::firewall::BlockedPort;
+use crate::interpolate;
+use crate::lint;
+use crate::merge::{merge_layers, take_replaced};
+use crate::presets::Preset;
+use crate::scratch::ScratchDir;
+use crate::seccomp;
+use crate::signing::SigningSpec;
+use crate::spec::{
+    Access, EnvironmentSpec, NetworkSpec, PermissionsSpec, RuntimeSpec, StorageSpec,
+};
+use anyhow::{bail, Context, Result};
+use policy_mcp::{PolicyDocument, PolicyParser};
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct PolicyConfig {
+    /// The loaded document as `policy_mcp` models it, `None` without policy
+    /// files.
+    #[deprecated(
+        note = "read the `storage`, `network`, `environment` and `runtime` fields, which semcp enforces"
+    )]
     pub policy: Option<PolicyDocument>,
+    /// The merged document, for the sections outside `permissions`.
+    raw: Option<Value>,
+    sources: Vec<String>,
+    pub storage: StorageSpec,
+    pub network: NetworkSpec,
+    pub environment: EnvironmentSpec,
+    pub runtime: RuntimeSpec,
+    /// Whether this is [`PolicyConfig::baseline`].
+    baseline: bool,
+    files: Vec<LoadedFile>,
+}
+
+/// A policy file as it was read, `extends` parents included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedFile {
+    /// The path, or the `oci://` or `https://` reference of a fetched one.
+    pub source: String,
+    /// SHA-256 of the bytes read, in hex.
+    pub sha256: String,
+}
+
+/// Limits of [`PolicyConfig::baseline`]. npx and uvx install packages under
+/// `/root`, so it is a writable tmpfs next to `/tmp`; Docker mounts tmpfs
+/// `noexec` unless told otherwise.
+const BASELINE: &str = r#"
+permissions:
+  network:
+    allow:
+    - host: registry.npmjs.org
+    - host: pypi.org
+    - host: files.pythonhosted.org
+    filter_dns: true
+  runtime:
+    docker:
+      memory_limit: 1g
+      pids_limit: 256
+      read_only_root_filesystem: true
+      tmpfs: ["/tmp:exec", "/root:exec"]
+"#;
+
+/// Runs a merged `document` through the parser single files go through, so
+/// layers cannot add what a file on its own could not.
+fn parse_merged(document: &Value) -> Result<()> {
+    let scratch = ScratchDir::new("semcp-policy")?;
+    let path = scratch.write("policy.yaml", serde_yaml::to_string(document)?.as_bytes())?;
+    PolicyParser::parse_file(&path.to_string_lossy()).context("Failed to parse merged policy")?;
+    Ok(())
+}
+
+/// `permissions.<key>` of `raw`, the default when it is not set.
+fn permission<T: DeserializeOwned + Default>(raw: &Value, key: &str) -> Result<T> {
+    match raw.get("permissions").and_then(|p| p.get(key)) {
+        Some(section) if !section.is_null() => serde_yaml::from_value(section.clone())
+            .with_context(|| format!("Failed to parse permissions.{}", key)),
+        _ => Ok(T::default()),
+    }
+}
+
+/// The [`PermissionsSpec`] of a policy document, checked.
+fn parse_permissions(raw: &Value) -> Result<PermissionsSpec> {
+    let permissions = PermissionsSpec {
+        storage: permission(raw, "storage")?,
+        network: permission(raw, "network")?,
+        environment: permission(raw, "environment")?,
+        runtime: permission(raw, "runtime")?,
+    };
+    permissions.runtime.validate()?;
+    validate_network(&permissions.network)?;
+    validate_storage(&permissions.storage)?;
+    Ok(permissions)
+}
+
+/// Checks the `permissions.network` values serde lets through.
+fn validate_network(network: &NetworkSpec) -> Result<()> {
+    for address in &network.dns_servers {
+        if address.parse::<std::net::IpAddr>().is_err() {
+            bail!(
+                "permissions.network.dns_servers: `{}` is not an IP address",
+                address
+            );
+        }
+    }
+    for port in &network.blocked_ports {
+        BlockedPort::parse(&port.text()).context("permissions.network.blocked_ports")?;
+    }
+    Ok(())
+}
+
+/// Checks `permissions.storage.blocked_paths` and `allowed_paths`.
+fn validate_storage(storage: &StorageSpec) -> Result<()> {
+    for (key, paths) in [
+        ("blocked_paths", &storage.blocked_paths),
+        ("allowed_paths", &storage.allowed_paths),
+    ] {
+        for path in paths {
+            let (host, _) = allowed_path(path);
+            if !host.starts_with('/') {
+                bail!(
+                    "permissions.storage.{}: `{}` is not an absolute path or under ~/",
+                    key,
+                    path
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The host path of an `allowed_paths` entry and whether it is writable:
+/// read-only unless it ends in `:rw`.
+fn allowed_path(entry: &str) -> (String, bool) {
+    let (path, writable) = match entry.rsplit_once(':') {
+        Some((path, "rw")) => (path, true),
+        Some((path, "ro")) => (path, false),
+        _ => (entry, false),
+    };
+    (expand_home(path), writable)
+}
+
+/// `grants` with each path once, writable when any grant of it is.
+fn merge_grants(grants: Vec<(String, bool)>) -> Vec<(String, bool)> {
+    let mut merged: Vec<(String, bool)> = Vec::new();
+    for (path, writable) in grants {
+        let path = match path.trim_end_matches('/') {
+            "" => "/".to_string(),
+            trimmed => trimmed.to_string(),
+        };
+        match merged.iter_mut().find(|(existing, _)| *existing == path) {
+            Some((_, existing)) => *existing |= writable,
+            None => merged.push((path, writable)),
+        }
+    }
+    merged
+}
+
+/// `path` with a leading `~/` replaced by the home directory.
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => path.to_string(),
+    }
+}
+
+/// How forgiving loading policy files is.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Ignore keys semcp does not know instead of rejecting them.
+    pub lenient: bool,
+    /// Fail on references to environment variables that are not set
+    /// instead of expanding them to nothing, see [`crate::interpolate`].
+    pub strict_env: bool,
+    /// Read `oci://` and `https://` policies from the cache only, see
+    /// [`crate::artifacts`].
+    pub offline: bool,
+    /// Signatures to verify before a file is read, see [`crate::signing`].
+    pub signing: SigningSpec,
+}
+
+/// Reads the policy file at `path`, once its signature verifies, and
+/// expands the environment variables its values name. Unless `lenient`,
+/// keys semcp does not know are an error rather than ignored, since they
+/// are most likely typos.
+fn read_yaml(path: &Path, options: &LoadOptions) -> Result<(Value, String)> {
+    options.signing.check(path)?;
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read policy file {}", path.display()))?;
+    let digest = format!("{:x}", Sha256::digest(&content));
+    let unknown = if options.lenient {
+        Vec::new()
+    } else {
+        lint::unknown_fields(&content)
+    };
+    if !unknown.is_empty() {
+        let mut message = format!("Unknown keys in policy file {}:", path.display());
+        for diagnostic in unknown {
+            message.push_str(&format!(
+                "\n  {}:{}: {}: {}",
+                diagnostic.line, diagnostic.column, diagnostic.path, diagnostic.message
+            ));
+        }
+        message.push_str("\nFix them, or pass --lenient-policy to snpx or suvx to ignore them");
+        bail!(message);
+    }
+    let mut document: Value = serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse policy file {}", path.display()))?;
+    let undefined = interpolate::expand_values(&mut document, &interpolate::from_env)
+        .with_context(|| format!("Failed to expand policy file {}", path.display()))?;
+    if !undefined.is_empty() {
+        let names = undefined.join(", ");
+        if options.strict_env {
+            bail!(
+                "Policy file {} uses unset variables: {}",
+                path.display(),
+                names
+            );
+        }
+        eprintln!(
+            "Warning: policy file {} uses unset variables, expanded to nothing: {}",
+            path.display(),
+            names
+        );
+    }
+    Ok((document, digest))
+}
+
+/// How deep `extends:` may nest before it is taken for a cycle.
+const MAX_EXTENDS_DEPTH: usize = 8;
+
+/// The layers of the policy at `source`, a path, an `oci://` reference or an
+/// `https://` URL: the policies it `extends`, recursively and in order, then
+/// the policy itself without the key. Relative paths in `extends` are
+/// resolved against the directory, or URL, of the policy naming them.
+fn load_layers(
+    source: &str,
+    base: &Path,
+    depth: usize,
+    options: &LoadOptions,
+    files: &mut Vec<LoadedFile>,
+) -> Result<Vec<Value>> {
+    if depth > MAX_EXTENDS_DEPTH {
+        bail!("Policy {} extends too deeply, is there a cycle?", source);
+    }
+    if let Some(preset) = Preset::from_source(source) {
+        // built in, so neither signed nor linted
+        return Ok(vec![preset?.layer()?]);
+    }
+    let path = if artifacts::is_remote(source) {
+        artifacts::policy_file(source, options.offline)?
+    } else {
+        base.join(source)
+    };
+    let (mut document, sha256) = read_yaml(&path, options)?;
+    files.push(LoadedFile {
+        source: if artifacts::is_remote(source) {
+            source.to_string()
+        } else {
+            path.display().to_string()
+        },
+        sha256,
+    });
+    let extends = match document.as_mapping_mut() {
+        Some(mapping) => mapping.remove("extends"),
+        None => None,
+    };
+    let parents: Vec<String> = match extends {
+        None => vec![],
+        Some(Value::String(parent)) => vec![parent],
+        Some(value) => serde_yaml::from_value(value)
+            .with_context(|| format!("extends in {} must be a list of policies", source))?,
+    };
+    // a fetched policy extends others relative to its URL, not the cache
+    let parents = parents.into_iter().map(|parent| {
+        if artifacts::is_url(source)
+            && !artifacts::is_remote(&parent)
+            && Preset::from_source(&parent).is_none()
+        {
+            artifacts::join_url(source, &parent)
+        } else {
+            parent
+        }
+    });
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut layers = Vec::new();
+    for parent in parents {
+        layers.extend(
+            load_layers(&parent, dir, depth + 1, options, files)
+                .with_context(|| format!("Failed to load {} extended by {}", parent, source))?,
+        );
+    }
+    layers.push(document);
+    Ok(layers)
 }
 
 impl PolicyConfig {
+    #[allow(deprecated)]
     pub fn new() -> Self {
-        Self { policy: None }
+        Self {
+            policy: None,
+            raw: None,
+            sources: Vec::new(),
+            storage: StorageSpec::default(),
+            network: NetworkSpec::default(),
+            environment: EnvironmentSpec::default(),
+            runtime: RuntimeSpec::default(),
+            baseline: false,
+            files: Vec::new(),
+        }
+    }
+
+    /// The configuration of a policy document loaded from `sources`.
+    #[allow(deprecated)]
+    fn from_document(raw: Value, sources: Vec<String>) -> Result<Self> {
+        let PermissionsSpec {
+            storage,
+            network,
+            environment,
+            runtime,
+        } = parse_permissions(&raw)?;
+        let policy = if sources.is_empty() {
+            None
+        } else {
+            Some(serde_yaml::from_value(raw.clone()).context("Failed to parse policy")?)
+        };
+        Ok(Self {
+            policy,
+            raw: Some(raw),
+            sources,
+            storage,
+            network,
+            environment,
+            runtime,
+            baseline: false,
+            files: Vec::new(),
+        })
+    }
+
+    /// The hardening applied when no policy is given. Like no policy it
+    /// grants and denies nothing, so `-v`, `-e` and `--network` work as
+    /// usual, but every capability is dropped, privileges cannot be gained,
+    /// memory and processes are limited, the root file system is read-only
+    /// and only the package registries resolve.
+    pub fn baseline() -> Self {
+        let raw: Value = serde_yaml::from_str(BASELINE).expect("the baseline is valid");
+        Self {
+            baseline: true,
+            ..Self::from_document(raw, Vec::new()).expect("the baseline is valid")
+        }
+    }
+
+    pub fn is_baseline(&self) -> bool {
+        self.baseline
+    }
+
+    /// Whether policy files were loaded, rather than none or the baseline,
+    /// which restrict nothing asked for on the command line.
+    pub fn is_loaded(&self) -> bool {
+        !self.sources.is_empty()
     }
 
     pub fn from_file(path: &str) -> Result<Self> {
-        let policy = PolicyParser::parse_file(path).context("Failed to parse policy file")?;
+        Self::read(path, &LoadOptions::default())
+    }
+
+    fn read(path: &str, options: &LoadOptions) -> Result<Self> {
+        PolicyParser::parse_file(path).context("Failed to parse policy file")?;
+        let (mut raw, sha256) = read_yaml(Path::new(path), options)?;
+        // a lone file has nothing to merge with
+        take_replaced(&mut raw)?;
+        // the parser validated the file as written, the policy is the
+        // document with its variables expanded
         Ok(Self {
-            policy: Some(policy),
+            files: vec![LoadedFile {
+                source: path.to_string(),
+                sha256,
+            }],
+            ..Self::from_document(raw, vec![path.to_string()])?
         })
     }
 
+    /// Loads several policy files as layers, typically org base, team and
+    /// project, in that order. See [`crate::merge`] for the merge rules.
+    /// Policies a file `extends`, and `oci://` and `https://` references,
+    /// become layers too, see [`crate::artifacts`].
+    pub fn from_files(paths: &[String]) -> Result<Self> {
+        Self::from_files_with(paths, LoadOptions::default())
+    }
+
+    /// Like [`PolicyConfig::from_files`], with the given [`LoadOptions`].
+    pub fn from_files_with(paths: &[String], options: LoadOptions) -> Result<Self> {
+        if paths.is_empty() {
+            return Ok(Self::new());
+        }
+        let mut layers = Vec::new();
+        let mut files = Vec::new();
+        for path in paths {
+            layers.extend(load_layers(path, Path::new(""), 0, &options, &mut files)?);
+        }
+        if let [path] = paths {
+            if layers.len() == 1
+                && !artifacts::is_remote(path)
+                && Preset::from_source(path).is_none()
+            {
+                return Self::read(path, &options);
+            }
+        }
+        let raw = merge_layers(layers)?;
+        parse_merged(&raw)?;
+        Ok(Self {
+            files,
+            ..Self::from_document(raw, paths.to_vec())?
+        })
+    }
+
+    /// Policy files this configuration was loaded from, in merge order.
+    pub fn sources(&self) -> &[String] {
+        &self.sources
+    }
+
+    /// The files read to load this configuration, with the digests of
+    /// their contents at the time.
+    pub fn files(&self) -> &[LoadedFile] {
+        &self.files
+    }
+
+    /// The policy as one YAML document, with all layers merged.
+    pub fn document(&self) -> Option<&Value> {
+        self.raw.as_ref()
+    }
+
+    /// Which engine evaluates this policy, see [`crate::engine`].
+    pub fn engine_spec(&self) -> Result<EngineSpec> {
+        match self.section(&["engine"]) {
+            Some(section) => {
+                serde_yaml::from_value(section.clone()).context("Failed to parse engine")
+            }
+            None => Ok(EngineSpec::default()),
+        }
+    }
+
+    fn section(&self, path: &[&str]) -> Option<&Value> {
+        let mut value = self.raw.as_ref()?;
+        for key in path {
+            value = value.get(*key)?;
+        }
+        Some(value)
+    }
+
+    pub fn allowed_network_hosts(&self) -> Vec<String> {
+        self.network
+            .allow
+            .iter()
+            .map(|grant| grant.host.clone())
+            .collect()
+    }
+
+    /// The network the policy puts the container on: `none`, `bridge`,
+    /// `host` or the name of a custom network.
+    pub fn network_mode(&self) -> Option<String> {
+        self.network
+            .policy
+            .as_deref()
+            .map(str::trim)
+            .filter(|mode| !mode.is_empty())
+            .map(str::to_string)
+    }
+
+    /// Whether the allowed hosts are enforced by a resolver sidecar.
+    pub fn filter_dns(&self) -> bool {
+        self.network.filter_dns
+    }
+
+    pub fn dns_filter_image(&self) -> String {
+        self.network
+            .filter_image
+            .clone()
+            .unwrap_or_else(|| crate::dnsfilter::DEFAULT_IMAGE.to_string())
+    }
+
+    /// Ports the firewall sidecar blocks, see [`crate::firewall`].
+    pub fn blocked_ports(&self) -> Vec<BlockedPort> {
+        self.network
+            .blocked_ports
+            .iter()
+            .filter_map(|port| BlockedPort::parse(&port.text()).ok())
+            .collect()
+    }
+
+    pub fn firewall_image(&self) -> String {
+        self.network
+            .firewall_image
+            .clone()
+            .unwrap_or_else(|| crate::firewall::DEFAULT_IMAGE.to_string())
+    }
+
+    /// `--dns`, `--dns-search` and `--dns-option` for the resolvers in
+    /// `permissions.network`, e.g. an internal filtering resolver.
+    pub fn map_dns_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        for (values, flag) in [
+            (&self.network.dns_servers, "--dns"),
+            (&self.network.dns_search, "--dns-search"),
+            (&self.network.dns_options, "--dns-option"),
+        ] {
+            for value in values {
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        }
+        args
+    }
+
+    pub fn allowed_env_keys(&self) -> Vec<String> {
+        self.environment
+            .allow
+            .iter()
+            .map(|grant| grant.key.clone())
+            .collect()
+    }
+
+    /// `-e KEY` for the host variables `permissions.runtime.environment_whitelist`
+    /// passes into the container and the ones `env_mapping` makes. The
+    /// runtime copies the values from its environment, so they do not show
+    /// up in the command line.
+    pub fn map_environment(&self) -> Vec<String> {
+        self.environment_args(std::env::vars())
+    }
+
+    /// Variables `permissions.runtime.env_mapping` makes of the host
+    /// environment, with their values.
+    pub fn mapped_env(&self) -> Result<Vec<(String, String)>> {
+        envmap::map(&self.runtime.env_mapping, std::env::vars())
+    }
+
+    fn environment_args(&self, host: impl IntoIterator<Item = (String, String)>) -> Vec<String> {
+        let host: Vec<(String, String)> = host.into_iter().collect();
+        let mut keys: Vec<String> = host
+            .iter()
+            .map(|(key, _)| key.clone())
+            .filter(|key| self.runtime.whitelists_env(key))
+            .collect();
+        // values are checked before the run starts
+        let mapped = envmap::map(&self.runtime.env_mapping, host).unwrap_or_default();
+        keys.extend(mapped.into_iter().map(|(name, _)| name));
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .flat_map(|key| ["-e".to_string(), key])
+            .collect()
+    }
+
+    /// Host paths hidden even inside mounts, see [`crate::masking`].
+    pub fn blocked_paths(&self) -> Vec<String> {
+        self.storage
+            .blocked_paths
+            .iter()
+            .map(|path| expand_home(path))
+            .collect()
+    }
+
+    /// Host paths granted through `fs://` storage permissions and
+    /// `permissions.storage.allowed_paths`, paired with whether write access
+    /// was granted. A path granted both ways is writable if either grant
+    /// says so.
+    pub fn storage_grants(&self) -> Vec<(String, bool)> {
+        let mut grants: Vec<(String, bool)> = self
+            .storage
+            .allow
+            .iter()
+            .filter_map(|grant| {
+                let path = grant.uri.strip_prefix("fs://")?;
+                Some((path.to_string(), grant.access.contains(&Access::Write)))
+            })
+            .collect();
+        grants.extend(
+            self.storage
+                .allowed_paths
+                .iter()
+                .map(|entry| allowed_path(entry)),
+        );
+        merge_grants(grants)
+    }
+
+    pub fn added_capabilities(&self) -> Vec<String> {
+        self.runtime.docker.security.capabilities.add.clone()
+    }
+
     pub fn map_docker_security_args(&self) -> Vec<String> {
         let mut args = Vec::new();
 
-        if let Some(ref policy) = self.policy {
-            if let Some(ref runtime) = policy.permissions.runtime {
-                if let Some(ref docker) = runtime.docker {
-                    if let Some(ref security) = docker.security {
-                        if let Some(privileged) = security.privileged {
-                            if !privileged {
-                                args.push("--security-opt".to_string());
-                                args.push("no-new-privileges".to_string());
-                            }
-                        }
-                        if let Some(ref capabilities) = security.capabilities {
-                            if let Some(ref drop_caps) = capabilities.drop {
-                                for cap in drop_caps {
-                                    args.push("--cap-drop".to_string());
-                                    args.push(format!("{:?}", cap));
-                                }
-                            }
-                            if let Some(ref add_caps) = capabilities.add {
-                                for cap in add_caps {
-                                    args.push("--cap-add".to_string());
-                                    args.push(format!("{:?}", cap));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+        if self.baseline {
+            args.extend(
+                ["--security-opt", "no-new-privileges", "--cap-drop", "ALL"].map(String::from),
+            );
+        }
+
+        let security = &self.runtime.docker.security;
+        if security.privileged == Some(false) || security.no_new_privileges == Some(true) {
+            args.push("--security-opt".to_string());
+            args.push("no-new-privileges".to_string());
+        }
+        for cap in &security.capabilities.drop {
+            args.push("--cap-drop".to_string());
+            args.push(cap.clone());
+        }
+        for cap in &security.capabilities.add {
+            args.push("--cap-add".to_string());
+            args.push(cap.clone());
         }
         args
     }
 
     pub fn map_file_mounts(&self) -> Vec<String> {
+        let relabel = self
+            .runtime
+            .docker
+            .selinux
+            .as_ref()
+            .and_then(|selinux| selinux.relabel)
+            .map_or(String::new(), |relabel| {
+                format!(",{}", relabel.mount_option())
+            });
         let mut mounts = Vec::new();
+        for (path, writable) in self.storage_grants() {
+            let mode = if writable { "rw" } else { "ro" };
+            mounts.push("-v".to_string());
+            mounts.push(format!("{}:{}:{}{}", path, path, mode, relabel));
+        }
+        mounts
+    }
 
-        if let Some(ref policy) = self.policy {
-            if let Some(ref storage) = policy.permissions.storage {
-                if let Some(ref allow_list) = storage.allow {
-                    for storage_permission in allow_list {
-                        if storage_permission.uri.starts_with("fs://") {
-                            let path = &storage_permission.uri[5..];
-                            let readonly = !storage_permission.access.contains(&AccessType::Write);
-                            let mode = if readonly { "ro" } else { "rw" };
-
-                            mounts.push("-v".to_string());
-                            mounts.push(format!("{}:{}:{}", path, path, mode));
-                        }
-                    }
-                }
-            }
+    pub fn map_named_volumes(&self) -> Vec<String> {
+        let mut mounts = Vec::new();
+        for volume in &self.runtime.docker.volumes {
+            mounts.push("-v".to_string());
+            mounts.push(format!(
+                "{}:{}:{}",
+                volume.name,
+                volume.mount,
+                volume.mode.as_str()
+            ));
         }
         mounts
     }
 
+    pub fn mount_host_paths(&self) -> Vec<String> {
+        self.storage_grants()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect()
+    }
+
+    /// The seccomp profile of `docker.seccomp`, written to the cache.
+    pub fn seccomp_profile(&self) -> Result<Option<PathBuf>> {
+        let Some(ref seccomp) = self.runtime.docker.seccomp else {
+            return Ok(None);
+        };
+        let profile = seccomp.profile(&self.added_capabilities());
+        seccomp::install(&profile).map(Some)
+    }
+
+    /// `--security-opt seccomp=` for the generated profile. Runs check
+    /// [`PolicyConfig::seccomp_profile`] first, so a profile that cannot be
+    /// written has already failed them.
+    pub fn map_seccomp(&self) -> Vec<String> {
+        match self.seccomp_profile() {
+            Ok(Some(path)) => vec![
+                "--security-opt".to_string(),
+                format!("seccomp={}", path.display()),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
     pub fn get_all_docker_args(&self) -> Vec<String> {
         let mut args = Vec::new();
         args.extend(self.map_file_mounts());
+        args.extend(self.map_named_volumes());
         args.extend(self.map_docker_security_args());
+        args.extend(self.runtime.docker.run_args());
+        args.extend(self.map_seccomp());
+        args.extend(self.map_environment());
         args
     }
 }
@@ -91,17 +701,18 @@ impl Default for PolicyConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::spec::{Relabel, SelinuxSpec};
 
     #[test]
     fn test_policy_config_new() {
         let config = PolicyConfig::new();
-        assert!(config.policy.is_none());
+        assert!(!config.is_loaded());
     }
 
     #[test]
     fn test_policy_config_default() {
         let config = PolicyConfig::default();
-        assert!(config.policy.is_none());
+        assert!(!config.is_loaded());
     }
 
     #[test]
@@ -111,6 +722,36 @@ mod tests {
         assert!(args.is_empty());
     }
 
+    #[test]
+    fn test_baseline_hardens_without_grants() {
+        let config = PolicyConfig::baseline();
+        assert!(config.is_baseline());
+        assert!(!config.is_loaded());
+        assert!(config.runtime.validate().is_ok());
+        let args = config.get_all_docker_args();
+        for expected in [
+            ["--cap-drop", "ALL"],
+            ["--security-opt", "no-new-privileges"],
+            ["--pids-limit", "256"],
+            ["--tmpfs", "/root:exec"],
+        ] {
+            assert!(
+                args.windows(2).any(|pair| pair == expected),
+                "{:?} in {:?}",
+                expected,
+                args
+            );
+        }
+        assert!(args.contains(&"--read-only".to_string()));
+        assert!(!PolicyConfig::new().is_baseline());
+        assert!(config.filter_dns());
+        assert_eq!(
+            config.allowed_network_hosts(),
+            ["registry.npmjs.org", "pypi.org", "files.pythonhosted.org"]
+        );
+        assert!(config.check_overrides(&Default::default()).is_empty());
+    }
+
     #[test]
     fn test_map_docker_security_args() {
         let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
@@ -118,8 +759,7 @@ mod tests {
 
         assert!(args.contains(&"--security-opt".to_string()));
         assert!(args.contains(&"no-new-privileges".to_string()));
-        assert!(args.contains(&"--cap-drop".to_string()));
-        assert!(args.iter().any(|arg| arg.contains("All")));
+        assert!(args.windows(2).any(|run| run == ["--cap-drop", "ALL"]));
     }
 
     #[test]
@@ -135,6 +775,15 @@ mod tests {
             .expect("Should contain mount path");
         assert!(mount_arg.contains(":ro"), "Should be read-only mount");
         assert!(mount_arg.contains("/tmp/mcp-filesystem:/tmp/mcp-filesystem:ro"));
+
+        let mut config = config;
+        config.runtime.docker.selinux = Some(SelinuxSpec {
+            relabel: Some(Relabel::Private),
+            ..Default::default()
+        });
+        assert!(config
+            .map_file_mounts()
+            .contains(&"/tmp/mcp-filesystem:/tmp/mcp-filesystem:ro,Z".to_string()));
     }
 
     #[test]
@@ -148,6 +797,273 @@ mod tests {
         assert!(mounts.is_empty());
     }
 
+    #[test]
+    fn test_environment_whitelist_args() {
+        let mut config = PolicyConfig::new();
+        let host = || {
+            [
+                ("GITHUB_TOKEN", "x"),
+                ("HOME", "/root"),
+                ("OPENAI_API_KEY", "y"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        };
+        assert!(config.environment_args(host()).is_empty());
+
+        config.runtime.environment_whitelist =
+            vec!["OPENAI_API_KEY".to_string(), "GITHUB_*".to_string()];
+        assert_eq!(
+            config.environment_args(host()),
+            ["-e", "GITHUB_TOKEN", "-e", "OPENAI_API_KEY"]
+        );
+
+        config.runtime.env_mapping =
+            serde_yaml::from_str("[{from: GITHUB_TOKEN, to: GITHUB_PERSONAL_ACCESS_TOKEN}]")
+                .unwrap();
+        assert_eq!(
+            config.environment_args(host()),
+            [
+                "-e",
+                "GITHUB_PERSONAL_ACCESS_TOKEN",
+                "-e",
+                "GITHUB_TOKEN",
+                "-e",
+                "OPENAI_API_KEY"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_allowed_paths_merge_with_grants() {
+        assert_eq!(allowed_path("/srv/data"), ("/srv/data".to_string(), false));
+        assert_eq!(
+            allowed_path("/srv/data:rw"),
+            ("/srv/data".to_string(), true)
+        );
+        assert_eq!(
+            allowed_path("/srv/data:ro"),
+            ("/srv/data".to_string(), false)
+        );
+        assert_eq!(
+            merge_grants(vec![
+                ("/srv/data".to_string(), false),
+                ("/srv/logs".to_string(), true),
+                ("/srv/data/".to_string(), true),
+                ("/srv/logs".to_string(), false),
+            ]),
+            [
+                ("/srv/data".to_string(), true),
+                ("/srv/logs".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mount_host_paths() {
+        let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+        assert_eq!(config.mount_host_paths(), vec!["/tmp/mcp-filesystem"]);
+        assert!(PolicyConfig::new().mount_host_paths().is_empty());
+    }
+
+    #[test]
+    fn test_raw_sections() {
+        let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
+        assert!(config.allowed_network_hosts().is_empty());
+        assert!(config.allowed_env_keys().is_empty());
+        assert_eq!(
+            config.storage_grants(),
+            vec![("/tmp/mcp-filesystem".to_string(), false)]
+        );
+
+        let config = PolicyConfig::from_file("../samples/github/policy.yaml").unwrap();
+        assert_eq!(config.allowed_network_hosts(), vec!["api.github.com"]);
+        assert_eq!(
+            config.allowed_env_keys(),
+            vec!["GITHUB_PERSONAL_ACCESS_TOKEN"]
+        );
+    }
+
+    #[test]
+    fn test_from_files_layers_policies() {
+        let config = PolicyConfig::from_files(&[
+            "testdata/policy.yaml".to_string(),
+            "testdata/layer-team.yaml".to_string(),
+            "testdata/layer-project.yaml".to_string(),
+        ])
+        .unwrap();
+
+        assert_eq!(config.sources().len(), 3);
+        assert_eq!(config.allowed_network_hosts(), vec!["api.github.com"]);
+        assert_eq!(
+            config.storage_grants(),
+            vec![
+                ("/tmp/mcp-filesystem".to_string(), false),
+                ("/tmp/mcp-workspace".to_string(), true),
+            ]
+        );
+        assert!(config
+            .map_docker_security_args()
+            .contains(&"no-new-privileges".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_layer_is_rejected() {
+        let overlay = "testdata/layer-invalid.yaml".to_string();
+        assert!(PolicyConfig::from_file(&overlay).is_err());
+        let error =
+            PolicyConfig::from_files(&["testdata/policy.yaml".to_string(), overlay]).unwrap_err();
+        assert!(format!("{:#}", error).contains("Failed to parse merged policy"));
+    }
+
+    #[test]
+    fn test_from_files_single_and_empty() {
+        assert!(!PolicyConfig::from_files(&[]).unwrap().is_loaded());
+
+        let config = PolicyConfig::from_files(&["testdata/policy.yaml".to_string()]).unwrap();
+        assert_eq!(config.sources(), ["testdata/policy.yaml"]);
+    }
+
+    #[test]
+    fn test_extends_layers_parents_first() {
+        let config =
+            PolicyConfig::from_files(&["testdata/layer-extends.yaml".to_string()]).unwrap();
+        assert_eq!(config.sources(), ["testdata/layer-extends.yaml"]);
+        let files: Vec<&str> = config
+            .files()
+            .iter()
+            .map(|file| file.source.as_str())
+            .collect();
+        assert_eq!(
+            files,
+            [
+                "testdata/policy.yaml",
+                "testdata/layer-team.yaml",
+                "testdata/layer-extends.yaml"
+            ]
+        );
+        assert!(config.document().unwrap().get("extends").is_none());
+        assert_eq!(config.allowed_network_hosts(), vec!["api.github.com"]);
+        assert_eq!(
+            config.storage_grants(),
+            vec![
+                ("/tmp/mcp-filesystem".to_string(), false),
+                ("/tmp/mcp-workspace".to_string(), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_fail_unless_lenient() {
+        let paths = ["testdata/typo-policy.yaml".to_string()];
+        let error = format!("{:#}", PolicyConfig::from_files(&paths).unwrap_err());
+        assert!(error.contains(
+            "5:5: permissions.runtime.idle_timout: unknown field (did you mean `idle_timeout`?)"
+        ));
+        let lenient = LoadOptions {
+            lenient: true,
+            ..LoadOptions::default()
+        };
+        let config = PolicyConfig::from_files_with(&paths, lenient).unwrap();
+        assert!(config.runtime.idle_timeout.is_none());
+    }
+
+    #[test]
+    fn test_environment_variables_are_expanded() {
+        let paths = ["testdata/env-policy.yaml".to_string()];
+        let config = PolicyConfig::from_files(&paths).unwrap();
+        assert_eq!(
+            config.storage_grants(),
+            vec![("/tmp/mcp-data".to_string(), false)]
+        );
+        let description = config.document().unwrap().get("description").cloned();
+        assert_eq!(
+            description.as_ref().and_then(Value::as_str),
+            Some("Storage under ${ENV:SEMCP_TEST_UNSET_ROOT}")
+        );
+
+        let strict_env = LoadOptions {
+            strict_env: true,
+            ..LoadOptions::default()
+        };
+        let error = format!(
+            "{:#}",
+            PolicyConfig::from_files_with(&paths, strict_env).unwrap_err()
+        );
+        assert!(error.contains("uses unset variables: SEMCP_TEST_UNSET_ROOT"));
+    }
+
+    #[test]
+    fn test_network_mode() {
+        assert_eq!(PolicyConfig::new().network_mode(), None);
+        let config = PolicyConfig::from_file("testdata/network-policy.yaml").unwrap();
+        assert_eq!(config.network_mode().as_deref(), Some("mcp-egress"));
+    }
+
+    #[test]
+    fn test_map_dns_args() {
+        assert!(PolicyConfig::new().map_dns_args().is_empty());
+        let config = PolicyConfig::from_file("testdata/network-policy.yaml").unwrap();
+        assert_eq!(
+            config.map_dns_args(),
+            [
+                "--dns",
+                "10.0.0.53",
+                "--dns",
+                "10.0.1.53",
+                "--dns-search",
+                "corp.example",
+                "--dns-option",
+                "ndots:1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_dns_server_is_rejected() {
+        let raw: Value =
+            serde_yaml::from_str("permissions: {network: {dns_servers: [resolver.corp]}}").unwrap();
+        assert!(parse_permissions(&raw).is_err());
+    }
+
+    #[test]
+    fn test_blocked_ports() {
+        let config = PolicyConfig::from_file("testdata/dns-filter-policy.yaml").unwrap();
+        let ports: Vec<String> = config
+            .blocked_ports()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(ports, ["25", "6379/tcp"]);
+
+        let raw: Value =
+            serde_yaml::from_str("permissions: {network: {blocked_ports: [70000]}}").unwrap();
+        assert!(parse_permissions(&raw).is_err());
+        let raw: Value =
+            serde_yaml::from_str("permissions: {network: {blocked_ports: [\"70000\"]}}").unwrap();
+        assert!(parse_permissions(&raw).is_err());
+    }
+
+    #[test]
+    fn test_map_named_volumes() {
+        let config = PolicyConfig::from_file("testdata/volumes-policy.yaml").unwrap();
+        let args = config.map_named_volumes();
+
+        assert_eq!(
+            args,
+            vec![
+                "-v",
+                "mcp-memory:/data:rw",
+                "-v",
+                "mcp-knowledge:/knowledge:ro"
+            ]
+        );
+        assert_eq!(
+            config.runtime.docker.volumes[0].size.as_deref(),
+            Some("1GiB")
+        );
+        assert!(PolicyConfig::new().map_named_volumes().is_empty());
+    }
+
     #[test]
     fn test_privileged_false_generates_security_opt() {
         let config = PolicyConfig::from_file("testdata/policy.yaml").unwrap();
@@ -160,4 +1076,16 @@ mod tests {
             assert_eq!(args.get(pos + 1), Some(&"no-new-privileges".to_string()));
         }
     }
+
+    #[test]
+    fn test_no_new_privileges_alone() {
+        let mut config = PolicyConfig::new();
+        config.runtime.docker.security.no_new_privileges = Some(true);
+        assert_eq!(
+            config.map_docker_security_args(),
+            ["--security-opt", "no-new-privileges"]
+        );
+        config.runtime.docker.security.privileged = Some(false);
+        assert_eq!(config.map_docker_security_args().len(), 2);
+    }
 }
@@ -0,0 +1,240 @@
+//! Test cases for policies, for `snpx policy test`.
+//!
+//! A case file names the policies under test, relative to the file, and
+//! cases of what a command line asks for and how the policy should answer:
+//!
+//! ```yaml
+//! policies: [snpx.yaml]
+//! tests:
+//! - name: no host network
+//!   network: host
+//!   expect: deny
+//! - name: the data directory can be mounted read-only
+//!   package: "@modelcontextprotocol/server-filesystem"
+//!   volumes: [/srv/mcp-data:/data:ro]
+//!   expect: allow
+//! - name: capabilities are dropped
+//!   docker_args: [--cap-drop, ALL]
+//!   not_docker_args: [--privileged]
+//! ```
+//!
+//! Cases are decided by the policy's engine, as a run would be, so they
+//! hold for Rego and Cedar policies too.
+
+use crate::artifacts;
+use crate::cli::ContainerArgs;
+use crate::engine::{self, PolicyEngine};
+use crate::presets::Preset;
+use crate::PolicyConfig;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestFile {
+    /// Policies layered in order, the built-in baseline without any.
+    pub policies: Vec<String>,
+    pub tests: Vec<TestCase>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TestCase {
+    pub name: String,
+    pub package: Option<String>,
+    /// What the command line asks for, as `--network`, `-e`, `-v` and
+    /// `--cap-add`.
+    pub network: Option<String>,
+    pub env: Vec<String>,
+    pub volumes: Vec<String>,
+    pub cap_add: Vec<String>,
+    /// Whether the policy grants the request, unchecked without.
+    pub expect: Option<Expect>,
+    /// Arguments the container must be run with, in this order.
+    pub docker_args: Vec<String>,
+    /// Arguments, or runs of them, the container must not be run with.
+    pub not_docker_args: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expect {
+    Allow,
+    Deny,
+}
+
+/// A case and why it failed, passed when there is no failure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub name: String,
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Whether `expected` appears in `args` as a run of consecutive arguments.
+fn contains_run(args: &[String], expected: &[String]) -> bool {
+    expected.is_empty() || args.windows(expected.len()).any(|run| run == expected)
+}
+
+impl TestCase {
+    fn args(&self) -> ContainerArgs {
+        ContainerArgs {
+            network: self.network.clone(),
+            env: self.env.clone(),
+            volumes: self.volumes.clone(),
+            cap_add: self.cap_add.clone(),
+            ..ContainerArgs::default()
+        }
+    }
+
+    /// Decides the case with `engine`.
+    pub fn run(&self, engine: &dyn PolicyEngine) -> Outcome {
+        let mut failures = Vec::new();
+        let denials = engine.check_overrides(&self.args(), self.package.as_deref());
+        match self.expect {
+            Some(Expect::Allow) => failures.extend(
+                denials
+                    .iter()
+                    .map(|denial| format!("expected allow, {}", denial)),
+            ),
+            Some(Expect::Deny) if denials.is_empty() => {
+                failures.push("expected deny, the policy allows it".to_string())
+            }
+            _ => {}
+        }
+        let docker_args = engine.docker_args();
+        if !contains_run(&docker_args, &self.docker_args) {
+            failures.push(format!(
+                "the container is not run with {}",
+                self.docker_args.join(" ")
+            ));
+        }
+        for arg in &self.not_docker_args {
+            let run: Vec<String> = arg.split_whitespace().map(str::to_string).collect();
+            if !run.is_empty() && contains_run(&docker_args, &run) {
+                failures.push(format!("the container is run with {}", arg));
+            }
+        }
+        Outcome {
+            name: self.name.clone(),
+            failures,
+        }
+    }
+}
+
+/// Runs the cases in the file at `path` against the policies it names.
+pub fn run_file(path: &str) -> Result<Vec<Outcome>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let file: TestFile =
+        serde_yaml::from_str(&text).with_context(|| format!("Failed to parse {}", path))?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let policies: Vec<String> = file
+        .policies
+        .iter()
+        .map(|policy| {
+            if artifacts::is_remote(policy) || Preset::from_source(policy).is_some() {
+                policy.clone()
+            } else {
+                dir.join(policy).to_string_lossy().into_owned()
+            }
+        })
+        .collect();
+    let policy = if policies.is_empty() {
+        PolicyConfig::baseline()
+    } else {
+        PolicyConfig::from_files(&policies)?
+    };
+    let engine = engine::from_policy(&policy)?;
+    Ok(file
+        .tests
+        .iter()
+        .map(|case| case.run(engine.as_ref()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overrides::Denial;
+
+    /// Refuses the host network and runs with every capability dropped.
+    #[derive(Debug)]
+    struct NoHostNetwork;
+
+    impl PolicyEngine for NoHostNetwork {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn check_overrides(&self, args: &ContainerArgs, _package: Option<&str>) -> Vec<Denial> {
+            match args.network.as_deref() {
+                Some("host") => vec![Denial {
+                    requested: "--network host".to_string(),
+                    clause: "permissions.network".to_string(),
+                    reason: "host networking is not allowed".to_string(),
+                }],
+                _ => vec![],
+            }
+        }
+
+        fn docker_args(&self) -> Vec<String> {
+            ["--cap-drop", "ALL", "--read-only"]
+                .map(String::from)
+                .to_vec()
+        }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_cases() {
+        let denied = TestCase {
+            name: "no host network".to_string(),
+            network: Some("host".to_string()),
+            expect: Some(Expect::Deny),
+            ..TestCase::default()
+        };
+        assert!(denied.run(&NoHostNetwork).passed());
+
+        let wrong = TestCase {
+            expect: Some(Expect::Allow),
+            ..denied.clone()
+        };
+        assert_eq!(
+            wrong.run(&NoHostNetwork).failures,
+            ["expected allow, --network host: denied by `permissions.network` (host networking is not allowed)"]
+        );
+
+        let args = TestCase {
+            name: "hardened".to_string(),
+            docker_args: strings(&["--cap-drop", "ALL"]),
+            not_docker_args: strings(&["--privileged", "--cap-drop ALL"]),
+            ..TestCase::default()
+        };
+        assert_eq!(
+            args.run(&NoHostNetwork).failures,
+            ["the container is run with --cap-drop ALL"]
+        );
+
+        let missing = TestCase {
+            docker_args: strings(&["ALL", "--cap-drop"]),
+            expect: Some(Expect::Deny),
+            ..TestCase::default()
+        };
+        assert_eq!(
+            missing.run(&NoHostNetwork).failures,
+            [
+                "expected deny, the policy allows it",
+                "the container is not run with ALL --cap-drop",
+            ]
+        );
+    }
+}
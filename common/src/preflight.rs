@@ -0,0 +1,293 @@
+//! Checks before a run starts.
+//!
+//! Registry `server.json` files list the environment variables each package
+//! needs. Checking them against what will actually be injected turns a
+//! server crashing on a missing token into an error before the container
+//! starts.
+//!
+//! The startup checks do not depend on each other, so [`ContainerArgs::preflight`]
+//! loads the policy, asks the runtime whether it is there and reads the
+//! registry metadata at the same time; a cold start waits for the slowest of
+//! them, not for their sum.
+
+use crate::cli::ContainerArgs;
+use crate::config::UserConfig;
+use crate::engine::{self, PolicyEngine};
+use crate::policy::LoadOptions;
+use crate::state::package_identity;
+use crate::{runtime, ContainerExecutor, PolicyConfig};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerMetadata {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<PackageMetadata>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PackageMetadata {
+    #[serde(default, alias = "identifier")]
+    pub name: Option<String>,
+    #[serde(default, alias = "environmentVariables")]
+    pub environment_variables: Vec<EnvVarSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct EnvVarSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default, alias = "isRequired")]
+    pub is_required: bool,
+    #[serde(default)]
+    pub default: Option<String>,
+}
+
+impl ServerMetadata {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read server metadata {}", path))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse server metadata {}", path))
+    }
+
+    /// The entry for `package`, or the only entry when there is just one.
+    pub fn package(&self, package: &str) -> Option<&PackageMetadata> {
+        let identity = package_identity(package);
+        self.packages
+            .iter()
+            .find(|entry| entry.name.as_deref() == Some(identity.as_str()))
+            .or(match self.packages.as_slice() {
+                [only] => Some(only),
+                _ => None,
+            })
+    }
+
+    /// Variables `package` cannot start without.
+    pub fn required_env(&self, package: &str) -> Vec<&EnvVarSpec> {
+        self.package(package)
+            .map(|entry| {
+                entry
+                    .environment_variables
+                    .iter()
+                    .filter(|var| var.is_required && var.default.is_none())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Keys that `-e` arguments will set in the container. `-e KEY` copies the
+/// host value, so it only counts when the host has it.
+pub fn injected_env_keys(env: &[String]) -> HashSet<String> {
+    env.iter()
+        .filter_map(|entry| match entry.split_once('=') {
+            Some((key, _)) => Some(key.to_string()),
+            None if std::env::var_os(entry).is_some() => Some(entry.clone()),
+            None => None,
+        })
+        .collect()
+}
+
+pub fn missing_env<'a>(
+    required: &[&'a EnvVarSpec],
+    injected: &HashSet<String>,
+) -> Vec<&'a EnvVarSpec> {
+    required
+        .iter()
+        .filter(|var| !injected.contains(&var.name))
+        .copied()
+        .collect()
+}
+
+/// What the startup checks found.
+pub struct Startup {
+    pub policy: PolicyConfig,
+    pub engine: Arc<dyn PolicyEngine>,
+    /// Whether the container runtime is installed and answers.
+    pub runtime_available: bool,
+}
+
+/// Loads the policy layers and the engine they select, which validates
+/// engine policies.
+fn load_policy(
+    policies: &[String],
+    options: LoadOptions,
+) -> Result<(PolicyConfig, Arc<dyn PolicyEngine>)> {
+    let policy = PolicyConfig::from_files_with(policies, options)?;
+    let engine = engine::from_policy(&policy)?;
+    Ok((policy, engine))
+}
+
+/// [`PolicyConfig::baseline`] and the engine for it, which is what puts
+/// its arguments on the command line.
+pub(crate) fn baseline_policy() -> Result<(PolicyConfig, Arc<dyn PolicyEngine>)> {
+    let policy = PolicyConfig::baseline();
+    let engine = engine::from_policy(&policy)?;
+    Ok((policy, engine))
+}
+
+impl ContainerArgs {
+    /// Loads `policies` while probing the selected runtime, then checks the
+    /// required environment of `packages`, which the policy can forward.
+    /// Errors are reported in that order. The `--preset`, then the
+    /// `policy_url` of the user's [`UserConfig`], come before `policies`. Without policies, containers get
+    /// [`PolicyConfig::baseline`] unless `--insecure-defaults` is given; the
+    /// host sandboxes have their own hardening.
+    pub async fn preflight(&self, policies: &[String], packages: &[String]) -> Result<Startup> {
+        let runtime = runtime::current();
+        let available = tokio::task::spawn_blocking(move || runtime.is_available());
+        let config = UserConfig::load()?;
+        let policies: Vec<String> = self
+            .preset
+            .map(|preset| preset.source())
+            .into_iter()
+            .chain(config.policies(policies))
+            .collect();
+        let no_policy = policies.is_empty();
+        let mut signing = config.policy_signing;
+        signing.require |= self.require_signed_policy;
+        signing.validate()?;
+        let options = LoadOptions {
+            lenient: self.lenient_policy,
+            strict_env: self.strict_env,
+            offline: self.offline,
+            signing,
+        };
+        let policy = tokio::task::spawn_blocking(move || load_policy(&policies, options));
+
+        let (mut policy, mut engine) = policy.await.context("Failed to load the policy")??;
+        for package in packages {
+            self.check_required_env(Some(package), &policy)?;
+        }
+        let runtime_available = available.await.unwrap_or(false);
+        if no_policy && runtime_available && !self.native_sandbox && !self.insecure_defaults {
+            (policy, engine) = baseline_policy()?;
+        }
+        Ok(Startup {
+            policy,
+            engine,
+            runtime_available,
+        })
+    }
+
+    /// Fails when the `--server-json` metadata declares required variables
+    /// for `package` that neither `-e` nor the policy's
+    /// `environment_whitelist` will set.
+    pub fn check_required_env(
+        &self,
+        package: Option<&String>,
+        policy: &PolicyConfig,
+    ) -> Result<()> {
+        let (Some(path), Some(package)) = (self.server_json.as_ref(), package) else {
+            return Ok(());
+        };
+        let metadata = ServerMetadata::from_file(path)?;
+        let required = metadata.required_env(package);
+        let mut injected = injected_env_keys(&self.env);
+        injected.extend(
+            std::env::vars()
+                .map(|(key, _)| key)
+                .filter(|key| policy.runtime.whitelists_env(key)),
+        );
+        let missing = missing_env(&required, &injected);
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "{} requires environment variables that are not set:",
+            package
+        );
+        for var in missing {
+            message.push_str(&format!("\n  {}", var.name));
+            if let Some(ref description) = var.description {
+                message.push_str(&format!(" - {}", description));
+            }
+        }
+        message.push_str(
+            "\nPass them with -e KEY=VALUE, or -e KEY to copy them from your environment, or list them in permissions.runtime.environment_whitelist",
+        );
+        bail!(message)
+    }
+}
+
+impl ContainerExecutor {
+    /// Pulls the image unless it is present, so the pull overlaps the other
+    /// preparations instead of starting with the container.
+    pub(crate) async fn ensure_image(&self) -> Result<()> {
+        if self.runtime.inspect_image(&self.docker_image).await? {
+            return Ok(());
+        }
+        if self.verbose {
+            eprintln!("Pulling {}", self.docker_image);
+        }
+        crate::pull::pull(self.runtime.as_ref(), &self.docker_image, self.verbose)
+            .await
+            .with_context(|| format!("Failed to pull {}", self.docker_image))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SERVER_JSON: &str = r#"{
+        "name": "io.github.modelcontextprotocol/server-github",
+        "packages": [{
+            "registry_name": "npm",
+            "name": "@modelcontextprotocol/server-github",
+            "environment_variables": [
+                {"name": "GITHUB_PERSONAL_ACCESS_TOKEN", "description": "GitHub token", "is_required": true},
+                {"name": "GITHUB_HOST", "is_required": true, "default": "github.com"},
+                {"name": "GITHUB_TOOLSETS"}
+            ]
+        }]
+    }"#;
+
+    #[test]
+    fn test_required_env() {
+        let metadata: ServerMetadata = serde_json::from_str(SERVER_JSON).unwrap();
+        let required = metadata.required_env("@modelcontextprotocol/server-github@1.0.0");
+        assert_eq!(required.len(), 1);
+        assert_eq!(required[0].name, "GITHUB_PERSONAL_ACCESS_TOKEN");
+    }
+
+    #[test]
+    fn test_missing_env() {
+        let metadata: ServerMetadata = serde_json::from_str(SERVER_JSON).unwrap();
+        let required = metadata.required_env("@modelcontextprotocol/server-github");
+
+        let injected = injected_env_keys(&["GITHUB_PERSONAL_ACCESS_TOKEN=ghp_x".to_string()]);
+        assert!(missing_env(&required, &injected).is_empty());
+
+        let injected = injected_env_keys(&["SEMCP_TEST_SURELY_UNSET_VAR".to_string()]);
+        assert_eq!(missing_env(&required, &injected).len(), 1);
+    }
+
+    #[test]
+    fn test_load_policy_selects_the_engine() {
+        let (policy, engine) = load_policy(&[], LoadOptions::default()).unwrap();
+        assert!(!policy.is_loaded());
+        assert_eq!(engine.name(), "yaml");
+        assert!(load_policy(
+            &["testdata/missing.yaml".to_string()],
+            LoadOptions::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_camel_case_registry_fields() {
+        let metadata: ServerMetadata = serde_json::from_str(
+            r#"{"packages": [{"identifier": "mcp-server-time", "environmentVariables": [{"name": "TZ", "isRequired": true}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.required_env("mcp-server-time")[0].name, "TZ");
+    }
+}
@@ -0,0 +1,170 @@
+//! Policy files to start from, for `snpx policy init`, and policies to run
+//! with, for `--preset` and `--policy preset:<name>`.
+//!
+//! Each preset is a commented policy that works as it is and explains the
+//! sections a user is most likely to change. All of them drop every
+//! capability; they differ in network access, limits and hardening.
+
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use serde_yaml::{Mapping, Value};
+
+/// Prefix of policy sources that name a preset, as in `preset:strict`.
+pub const PRESET_PREFIX: &str = "preset:";
+
+/// Hosts uv installs from, allowed wherever a preset allows npm's.
+const PYPI_HOSTS: [&str; 2] = ["pypi.org", "files.pythonhosted.org"];
+const NPM_REGISTRY: &str = "registry.npmjs.org";
+const NPM_CACHE: &str = "/root/.npm";
+const UV_CACHE: &str = "/root/.cache/uv";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// No network once installed, read-only root, strict seccomp.
+    Strict,
+    /// The npm registry only, read-only root, standard seccomp.
+    #[value(alias = "balanced")]
+    Standard,
+    /// The npm registry, writable root and no limits but memory.
+    #[value(alias = "permissive")]
+    Dev,
+}
+
+impl Preset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Strict => "strict",
+            Preset::Standard => "standard",
+            Preset::Dev => "dev",
+        }
+    }
+
+    /// The preset called `name`, or by its alias.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "strict" => Some(Preset::Strict),
+            "standard" | "balanced" => Some(Preset::Standard),
+            "dev" | "permissive" => Some(Preset::Dev),
+            _ => None,
+        }
+    }
+
+    /// The policy source naming the preset, e.g. `preset:strict`.
+    pub fn source(&self) -> String {
+        format!("{}{}", PRESET_PREFIX, self.name())
+    }
+
+    /// The preset a `preset:<name>` source names, `None` for other sources.
+    pub fn from_source(source: &str) -> Option<Result<Self>> {
+        let name = source.strip_prefix(PRESET_PREFIX)?;
+        Some(Self::from_name(name).ok_or_else(|| {
+            anyhow!(
+                "Unknown preset {}, expected strict, balanced (standard) or permissive (dev)",
+                name
+            )
+        }))
+    }
+
+    /// The preset as a policy layer, for snpx and suvx alike: where it
+    /// allows the npm registry it allows PyPI too, and uv gets a cache
+    /// where npx does.
+    pub fn layer(&self) -> Result<Value> {
+        let mut policy: Value = serde_yaml::from_str(self.template())
+            .with_context(|| format!("Failed to parse the {} preset", self.name()))?;
+        let Some(permissions) = policy.get_mut("permissions") else {
+            return Ok(policy);
+        };
+        if let Some(allow) = permissions
+            .get_mut("network")
+            .and_then(|network| network.get_mut("allow"))
+            .and_then(Value::as_sequence_mut)
+        {
+            if allow
+                .iter()
+                .any(|rule| rule.get("host").and_then(Value::as_str) == Some(NPM_REGISTRY))
+            {
+                for host in PYPI_HOSTS {
+                    let mut rule = Mapping::new();
+                    rule.insert(Value::from("host"), Value::from(host));
+                    allow.push(Value::Mapping(rule));
+                }
+            }
+        }
+        if let Some(tmpfs) = permissions
+            .get_mut("runtime")
+            .and_then(|runtime| runtime.get_mut("docker"))
+            .and_then(|docker| docker.get_mut("tmpfs"))
+            .and_then(Value::as_sequence_mut)
+        {
+            if tmpfs.iter().any(|mount| mount.as_str() == Some(NPM_CACHE)) {
+                tmpfs.push(Value::from(UV_CACHE));
+            }
+        }
+        Ok(policy)
+    }
+
+    /// The commented policy file of the preset.
+    pub fn template(&self) -> &'static str {
+        match self {
+            Preset::Strict => include_str!("../data/presets/strict.yaml"),
+            Preset::Standard => include_str!("../data/presets/standard.yaml"),
+            Preset::Dev => include_str!("../data/presets/dev.yaml"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lint;
+
+    #[test]
+    fn test_presets_are_valid_policies() {
+        for preset in [Preset::Strict, Preset::Standard, Preset::Dev] {
+            assert_eq!(
+                lint::check(preset.template()),
+                [],
+                "{} preset",
+                preset.name()
+            );
+            let policy: serde_yaml::Value = serde_yaml::from_str(preset.template()).unwrap();
+            let dropped =
+                &policy["permissions"]["runtime"]["docker"]["security"]["capabilities"]["drop"];
+            assert_eq!(dropped[0].as_str(), Some("ALL"), "{} preset", preset.name());
+        }
+    }
+
+    #[test]
+    fn test_preset_sources() {
+        assert_eq!(
+            Preset::from_source("preset:balanced").unwrap().unwrap(),
+            Preset::Standard
+        );
+        assert_eq!(Preset::Dev.source(), "preset:dev");
+        assert_eq!(
+            Preset::from_source(&Preset::Strict.source())
+                .unwrap()
+                .unwrap(),
+            Preset::Strict
+        );
+        assert!(Preset::from_source("preset:lax").unwrap().is_err());
+        assert!(Preset::from_source("policy.yaml").is_none());
+    }
+
+    #[test]
+    fn test_preset_layers_work_for_uv() {
+        let layer = Preset::Standard.layer().unwrap();
+        let hosts: Vec<&str> = layer["permissions"]["network"]["allow"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|rule| rule["host"].as_str())
+            .collect();
+        assert_eq!(
+            hosts,
+            ["registry.npmjs.org", "pypi.org", "files.pythonhosted.org"]
+        );
+        let tmpfs = &layer["permissions"]["runtime"]["docker"]["tmpfs"];
+        assert_eq!(tmpfs[2].as_str(), Some("/root/.cache/uv"));
+    }
+}
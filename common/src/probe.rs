@@ -0,0 +1,225 @@
+use crate::{ContainerExecutor, Runner};
+use anyhow::{Context, Result};
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct ProbeCheck {
+    pub name: String,
+    pub error: Option<String>,
+}
+
+/// Outcome of a dry-run probe. Every check is run even when an earlier
+/// one fails so that all problems can be reported at once.
+#[derive(Debug, Clone, Default)]
+pub struct ProbeReport {
+    pub checks: Vec<ProbeCheck>,
+}
+
+impl ProbeReport {
+    pub fn record(&mut self, name: impl Into<String>, result: Result<(), String>) {
+        self.checks.push(ProbeCheck {
+            name: name.into(),
+            error: result.err(),
+        });
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &ProbeCheck> {
+        self.checks.iter().filter(|check| check.error.is_some())
+    }
+}
+
+impl fmt::Display for ProbeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match check.error {
+                Some(ref error) => writeln!(f, "[FAIL] {}: {}", check.name, error)?,
+                None => writeln!(f, "[ ok ] {}", check.name)?,
+            }
+        }
+        let failed = self.failures().count();
+        if failed == 0 {
+            write!(f, "probe passed ({} checks)", self.checks.len())
+        } else {
+            write!(
+                f,
+                "probe failed ({} of {} checks)",
+                failed,
+                self.checks.len()
+            )
+        }
+    }
+}
+
+pub fn check_mount_sources(paths: &[String]) -> Result<(), String> {
+    let missing: Vec<&str> = paths
+        .iter()
+        .filter(|path| !Path::new(path).exists())
+        .map(|path| path.as_str())
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("missing on host: {}", missing.join(", ")))
+    }
+}
+
+pub(crate) fn last_line(output: &[u8]) -> String {
+    String::from_utf8_lossy(output)
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .unwrap_or("no output")
+        .trim()
+        .to_string()
+}
+
+impl ContainerExecutor {
+    /// Validates the image, policy and entry command without starting the
+    /// MCP server. The container is started with the runner's command and
+    /// `--version` so that no package is installed or executed.
+    pub async fn probe<R: Runner>(&self, runner: &R) -> Result<ProbeReport> {
+        let mut report = ProbeReport::default();
+
+        report.record(
+            "image variant",
+            self.check_variant(runner, &runner.default_flags())
+                .map_err(|e| e.to_string()),
+        );
+
+        report.record(
+            "runtime class",
+            self.check_runtime_class().await.map_err(|e| e.to_string()),
+        );
+
+        let image_ready = self.probe_image().await?;
+        report.record(format!("image {}", self.docker_image), image_ready.clone());
+
+        report.record(
+            "policy mount sources",
+            check_mount_sources(&self.policy_config.mount_host_paths()),
+        );
+
+        let entry = format!("container start and `{}` entry command", runner.command());
+        if image_ready.is_ok() {
+            report.record(entry, self.probe_container(runner).await?);
+        } else {
+            report.record(entry, Err("skipped, image is not available".to_string()));
+        }
+
+        Ok(report)
+    }
+
+    async fn probe_image(&self) -> Result<Result<(), String>> {
+        if self.runtime.inspect_image(&self.docker_image).await? {
+            return Ok(Ok(()));
+        }
+
+        if self.verbose {
+            eprintln!("Pulling image: {}", self.docker_image);
+        }
+        Ok(
+            crate::pull::pull(self.runtime.as_ref(), &self.docker_image, self.verbose)
+                .await
+                .map_err(|e| format!("pull failed: {}", e)),
+        )
+    }
+
+    async fn probe_container<R: Runner>(&self, runner: &R) -> Result<Result<(), String>> {
+        let mut docker_args = vec![
+            "--rm".to_string(),
+            "--entrypoint".to_string(),
+            runner.command().to_string(),
+        ];
+        docker_args.extend(self.runtime.run_args());
+        docker_args.extend(self.engine.docker_args());
+        docker_args.extend(self.extra_args.iter().cloned());
+        docker_args.extend(runner.additional_docker_args());
+        docker_args.push(self.docker_image.clone());
+        docker_args.push("--version".to_string());
+
+        if self.verbose {
+            eprintln!("Probing: {} run {}", self.runtime, docker_args.join(" "));
+        }
+
+        let output = self
+            .runtime
+            .run(&docker_args)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {} run", self.runtime))?;
+
+        // docker and podman reserve 125-127 for their own failures, anything else comes
+        // from the entry command itself
+        Ok(match output.status.code() {
+            Some(0) => Ok(()),
+            Some(125) => Err(format!(
+                "{} rejected the container configuration: {}",
+                self.runtime,
+                last_line(&output.stderr)
+            )),
+            Some(126) => Err(format!(
+                "`{}` is not executable in the image",
+                runner.command()
+            )),
+            Some(127) => Err(format!("`{}` was not found in the image", runner.command())),
+            _ => Err(format!(
+                "`{} --version` failed: {}",
+                runner.command(),
+                last_line(&output.stderr)
+            )),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_passes_without_failures() {
+        let mut report = ProbeReport::default();
+        report.record("image", Ok(()));
+        report.record("mounts", Ok(()));
+
+        assert!(report.passed());
+        assert_eq!(report.failures().count(), 0);
+    }
+
+    #[test]
+    fn test_report_collects_all_failures() {
+        let mut report = ProbeReport::default();
+        report.record("image", Err("pull failed".to_string()));
+        report.record("mounts", Ok(()));
+        report.record("entry", Err("skipped".to_string()));
+
+        assert!(!report.passed());
+        assert_eq!(report.failures().count(), 2);
+
+        let output = report.to_string();
+        assert!(output.contains("[FAIL] image: pull failed"));
+        assert!(output.contains("[ ok ] mounts"));
+        assert!(output.contains("probe failed (2 of 3 checks)"));
+    }
+
+    #[test]
+    fn test_check_mount_sources() {
+        let mut paths = vec![std::env::temp_dir().to_string_lossy().to_string()];
+        assert!(check_mount_sources(&paths).is_ok());
+
+        paths.push("/nonexistent/semcp-probe-path".to_string());
+        let error = check_mount_sources(&paths).unwrap_err();
+        assert!(error.contains("/nonexistent/semcp-probe-path"));
+    }
+
+    #[test]
+    fn test_last_line_skips_blank_lines() {
+        assert_eq!(last_line(b"first\nsecond\n\n"), "second");
+        assert_eq!(last_line(b""), "no output");
+    }
+}
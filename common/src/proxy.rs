@@ -0,0 +1,1217 @@
+use crate::audit::{self, AuditSpec};
+use crate::budget::{Budget, BudgetSpec};
+use crate::engine::PolicyEngine;
+use crate::pump::Pump;
+use crate::refusal::Refusal;
+use crate::roots::{self, PathMap};
+use crate::trace::TraceContext;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::Child;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Client lines read ahead of the server.
+const CLIENT_BACKLOG: usize = 64;
+
+/// Options for the stdio proxy placed between the MCP client and the
+/// containerized server.
+#[derive(Debug, Clone)]
+pub struct ProxyOptions {
+    /// Answer the client's `initialize` request immediately instead of
+    /// waiting for the package to be installed and the server to start.
+    pub eager_initialize: bool,
+    /// How often pending requests carrying a progress token receive a
+    /// progress notification while the server is still starting.
+    pub keepalive_interval: Duration,
+    /// Stop the session when no messages have passed in either direction
+    /// and no request is outstanding for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Refuse tool calls beyond this budget, see [`crate::budget`].
+    pub budget: Option<BudgetSpec>,
+    /// Translate paths between host and container, see [`crate::roots`].
+    pub paths: Option<PathMap>,
+    /// Policy files in force, named in refusals.
+    pub policies: Vec<String>,
+    /// Decides tool calls, for engines that do, see [`crate::engine`].
+    pub engine: Option<Arc<dyn PolicyEngine>>,
+    /// Methods of idempotent setup requests, such as `logging/setLevel`,
+    /// sent again to a restarted server after the handshake.
+    pub replay_methods: Vec<String>,
+    /// Give requests a trace context and audit tool calls with it, see
+    /// [`crate::trace`].
+    pub trace: bool,
+    /// Where tool calls are audited, and whether they are.
+    pub audit: AuditSpec,
+}
+
+impl Default for ProxyOptions {
+    fn default() -> Self {
+        Self {
+            eager_initialize: false,
+            keepalive_interval: Duration::from_secs(10),
+            idle_timeout: None,
+            budget: None,
+            paths: None,
+            policies: Vec::new(),
+            engine: None,
+            replay_methods: Vec::new(),
+            trace: false,
+            audit: AuditSpec::default(),
+        }
+    }
+}
+
+/// Why the proxy stopped relaying.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyExit {
+    /// The server closed its stdout.
+    ServerClosed,
+    /// The session hit the idle timeout; the container is still running.
+    Idle,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Routed {
+    to_server: Vec<String>,
+    to_client: Vec<String>,
+    /// Fields of `tool_call` audit records.
+    audit: Vec<Value>,
+}
+
+/// Message-level state of a proxied session. It never performs IO, which
+/// keeps the handshake logic testable.
+#[derive(Debug)]
+struct Session {
+    server_ready: bool,
+    deferred_initialize: Option<Value>,
+    /// The protocol version the eager `initialize` result gave the client.
+    answered_version: Option<Value>,
+    /// Capabilities the eager `initialize` result advertised but the server
+    /// turned out not to have.
+    lacking: HashSet<&'static str>,
+    /// Ids of requests that get an empty result when the server lacks their
+    /// capability, with their method.
+    empty_requests: HashMap<String, String>,
+    /// Requests waiting for the server to start, with the client's progress
+    /// token.
+    waiting: Vec<(Value, Value)>,
+    /// The client's progress tokens by the proxy-owned token forwarded in
+    /// their place, which keeps the server's progress from colliding with
+    /// the proxy's own.
+    progress_tokens: HashMap<String, Value>,
+    progress: u64,
+    /// Ids of client requests the server has not answered yet.
+    outstanding: HashSet<String>,
+    last_activity: Instant,
+    paths: Option<PathMap>,
+    /// Ids of `roots/list` requests the client has not answered yet.
+    roots_requests: HashSet<String>,
+    budget: Option<Budget>,
+    policies: Vec<String>,
+    engine: Option<Arc<dyn PolicyEngine>>,
+    /// The client's name from its `initialize` request.
+    client: Option<String>,
+    /// The client's `initialize` request, replayed to a restarted server.
+    handshake: Option<Value>,
+    /// Whether the client sent `notifications/initialized`.
+    initialized: bool,
+    replay_methods: Vec<String>,
+    /// Setup requests of `replay_methods`, the last one per method and
+    /// parameters.
+    setup: Vec<Value>,
+    /// Ids of replayed requests, whose answers are not the client's.
+    replayed: HashSet<String>,
+    replays: u64,
+    trace: bool,
+}
+
+fn is_response(message: &Value) -> bool {
+    message.get("method").is_none()
+        && message.get("id").is_some()
+        && (message.get("result").is_some() || message.get("error").is_some())
+}
+
+/// Capabilities the eager `initialize` result advertises.
+const CAPABILITIES: [&str; 4] = ["tools", "resources", "prompts", "logging"];
+
+/// The capability of a request that has an empty answer, and that answer,
+/// given to the client when the server lacks the capability.
+fn empty_result(method: &str) -> Option<(&'static str, Value)> {
+    match method {
+        "tools/list" => Some(("tools", json!({ "tools": [] }))),
+        "resources/list" => Some(("resources", json!({ "resources": [] }))),
+        "resources/templates/list" => Some(("resources", json!({ "resourceTemplates": [] }))),
+        "prompts/list" => Some(("prompts", json!({ "prompts": [] }))),
+        "logging/setLevel" => Some(("logging", json!({}))),
+        _ => None,
+    }
+}
+
+/// The progress token the proxy forwards in place of the client's, for
+/// the request `id`.
+fn progress_token(id: &Value) -> Value {
+    let id = id.as_str().map_or_else(|| id.to_string(), str::to_string);
+    json!(format!("semcp-progress-{}", id))
+}
+
+fn initialize_result(id: &Value, protocol_version: &Value) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id.clone(),
+        "result": {
+            "protocolVersion": protocol_version,
+            "capabilities": {
+                "tools": { "listChanged": true },
+                "resources": { "listChanged": true },
+                "prompts": { "listChanged": true },
+                "logging": {}
+            },
+            "serverInfo": {
+                "name": "semcp",
+                "version": env!("CARGO_PKG_VERSION")
+            }
+        }
+    })
+}
+
+impl Session {
+    fn new(options: &ProxyOptions) -> Self {
+        Self {
+            server_ready: !options.eager_initialize,
+            deferred_initialize: None,
+            answered_version: None,
+            lacking: HashSet::new(),
+            empty_requests: HashMap::new(),
+            waiting: Vec::new(),
+            progress_tokens: HashMap::new(),
+            progress: 0,
+            outstanding: HashSet::new(),
+            last_activity: Instant::now(),
+            paths: options.paths.clone(),
+            roots_requests: HashSet::new(),
+            budget: options
+                .budget
+                .clone()
+                .map(|spec| Budget::new(spec, Instant::now())),
+            policies: options.policies.clone(),
+            engine: options.engine.clone(),
+            client: None,
+            handshake: None,
+            initialized: false,
+            replay_methods: options.replay_methods.clone(),
+            setup: Vec::new(),
+            replayed: HashSet::new(),
+            replays: 0,
+            trace: options.trace,
+        }
+    }
+
+    /// Remembers what a restarted server has to be told again.
+    fn record(&mut self, message: &Value) {
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            return;
+        };
+        match method {
+            "initialize" => self.handshake = Some(message.clone()),
+            "notifications/initialized" => self.initialized = true,
+            _ if message.get("id").is_some() && self.replay_methods.iter().any(|m| m == method) => {
+                let params = message.get("params");
+                self.setup.retain(|earlier| {
+                    earlier.get("method") != message.get("method")
+                        || earlier.get("params") != params
+                });
+                self.setup.push(message.clone());
+            }
+            _ => {}
+        }
+    }
+
+    /// A request of the session under an id of the proxy's own.
+    fn replay(&mut self, message: &Value) -> String {
+        self.replays += 1;
+        let id = json!(format!("semcp-replay-{}", self.replays));
+        self.replayed.insert(id.to_string());
+        let mut message = message.clone();
+        message["id"] = id;
+        message.to_string()
+    }
+
+    /// Brings a restarted server to where the crashed one was: the
+    /// handshake and the setup requests are sent again, and requests the
+    /// crashed server took with it fail so the client can retry them.
+    fn restart(&mut self) -> Routed {
+        let mut routed = Routed::default();
+        let handshake_id = self
+            .handshake
+            .as_ref()
+            .and_then(|handshake| handshake.get("id"))
+            .map(Value::to_string);
+        // the client still waits for this answer, from whichever server
+        let handshake_pending = handshake_id
+            .as_ref()
+            .is_some_and(|id| self.outstanding.contains(id));
+        for id in std::mem::take(&mut self.outstanding) {
+            if Some(&id) == handshake_id.as_ref() {
+                self.outstanding.insert(id);
+                continue;
+            }
+            let Ok(id) = serde_json::from_str::<Value>(&id) else {
+                continue;
+            };
+            routed.to_client.push(
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": -32603,
+                        "message": "The MCP server crashed and was restarted, retry the request"
+                    }
+                })
+                .to_string(),
+            );
+        }
+        self.waiting.clear();
+        self.progress_tokens.clear();
+        self.empty_requests.clear();
+        self.replayed.clear();
+        self.roots_requests.clear();
+
+        let Some(handshake) = self.handshake.clone() else {
+            return routed;
+        };
+        if handshake_pending {
+            routed.to_server.push(handshake.to_string());
+        } else {
+            let replayed = self.replay(&handshake);
+            routed.to_server.push(replayed);
+        }
+        if self.initialized {
+            routed
+                .to_server
+                .push(json!({"jsonrpc": "2.0", "method": "notifications/initialized"}).to_string());
+        }
+        for request in self.setup.clone() {
+            let replayed = self.replay(&request);
+            routed.to_server.push(replayed);
+        }
+        routed
+    }
+
+    /// Why the engine refuses a `tools/call`.
+    fn authorize(&self, message: &Value) -> Option<Refusal> {
+        let engine = self.engine.as_ref()?;
+        if message.get("method").and_then(Value::as_str) != Some("tools/call") {
+            return None;
+        }
+        message.get("id")?;
+        let tool = message
+            .pointer("/params/name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let arguments = message
+            .pointer("/params/arguments")
+            .cloned()
+            .unwrap_or(Value::Null);
+        engine
+            .authorize_tool(self.client.as_deref(), tool, &arguments)
+            .err()
+    }
+
+    /// Why the budget cannot pay for a `tools/call`.
+    fn charge(&mut self, message: &Value) -> Option<Refusal> {
+        let budget = self.budget.as_mut()?;
+        if message.get("method").and_then(Value::as_str) != Some("tools/call") {
+            return None;
+        }
+        message.get("id")?;
+        let tool = message
+            .pointer("/params/name")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        let exhausted = budget.charge(tool, Instant::now()).err()?;
+        Some(Refusal::from(exhausted))
+    }
+
+    /// Fields of the audit record of a `tools/call` that was traced.
+    fn tool_call_record(
+        &self,
+        message: &Value,
+        context: &TraceContext,
+        refusal: Option<&Refusal>,
+    ) -> Option<Value> {
+        if message.get("method").and_then(Value::as_str) != Some("tools/call") {
+            return None;
+        }
+        let mut fields = context.fields();
+        fields["tool"] = message
+            .pointer("/params/name")
+            .cloned()
+            .unwrap_or(Value::Null);
+        fields["client"] = json!(self.client);
+        fields["outcome"] = json!(if refusal.is_some() {
+            "refused"
+        } else {
+            "forwarded"
+        });
+        if let Some(refusal) = refusal {
+            fields["reason"] = json!(refusal.reason);
+        }
+        Some(fields)
+    }
+
+    /// Translates a client message to container paths. The answer to
+    /// `roots/list` loses the roots that are not mounted.
+    fn translate_client_line(&mut self, line: &str, routed: &mut Routed) -> String {
+        let Some(ref paths) = self.paths else {
+            return line.to_string();
+        };
+        let Ok(mut message) = serde_json::from_str::<Value>(line) else {
+            return line.to_string();
+        };
+        let id = message.get("id").map(Value::to_string);
+        if is_response(&message)
+            && paths.filters_roots()
+            && id.is_some_and(|id| self.roots_requests.remove(&id))
+        {
+            let hidden = roots::translate_roots(&mut message, paths);
+            if !hidden.is_empty() {
+                routed.to_client.push(
+                    roots::hidden_roots(&hidden)
+                        .notification(&self.policies)
+                        .to_string(),
+                );
+            }
+            return message.to_string();
+        }
+        roots::translate_value(&message, &|text| paths.to_container(text)).to_string()
+    }
+
+    /// Translates a server message to host paths.
+    fn translate_server_line(&mut self, line: &str) -> String {
+        let Some(ref paths) = self.paths else {
+            return line.to_string();
+        };
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            return line.to_string();
+        };
+        if message.get("method").and_then(Value::as_str) == Some("roots/list") {
+            if let Some(id) = message.get("id") {
+                self.roots_requests.insert(id.to_string());
+            }
+        }
+        roots::translate_value(&message, &|text| paths.to_host(text)).to_string()
+    }
+
+    fn is_idle(&self, timeout: Duration, now: Instant) -> bool {
+        self.outstanding.is_empty() && now.duration_since(self.last_activity) >= timeout
+    }
+
+    fn on_client_line(&mut self, line: &str) -> Routed {
+        let mut routed = Routed::default();
+        self.last_activity = Instant::now();
+        let mut line = self.translate_client_line(line, &mut routed);
+
+        if let Ok(mut message) = serde_json::from_str::<Value>(&line) {
+            if message.get("method").and_then(Value::as_str) == Some("initialize") {
+                self.client = message
+                    .pointer("/params/clientInfo/name")
+                    .and_then(Value::as_str)
+                    .map(str::to_string);
+            }
+            let is_request = message.get("id").is_some() && message.get("method").is_some();
+            let context = (self.trace && is_request).then(|| TraceContext::for_request(&message));
+            let refusal = self.authorize(&message).or_else(|| self.charge(&message));
+            if let Some(ref context) = context {
+                routed
+                    .audit
+                    .extend(self.tool_call_record(&message, context, refusal.as_ref()));
+            }
+            if let (Some(refusal), Some(id)) = (refusal, message.get("id")) {
+                let mut response = refusal.error_response(id, &self.policies);
+                if let Some(ref context) = context {
+                    response["error"]["data"]["trace_id"] = json!(context.trace_id);
+                }
+                routed.to_client.push(response.to_string());
+                return routed;
+            }
+            if let Some(ref context) = context {
+                context.inject(&mut message);
+                line = message.to_string();
+            }
+            self.record(&message);
+            if let (Some(id), Some(method)) = (message.get("id"), message.get("method")) {
+                self.outstanding.insert(id.to_string());
+                if let Some(method) = method.as_str().filter(|method| {
+                    self.answered_version.is_some() && empty_result(method).is_some()
+                }) {
+                    self.empty_requests
+                        .insert(id.to_string(), method.to_string());
+                }
+            }
+        }
+
+        if !self.server_ready {
+            if let Ok(mut message) = serde_json::from_str::<Value>(&line) {
+                let method = message.get("method").and_then(Value::as_str);
+                match (message.get("id").cloned(), method) {
+                    (Some(id), Some("initialize")) if self.deferred_initialize.is_none() => {
+                        let version = message
+                            .pointer("/params/protocolVersion")
+                            .cloned()
+                            .unwrap_or_else(|| json!("2025-03-26"));
+                        routed
+                            .to_client
+                            .push(initialize_result(&id, &version).to_string());
+                        self.deferred_initialize = Some(id);
+                        self.answered_version = Some(version);
+                    }
+                    (Some(id), Some(_)) => {
+                        if let Some(token) = message.pointer_mut("/params/_meta/progressToken") {
+                            let owned = progress_token(&id);
+                            let token = std::mem::replace(token, owned.clone());
+                            self.progress_tokens
+                                .insert(owned.to_string(), token.clone());
+                            self.waiting.push((id, token));
+                            line = message.to_string();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        routed.to_server.push(line);
+        routed
+    }
+
+    fn on_server_line(&mut self, line: &str) -> Routed {
+        let mut routed = Routed::default();
+        self.last_activity = Instant::now();
+        let line = &self.translate_server_line(line);
+
+        let message = match serde_json::from_str::<Value>(line) {
+            Ok(message) if is_response(&message) => message,
+            Ok(message) => {
+                routed.to_client.push(self.client_progress(line, message));
+                return routed;
+            }
+            _ => {
+                routed.to_client.push(line.to_string());
+                return routed;
+            }
+        };
+
+        if let Some(id) = message.get("id") {
+            self.outstanding.remove(&id.to_string());
+            if self.replayed.remove(&id.to_string()) {
+                return routed;
+            }
+        }
+
+        if self.deferred_initialize.as_ref() == message.get("id") {
+            self.deferred_initialize = None;
+            self.server_ready = true;
+            self.waiting.clear();
+
+            if let Some(result) = message.get("result") {
+                routed.to_client.extend(self.reconcile(result));
+            } else if let Some(error) = message.get("error") {
+                routed.to_client.push(
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/message",
+                        "params": {
+                            "level": "error",
+                            "logger": "semcp",
+                            "data": { "initializeError": error.clone() }
+                        }
+                    })
+                    .to_string(),
+                );
+            }
+            return routed;
+        }
+
+        if let Some(id) = message.get("id") {
+            self.waiting.retain(|(waiting_id, _)| waiting_id != id);
+            self.progress_tokens.remove(&progress_token(id).to_string());
+            let empty = self
+                .empty_requests
+                .remove(&id.to_string())
+                .and_then(|method| empty_result(&method))
+                .filter(|(capability, _)| self.lacking.contains(capability));
+            if let (Some((_, result)), Some(_)) = (empty, message.get("error")) {
+                routed.to_client.push(
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": id.clone(),
+                        "result": result
+                    })
+                    .to_string(),
+                );
+                return routed;
+            }
+        }
+        routed.to_client.push(line.to_string());
+        routed
+    }
+
+    /// Reconciles the eager `initialize` result with the server's: the
+    /// client learns of the server's lists, requests for capabilities the
+    /// server lacks get empty answers, and a protocol version other than
+    /// the one answered is reported.
+    fn reconcile(&mut self, result: &Value) -> Vec<String> {
+        let mut notifications = Vec::new();
+        for capability in CAPABILITIES {
+            if result
+                .pointer(&format!("/capabilities/{}", capability))
+                .is_none()
+            {
+                self.lacking.insert(capability);
+            } else if capability != "logging" {
+                notifications.push(
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": format!("notifications/{}/list_changed", capability)
+                    })
+                    .to_string(),
+                );
+            }
+        }
+
+        let version = result.get("protocolVersion");
+        if let (Some(version), Some(answered)) = (version, self.answered_version.as_ref()) {
+            if version != answered {
+                notifications.push(
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/message",
+                        "params": {
+                            "level": "warning",
+                            "logger": "semcp",
+                            "data": {
+                                "message": format!(
+                                    "The MCP server negotiated protocol version {}, not the {} answered to the client; run it without --eager-init",
+                                    version, answered
+                                ),
+                                "answered": answered.clone(),
+                                "server": version.clone()
+                            }
+                        }
+                    })
+                    .to_string(),
+                );
+            }
+        }
+        notifications
+    }
+
+    /// Forwards a server message to the client, giving progress on a
+    /// proxy-owned token the client's token back. Its progress continues
+    /// from the proxy's own, which only ever increases.
+    fn client_progress(&self, line: &str, mut message: Value) -> String {
+        if message.get("method").and_then(Value::as_str) != Some("notifications/progress") {
+            return line.to_string();
+        }
+        let Some(client_token) = message
+            .pointer("/params/progressToken")
+            .and_then(|token| self.progress_tokens.get(&token.to_string()))
+        else {
+            return line.to_string();
+        };
+        message["params"]["progressToken"] = client_token.clone();
+        for field in ["progress", "total"] {
+            let value = &message["params"][field];
+            let shifted = match (value.as_u64(), value.as_f64()) {
+                (Some(value), _) => json!(value + self.progress),
+                (None, Some(value)) => json!(value + self.progress as f64),
+                _ => continue,
+            };
+            message["params"][field] = shifted;
+        }
+        message.to_string()
+    }
+
+    fn keepalive(&mut self) -> Vec<String> {
+        if self.server_ready || self.waiting.is_empty() {
+            return Vec::new();
+        }
+
+        self.progress += 1;
+        self.waiting
+            .iter()
+            .map(|(_, token)| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/progress",
+                    "params": {
+                        "progressToken": token.clone(),
+                        "progress": self.progress,
+                        "message": "waiting for the MCP server to start"
+                    }
+                })
+                .to_string()
+            })
+            .collect()
+    }
+}
+
+fn idle_notification(timeout: Duration) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "notifications/message",
+        "params": {
+            "level": "warning",
+            "logger": "semcp",
+            "data": format!(
+                "MCP server stopped after {}s without requests (runtime.idle_timeout)",
+                timeout.as_secs()
+            )
+        }
+    })
+    .to_string()
+}
+
+/// The client side of the proxy: this process's stdio and the session. It
+/// outlives the server it relays to, so a restarted server continues the
+/// session of the one that crashed.
+pub struct Relay {
+    session: Arc<Mutex<Session>>,
+    options: ProxyOptions,
+    to_client: Pump,
+    writer: JoinHandle<std::io::Result<()>>,
+    /// Lines from the client, read by a task of their own so that none is
+    /// lost between servers.
+    from_client: Arc<tokio::sync::Mutex<mpsc::Receiver<String>>>,
+    reader: JoinHandle<std::io::Result<()>>,
+    servers: u32,
+}
+
+impl Relay {
+    pub fn start(options: &ProxyOptions) -> Self {
+        let (to_client, writer) = Pump::spawn(tokio::io::stdout());
+        let (lines_tx, from_client) = mpsc::channel(CLIENT_BACKLOG);
+        let reader = tokio::spawn(async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+            while let Some(line) = lines.next_line().await? {
+                if lines_tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+        Self {
+            session: Arc::new(Mutex::new(Session::new(options))),
+            options: options.clone(),
+            to_client,
+            writer,
+            from_client: Arc::new(tokio::sync::Mutex::new(from_client)),
+            reader,
+            servers: 0,
+        }
+    }
+
+    /// Relays newline-delimited JSON-RPC between the client and the piped
+    /// stdio of `child` until the server closes its stdout or the session
+    /// goes idle. From the second server on, the session is replayed to it
+    /// first.
+    pub async fn serve(&mut self, child: &mut Child, verbose: bool) -> Result<ProxyExit> {
+        let server_in = child.stdin.take().context("Container stdin is not piped")?;
+        let server_out = child
+            .stdout
+            .take()
+            .context("Container stdout is not piped")?;
+        // dropping the last sender closes the pipe, which tells the server
+        // the client went away
+        let (to_server, _) = Pump::spawn(server_in);
+
+        self.servers += 1;
+        if self.servers > 1 {
+            let routed = self.session.lock().unwrap().restart();
+            if verbose {
+                eprintln!(
+                    "Replaying the session to the restarted server ({} message(s))",
+                    routed.to_server.len()
+                );
+            }
+            self.to_client.send_all(routed.to_client).await;
+            to_server.send_all(routed.to_server).await;
+        }
+
+        let client_session = Arc::clone(&self.session);
+        let client_tx = self.to_client.clone();
+        let from_client = Arc::clone(&self.from_client);
+        let audit_spec = self.options.audit.clone();
+        let client = tokio::spawn(async move {
+            let mut from_client = from_client.lock().await;
+            while let Some(line) = from_client.recv().await {
+                let routed = client_session.lock().unwrap().on_client_line(&line);
+                let audited = audit_spec.log_commands && audit_spec.records(false);
+                for fields in routed.audit.into_iter().filter(|_| audited) {
+                    if let Err(e) = audit::record_for(&audit_spec, "tool_call", fields) {
+                        if verbose {
+                            eprintln!("Warning: {:#}", e);
+                        }
+                    }
+                }
+                if !client_tx.send_all(routed.to_client).await
+                    || !to_server.send_all(routed.to_server).await
+                {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = BufReader::new(server_out).lines();
+        let mut keepalive = tokio::time::interval(self.options.keepalive_interval);
+        let mut idle_check = tokio::time::interval(Duration::from_secs(1));
+        let mut exit = ProxyExit::ServerClosed;
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Some(line) = line.context("Failed to read from container")? else {
+                        break;
+                    };
+                    let routed = self.session.lock().unwrap().on_server_line(&line);
+                    if !self.to_client.send_all(routed.to_client).await {
+                        break;
+                    }
+                }
+                _ = keepalive.tick() => {
+                    let notifications = self.session.lock().unwrap().keepalive();
+                    if verbose && !notifications.is_empty() {
+                        eprintln!("Server still starting, sent {} progress notification(s)", notifications.len());
+                    }
+                    self.to_client.send_all(notifications).await;
+                }
+                _ = idle_check.tick(), if self.options.idle_timeout.is_some() => {
+                    let timeout = self.options.idle_timeout.unwrap_or_default();
+                    if self.session.lock().unwrap().is_idle(timeout, Instant::now()) {
+                        if verbose {
+                            eprintln!("No MCP traffic for {}s, stopping the server", timeout.as_secs());
+                        }
+                        self.to_client.send(idle_notification(timeout)).await;
+                        exit = ProxyExit::Idle;
+                        break;
+                    }
+                }
+            }
+        }
+
+        // receiving is cancel safe, lines not taken yet wait for the next
+        // server
+        client.abort();
+        let _ = client.await;
+        Ok(exit)
+    }
+
+    /// Stops reading from the client and writes what is still queued.
+    pub async fn finish(self) -> Result<()> {
+        self.reader.abort();
+        drop(self.to_client);
+        self.writer
+            .await
+            .context("Proxy writer task failed")?
+            .context("Failed to write to client")
+    }
+}
+
+/// Relays between this process's stdio and `child` for one server, see
+/// [`Relay::serve`].
+pub async fn proxy_stdio(
+    child: &mut Child,
+    options: &ProxyOptions,
+    verbose: bool,
+) -> Result<ProxyExit> {
+    let mut relay = Relay::start(options);
+    let exit = relay.serve(child, verbose).await?;
+    relay.finish().await?;
+    Ok(exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eager() -> Session {
+        Session::new(&ProxyOptions {
+            eager_initialize: true,
+            ..ProxyOptions::default()
+        })
+    }
+
+    const INITIALIZE: &str = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-03-26","capabilities":{},"clientInfo":{"name":"test","version":"1"}}}"#;
+
+    #[test]
+    fn test_passthrough_without_eager_initialize() {
+        let mut session = Session::new(&ProxyOptions::default());
+        let routed = session.on_client_line(INITIALIZE);
+        assert!(routed.to_client.is_empty());
+        assert_eq!(routed.to_server, vec![INITIALIZE.to_string()]);
+
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#;
+        let routed = session.on_server_line(response);
+        assert_eq!(routed.to_client, vec![response.to_string()]);
+    }
+
+    #[test]
+    fn test_eager_initialize_answers_immediately() {
+        let mut session = eager();
+        let routed = session.on_client_line(INITIALIZE);
+
+        assert_eq!(routed.to_server, vec![INITIALIZE.to_string()]);
+        assert_eq!(routed.to_client.len(), 1);
+        let reply: Value = serde_json::from_str(&routed.to_client[0]).unwrap();
+        assert_eq!(reply["id"], json!(1));
+        assert_eq!(reply["result"]["protocolVersion"], json!("2025-03-26"));
+    }
+
+    #[test]
+    fn test_deferred_initialize_response_is_swallowed() {
+        let mut session = eager();
+        session.on_client_line(INITIALIZE);
+
+        let response = r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2025-03-26","capabilities":{"tools":{}}}}"#;
+        let routed = session.on_server_line(response);
+        assert!(session.server_ready);
+        assert_eq!(routed.to_client.len(), 1);
+        assert!(routed.to_client[0].contains("notifications/tools/list_changed"));
+
+        let next = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#;
+        assert_eq!(
+            session.on_server_line(next).to_client,
+            vec![next.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_roots_are_translated() {
+        let mut session = Session::new(&ProxyOptions {
+            paths: Some(PathMap::for_host_paths(&["/home/me/project".to_string()])),
+            ..ProxyOptions::default()
+        });
+        let request =
+            session.on_server_line(r#"{"jsonrpc":"2.0","id":"r1","method":"roots/list"}"#);
+        assert_eq!(request.to_client.len(), 1);
+
+        let routed = session.on_client_line(
+            r#"{"jsonrpc":"2.0","id":"r1","result":{"roots":[{"uri":"file:///home/me/project"},{"uri":"file:///etc"}]}}"#,
+        );
+        let forwarded: Value = serde_json::from_str(&routed.to_server[0]).unwrap();
+        assert_eq!(
+            forwarded["result"]["roots"],
+            json!([{ "uri": "file:///roots/project" }])
+        );
+        assert!(routed.to_client[0].contains("file:///etc"));
+
+        let reply = session.on_server_line(
+            r#"{"jsonrpc":"2.0","id":3,"result":{"content":[{"type":"text","text":"wrote /roots/project/a.txt"}]}}"#,
+        );
+        assert!(reply.to_client[0].contains("wrote /home/me/project/a.txt"));
+    }
+
+    #[test]
+    fn test_keepalive_for_pending_requests() {
+        let mut session = eager();
+        session.on_client_line(INITIALIZE);
+        session.on_client_line(
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/list","params":{"_meta":{"progressToken":"t1"}}}"#,
+        );
+
+        let first = session.keepalive();
+        assert_eq!(first.len(), 1);
+        assert!(first[0].contains("\"progressToken\":\"t1\""));
+        assert!(session.keepalive()[0].contains("\"progress\":2"));
+
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":2,"result":{"tools":[]}}"#);
+        assert!(session.keepalive().is_empty());
+    }
+
+    #[test]
+    fn test_server_progress_continues_the_proxys() {
+        let mut session = eager();
+        session.on_client_line(INITIALIZE);
+        let routed = session.on_client_line(
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"slow","_meta":{"progressToken":"t1"}}}"#,
+        );
+        let forwarded: Value = serde_json::from_str(&routed.to_server[0]).unwrap();
+        assert_eq!(
+            forwarded["params"]["_meta"]["progressToken"],
+            json!("semcp-progress-2")
+        );
+        session.keepalive();
+        session.keepalive();
+
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2025-03-26","capabilities":{"tools":{}}}}"#);
+        let routed = session.on_server_line(
+            r#"{"jsonrpc":"2.0","method":"notifications/progress","params":{"progressToken":"semcp-progress-2","progress":1,"total":4}}"#,
+        );
+        let progress: Value = serde_json::from_str(&routed.to_client[0]).unwrap();
+        assert_eq!(progress["params"]["progressToken"], json!("t1"));
+        assert_eq!(progress["params"]["progress"], json!(3));
+        assert_eq!(progress["params"]["total"], json!(6));
+
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":2,"result":{"content":[]}}"#);
+        let late = r#"{"jsonrpc":"2.0","method":"notifications/progress","params":{"progressToken":"semcp-progress-2","progress":2}}"#;
+        assert_eq!(
+            session.on_server_line(late).to_client,
+            vec![late.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_lacking_capabilities_are_answered_empty() {
+        let mut session = eager();
+        session.on_client_line(INITIALIZE);
+        session.on_client_line(r#"{"jsonrpc":"2.0","id":2,"method":"prompts/list"}"#);
+        session.on_client_line(r#"{"jsonrpc":"2.0","id":3,"method":"tools/list"}"#);
+
+        let routed = session.on_server_line(r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2025-03-26","capabilities":{"tools":{}}}}"#);
+        assert_eq!(routed.to_client.len(), 1);
+
+        let routed = session.on_server_line(
+            r#"{"jsonrpc":"2.0","id":2,"error":{"code":-32601,"message":"Method not found"}}"#,
+        );
+        let reply: Value = serde_json::from_str(&routed.to_client[0]).unwrap();
+        assert_eq!(reply["result"], json!({ "prompts": [] }));
+
+        let error = r#"{"jsonrpc":"2.0","id":3,"error":{"code":-32603,"message":"boom"}}"#;
+        assert_eq!(
+            session.on_server_line(error).to_client,
+            vec![error.to_string()]
+        );
+    }
+
+    #[test]
+    fn test_protocol_version_mismatch_is_reported() {
+        let mut session = eager();
+        session.on_client_line(INITIALIZE);
+        let routed = session.on_server_line(r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2024-11-05","capabilities":{}}}"#);
+        assert_eq!(routed.to_client.len(), 1);
+        let warning: Value = serde_json::from_str(&routed.to_client[0]).unwrap();
+        assert_eq!(warning["params"]["level"], json!("warning"));
+        assert_eq!(warning["params"]["data"]["server"], json!("2024-11-05"));
+    }
+
+    #[test]
+    fn test_idle_waits_for_outstanding_requests() {
+        let mut session = Session::new(&ProxyOptions::default());
+        let timeout = Duration::from_secs(60);
+        let later = Instant::now() + timeout;
+
+        session.on_client_line(r#"{"jsonrpc":"2.0","id":"a","method":"tools/call"}"#);
+        assert!(!session.is_idle(timeout, later));
+
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":"a","result":{}}"#);
+        assert!(!session.is_idle(timeout, Instant::now()));
+        assert!(session.is_idle(timeout, Instant::now() + timeout));
+    }
+
+    #[test]
+    fn test_calls_beyond_the_budget_are_refused() {
+        let mut session = Session::new(&ProxyOptions {
+            budget: Some(BudgetSpec {
+                per_session: 5,
+                tools: [("search".to_string(), 3)].into(),
+                ..BudgetSpec::default()
+            }),
+            ..ProxyOptions::default()
+        });
+        let call = |id: u32, tool: &str| {
+            json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": tool}})
+                .to_string()
+        };
+
+        assert_eq!(
+            session.on_client_line(&call(1, "search")).to_server.len(),
+            1
+        );
+        let refused = session.on_client_line(&call(2, "search"));
+        assert!(refused.to_server.is_empty());
+        let reply: Value = serde_json::from_str(&refused.to_client[0]).unwrap();
+        assert_eq!(reply["id"], json!(2));
+        assert!(reply["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("search costs 3, 2 left"));
+        assert_eq!(reply["error"]["code"], json!(crate::refusal::POLICY_DENIED));
+        assert_eq!(reply["error"]["data"]["reason"], json!("budget_exhausted"));
+        assert!(!session.outstanding.contains("2"));
+
+        let list = r#"{"jsonrpc":"2.0","id":3,"method":"tools/list"}"#;
+        assert_eq!(session.on_client_line(list).to_server.len(), 1);
+        assert_eq!(session.on_client_line(&call(4, "echo")).to_server.len(), 1);
+    }
+
+    #[test]
+    fn test_traced_requests_carry_their_context() {
+        let mut session = Session::new(&ProxyOptions {
+            trace: true,
+            budget: Some(BudgetSpec {
+                per_session: 1,
+                ..BudgetSpec::default()
+            }),
+            ..ProxyOptions::default()
+        });
+        let call = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"name":"search","_meta":{"traceparent":"00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"}}}"#;
+        let routed = session.on_client_line(call);
+        let forwarded: Value = serde_json::from_str(&routed.to_server[0]).unwrap();
+        let traceparent = forwarded["params"]["_meta"]["traceparent"]
+            .as_str()
+            .unwrap();
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert_ne!(
+            traceparent,
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        assert_eq!(routed.audit.len(), 1);
+        assert_eq!(routed.audit[0]["tool"], json!("search"));
+        assert_eq!(routed.audit[0]["outcome"], json!("forwarded"));
+
+        let refused = session.on_client_line(
+            r#"{"jsonrpc":"2.0","id":2,"method":"tools/call","params":{"name":"search"}}"#,
+        );
+        let reply: Value = serde_json::from_str(&refused.to_client[0]).unwrap();
+        assert_eq!(refused.audit[0]["outcome"], json!("refused"));
+        assert_eq!(
+            reply["error"]["data"]["trace_id"],
+            refused.audit[0]["trace_id"]
+        );
+
+        let notification = r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        let routed = session.on_client_line(notification);
+        assert_eq!(routed.to_server, [notification]);
+        assert!(routed.audit.is_empty());
+    }
+
+    #[derive(Debug)]
+    struct NoDeletes;
+
+    impl PolicyEngine for NoDeletes {
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn check_overrides(
+            &self,
+            _args: &crate::ContainerArgs,
+            _package: Option<&str>,
+        ) -> Vec<crate::Denial> {
+            Vec::new()
+        }
+
+        fn docker_args(&self) -> Vec<String> {
+            Vec::new()
+        }
+
+        fn authorize_tool(
+            &self,
+            client: Option<&str>,
+            tool: &str,
+            _arguments: &Value,
+        ) -> Result<(), Refusal> {
+            if tool != "delete" || client == Some("admin") {
+                return Ok(());
+            }
+            Err(Refusal {
+                reason: "tool_denied",
+                rule: "engine",
+                message: "delete denied".to_string(),
+                details: json!({"tool": tool}),
+            })
+        }
+    }
+
+    #[test]
+    fn test_engine_refuses_tool_calls() {
+        let mut session = Session::new(&ProxyOptions {
+            engine: Some(Arc::new(NoDeletes)),
+            ..ProxyOptions::default()
+        });
+        let call = |id: u32, tool: &str| {
+            json!({"jsonrpc": "2.0", "id": id, "method": "tools/call", "params": {"name": tool}})
+                .to_string()
+        };
+
+        let refused = session.on_client_line(&call(1, "delete"));
+        assert!(refused.to_server.is_empty());
+        let reply: Value = serde_json::from_str(&refused.to_client[0]).unwrap();
+        assert_eq!(reply["error"]["data"]["reason"], json!("tool_denied"));
+        assert_eq!(session.on_client_line(&call(2, "read")).to_server.len(), 1);
+
+        let initialize = json!({"jsonrpc": "2.0", "id": 0, "method": "initialize",
+            "params": {"clientInfo": {"name": "admin"}}});
+        session.on_client_line(&initialize.to_string());
+        assert_eq!(
+            session.on_client_line(&call(3, "delete")).to_server.len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_restart_replays_the_session() {
+        let mut session = Session::new(&ProxyOptions {
+            replay_methods: vec!["logging/setLevel".to_string()],
+            ..ProxyOptions::default()
+        });
+        session.on_client_line(INITIALIZE);
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":1,"result":{}}"#);
+        session.on_client_line(r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#);
+        let level = |id: u32, level: &str| {
+            json!({"jsonrpc": "2.0", "id": id, "method": "logging/setLevel", "params": {"level": level}})
+                .to_string()
+        };
+        session.on_client_line(&level(2, "debug"));
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":2,"result":{}}"#);
+        session.on_client_line(&level(3, "debug"));
+        session.on_server_line(r#"{"jsonrpc":"2.0","id":3,"result":{}}"#);
+        session.on_client_line(r#"{"jsonrpc":"2.0","id":4,"method":"tools/call"}"#);
+
+        let routed = session.restart();
+        let lost: Value = serde_json::from_str(&routed.to_client[0]).unwrap();
+        assert_eq!(lost["id"], json!(4));
+        assert_eq!(lost["error"]["code"], json!(-32603));
+        let replayed: Vec<Value> = routed
+            .to_server
+            .iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(replayed.len(), 3);
+        assert_eq!(replayed[0]["method"], json!("initialize"));
+        assert_eq!(replayed[0]["id"], json!("semcp-replay-1"));
+        assert_eq!(replayed[1]["method"], json!("notifications/initialized"));
+        assert_eq!(replayed[2]["params"]["level"], json!("debug"));
+
+        // the answers to replayed requests are not the client's
+        let answer = r#"{"jsonrpc":"2.0","id":"semcp-replay-1","result":{}}"#;
+        assert!(session.on_server_line(answer).to_client.is_empty());
+        let answer = r#"{"jsonrpc":"2.0","id":5,"result":{}}"#;
+        assert_eq!(session.on_server_line(answer).to_client.len(), 1);
+    }
+
+    #[test]
+    fn test_restart_during_the_handshake_resends_it() {
+        let mut session = Session::new(&ProxyOptions::default());
+        session.on_client_line(INITIALIZE);
+        let routed = session.restart();
+        assert!(routed.to_client.is_empty());
+        let resent: Value = serde_json::from_str(&routed.to_server[0]).unwrap();
+        assert_eq!(resent, serde_json::from_str::<Value>(INITIALIZE).unwrap());
+        let answer = r#"{"jsonrpc":"2.0","id":1,"result":{}}"#;
+        assert_eq!(session.on_server_line(answer).to_client.len(), 1);
+    }
+
+    #[test]
+    fn test_server_requests_are_not_responses() {
+        let request = json!({"jsonrpc": "2.0", "id": 7, "method": "sampling/createMessage"});
+        assert!(!is_response(&request));
+        assert!(is_response(
+            &json!({"jsonrpc": "2.0", "id": 7, "result": {}})
+        ));
+    }
+}
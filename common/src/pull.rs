@@ -0,0 +1,301 @@
+//! Pulling images past registry rate limits and outages.
+//!
+//! Docker Hub limits how many images an address or account pulls in a
+//! window, which a CI runner or a shared NAT reaches quickly. When a pull is
+//! refused for it, the error says so and when the limit resets, as far as
+//! Docker Hub tells, and a reset within [`MAX_WAIT`] is waited for and the
+//! pull retried once.
+//!
+//! Before that, a failed pull is retried from the mirror the user's config
+//! names for the image's registry, and the mirrored image is tagged with the
+//! original name:
+//!
+//! ```yaml
+//! registry_mirrors:
+//!   docker.io: mirror.gcr.io
+//!   ghcr.io: registry.internal/ghcr
+//! ```
+//!
+//! Images pinned by digest cannot be tagged, so they are only pulled from
+//! their own registry.
+
+use crate::config::UserConfig;
+use crate::runtime::ContainerRuntime;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Longest wait for a rate limit to reset before the pull is given up.
+pub const MAX_WAIT: Duration = Duration::from_secs(5 * 60);
+
+const DOCKER_HUB: &str = "docker.io";
+
+/// Whether a pull error is a registry refusing for its rate limit.
+pub fn is_rate_limited(error: &str) -> bool {
+    let error = error.to_lowercase();
+    error.contains("toomanyrequests")
+        || error.contains("429 too many requests")
+        || error.contains("pull rate limit")
+}
+
+/// The registry `image` is pulled from, `docker.io` when it names none.
+pub fn registry(image: &str) -> &str {
+    match image.split_once('/') {
+        Some((first, _)) if first.contains(['.', ':']) || first == "localhost" => first,
+        _ => DOCKER_HUB,
+    }
+}
+
+/// `image` pulled from `mirror` instead of its registry. Official Docker
+/// Hub images live under `library/`, as mirrors serve them.
+pub fn mirrored(image: &str, mirror: &str) -> String {
+    let mirror = mirror.trim_end_matches('/');
+    let registry = registry(image);
+    let path = match image
+        .strip_prefix(registry)
+        .and_then(|p| p.strip_prefix('/'))
+    {
+        Some(path) => path.to_string(),
+        None if image.contains('/') => image.to_string(),
+        None => format!("library/{}", image),
+    };
+    format!("{}/{}", mirror, path)
+}
+
+/// What Docker Hub's rate limit headers say.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RateLimit {
+    /// Pulls allowed per window.
+    pub limit: Option<u64>,
+    pub remaining: Option<u64>,
+    /// Length of the window.
+    pub window: Option<Duration>,
+    /// How long until a pull is allowed again.
+    pub reset: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Reads the `ratelimit-*` and `retry-after` headers of a response,
+    /// which look like `ratelimit-limit: 100;w=21600`.
+    pub fn from_headers(headers: &str) -> Self {
+        let mut limit = Self::default();
+        for line in headers.lines() {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let mut parts = value.trim().split(';');
+            let number = parts.next().and_then(|n| n.trim().parse::<u64>().ok());
+            let window = parts
+                .find_map(|part| part.trim().strip_prefix("w="))
+                .and_then(|w| w.parse().ok())
+                .map(Duration::from_secs);
+            match name.trim().to_lowercase().as_str() {
+                "ratelimit-limit" => {
+                    limit.limit = number;
+                    limit.window = limit.window.or(window);
+                }
+                "ratelimit-remaining" => {
+                    limit.remaining = number;
+                    limit.window = limit.window.or(window);
+                }
+                "ratelimit-reset" | "retry-after" => {
+                    limit.reset = limit.reset.or(number.map(Duration::from_secs));
+                }
+                _ => {}
+            }
+        }
+        limit
+    }
+
+    /// The limit and its reset, for messages.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let (Some(limit), Some(window)) = (self.limit, self.window) {
+            parts.push(format!("{} pulls per {}", limit, format_duration(window)));
+        }
+        match self.reset {
+            Some(reset) => parts.push(format!("resets in {}", format_duration(reset))),
+            None => {
+                if let Some(window) = self.window {
+                    parts.push(format!("resets within {}", format_duration(window)))
+                }
+            }
+        }
+        parts.join(", ")
+    }
+}
+
+/// `90m` as `1h 30m`, rounded up to the minute.
+fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs().div_ceil(60);
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, 0) => format!("{}h", hours),
+        (hours, minutes) => format!("{}h {}m", hours, minutes),
+    }
+}
+
+/// Asks Docker Hub for the rate limit of this machine's address, with a
+/// `HEAD` request that does not count against it.
+async fn docker_hub_limit() -> Option<RateLimit> {
+    let token = Command::new("curl")
+        .args(["-fsS", "--max-time", "10"])
+        .arg("https://auth.docker.io/token?service=registry.docker.io&scope=repository:ratelimitpreview/test:pull")
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+    let token: Value = serde_json::from_slice(&token.stdout).ok()?;
+    let token = token.get("token")?.as_str()?;
+    let headers = Command::new("curl")
+        .args(["-sSI", "--max-time", "10", "-H"])
+        .arg(format!("Authorization: Bearer {}", token))
+        .arg("https://registry-1.docker.io/v2/ratelimitpreview/test/manifests/latest")
+        .output()
+        .await
+        .ok()?;
+    Some(RateLimit::from_headers(&String::from_utf8_lossy(
+        &headers.stdout,
+    )))
+}
+
+/// Pulls `image` from `mirror` and tags it with its own name.
+async fn pull_mirrored(
+    runtime: &dyn ContainerRuntime,
+    image: &str,
+    mirror: &str,
+    verbose: bool,
+) -> Result<()> {
+    let mirrored = mirrored(image, mirror);
+    if verbose {
+        eprintln!("Pulling {} from the mirror {}", image, mirrored);
+    }
+    runtime.pull(&mirrored).await?;
+    let output = runtime
+        .command()
+        .args(["image", "tag", &mirrored, image])
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} image tag", runtime.name()))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to tag {} as {}: {}",
+            mirrored,
+            image,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Pulls `image`, falling back to a configured mirror and waiting out a
+/// rate limit that resets soon.
+pub async fn pull(runtime: &dyn ContainerRuntime, image: &str, verbose: bool) -> Result<()> {
+    let error = match runtime.pull(image).await {
+        Ok(()) => return Ok(()),
+        Err(error) => error,
+    };
+    let mirrors = UserConfig::load()?.registry_mirrors;
+    let mirror = mirrors
+        .get(registry(image))
+        .filter(|_| !image.contains('@'));
+    if let Some(mirror) = mirror {
+        match pull_mirrored(runtime, image, mirror, verbose).await {
+            Ok(()) => return Ok(()),
+            Err(mirror_error) => eprintln!(
+                "Warning: Failed to pull {} from the mirror: {:#}",
+                image, mirror_error
+            ),
+        }
+    }
+    if !is_rate_limited(&error.to_string()) {
+        return Err(error);
+    }
+
+    let limit = match registry(image) {
+        DOCKER_HUB => docker_hub_limit().await.unwrap_or_default(),
+        _ => RateLimit::default(),
+    };
+    if let Some(reset) = limit.reset.filter(|reset| *reset <= MAX_WAIT) {
+        eprintln!(
+            "The pull rate limit of {} is reached, retrying in {}",
+            registry(image),
+            format_duration(reset)
+        );
+        tokio::time::sleep(reset).await;
+        if runtime.pull(image).await.is_ok() {
+            return Ok(());
+        }
+    }
+
+    let mut message = format!("The pull rate limit of {} is reached", registry(image));
+    let described = limit.describe();
+    if !described.is_empty() {
+        message.push_str(&format!(" ({})", described));
+    }
+    message.push_str(&format!(
+        ". Log in to the registry with `{} login` for a higher limit",
+        runtime.name()
+    ));
+    if !mirrors.contains_key(registry(image)) {
+        message.push_str(", or set a mirror for it in registry_mirrors of ");
+        message.push_str(
+            &crate::config::user_file()
+                .map(|path| path.display().to_string())
+                .unwrap_or_else(|| "the semcp config".to_string()),
+        );
+    }
+    Err(error.context(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mirrored_references() {
+        assert_eq!(registry("node:24-alpine"), "docker.io");
+        assert_eq!(registry("acme/server:1"), "docker.io");
+        assert_eq!(
+            registry("ghcr.io/astral-sh/uv:python3.12-alpine"),
+            "ghcr.io"
+        );
+        assert_eq!(registry("localhost:5000/mcp"), "localhost:5000");
+        assert_eq!(
+            mirrored("node:24-alpine", "mirror.gcr.io/"),
+            "mirror.gcr.io/library/node:24-alpine"
+        );
+        assert_eq!(
+            mirrored("docker.io/acme/server:1", "mirror.gcr.io"),
+            "mirror.gcr.io/acme/server:1"
+        );
+        assert_eq!(
+            mirrored(
+                "ghcr.io/astral-sh/uv:python3.12-alpine",
+                "registry.internal/ghcr"
+            ),
+            "registry.internal/ghcr/astral-sh/uv:python3.12-alpine"
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_headers() {
+        assert!(is_rate_limited(
+            "toomanyrequests: You have reached your unauthenticated pull rate limit."
+        ));
+        assert!(!is_rate_limited("manifest unknown"));
+
+        let limit = RateLimit::from_headers(
+            "HTTP/1.1 200 OK\r\nRateLimit-Limit: 100;w=21600\r\nratelimit-remaining: 0;w=21600\r\nretry-after: 240\r\n",
+        );
+        assert_eq!(limit.limit, Some(100));
+        assert_eq!(limit.remaining, Some(0));
+        assert_eq!(limit.window, Some(Duration::from_secs(21600)));
+        assert_eq!(limit.describe(), "100 pulls per 6h, resets in 4m");
+
+        let limit = RateLimit::from_headers("ratelimit-limit: 200;w=21600\n");
+        assert_eq!(limit.describe(), "200 pulls per 6h, resets within 6h");
+        assert_eq!(format_duration(Duration::from_secs(5430)), "1h 31m");
+    }
+}
@@ -0,0 +1,123 @@
+//! Output pumps of the stdio proxy.
+//!
+//! Messages bound for the client or the server go through a [`Pump`]: a
+//! queue bounded by bytes rather than by messages, drained by a task that
+//! writes everything queued with vectored writes and a single flush. When
+//! the reader on the other end falls behind, the queue fills, the proxy
+//! stops reading from the other side, and that side's pipe pushes back on
+//! the process filling it. However large the tool results a server streams,
+//! the proxy holds at most [`MAX_IN_FLIGHT`] bytes of queued output.
+//!
+//! Without the proxy, the container inherits this process's stdio and no
+//! data passes through semcp at all.
+
+use std::io::{self, IoSlice};
+use std::sync::Arc;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+
+/// Bytes queued in one direction before senders wait.
+pub const MAX_IN_FLIGHT: usize = 4 << 20;
+/// Messages written with one vectored write at most.
+const MAX_BATCH: usize = 64;
+
+/// Credit a message of `len` bytes takes from the queue, newline included.
+/// A message larger than the whole queue takes all of it, so it still
+/// passes, alone.
+fn credit(len: usize) -> u32 {
+    (len + 1).min(MAX_IN_FLIGHT) as u32
+}
+
+/// Sending end of a pump; clones share the queue.
+#[derive(Clone)]
+pub struct Pump {
+    queue: mpsc::Sender<(String, OwnedSemaphorePermit)>,
+    credit: Arc<Semaphore>,
+}
+
+impl Pump {
+    /// Starts the task writing queued lines to `out`. It ends, dropping
+    /// `out`, once every `Pump` is dropped and the queue is written.
+    pub fn spawn<W>(mut out: W) -> (Self, JoinHandle<io::Result<()>>)
+    where
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let (queue, mut queued) = mpsc::channel::<(String, OwnedSemaphorePermit)>(MAX_BATCH);
+        let writer = tokio::spawn(async move {
+            let mut batch = Vec::with_capacity(MAX_BATCH);
+            while let Some(message) = queued.recv().await {
+                batch.push(message);
+                while batch.len() < MAX_BATCH {
+                    match queued.try_recv() {
+                        Ok(message) => batch.push(message),
+                        Err(_) => break,
+                    }
+                }
+                write_lines(&mut out, &batch).await?;
+                // releases the credit of what was written
+                batch.clear();
+            }
+            Ok(())
+        });
+        let pump = Self {
+            queue,
+            credit: Arc::new(Semaphore::new(MAX_IN_FLIGHT)),
+        };
+        (pump, writer)
+    }
+
+    /// Queues `line`, waiting while the queue is full. False once the
+    /// writer has stopped.
+    pub async fn send(&self, line: String) -> bool {
+        let Ok(permit) = Arc::clone(&self.credit)
+            .acquire_many_owned(credit(line.len()))
+            .await
+        else {
+            return false;
+        };
+        self.queue.send((line, permit)).await.is_ok()
+    }
+
+    pub async fn send_all(&self, lines: Vec<String>) -> bool {
+        for line in lines {
+            if !self.send(line).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Writes each of `lines` followed by a newline, then flushes.
+async fn write_lines<W, P>(out: &mut W, lines: &[(String, P)]) -> io::Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut slices = Vec::with_capacity(lines.len() * 2);
+    for (line, _) in lines {
+        slices.push(IoSlice::new(line.as_bytes()));
+        slices.push(IoSlice::new(b"\n"));
+    }
+    let mut slices = &mut slices[..];
+    while !slices.is_empty() {
+        let written = out.write_vectored(slices).await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        IoSlice::advance_slices(&mut slices, written);
+    }
+    out.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oversized_messages_take_the_whole_queue() {
+        assert_eq!(credit(0), 1);
+        assert_eq!(credit(99), 100);
+        assert_eq!(credit(MAX_IN_FLIGHT * 3), MAX_IN_FLIGHT as u32);
+    }
+}
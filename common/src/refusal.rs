@@ -0,0 +1,90 @@
+//! Structured refusals for MCP clients.
+//!
+//! When semcp keeps a request from the server, or hides part of a message,
+//! the client is told why in a form an agent can act on. Refused requests get
+//! a JSON-RPC error with the code [`POLICY_DENIED`]; hidden content gets a
+//! `notifications/message` warning. Both carry, under `data`, a
+//! machine-readable reason, the policy clause responsible and the policy
+//! files in force.
+
+use serde_json::{json, Value};
+
+/// JSON-RPC error code of requests refused by the policy.
+pub const POLICY_DENIED: i64 = -32010;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Refusal {
+    /// Machine-readable reason, e.g. `budget_exhausted`.
+    pub reason: &'static str,
+    /// The policy clause responsible, e.g. `permissions.runtime.budget`.
+    pub rule: &'static str,
+    /// Explanation for humans.
+    pub message: String,
+    /// Facts specific to the reason, such as the tool and its cost.
+    pub details: Value,
+}
+
+impl Refusal {
+    fn data(&self, policies: &[String]) -> Value {
+        json!({
+            "reason": self.reason,
+            "rule": self.rule,
+            "policies": policies,
+            "message": self.message,
+            "details": self.details,
+        })
+    }
+
+    /// The error answering the refused request `id`.
+    pub fn error_response(&self, id: &Value, policies: &[String]) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id.clone(),
+            "error": {
+                "code": POLICY_DENIED,
+                "message": format!("semcp: {}", self.message),
+                "data": self.data(policies),
+            }
+        })
+    }
+
+    /// A warning about content that was hidden from one side.
+    pub fn notification(&self, policies: &[String]) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/message",
+            "params": {
+                "level": "warning",
+                "logger": "semcp",
+                "data": self.data(policies),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_response_is_machine_readable() {
+        let refusal = Refusal {
+            reason: "budget_exhausted",
+            rule: "permissions.runtime.budget",
+            message: "tool budget exhausted".to_string(),
+            details: json!({"tool": "search"}),
+        };
+        let response = refusal.error_response(&json!(4), &["org.yaml".to_string()]);
+        assert_eq!(response["id"], json!(4));
+        assert_eq!(response["error"]["code"], json!(POLICY_DENIED));
+        assert_eq!(
+            response["error"]["data"]["reason"],
+            json!("budget_exhausted")
+        );
+        assert_eq!(response["error"]["data"]["policies"], json!(["org.yaml"]));
+        assert_eq!(
+            response["error"]["data"]["details"]["tool"],
+            json!("search")
+        );
+    }
+}
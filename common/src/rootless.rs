@@ -0,0 +1,147 @@
+//! Adapting container options to rootless Docker, Podman and nerdctl.
+//!
+//! A rootless daemon runs its containers in the user's own cgroup, so it can
+//! only apply a resource limit when systemd delegates that controller to the
+//! user (on cgroup v2; on cgroup v1 nothing is delegated). Docker then fails
+//! the run or drops the limit with only a warning, depending on the version.
+//! Instead, limits whose controller is missing are left out up front, along
+//! with `--cgroup-parent` and a negative `--oom-score-adj`, which need root,
+//! and each one is reported so the user knows which policy knobs were
+//! downgraded.
+
+use crate::runtime::AdaptedOptions;
+
+/// Limit flags, each taking a value, with the controller they need.
+const LIMIT_FLAGS: &[(&str, &str)] = &[
+    ("--memory", "memory"),
+    ("-m", "memory"),
+    ("--memory-swap", "memory"),
+    ("--memory-reservation", "memory"),
+    ("--cpus", "cpu"),
+    ("--cpu-shares", "cpu"),
+    ("--cpu-quota", "cpu"),
+    ("--cpu-period", "cpu"),
+    ("--cpuset-cpus", "cpuset"),
+    ("--cpuset-mems", "cpuset"),
+    ("--pids-limit", "pids"),
+    ("--blkio-weight", "io"),
+    ("--device-read-bps", "io"),
+    ("--device-write-bps", "io"),
+];
+
+/// Splits `--flag=value` and `--flag value` forms.
+fn take_flag<'a>(arg: &'a str, rest: &mut impl Iterator<Item = &'a String>) -> (&'a str, String) {
+    match arg.split_once('=') {
+        Some((flag, value)) if flag.starts_with('-') => (flag, value.to_string()),
+        _ => (arg, rest.next().cloned().unwrap_or_default()),
+    }
+}
+
+/// `options` without what a rootless runtime with the delegated cgroup
+/// `controllers` cannot apply.
+pub fn adapt(options: Vec<String>, controllers: &[String]) -> AdaptedOptions {
+    let mut adapted = AdaptedOptions::default();
+    let mut iter = options.iter();
+    while let Some(arg) = iter.next() {
+        let flag = arg.split_once('=').map_or(arg.as_str(), |(flag, _)| flag);
+        if let Some((_, controller)) = LIMIT_FLAGS.iter().find(|(name, _)| *name == flag) {
+            let (flag, value) = take_flag(arg, &mut iter);
+            if controllers.iter().any(|delegated| delegated == controller) {
+                adapted.options.push(format!("{}={}", flag, value));
+            } else {
+                adapted.downgraded.push(format!(
+                    "{} {} left out: the {} cgroup controller is not delegated to this user",
+                    flag, value, controller
+                ));
+            }
+            continue;
+        }
+        match flag {
+            "--cgroup-parent" => {
+                let (flag, value) = take_flag(arg, &mut iter);
+                adapted.downgraded.push(format!(
+                    "{} {} left out: rootless containers stay in the user's cgroup",
+                    flag, value
+                ));
+            }
+            "--oom-score-adj" => {
+                let (flag, value) = take_flag(arg, &mut iter);
+                if value.starts_with('-') {
+                    adapted.downgraded.push(format!(
+                        "{} {} left out: lowering the OOM score needs root",
+                        flag, value
+                    ));
+                } else {
+                    adapted.options.push(format!("{}={}", flag, value));
+                }
+            }
+            _ => adapted.options.push(arg.clone()),
+        }
+    }
+    adapted
+}
+
+/// The cgroup controllers delegated to the current user, empty when the
+/// host has cgroup v1 or no delegation.
+#[cfg(target_os = "linux")]
+pub fn delegated_controllers() -> Vec<String> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(uid) = std::fs::metadata("/proc/self").map(|metadata| metadata.uid()) else {
+        return vec![];
+    };
+    let path = format!(
+        "/sys/fs/cgroup/user.slice/user-{}.slice/user@{}.service/cgroup.controllers",
+        uid, uid
+    );
+    std::fs::read_to_string(path)
+        .map(|content| content.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn delegated_controllers() -> Vec<String> {
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_limits_need_delegated_controllers() {
+        let options = strings(&[
+            "--memory",
+            "512m",
+            "--cpus=1.5",
+            "--pids-limit",
+            "100",
+            "--cgroup-parent",
+            "semcp-tools.slice",
+            "-v",
+            "/data:/data:ro",
+        ]);
+        let adapted = adapt(options, &strings(&["memory", "pids"]));
+        assert_eq!(
+            adapted.options,
+            ["--memory=512m", "--pids-limit=100", "-v", "/data:/data:ro"]
+        );
+        assert_eq!(adapted.downgraded.len(), 2);
+        assert!(adapted.downgraded[0].starts_with("--cpus 1.5 left out: the cpu"));
+        assert!(adapted.downgraded[1].contains("--cgroup-parent semcp-tools.slice"));
+    }
+
+    #[test]
+    fn test_cgroup_v1_drops_every_limit() {
+        let adapted = adapt(
+            strings(&["-m", "1g", "--oom-score-adj=-500", "--init"]),
+            &[],
+        );
+        assert_eq!(adapted.options, ["--init"]);
+        assert_eq!(adapted.downgraded.len(), 2);
+    }
+}
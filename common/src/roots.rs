@@ -0,0 +1,368 @@
+//! MCP roots and path translation.
+//!
+//! With `--translate-roots`, host directories are mounted under `/roots`
+//! instead of at their host path, and the stdio proxy translates paths in
+//! every message: host paths become container paths on the way to the
+//! server and back on the way to the client. The server never sees a host
+//! path.
+//!
+//! The client's answer to `roots/list` is rewritten the same way. A root
+//! only reaches the server when the policy (or `-v`) mounts it; other roots
+//! are removed and the client is told why. Mounts are fixed when the
+//! container starts, so roots cannot widen them.
+//!
+//! `--translate-paths` is the lighter variant: mounts keep the target they
+//! were given, and paths are translated according to that mount table so a
+//! `-v ~/project:/workspace` server answers with paths that exist on the
+//! host. Roots are translated but not filtered.
+
+use crate::refusal::Refusal;
+use crate::ContainerExecutor;
+use serde_json::{json, Value};
+
+pub const ROOTS_DIR: &str = "/roots";
+
+/// Host paths paired with where they are mounted in the container.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathMap {
+    /// `(host, container)`, longest host path first so nested mounts win.
+    entries: Vec<(String, String)>,
+    /// Whether roots that nothing mounts are hidden from the server.
+    filter_roots: bool,
+}
+
+fn is_boundary(rest: &str) -> bool {
+    rest.chars()
+        .next()
+        .is_none_or(|c| !(c.is_alphanumeric() || "-_.~".contains(c)))
+}
+
+/// Replaces `from` with `to` wherever it is a whole path prefix.
+fn replace_prefix(text: &str, from: &str, to: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find(from) {
+        let after = &rest[at + from.len()..];
+        result.push_str(&rest[..at]);
+        result.push_str(if is_boundary(after) { to } else { from });
+        rest = after;
+    }
+    result.push_str(rest);
+    result
+}
+
+fn within<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+impl PathMap {
+    /// Mounts each host path under [`ROOTS_DIR`], named after its last
+    /// component.
+    pub fn for_host_paths(host_paths: &[String]) -> Self {
+        let mut entries: Vec<(String, String)> = Vec::new();
+        for host in host_paths {
+            let host = host.trim_end_matches('/');
+            if host.is_empty() || entries.iter().any(|(h, _)| h == host) {
+                continue;
+            }
+            let name = host.rsplit('/').next().unwrap_or("root");
+            let mut target = format!("{}/{}", ROOTS_DIR, name);
+            let mut n = 2;
+            while entries.iter().any(|(_, c)| *c == target) {
+                target = format!("{}/{}-{}", ROOTS_DIR, name, n);
+                n += 1;
+            }
+            entries.push((host.to_string(), target));
+        }
+        entries.sort_by_key(|(host, _)| std::cmp::Reverse(host.len()));
+        Self {
+            entries,
+            filter_roots: true,
+        }
+    }
+
+    /// Follows the bind mounts of docker arguments as they are. Mounts that
+    /// keep their host path need no translation and are left out.
+    pub fn from_mounts(args: &[String]) -> Self {
+        let mut entries: Vec<(String, String)> = Vec::new();
+        for volume in args
+            .windows(2)
+            .filter(|pair| pair[0] == "-v")
+            .map(|pair| &pair[1])
+        {
+            let mut parts = volume.split(':');
+            let (Some(host), Some(container)) = (parts.next(), parts.next()) else {
+                continue;
+            };
+            let host = host.trim_end_matches('/');
+            let container = container.trim_end_matches('/');
+            if !host.starts_with('/') || host == container || container.is_empty() {
+                continue;
+            }
+            if !entries.iter().any(|(h, _)| h == host) {
+                entries.push((host.to_string(), container.to_string()));
+            }
+        }
+        entries.sort_by_key(|(host, _)| std::cmp::Reverse(host.len()));
+        Self {
+            entries,
+            filter_roots: false,
+        }
+    }
+
+    pub fn filters_roots(&self) -> bool {
+        self.filter_roots
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Where `host` is mounted in the container.
+    pub fn target(&self, host: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(h, _)| h == host.trim_end_matches('/'))
+            .map(|(_, container)| container.as_str())
+    }
+
+    /// The container path of a host path, if a mount covers it.
+    pub fn host_to_container(&self, path: &str) -> Option<String> {
+        self.entries
+            .iter()
+            .find_map(|(host, container)| Some(format!("{}{}", container, within(path, host)?)))
+    }
+
+    pub fn to_container(&self, text: &str) -> String {
+        self.entries
+            .iter()
+            .fold(text.to_string(), |text, (host, container)| {
+                replace_prefix(&text, host, container)
+            })
+    }
+
+    pub fn to_host(&self, text: &str) -> String {
+        let mut entries: Vec<&(String, String)> = self.entries.iter().collect();
+        entries.sort_by_key(|(_, container)| std::cmp::Reverse(container.len()));
+        entries
+            .iter()
+            .fold(text.to_string(), |text, (host, container)| {
+                replace_prefix(&text, container, host)
+            })
+    }
+}
+
+/// Applies `translate` to every string in `value`, object keys included.
+pub fn translate_value(value: &Value, translate: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(text) => Value::String(translate(text)),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| translate_value(item, translate))
+                .collect(),
+        ),
+        Value::Object(object) => Value::Object(
+            object
+                .iter()
+                .map(|(key, item)| (translate(key), translate_value(item, translate)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Rewrites the client's answer to `roots/list` to container paths, and
+/// returns the roots that were removed because nothing mounts them.
+pub fn translate_roots(response: &mut Value, paths: &PathMap) -> Vec<String> {
+    let mut hidden = Vec::new();
+    let Some(roots) = response
+        .pointer_mut("/result/roots")
+        .and_then(Value::as_array_mut)
+    else {
+        return hidden;
+    };
+    roots.retain_mut(|root| {
+        let uri = root
+            .get("uri")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_string();
+        let translated = uri
+            .strip_prefix("file://")
+            .and_then(|path| paths.host_to_container(path));
+        match translated {
+            Some(path) => {
+                root["uri"] = json!(format!("file://{}", path));
+                true
+            }
+            None => {
+                hidden.push(uri);
+                false
+            }
+        }
+    });
+    hidden
+}
+
+pub fn hidden_roots(hidden: &[String]) -> Refusal {
+    Refusal {
+        reason: "roots_hidden",
+        rule: "permissions.storage.allow",
+        message: format!(
+            "Roots not mounted in the container (no policy grant), hidden from the server: {}",
+            hidden.join(", ")
+        ),
+        details: json!({ "roots": hidden }),
+    }
+}
+
+/// Host paths of the bind mounts in docker arguments.
+fn bind_mount_hosts(args: &[String]) -> Vec<String> {
+    args.windows(2)
+        .filter(|pair| pair[0] == "-v")
+        .filter_map(|pair| pair[1].split(':').next())
+        .filter(|host| host.starts_with('/'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Moves the bind mounts of `args` whose host path is in `paths` to their
+/// target under [`ROOTS_DIR`].
+pub fn translate_mounts(args: Vec<String>, paths: &PathMap) -> Vec<String> {
+    let mut translated = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let is_volume = arg == "-v";
+        translated.push(arg);
+        if !is_volume {
+            continue;
+        }
+        let Some(volume) = args.next() else {
+            break;
+        };
+        let parts: Vec<&str> = volume.splitn(3, ':').collect();
+        match (
+            parts.first().and_then(|host| paths.target(host)),
+            parts.get(2),
+        ) {
+            (Some(target), Some(mode)) => {
+                translated.push(format!("{}:{}:{}", parts[0], target, mode))
+            }
+            (Some(target), None) => translated.push(format!("{}:{}", parts[0], target)),
+            (None, _) => translated.push(volume),
+        }
+    }
+    translated
+}
+
+impl ContainerExecutor {
+    /// How paths translate between host and container, with
+    /// `--translate-roots` or `--translate-paths`.
+    pub fn path_map(&self) -> Option<PathMap> {
+        let mut mounts = self.engine.docker_args();
+        mounts.extend(self.extra_args.iter().cloned());
+        if self.translate_roots {
+            return Some(PathMap::for_host_paths(&bind_mount_hosts(&mounts)));
+        }
+        if !self.translate_paths {
+            return None;
+        }
+        Some(PathMap::from_mounts(&mounts)).filter(|paths| !paths.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths() -> PathMap {
+        PathMap::for_host_paths(&[
+            "/home/me/project".to_string(),
+            "/home/me/project/docs".to_string(),
+            "/srv/project".to_string(),
+        ])
+    }
+
+    #[test]
+    fn test_path_map_targets() {
+        let paths = paths();
+        assert_eq!(paths.target("/home/me/project"), Some("/roots/project"));
+        assert_eq!(paths.target("/home/me/project/docs"), Some("/roots/docs"));
+        assert_eq!(paths.target("/srv/project"), Some("/roots/project-2"));
+        assert_eq!(
+            paths
+                .host_to_container("/home/me/project/docs/a.md")
+                .as_deref(),
+            Some("/roots/docs/a.md")
+        );
+        assert_eq!(paths.host_to_container("/home/me/projects"), None);
+    }
+
+    #[test]
+    fn test_translation_round_trip() {
+        let paths = paths();
+        let text = "read /home/me/project/src/main.rs and /home/me/projects/x";
+        let container = paths.to_container(text);
+        assert_eq!(
+            container,
+            "read /roots/project/src/main.rs and /home/me/projects/x"
+        );
+        assert_eq!(paths.to_host(&container), text);
+    }
+
+    #[test]
+    fn test_translate_roots_hides_unmounted() {
+        let mut response = json!({
+            "jsonrpc": "2.0",
+            "id": 7,
+            "result": { "roots": [
+                { "uri": "file:///home/me/project", "name": "project" },
+                { "uri": "file:///home/me/secrets" }
+            ]}
+        });
+        let hidden = translate_roots(&mut response, &paths());
+        assert_eq!(hidden, ["file:///home/me/secrets"]);
+        assert_eq!(
+            response["result"]["roots"],
+            json!([{ "uri": "file:///roots/project", "name": "project" }])
+        );
+    }
+
+    #[test]
+    fn test_paths_from_mount_table() {
+        let args = [
+            "-v",
+            "/home/me/project:/workspace:rw",
+            "-v",
+            "/etc/localtime:/etc/localtime:ro",
+            "-v",
+            "semcp-state:/state",
+        ]
+        .map(str::to_string);
+        let paths = PathMap::from_mounts(&args);
+        assert!(!paths.filters_roots());
+        assert_eq!(paths.target("/etc/localtime"), None);
+        assert_eq!(
+            paths.to_host("created /workspace/notes.md"),
+            "created /home/me/project/notes.md"
+        );
+        assert_eq!(paths.to_container("/home/me/project"), "/workspace");
+    }
+
+    #[test]
+    fn test_translate_mounts() {
+        let args = vec![
+            "-v".to_string(),
+            "/home/me/project:/home/me/project:ro".to_string(),
+            "-v".to_string(),
+            "/etc/localtime:/etc/localtime:ro".to_string(),
+            "-e".to_string(),
+            "TZ=UTC".to_string(),
+        ];
+        let translated = translate_mounts(args, &paths());
+        assert_eq!(translated[1], "/home/me/project:/roots/project:ro");
+        assert_eq!(translated[3], "/etc/localtime:/etc/localtime:ro");
+    }
+}
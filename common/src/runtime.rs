@@ -0,0 +1,707 @@
+//! Container runtime backends.
+//!
+//! [`ContainerRuntime`] covers what semcp asks of a runtime: run, stop,
+//! inspect and pull. [`CliRuntime`] implements it for Docker-compatible
+//! command line tools: Docker, Podman and nerdctl (containerd). Unless
+//! `--runtime` picks one, the first installed runtime in that order is used.
+//! Rootless Podman additionally gets `--userns=keep-id` so files written to
+//! bind mounts keep the user's ownership, and with any rootless runtime the
+//! options it cannot apply are left out, see [`crate::rootless`].
+//!
+//! An [`Endpoint`] points the runtime at another daemon: a docker context
+//! (a Podman connection) or a host URL, with TLS certificates for Docker.
+//! Bind mounts name paths on the daemon's machine, so when the daemon is
+//! remote they are refused instead of silently mounting the wrong files.
+//! Without an endpoint, Docker falls back to the socket of a VM runtime such
+//! as Colima when the default socket does not answer, see [`crate::sockets`].
+
+use crate::apple::{self, AppleRuntime};
+use crate::probe::last_line;
+use crate::rootless;
+use crate::sockets;
+use crate::spec::RuntimeClass;
+use crate::ContainerExecutor;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use std::fmt;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::Command;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::process::Command as AsyncCommand;
+
+const KVM_DEVICE: &str = "/dev/kvm";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeKind {
+    Docker,
+    Podman,
+    Nerdctl,
+    /// Apple's `container` CLI, macOS 15 and later.
+    Apple,
+}
+
+impl RuntimeKind {
+    /// The value of `--runtime` selecting this runtime.
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeKind::Docker => "docker",
+            RuntimeKind::Podman => "podman",
+            RuntimeKind::Nerdctl => "nerdctl",
+            RuntimeKind::Apple => "apple",
+        }
+    }
+
+    pub fn binary(&self) -> &'static str {
+        match self {
+            RuntimeKind::Docker => "docker",
+            RuntimeKind::Podman => "podman",
+            RuntimeKind::Nerdctl => "nerdctl",
+            RuntimeKind::Apple => apple::BINARY,
+        }
+    }
+
+    pub fn runtime(&self) -> Arc<dyn ContainerRuntime> {
+        match self {
+            RuntimeKind::Apple => Arc::new(AppleRuntime::new()),
+            _ => Arc::new(CliRuntime::new(self.binary())),
+        }
+    }
+}
+
+/// The daemon a runtime talks to, when it is not the local default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Endpoint {
+    /// A docker context, or a Podman connection.
+    pub context: Option<String>,
+    /// A daemon URL such as `ssh://build@ci-host` or `tcp://10.0.0.5:2376`.
+    pub host: Option<String>,
+    /// Directory with `ca.pem`, `cert.pem` and `key.pem` for a TLS daemon.
+    pub tls_cert_path: Option<String>,
+}
+
+impl Endpoint {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fails for settings `binary` has no equivalent for.
+    pub fn validate(&self, binary: &str) -> Result<()> {
+        if self.context.is_some() && self.host.is_some() {
+            bail!("Use either a context or a daemon host, not both");
+        }
+        match binary {
+            "docker" => {}
+            "podman" if self.tls_cert_path.is_none() => {}
+            "podman" => bail!("Podman connects over ssh or a socket and takes no TLS certificates"),
+            apple::BINARY if !self.is_default() => {
+                bail!("Apple's container CLI only runs local containers")
+            }
+            _ if self.context.is_some() => bail!("{} has no contexts", binary),
+            _ if self.tls_cert_path.is_some() => {
+                bail!("{} takes no TLS certificates", binary)
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Global arguments that go before the subcommand.
+    pub fn global_args(&self, binary: &str) -> Vec<String> {
+        let mut args = Vec::new();
+        match (binary, &self.context, &self.host) {
+            ("docker", Some(context), _) => args.extend(["--context".to_string(), context.clone()]),
+            ("podman", Some(context), _) => {
+                args.extend(["--connection".to_string(), context.clone()])
+            }
+            ("podman", None, Some(host)) => args.extend(["--url".to_string(), host.clone()]),
+            ("nerdctl", None, Some(host)) => args.extend(["--address".to_string(), host.clone()]),
+            _ => {}
+        }
+        args
+    }
+
+    /// Environment variables the runtime reads its endpoint from.
+    pub fn env(&self, binary: &str) -> Vec<(String, String)> {
+        let mut env = Vec::new();
+        if binary != "docker" {
+            return env;
+        }
+        if let Some(ref host) = self.host {
+            env.push(("DOCKER_HOST".to_string(), host.clone()));
+        }
+        if let Some(ref path) = self.tls_cert_path {
+            env.push(("DOCKER_CERT_PATH".to_string(), path.clone()));
+            env.push(("DOCKER_TLS_VERIFY".to_string(), "1".to_string()));
+        }
+        env
+    }
+}
+
+/// Whether a daemon URL points at another machine.
+pub fn is_remote_url(url: &str) -> bool {
+    let url = url.trim();
+    !(url.is_empty() || url.starts_with("unix://") || url.starts_with("npipe://"))
+}
+
+/// Container options after [`ContainerRuntime::adapt_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdaptedOptions {
+    pub options: Vec<String>,
+    /// What was left out or changed, and why.
+    pub downgraded: Vec<String>,
+}
+
+pub type RuntimeFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+pub trait ContainerRuntime: fmt::Debug + Send + Sync {
+    /// Name used in messages, e.g. `docker`.
+    fn name(&self) -> &str;
+
+    fn is_available(&self) -> bool;
+
+    /// Command for other subcommands (volumes, exec, pause).
+    fn command(&self) -> AsyncCommand;
+
+    /// Extra `run` arguments this runtime needs.
+    fn run_args(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Command that starts a container from `run` arguments. The caller
+    /// decides how its stdio is connected.
+    fn run(&self, args: &[String]) -> AsyncCommand {
+        let mut command = self.command();
+        command.arg("run").args(args);
+        command
+    }
+
+    /// Container options adapted to what this runtime can apply.
+    fn adapt_options(&self, options: Vec<String>) -> AdaptedOptions {
+        AdaptedOptions {
+            options,
+            downgraded: vec![],
+        }
+    }
+
+    /// Stops `container`, giving it `grace` to exit before it is killed.
+    fn stop<'a>(&'a self, container: &'a str, grace: Duration) -> RuntimeFuture<'a, ()>;
+
+    /// Whether `image` is present locally.
+    fn inspect_image<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, bool>;
+
+    fn pull<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, ()>;
+
+    /// Whether the daemon runs on another machine, where host paths
+    /// cannot be bind mounted.
+    fn is_remote(&self) -> bool {
+        false
+    }
+
+    /// The daemon this runtime talks to, for diagnostics.
+    fn describe_endpoint(&self) -> Option<String> {
+        None
+    }
+
+    /// Whether containers can be started with `--runtime=<name>`.
+    fn has_oci_runtime<'a>(&'a self, name: &'a str) -> RuntimeFuture<'a, bool> {
+        Box::pin(async move { Ok(which::which(name).is_ok()) })
+    }
+}
+
+impl fmt::Display for dyn ContainerRuntime + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A runtime driven through a Docker-compatible CLI.
+#[derive(Debug)]
+pub struct CliRuntime {
+    binary: String,
+    endpoint: Endpoint,
+    available: OnceLock<bool>,
+    rootless: OnceLock<bool>,
+    remote: OnceLock<bool>,
+    controllers: OnceLock<Vec<String>>,
+    /// The VM runtime whose socket was picked automatically.
+    vm_socket: Option<&'static str>,
+}
+
+impl CliRuntime {
+    pub fn new(binary: &str) -> Self {
+        Self {
+            binary: binary.to_string(),
+            endpoint: Endpoint::default(),
+            available: OnceLock::new(),
+            rootless: OnceLock::new(),
+            remote: OnceLock::new(),
+            controllers: OnceLock::new(),
+            vm_socket: None,
+        }
+    }
+
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Points Docker at the socket of Colima, OrbStack or Lima when no
+    /// daemon was chosen and the default socket does not answer.
+    pub fn with_vm_socket(self) -> Self {
+        if self.binary != "docker" || !self.endpoint.is_default() {
+            return self;
+        }
+        match sockets::vm_socket() {
+            Some(candidate) => Self {
+                vm_socket: Some(candidate.provider),
+                ..self.with_endpoint(Endpoint {
+                    host: Some(candidate.url()),
+                    ..Endpoint::default()
+                })
+            },
+            None => self,
+        }
+    }
+
+    /// A blocking command for probes, pointed at the endpoint.
+    fn sync_command(&self) -> Command {
+        let mut command = Command::new(&self.binary);
+        command
+            .args(self.endpoint.global_args(&self.binary))
+            .envs(self.endpoint.env(&self.binary));
+        command
+    }
+
+    fn output_line(&self, args: &[&str]) -> Option<String> {
+        let output = self.sync_command().args(args).output().ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// The URL of the daemon, as far as it can be told.
+    fn daemon_url(&self) -> Option<String> {
+        if let Some(ref host) = self.endpoint.host {
+            return Some(host.clone());
+        }
+        let env_var = match self.binary.as_str() {
+            "docker" => "DOCKER_HOST",
+            "podman" => "CONTAINER_HOST",
+            _ => "CONTAINERD_ADDRESS",
+        };
+        if let Ok(host) = std::env::var(env_var) {
+            return Some(host);
+        }
+        match self.binary.as_str() {
+            "docker" => self.output_line(&[
+                "context",
+                "inspect",
+                "--format",
+                "{{.Endpoints.docker.Host}}",
+            ]),
+            "podman" => {
+                let connection = self.endpoint.context.as_ref()?;
+                self.output_line(&[
+                    "system",
+                    "connection",
+                    "list",
+                    "--format",
+                    "{{.Name}} {{.URI}}",
+                ])?
+                .lines()
+                .find_map(|line| {
+                    let (name, uri) = line.split_once(' ')?;
+                    (name == connection).then(|| uri.to_string())
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn docker() -> Self {
+        Self::new("docker")
+    }
+
+    pub fn podman() -> Self {
+        Self::new("podman")
+    }
+
+    pub fn nerdctl() -> Self {
+        Self::new("nerdctl")
+    }
+
+    /// The first installed runtime out of Docker, Podman and nerdctl.
+    pub fn detect() -> Option<Self> {
+        [Self::docker(), Self::podman(), Self::nerdctl()]
+            .into_iter()
+            .find(|runtime| which::which(&runtime.binary).is_ok())
+    }
+
+    /// Whether the daemon runs as the invoking user. Only a local daemon is
+    /// checked, since the cgroup delegation is read from this host.
+    fn is_rootless(&self) -> bool {
+        *self.rootless.get_or_init(|| {
+            if self.is_remote() {
+                return false;
+            }
+            match self.binary.as_str() {
+                "podman" => {
+                    self.output_line(&["info", "--format", "{{.Host.Security.Rootless}}"])
+                        .as_deref()
+                        == Some("true")
+                }
+                // docker and nerdctl list it among the security options
+                _ => self
+                    .output_line(&["info", "--format", "{{json .SecurityOptions}}"])
+                    .is_some_and(|options| options.contains("rootless")),
+            }
+        })
+    }
+}
+
+impl ContainerRuntime for CliRuntime {
+    fn name(&self) -> &str {
+        &self.binary
+    }
+
+    fn is_available(&self) -> bool {
+        *self.available.get_or_init(|| {
+            which::which(&self.binary).is_ok()
+                && self
+                    .sync_command()
+                    .arg("--version")
+                    .output()
+                    .map(|output| output.status.success())
+                    .unwrap_or(false)
+        })
+    }
+
+    fn command(&self) -> AsyncCommand {
+        let mut command = AsyncCommand::new(&self.binary);
+        command
+            .args(self.endpoint.global_args(&self.binary))
+            .envs(self.endpoint.env(&self.binary));
+        command
+    }
+
+    fn describe_endpoint(&self) -> Option<String> {
+        let url = self.daemon_url()?;
+        Some(match self.vm_socket {
+            Some(provider) => format!("{} ({}, found automatically)", url, provider),
+            None => url,
+        })
+    }
+
+    fn is_remote(&self) -> bool {
+        *self
+            .remote
+            .get_or_init(|| self.daemon_url().is_some_and(|url| is_remote_url(&url)))
+    }
+
+    fn run_args(&self) -> Vec<String> {
+        if self.binary == "podman" && self.is_rootless() {
+            vec!["--userns=keep-id".to_string()]
+        } else {
+            vec![]
+        }
+    }
+
+    fn adapt_options(&self, options: Vec<String>) -> AdaptedOptions {
+        if !self.is_rootless() {
+            return AdaptedOptions {
+                options,
+                downgraded: vec![],
+            };
+        }
+        let controllers = self
+            .controllers
+            .get_or_init(rootless::delegated_controllers);
+        rootless::adapt(options, controllers)
+    }
+
+    fn stop<'a>(&'a self, container: &'a str, grace: Duration) -> RuntimeFuture<'a, ()> {
+        Box::pin(async move {
+            self.command()
+                .args(["stop", "-t", &grace.as_secs().to_string(), container])
+                .output()
+                .await
+                .with_context(|| format!("Failed to execute {} stop", self.binary))?;
+            Ok(())
+        })
+    }
+
+    fn inspect_image<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, bool> {
+        Box::pin(async move {
+            let output = self
+                .command()
+                .args(["image", "inspect", image])
+                .output()
+                .await
+                .with_context(|| format!("Failed to execute {} image inspect", self.binary))?;
+            Ok(output.status.success())
+        })
+    }
+
+    fn pull<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, ()> {
+        Box::pin(async move {
+            let output = self
+                .command()
+                .args(["pull", image])
+                .output()
+                .await
+                .with_context(|| format!("Failed to execute {} pull", self.binary))?;
+            if !output.status.success() {
+                bail!("{}", last_line(&output.stderr));
+            }
+            Ok(())
+        })
+    }
+
+    fn has_oci_runtime<'a>(&'a self, name: &'a str) -> RuntimeFuture<'a, bool> {
+        Box::pin(async move {
+            // docker only accepts runtimes registered with the daemon
+            if self.binary != "docker" {
+                return Ok(which::which(name).is_ok());
+            }
+            let output = self
+                .command()
+                .args(["info", "--format", "{{json .Runtimes}}"])
+                .output()
+                .await
+                .with_context(|| format!("Failed to execute {} info", self.binary))?;
+            Ok(String::from_utf8_lossy(&output.stdout).contains(&format!("\"{}\"", name)))
+        })
+    }
+}
+
+impl ContainerExecutor {
+    /// The runtime class from the command line, else from the policy.
+    pub fn runtime_class(&self) -> RuntimeClass {
+        self.runtime_class
+            .or(self.policy_config.runtime.docker.runtime_class)
+            .unwrap_or(RuntimeClass::Runc)
+    }
+
+    /// Fails when the runtime class needs an OCI runtime that is missing,
+    /// or a VM on a host without hardware virtualization.
+    pub async fn check_runtime_class(&self) -> Result<()> {
+        let class = self.runtime_class();
+        let Some(oci_runtime) = class.oci_runtime() else {
+            return Ok(());
+        };
+        if !self.runtime.has_oci_runtime(oci_runtime).await? {
+            bail!(
+                "The {} runtime class needs the `{}` OCI runtime, which {} cannot use. \
+                 Install it and register {} with {} ({})",
+                class.name(),
+                oci_runtime,
+                self.runtime,
+                oci_runtime,
+                self.runtime,
+                class.install_guide()
+            );
+        }
+        if class.needs_kvm() && !Path::new(KVM_DEVICE).exists() {
+            bail!(
+                "The {} runtime class starts a VM per container, but {} is missing. \
+                 Enable hardware virtualization (or nested virtualization on a VM) and load the kvm module",
+                class.name(),
+                KVM_DEVICE
+            );
+        }
+        Ok(())
+    }
+}
+
+impl ContainerExecutor {
+    /// Fails when the daemon is remote and the run bind mounts host paths,
+    /// which would name files on the daemon's machine.
+    pub fn check_remote_mounts(&self) -> Result<()> {
+        if !self.runtime.is_remote() {
+            return Ok(());
+        }
+        let options = self.container_options();
+        let mounts: Vec<&str> = options
+            .windows(2)
+            .filter(|pair| pair[0] == "-v" && pair[1].starts_with('/'))
+            .filter_map(|pair| pair[1].split(':').next())
+            .collect();
+        if !mounts.is_empty() {
+            bail!(
+                "The {} daemon is remote, so bind mounts would use its files, not yours: {}. \
+                 Use named volumes from the policy, or a local daemon",
+                self.runtime,
+                mounts.join(", ")
+            );
+        }
+        Ok(())
+    }
+}
+
+static CURRENT: OnceLock<Arc<dyn ContainerRuntime>> = OnceLock::new();
+
+/// The first installed runtime out of Docker, Podman and nerdctl, then
+/// Apple's `container` CLI on macOS. Falls back to Docker so errors name the
+/// runtime most users expect.
+fn detect() -> Arc<dyn ContainerRuntime> {
+    match CliRuntime::detect() {
+        Some(runtime) => Arc::new(runtime.with_vm_socket()),
+        None if AppleRuntime::is_installed() => Arc::new(AppleRuntime::new()),
+        None => Arc::new(CliRuntime::docker()),
+    }
+}
+
+/// The runtime `kind` (or the detected one) talking to `endpoint`.
+pub fn select(kind: Option<RuntimeKind>, endpoint: Endpoint) -> Result<Arc<dyn ContainerRuntime>> {
+    let runtime = match kind {
+        Some(RuntimeKind::Apple) => {
+            endpoint.validate(apple::BINARY)?;
+            return Ok(RuntimeKind::Apple.runtime());
+        }
+        Some(kind) => CliRuntime::new(kind.binary()),
+        None if endpoint.is_default() => return Ok(detect()),
+        None => CliRuntime::detect().unwrap_or_else(CliRuntime::docker),
+    };
+    endpoint.validate(&runtime.binary)?;
+    Ok(Arc::new(runtime.with_endpoint(endpoint).with_vm_socket()))
+}
+
+/// Makes `kind` at `endpoint` the runtime of this process. Must be called
+/// before anything uses [`current`].
+pub fn set_current(kind: Option<RuntimeKind>, endpoint: Endpoint) -> Result<()> {
+    CURRENT
+        .set(select(kind, endpoint)?)
+        .map_err(|_| anyhow!("The container runtime was already selected"))
+}
+
+/// The runtime selected for this process, or the detected one.
+pub fn current() -> Arc<dyn ContainerRuntime> {
+    CURRENT.get_or_init(detect).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContainerExecutor;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Default)]
+    struct FakeRuntime {
+        stopped: Mutex<Vec<String>>,
+        remote: bool,
+    }
+
+    impl ContainerRuntime for FakeRuntime {
+        fn name(&self) -> &str {
+            "fake"
+        }
+
+        fn is_available(&self) -> bool {
+            true
+        }
+
+        fn command(&self) -> AsyncCommand {
+            AsyncCommand::new("true")
+        }
+
+        fn run_args(&self) -> Vec<String> {
+            vec!["--fake-runtime".to_string()]
+        }
+
+        fn is_remote(&self) -> bool {
+            self.remote
+        }
+
+        fn stop<'a>(&'a self, container: &'a str, _grace: Duration) -> RuntimeFuture<'a, ()> {
+            self.stopped.lock().unwrap().push(container.to_string());
+            Box::pin(async { Ok(()) })
+        }
+
+        fn inspect_image<'a>(&'a self, _image: &'a str) -> RuntimeFuture<'a, bool> {
+            Box::pin(async { Ok(true) })
+        }
+
+        fn pull<'a>(&'a self, _image: &'a str) -> RuntimeFuture<'a, ()> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[test]
+    fn test_docker_needs_no_extra_run_args() {
+        let docker = CliRuntime::docker();
+        assert_eq!(docker.name(), "docker");
+        assert!(docker.run_args().is_empty());
+    }
+
+    #[test]
+    fn test_endpoint_arguments() {
+        let context = Endpoint {
+            context: Some("ci".to_string()),
+            ..Endpoint::default()
+        };
+        assert_eq!(context.global_args("docker"), ["--context", "ci"]);
+        assert_eq!(context.global_args("podman"), ["--connection", "ci"]);
+        assert!(context.validate("nerdctl").is_err());
+
+        let host = Endpoint {
+            host: Some("tcp://10.0.0.5:2376".to_string()),
+            tls_cert_path: Some("/certs".to_string()),
+            ..Endpoint::default()
+        };
+        assert!(host.global_args("docker").is_empty());
+        assert_eq!(
+            host.env("docker"),
+            [
+                ("DOCKER_HOST".to_string(), "tcp://10.0.0.5:2376".to_string()),
+                ("DOCKER_CERT_PATH".to_string(), "/certs".to_string()),
+                ("DOCKER_TLS_VERIFY".to_string(), "1".to_string()),
+            ]
+        );
+        assert!(host.validate("podman").is_err());
+        assert!(host.validate(apple::BINARY).is_err());
+        assert!(Endpoint::default().validate(apple::BINARY).is_ok());
+        assert!(is_remote_url("ssh://build@ci-host"));
+        assert!(!is_remote_url("unix:///var/run/docker.sock"));
+    }
+
+    #[test]
+    fn test_executor_uses_plugged_runtime() {
+        let fake = Arc::new(FakeRuntime::default());
+        let executor =
+            ContainerExecutor::new("node:24-alpine".to_string(), false).with_runtime(fake.clone());
+
+        assert_eq!(executor.runtime().name(), "fake");
+        assert!(executor
+            .container_options()
+            .contains(&"--fake-runtime".to_string()));
+
+        // stop() records synchronously, so the future need not be polled
+        drop(
+            executor
+                .runtime()
+                .stop(executor.container_name(), Duration::ZERO),
+        );
+        assert_eq!(*fake.stopped.lock().unwrap(), [executor.container_name()]);
+    }
+
+    #[test]
+    fn test_remote_daemon_refuses_bind_mounts() {
+        let remote = Arc::new(FakeRuntime {
+            remote: true,
+            ..FakeRuntime::default()
+        });
+        let executor =
+            ContainerExecutor::new("node:24-alpine".to_string(), false).with_runtime(remote);
+        assert!(executor.check_remote_mounts().is_ok());
+        let executor = executor.with_extra_args(vec![
+            "-v".to_string(),
+            "/home/me/project:/project".to_string(),
+        ]);
+        let error = executor.check_remote_mounts().unwrap_err().to_string();
+        assert!(error.contains("/home/me/project"));
+    }
+}
@@ -0,0 +1,568 @@
+//! Host fallback under bubblewrap (Linux) or `sandbox-exec` (macOS).
+//!
+//! With `--sandbox-fallback`, a host without a container runtime runs the
+//! package with the host's own `npx`/`uvx` inside a sandbox instead of
+//! refusing to start. The sandbox is built from the same container options
+//! as a container run, so the policy still decides what is mounted, which
+//! variables are set and whether there is network. Under bubblewrap:
+//!
+//! - all namespaces are unshared (the network one is shared back unless the
+//!   options say `--network none`);
+//! - only system directories, the runner's install prefix and the mounts of
+//!   the policy are visible, `/tmp` and `$HOME` are empty tmpfs;
+//! - the environment is cleared and rebuilt from the `-e` options;
+//! - a seccomp filter denies syscalls a package has no business making
+//!   (ptrace, mount, module loading, bpf, keyrings...) on x86_64 and aarch64.
+//!
+//! Options that bubblewrap cannot honor, such as named volumes, added
+//! capabilities or a runtime class, fail the run rather than being dropped.
+//! This is weaker isolation than a container: the host kernel, `/usr` and
+//! `/etc` are shared. See [`crate::seatbelt`] for macOS.
+
+use crate::proxy;
+use crate::seatbelt;
+use crate::{ContainerExecutor, Runner};
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use tokio::process::Command as AsyncCommand;
+
+/// Host directories the sandbox sees read-only.
+pub(crate) const SYSTEM_DIRS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/lib",
+    "/lib32",
+    "/lib64",
+    "/etc",
+    "/opt",
+    "/run/systemd/resolve",
+];
+
+const SANDBOX_HOME: &str = "/tmp/home";
+
+/// `AF_INET` and `AF_INET6`, the socket families denied without network.
+const INET_FAMILIES: [u32; 2] = [2, 10];
+
+/// File descriptor the seccomp filter is passed to bubblewrap on.
+const SECCOMP_FD: u32 = 10;
+
+// classic BPF, see linux/filter.h and linux/seccomp.h
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JEQ_K: u16 = 0x15;
+const BPF_JGE_K: u16 = 0x35;
+const BPF_RET_K: u16 = 0x06;
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO_EPERM: u32 = 0x0005_0001;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_DATA_NR: u32 = 0;
+const SECCOMP_DATA_ARCH: u32 = 4;
+const SECCOMP_DATA_ARGS: u32 = 16;
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// Audit arch and numbers of the denied syscalls: ptrace, mount, umount2,
+/// pivot_root, swapon, swapoff, reboot, kexec_load, kexec_file_load,
+/// init_module, finit_module, delete_module, bpf, perf_event_open,
+/// userfaultfd, keyctl, add_key, request_key, process_vm_readv,
+/// process_vm_writev, open_by_handle_at, unshare and setns.
+#[cfg(target_arch = "x86_64")]
+pub(crate) const DENIED_SYSCALLS: Option<(u32, &[u32])> = Some((
+    0xC000_003E,
+    &[
+        101, 165, 166, 155, 167, 168, 169, 246, 320, 175, 313, 176, 321, 298, 323, 250, 248, 249,
+        310, 311, 304, 272, 308,
+    ],
+));
+#[cfg(target_arch = "aarch64")]
+pub(crate) const DENIED_SYSCALLS: Option<(u32, &[u32])> = Some((
+    0xC000_00B7,
+    &[
+        117, 40, 39, 41, 224, 225, 142, 104, 294, 105, 273, 106, 280, 241, 282, 219, 217, 218, 270,
+        271, 265, 97, 268,
+    ],
+));
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) const DENIED_SYSCALLS: Option<(u32, &[u32])> = None;
+
+/// Number of the socket syscall.
+#[cfg(target_arch = "x86_64")]
+pub(crate) const SOCKET_SYSCALL: u32 = 41;
+#[cfg(target_arch = "aarch64")]
+pub(crate) const SOCKET_SYSCALL: u32 = 198;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub(crate) const SOCKET_SYSCALL: u32 = 0;
+
+fn bpf(code: u16, jt: u8, jf: u8, k: u32) -> [u8; 8] {
+    let mut instruction = [0u8; 8];
+    instruction[..2].copy_from_slice(&code.to_ne_bytes());
+    instruction[2] = jt;
+    instruction[3] = jf;
+    instruction[4..].copy_from_slice(&k.to_ne_bytes());
+    instruction
+}
+
+/// A seccomp program that kills other architectures, fails the denied
+/// syscalls with EPERM and allows the rest. With `deny_network`, the socket
+/// syscall `deny_network` also fails for IPv4 and IPv6 sockets.
+pub fn seccomp_filter(arch: u32, denied: &[u32], deny_network: Option<u32>) -> Vec<u8> {
+    let mut program = vec![
+        bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARCH),
+        bpf(BPF_JEQ_K, 1, 0, arch),
+        bpf(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+        bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_NR),
+        // x32 syscalls would bypass the numbers below
+        bpf(BPF_JGE_K, 0, 1, X32_SYSCALL_BIT),
+        bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO_EPERM),
+    ];
+    for nr in denied {
+        program.push(bpf(BPF_JEQ_K, 0, 1, *nr));
+        program.push(bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO_EPERM));
+    }
+    if let Some(socket) = deny_network {
+        let [inet, inet6] = INET_FAMILIES;
+        program.extend([
+            bpf(BPF_JEQ_K, 0, 5, socket),
+            // the low word of the first argument, the family
+            bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARGS),
+            bpf(BPF_JEQ_K, 2, 0, inet),
+            bpf(BPF_JEQ_K, 1, 0, inet6),
+            bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW),
+            bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO_EPERM),
+        ]);
+    }
+    program.push(bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+    program.concat()
+}
+
+/// A bind mount of the container options.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub host: String,
+    pub target: String,
+    pub read_only: bool,
+}
+
+/// What the container options ask of a sandbox.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SandboxOptions {
+    pub mounts: Vec<Mount>,
+    pub env: Vec<(String, String)>,
+    pub network: bool,
+}
+
+/// Reads the container options a sandbox can honor, and fails on the ones
+/// it cannot. `env` looks up the host value of a variable passed as
+/// `-e KEY`.
+pub fn parse_options(
+    options: &[String],
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<SandboxOptions> {
+    let mut parsed = SandboxOptions {
+        network: true,
+        ..SandboxOptions::default()
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let mut value = || {
+            options
+                .next()
+                .with_context(|| format!("{} is missing its value", option))
+        };
+        match option.as_str() {
+            "-v" | "--volume" => {
+                let volume = value()?;
+                let parts: Vec<&str> = volume.split(':').collect();
+                let (host, target) = match parts.as_slice() {
+                    [host] => (*host, *host),
+                    [host, target, ..] => (*host, *target),
+                    [] => unreachable!(),
+                };
+                if !host.starts_with('/') {
+                    bail!(
+                        "The named volume `{}` needs a container runtime, the sandbox can only bind host paths",
+                        host
+                    );
+                }
+                let read_only = parts
+                    .get(2)
+                    .is_some_and(|mode| mode.split(',').any(|m| m == "ro"));
+                parsed.mounts.push(Mount {
+                    host: host.to_string(),
+                    target: target.to_string(),
+                    read_only,
+                });
+            }
+            "-e" | "--env" => {
+                let variable = value()?;
+                match variable.split_once('=') {
+                    Some((key, val)) => parsed.env.push((key.to_string(), val.to_string())),
+                    None => {
+                        if let Some(val) = env(variable) {
+                            parsed.env.push((variable.clone(), val));
+                        }
+                    }
+                }
+            }
+            "--network" => parsed.network = value()? != "none",
+            // unprivileged bubblewrap holds no capabilities and always sets
+            // no_new_privs
+            "--cap-drop" => {
+                value()?;
+            }
+            "--security-opt" => {
+                let security_opt = value()?;
+                if !security_opt.starts_with("no-new-privileges") {
+                    bail!(
+                        "The sandbox cannot apply --security-opt {}, it needs a container runtime",
+                        security_opt
+                    );
+                }
+            }
+            // everything but the granted mounts is read-only already
+            "--read-only" => {}
+            "--cap-add" => bail!(
+                "The sandbox cannot grant the capability {}, it needs a container runtime",
+                value()?
+            ),
+            other if other.starts_with("--runtime=") => bail!(
+                "The policy asks for {}, which needs a container runtime",
+                other
+            ),
+            other => bail!("The container option {} has no sandbox equivalent", other),
+        }
+    }
+    Ok(parsed)
+}
+
+/// Translates container options into bubblewrap arguments.
+pub fn translate_options(
+    options: &[String],
+    env: impl Fn(&str) -> Option<String>,
+) -> Result<Vec<String>> {
+    let parsed = parse_options(options, env)?;
+    let mut args = Vec::new();
+    for mount in parsed.mounts {
+        let bind = if mount.read_only {
+            "--ro-bind"
+        } else {
+            "--bind"
+        };
+        args.extend([bind.to_string(), mount.host, mount.target]);
+    }
+    for (key, val) in parsed.env {
+        args.extend(["--setenv".to_string(), key, val]);
+    }
+    if parsed.network {
+        args.push("--share-net".to_string());
+    }
+    Ok(args)
+}
+
+/// Install prefix directories of the host's runner, when it lives outside
+/// `system_dirs` (nvm, `~/.local`...).
+pub(crate) fn runner_dirs(runner_path: &Path, system_dirs: &[&str]) -> Vec<PathBuf> {
+    let Some(bin) = runner_path.parent() else {
+        return vec![];
+    };
+    if system_dirs.iter().any(|dir| bin.starts_with(dir)) {
+        return vec![];
+    }
+    let mut dirs = vec![bin.to_path_buf()];
+    if let Some(prefix) = bin.parent() {
+        dirs.push(prefix.join("lib"));
+    }
+    dirs
+}
+
+fn base_args(runner_path: &Path) -> Vec<String> {
+    let mut args: Vec<String> = [
+        "--unshare-all",
+        "--die-with-parent",
+        "--new-session",
+        "--clearenv",
+        "--proc",
+        "/proc",
+        "--dev",
+        "/dev",
+        "--tmpfs",
+        "/tmp",
+        "--dir",
+        SANDBOX_HOME,
+        "--setenv",
+        "HOME",
+        SANDBOX_HOME,
+        "--chdir",
+        SANDBOX_HOME,
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+    let dirs = SYSTEM_DIRS
+        .iter()
+        .map(PathBuf::from)
+        .chain(runner_dirs(runner_path, SYSTEM_DIRS));
+    for dir in dirs {
+        let dir = dir.to_string_lossy().into_owned();
+        args.extend(["--ro-bind-try".to_string(), dir.clone(), dir]);
+    }
+    if let Ok(path) = std::env::var("PATH") {
+        args.extend(["--setenv".to_string(), "PATH".to_string(), path]);
+    }
+    args
+}
+
+/// A file or directory the sandbox needs while it runs, removed on drop.
+pub(crate) enum Scratch {
+    File(PathBuf),
+    Dir(PathBuf),
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = match self {
+            Scratch::File(path) => std::fs::remove_file(path),
+            Scratch::Dir(path) => std::fs::remove_dir_all(path),
+        };
+    }
+}
+
+impl ContainerExecutor {
+    /// Runs the package on the host under bubblewrap or `sandbox-exec`, for
+    /// hosts without a container runtime.
+    pub async fn run_fallback<R: Runner>(
+        &self,
+        runner: &R,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<ExitStatus> {
+        if !runner.supports_fallback() {
+            bail!("{} has no sandbox fallback", runner.command());
+        }
+        let runner_path = which::which(runner.command()).with_context(|| {
+            format!(
+                "The sandbox fallback runs the host's {}, which is not installed",
+                runner.command()
+            )
+        })?;
+        let (command, _scratch) = if cfg!(target_os = "linux") {
+            self.bwrap_command(runner, &runner_path, flags, args)?
+        } else if cfg!(target_os = "macos") {
+            self.seatbelt_command(runner, &runner_path, flags, args)?
+        } else {
+            bail!("The sandbox fallback needs Linux or macOS");
+        };
+        self.run_sandbox(command).await
+    }
+
+    /// Runs a sandboxed command, through the proxy when the run needs it.
+    pub(crate) async fn run_sandbox(&self, mut command: AsyncCommand) -> Result<ExitStatus> {
+        command.kill_on_drop(true);
+
+        let proxy_options = self.proxy_options();
+        if proxy_options.is_some() {
+            command.stdin(Stdio::piped()).stdout(Stdio::piped());
+        }
+        let mut child = command.spawn().context("Failed to spawn the sandbox")?;
+        let run = async {
+            if let Some(ref options) = proxy_options {
+                let exit = proxy::proxy_stdio(&mut child, options, self.verbose).await?;
+                if exit == proxy::ProxyExit::Idle {
+                    child.kill().await?;
+                }
+            }
+            child.wait().await.context("Failed to wait for the sandbox")
+        };
+        tokio::select! {
+            result = run => result,
+            _ = tokio::signal::ctrl_c() => std::process::exit(130),
+        }
+    }
+
+    fn bwrap_command<R: Runner>(
+        &self,
+        runner: &R,
+        runner_path: &Path,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<(AsyncCommand, Vec<Scratch>)> {
+        let bwrap = which::which("bwrap")
+            .context("The sandbox fallback needs bubblewrap (bwrap) to be installed")?;
+
+        let mut sandbox_args = base_args(runner_path);
+        sandbox_args.extend(translate_options(
+            &self.container_options(),
+            self.env_lookup(),
+        )?);
+        sandbox_args.push("--".to_string());
+        sandbox_args.extend(self.command_args(runner, flags, args));
+
+        let (mut command, scratch) = match DENIED_SYSCALLS {
+            Some((arch, denied)) => {
+                let path = std::env::temp_dir().join(format!("{}.seccomp", self.container_name));
+                std::fs::write(&path, seccomp_filter(arch, denied, None))
+                    .context("Failed to write the seccomp filter")?;
+                let filter = Scratch::File(path.clone());
+                // bwrap reads the filter from an inherited descriptor
+                let mut command = AsyncCommand::new("/bin/sh");
+                command
+                    .arg("-c")
+                    .arg(format!(
+                        "f=$1; b=$2; shift 2; exec \"$b\" --seccomp {fd} \"$@\" {fd}<\"$f\"",
+                        fd = SECCOMP_FD
+                    ))
+                    .arg("sh")
+                    .arg(&path)
+                    .arg(&bwrap);
+                (command, vec![filter])
+            }
+            None => {
+                eprintln!("Warning: no seccomp filter for this architecture");
+                (AsyncCommand::new(&bwrap), vec![])
+            }
+        };
+        command.args(&sandbox_args);
+
+        if self.verbose {
+            eprintln!(
+                "No container runtime, running in a bubblewrap sandbox: bwrap {}",
+                sandbox_args.join(" ")
+            );
+        }
+        Ok((command, scratch))
+    }
+
+    fn seatbelt_command<R: Runner>(
+        &self,
+        runner: &R,
+        runner_path: &Path,
+        flags: &[String],
+        args: &[String],
+    ) -> Result<(AsyncCommand, Vec<Scratch>)> {
+        let sandbox_exec = which::which("sandbox-exec")
+            .context("The sandbox fallback needs sandbox-exec, which ships with macOS")?;
+        let options = parse_options(&self.container_options(), self.env_lookup())?;
+
+        let home = std::env::temp_dir().join(format!("{}.home", self.container_name));
+        std::fs::create_dir_all(&home)
+            .with_context(|| format!("Failed to create {}", home.display()))?;
+        let scratch = Scratch::Dir(home.clone());
+        let profile = seatbelt::profile(&options, runner_path, &home)?;
+
+        let mut command = AsyncCommand::new(&sandbox_exec);
+        command
+            .arg("-p")
+            .arg(&profile)
+            .args(self.command_args(runner, flags, args))
+            .env_clear()
+            .env("HOME", &home)
+            .env("TMPDIR", &home)
+            .envs(options.env.iter().map(|(key, val)| (key, val)))
+            .current_dir(&home);
+        if let Ok(path) = std::env::var("PATH") {
+            command.env("PATH", path);
+        }
+
+        if self.verbose {
+            eprintln!(
+                "No container runtime, running under sandbox-exec with profile:\n{}",
+                profile
+            );
+        }
+        Ok((command, vec![scratch]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_translate_options() {
+        let options = strings(&[
+            "-v",
+            "/srv/data:/data:ro",
+            "-v",
+            "/home/me/out:/home/me/out:rw",
+            "-e",
+            "TZ=UTC",
+            "-e",
+            "TOKEN",
+            "-e",
+            "UNSET",
+            "--security-opt",
+            "no-new-privileges",
+            "--network",
+            "none",
+        ]);
+        let args = translate_options(&options, |key| {
+            (key == "TOKEN").then(|| "secret".to_string())
+        })
+        .unwrap();
+        assert_eq!(
+            args,
+            strings(&[
+                "--ro-bind",
+                "/srv/data",
+                "/data",
+                "--bind",
+                "/home/me/out",
+                "/home/me/out",
+                "--setenv",
+                "TZ",
+                "UTC",
+                "--setenv",
+                "TOKEN",
+                "secret",
+            ])
+        );
+        assert_eq!(translate_options(&[], |_| None).unwrap(), ["--share-net"]);
+    }
+
+    #[test]
+    fn test_translate_rejects_what_it_cannot_enforce() {
+        for options in [
+            strings(&["-v", "semcp-state:/state"]),
+            strings(&["--cap-add", "NET_ADMIN"]),
+            strings(&["--runtime=runsc"]),
+            strings(&["--privileged"]),
+        ] {
+            assert!(translate_options(&options, |_| None).is_err());
+        }
+    }
+
+    #[test]
+    fn test_seccomp_filter() {
+        let program = seccomp_filter(0xC000_003E, &[101, 165], None);
+        // 6 header instructions, 2 per syscall and the final allow
+        assert_eq!(program.len(), (6 + 2 * 2 + 1) * 8);
+        assert_eq!(
+            seccomp_filter(0xC000_003E, &[101, 165], Some(41)).len(),
+            program.len() + 6 * 8
+        );
+        assert_eq!(
+            &program[program.len() - 4..],
+            &SECCOMP_RET_ALLOW.to_ne_bytes()
+        );
+        assert_eq!(&program[4..8], &SECCOMP_DATA_ARCH.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_runner_dirs() {
+        assert!(runner_dirs(Path::new("/usr/bin/npx"), SYSTEM_DIRS).is_empty());
+        assert_eq!(
+            runner_dirs(
+                Path::new("/home/me/.nvm/versions/node/v24/bin/npx"),
+                SYSTEM_DIRS
+            ),
+            [
+                PathBuf::from("/home/me/.nvm/versions/node/v24/bin"),
+                PathBuf::from("/home/me/.nvm/versions/node/v24/lib"),
+            ]
+        );
+    }
+}
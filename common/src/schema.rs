@@ -0,0 +1,75 @@
+//! JSON Schema of the policy format, for `snpx policy schema`.
+//!
+//! The `permissions` and `engine` sections come straight from the structs
+//! semcp loads them into, [`PermissionsSpec`] and [`EngineSpec`]. Keys semcp
+//! does not read are allowed, as loading ignores them; `snpx policy
+//! validate` reports them.
+
+use crate::engine::EngineSpec;
+use crate::merge::MergeMode;
+use crate::spec::PermissionsSpec;
+use schemars::{schema_for, JsonSchema};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// An snpx/suvx policy file.
+#[derive(Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct PolicyFile {
+    /// Format version, `1.0`.
+    version: Option<String>,
+    description: Option<String>,
+    /// Policies this one is layered on, by path relative to this file,
+    /// `oci://` reference or `https://` URL.
+    extends: Option<Extends>,
+    /// Keys, as dotted paths, whose values replace those of the policies
+    /// below instead of being merged with them.
+    merge: Option<BTreeMap<String, MergeMode>>,
+    /// Which engine decides, see the README on policy engines.
+    engine: Option<EngineSpec>,
+    permissions: Option<PermissionsSpec>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum Extends {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// The schema of policy files, JSON Schema draft 2020-12.
+pub fn policy_schema() -> Value {
+    let mut schema = serde_json::to_value(schema_for!(PolicyFile)).unwrap_or_default();
+    schema["title"] = "snpx policy".into();
+    schema
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_schema() {
+        let schema = policy_schema();
+        assert_eq!(schema["title"], "snpx policy");
+        assert!(!schema["properties"]["permissions"].is_null());
+        assert!(schema
+            .pointer("/$defs/SecuritySpec/properties/capabilities")
+            .is_some());
+
+        let text = schema.to_string();
+        for key in [
+            "filter_dns",
+            "blocked_ports",
+            "memory_limit",
+            "idle_timeout",
+            "no_new_privileges",
+            "env_mapping",
+            "trusted_publishers",
+        ] {
+            assert!(text.contains(&format!("\"{}\"", key)), "{}", key);
+        }
+    }
+}
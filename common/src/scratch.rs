@@ -0,0 +1,105 @@
+//! Private scratch directories for files handed to other programs.
+//!
+//! Names under the shared temp directory are predictable, so files are not
+//! written there directly: each scratch directory is created fresh and
+//! readable by its owner only, and files in it are created exclusively, so
+//! neither follows a link planted by another user.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{DirBuilder, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A directory under the temp directory, removed with its files when
+/// dropped.
+#[derive(Debug)]
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    pub fn new(prefix: &str) -> Result<Self> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or_default();
+        for _ in 0..16 {
+            let path = std::env::temp_dir().join(format!(
+                "{}-{}-{}-{}",
+                prefix,
+                std::process::id(),
+                nanos,
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut builder = DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            match builder.create(&path) {
+                Ok(()) => return Ok(Self(path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("Failed to create {}", path.display()))
+                }
+            }
+        }
+        bail!(
+            "Failed to create a scratch directory in {}",
+            std::env::temp_dir().display()
+        )
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes the file `name`, which must not exist yet, and returns its
+    /// path.
+    pub fn write(&self, name: &str, contents: &[u8]) -> Result<PathBuf> {
+        let path = self.0.join(name);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        options
+            .open(&path)
+            .and_then(|mut file| file.write_all(contents))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scratch_dir() {
+        let scratch = ScratchDir::new("semcp-scratch-test").unwrap();
+        let other = ScratchDir::new("semcp-scratch-test").unwrap();
+        assert_ne!(scratch.path(), other.path());
+
+        let path = scratch.write("policy.yaml", b"version: '1.0'").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"version: '1.0'");
+        assert!(scratch.write("policy.yaml", b"again").is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(scratch.path())
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        let dir = scratch.path().to_path_buf();
+        drop(scratch);
+        assert!(!dir.exists());
+    }
+}
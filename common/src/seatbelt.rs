@@ -0,0 +1,151 @@
+//! `sandbox-exec` profiles for the macOS sandbox fallback.
+//!
+//! The profile denies everything by default, then allows what a runner
+//! needs: reading the system directories and its install prefix, a scratch
+//! `$HOME`, and the mounts of the container options, read-only or writable
+//! as declared. Network access is allowed unless the options say
+//! `--network none`.
+//!
+//! Seatbelt cannot remap paths, so a mount whose target differs from its
+//! host path fails the run, as does everything [`parse_options`] rejects.
+//!
+//! [`parse_options`]: crate::sandbox::parse_options
+
+use crate::sandbox::{runner_dirs, SandboxOptions};
+use anyhow::{bail, Result};
+use std::path::Path;
+
+/// Host directories the sandbox can read.
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr",
+    "/bin",
+    "/sbin",
+    "/System",
+    "/Library",
+    "/opt/homebrew",
+    "/private/etc",
+    "/private/var/db/timezone",
+    "/dev",
+];
+
+/// Devices the sandbox can write to.
+const WRITABLE_DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/tty", "/dev/dtracehelper"];
+
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Seatbelt matches resolved paths, and `/tmp`, `/var` and `/etc` are
+/// symlinks into `/private` on macOS.
+fn resolve(path: &Path) -> String {
+    std::fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_path_buf())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn subpaths(paths: impl IntoIterator<Item = String>) -> String {
+    paths
+        .into_iter()
+        .map(|path| format!(" (subpath {})", quote(&path)))
+        .collect()
+}
+
+/// The profile for `options`, with `home` as the scratch home directory.
+pub fn profile(options: &SandboxOptions, runner_path: &Path, home: &Path) -> Result<String> {
+    let mut readable: Vec<String> = SYSTEM_DIRS.iter().map(|dir| dir.to_string()).collect();
+    readable.extend(
+        runner_dirs(runner_path, SYSTEM_DIRS)
+            .iter()
+            .map(|dir| resolve(dir)),
+    );
+    let mut writable = vec![resolve(home)];
+    for mount in &options.mounts {
+        if mount.host != mount.target {
+            bail!(
+                "sandbox-exec cannot mount {} at {}, only at its own path",
+                mount.host,
+                mount.target
+            );
+        }
+        let host = resolve(Path::new(&mount.host));
+        if mount.read_only {
+            readable.push(host);
+        } else {
+            writable.push(host);
+        }
+    }
+
+    let mut profile = String::from(
+        "(version 1)\n\
+         (deny default)\n\
+         (allow process-fork process-exec signal)\n\
+         (allow sysctl-read mach-lookup ipc-posix-shm)\n\
+         (allow file-read-metadata)\n",
+    );
+    profile.push_str(&format!("(allow file-read*{})\n", subpaths(readable)));
+    profile.push_str(&format!(
+        "(allow file-read* file-write*{})\n",
+        subpaths(writable)
+    ));
+    profile.push_str(&format!(
+        "(allow file-write-data{})\n",
+        WRITABLE_DEVICES
+            .iter()
+            .map(|device| format!(" (literal {})", quote(device)))
+            .collect::<String>()
+    ));
+    if options.network {
+        profile.push_str("(allow network*)\n");
+    }
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sandbox::Mount;
+
+    fn mount(host: &str, target: &str, read_only: bool) -> Mount {
+        Mount {
+            host: host.to_string(),
+            target: target.to_string(),
+            read_only,
+        }
+    }
+
+    #[test]
+    fn test_profile_follows_the_options() {
+        let options = SandboxOptions {
+            mounts: vec![
+                mount("/Users/me/docs", "/Users/me/docs", true),
+                mount("/Users/me/out", "/Users/me/out", false),
+            ],
+            env: vec![],
+            network: false,
+        };
+        let profile = profile(
+            &options,
+            Path::new("/opt/homebrew/bin/npx"),
+            Path::new("/scratch/home"),
+        )
+        .unwrap();
+        assert!(profile.starts_with("(version 1)\n(deny default)\n"));
+        assert!(profile.contains("(allow file-read* (subpath \"/usr\")"));
+        assert!(profile.contains("(subpath \"/Users/me/docs\"))\n"));
+        assert!(profile.contains(
+            "(allow file-read* file-write* (subpath \"/scratch/home\") (subpath \"/Users/me/out\"))"
+        ));
+        assert!(!profile.contains("network"));
+    }
+
+    #[test]
+    fn test_profile_refuses_remapped_mounts() {
+        let options = SandboxOptions {
+            mounts: vec![mount("/Users/me/project", "/workspace", false)],
+            env: vec![],
+            network: true,
+        };
+        assert!(profile(&options, Path::new("/usr/bin/npx"), Path::new("/scratch")).is_err());
+    }
+}
@@ -0,0 +1,522 @@
+//! Seccomp profiles generated from the policy.
+//!
+//! A `seccomp` section under `permissions.runtime.docker` replaces the
+//! runtime's default profile with one built from a preset:
+//!
+//! ```yaml
+//! docker:
+//!   seccomp:
+//!     preset: strict        # or standard, the default
+//!     allow: [io_uring_setup, io_uring_enter]
+//!     deny: [personality]
+//! ```
+//!
+//! - `standard` allows every syscall except those a Node or Python server
+//!   has no business making: the list the bubblewrap fallback denies, plus
+//!   clock setting, raw I/O, accounting and the new mount API.
+//! - `strict` denies everything but the syscalls Node, Python, npm and uv
+//!   are known to use, process creation included.
+//!
+//! Syscalls unlocked by a capability the policy adds, such as `ptrace` for
+//! `SYS_PTRACE`, are allowed whatever the preset. The profile is written to
+//! `$XDG_CACHE_HOME/semcp/seccomp/` under a name derived from its content
+//! and passed as `--security-opt seccomp=<file>`.
+
+use anyhow::{bail, Context, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+const EPERM: u32 = 1;
+const ENOSYS: u32 = 38;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SeccompSpec {
+    pub preset: SeccompPreset,
+    /// Syscalls allowed on top of the preset.
+    pub allow: Vec<String>,
+    /// Syscalls denied on top of the preset.
+    pub deny: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SeccompPreset {
+    Strict,
+    #[default]
+    Standard,
+}
+
+/// Denied by `standard`: the syscalls of [`crate::sandbox::DENIED_SYSCALLS`]
+/// and more of the kind.
+const STANDARD_DENIED: &[&str] = &[
+    "ptrace",
+    "mount",
+    "umount2",
+    "pivot_root",
+    "swapon",
+    "swapoff",
+    "reboot",
+    "kexec_load",
+    "kexec_file_load",
+    "init_module",
+    "finit_module",
+    "delete_module",
+    "bpf",
+    "perf_event_open",
+    "userfaultfd",
+    "keyctl",
+    "add_key",
+    "request_key",
+    "process_vm_readv",
+    "process_vm_writev",
+    "open_by_handle_at",
+    "name_to_handle_at",
+    "unshare",
+    "setns",
+    "acct",
+    "settimeofday",
+    "clock_settime",
+    "clock_adjtime",
+    "adjtimex",
+    "syslog",
+    "iopl",
+    "ioperm",
+    "quotactl",
+    "lookup_dcookie",
+    "uselib",
+    "vhangup",
+    "fanotify_init",
+    "kcmp",
+    "move_mount",
+    "open_tree",
+    "fsopen",
+    "fsconfig",
+    "fsmount",
+    "fspick",
+    "io_uring_setup",
+    "io_uring_enter",
+    "io_uring_register",
+];
+
+/// Allowed by `strict`: what Node, Python and their package managers use.
+const STRICT_ALLOWED: &[&str] = &[
+    // files
+    "read",
+    "write",
+    "readv",
+    "writev",
+    "pread64",
+    "pwrite64",
+    "preadv",
+    "pwritev",
+    "preadv2",
+    "pwritev2",
+    "open",
+    "openat",
+    "openat2",
+    "creat",
+    "close",
+    "close_range",
+    "lseek",
+    "stat",
+    "fstat",
+    "lstat",
+    "newfstatat",
+    "statx",
+    "statfs",
+    "fstatfs",
+    "access",
+    "faccessat",
+    "faccessat2",
+    "getdents",
+    "getdents64",
+    "getcwd",
+    "chdir",
+    "fchdir",
+    "rename",
+    "renameat",
+    "renameat2",
+    "mkdir",
+    "mkdirat",
+    "rmdir",
+    "link",
+    "linkat",
+    "unlink",
+    "unlinkat",
+    "symlink",
+    "symlinkat",
+    "readlink",
+    "readlinkat",
+    "chmod",
+    "fchmod",
+    "fchmodat",
+    "chown",
+    "fchown",
+    "lchown",
+    "fchownat",
+    "umask",
+    "truncate",
+    "ftruncate",
+    "fsync",
+    "fdatasync",
+    "flock",
+    "fcntl",
+    "ioctl",
+    "dup",
+    "dup2",
+    "dup3",
+    "pipe",
+    "pipe2",
+    "sendfile",
+    "copy_file_range",
+    "splice",
+    "tee",
+    "fadvise64",
+    "fallocate",
+    "utime",
+    "utimes",
+    "utimensat",
+    "futimesat",
+    "getxattr",
+    "lgetxattr",
+    "fgetxattr",
+    "listxattr",
+    "llistxattr",
+    "flistxattr",
+    "inotify_init",
+    "inotify_init1",
+    "inotify_add_watch",
+    "inotify_rm_watch",
+    "memfd_create",
+    // memory
+    "brk",
+    "mmap",
+    "munmap",
+    "mremap",
+    "mprotect",
+    "madvise",
+    "mincore",
+    "msync",
+    "mlock",
+    "munlock",
+    "membarrier",
+    "pkey_alloc",
+    "pkey_free",
+    "pkey_mprotect",
+    "get_mempolicy",
+    // processes and threads
+    "clone",
+    "clone3",
+    "fork",
+    "vfork",
+    "execve",
+    "execveat",
+    "exit",
+    "exit_group",
+    "wait4",
+    "waitid",
+    "kill",
+    "tkill",
+    "tgkill",
+    "getpid",
+    "getppid",
+    "gettid",
+    "getpgid",
+    "getpgrp",
+    "setpgid",
+    "getsid",
+    "setsid",
+    "getuid",
+    "geteuid",
+    "getgid",
+    "getegid",
+    "getgroups",
+    "getresuid",
+    "getresgid",
+    "capget",
+    "prctl",
+    "arch_prctl",
+    "set_tid_address",
+    "set_robust_list",
+    "get_robust_list",
+    "rseq",
+    "futex",
+    "futex_waitv",
+    "sched_yield",
+    "sched_getaffinity",
+    "sched_setaffinity",
+    "sched_getparam",
+    "sched_getscheduler",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "getpriority",
+    "setpriority",
+    "getrlimit",
+    "setrlimit",
+    "prlimit64",
+    "getrusage",
+    "uname",
+    "sysinfo",
+    "times",
+    "getcpu",
+    // signals
+    "rt_sigaction",
+    "rt_sigprocmask",
+    "rt_sigreturn",
+    "rt_sigsuspend",
+    "rt_sigtimedwait",
+    "rt_sigqueueinfo",
+    "sigaltstack",
+    "signalfd",
+    "signalfd4",
+    "pause",
+    "alarm",
+    "setitimer",
+    "getitimer",
+    "restart_syscall",
+    // time and events
+    "clock_gettime",
+    "clock_getres",
+    "clock_nanosleep",
+    "nanosleep",
+    "gettimeofday",
+    "time",
+    "timerfd_create",
+    "timerfd_settime",
+    "timerfd_gettime",
+    "eventfd",
+    "eventfd2",
+    "poll",
+    "ppoll",
+    "select",
+    "pselect6",
+    "epoll_create",
+    "epoll_create1",
+    "epoll_ctl",
+    "epoll_wait",
+    "epoll_pwait",
+    "epoll_pwait2",
+    "getrandom",
+    // network
+    "socket",
+    "socketpair",
+    "connect",
+    "accept",
+    "accept4",
+    "bind",
+    "listen",
+    "shutdown",
+    "getsockname",
+    "getpeername",
+    "setsockopt",
+    "getsockopt",
+    "sendto",
+    "recvfrom",
+    "sendmsg",
+    "recvmsg",
+    "sendmmsg",
+    "recvmmsg",
+];
+
+/// Syscalls a capability added by the policy needs, by normalized name.
+const CAPABILITY_SYSCALLS: &[(&str, &[&str])] = &[
+    (
+        "SYSPTRACE",
+        &["ptrace", "process_vm_readv", "process_vm_writev", "kcmp"],
+    ),
+    (
+        "SYSADMIN",
+        &[
+            "mount",
+            "umount2",
+            "pivot_root",
+            "unshare",
+            "setns",
+            "quotactl",
+            "move_mount",
+            "open_tree",
+            "fsopen",
+            "fsconfig",
+            "fsmount",
+            "fspick",
+        ],
+    ),
+    (
+        "SYSMODULE",
+        &["init_module", "finit_module", "delete_module"],
+    ),
+    (
+        "SYSTIME",
+        &["settimeofday", "clock_settime", "clock_adjtime", "adjtimex"],
+    ),
+    ("SYSBOOT", &["reboot", "kexec_load", "kexec_file_load"]),
+    ("SYSRAWIO", &["iopl", "ioperm"]),
+    ("SYSPACCT", &["acct"]),
+    ("SYSLOG", &["syslog"]),
+    ("BPF", &["bpf"]),
+    ("PERFMON", &["perf_event_open"]),
+    ("DACREADSEARCH", &["open_by_handle_at", "name_to_handle_at"]),
+];
+
+fn is_syscall_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+impl SeccompSpec {
+    pub fn validate(&self) -> Result<()> {
+        for name in self.allow.iter().chain(&self.deny) {
+            if !is_syscall_name(name) {
+                bail!("docker.seccomp: `{}` is not a syscall name", name);
+            }
+        }
+        if let Some(name) = self.allow.iter().find(|name| self.deny.contains(name)) {
+            bail!("docker.seccomp: `{}` is both allowed and denied", name);
+        }
+        Ok(())
+    }
+
+    /// The profile in the format of Docker and Podman. `capabilities` are
+    /// the ones the policy adds, as written there.
+    pub fn profile(&self, capabilities: &[String]) -> Value {
+        let normalized: Vec<String> = capabilities
+            .iter()
+            .map(|cap| crate::overrides::normalize_capability(cap))
+            .collect();
+        let unlocked: BTreeSet<&str> = CAPABILITY_SYSCALLS
+            .iter()
+            .filter(|(cap, _)| normalized.iter().any(|n| n == cap || n == "ALL"))
+            .flat_map(|(_, syscalls)| syscalls.iter().copied())
+            .collect();
+        let extra_allowed = self.allow.iter().map(String::as_str).chain(unlocked);
+
+        let (default_action, rule): (_, Value) = match self.preset {
+            SeccompPreset::Strict => {
+                let names: BTreeSet<&str> = STRICT_ALLOWED
+                    .iter()
+                    .copied()
+                    .chain(extra_allowed)
+                    .filter(|name| !self.deny.iter().any(|denied| denied == name))
+                    .collect();
+                (
+                    "SCMP_ACT_ERRNO",
+                    json!({"names": names, "action": "SCMP_ACT_ALLOW"}),
+                )
+            }
+            SeccompPreset::Standard => {
+                let allowed: BTreeSet<&str> = extra_allowed.collect();
+                let names: BTreeSet<&str> = STANDARD_DENIED
+                    .iter()
+                    .copied()
+                    .chain(self.deny.iter().map(String::as_str))
+                    .filter(|name| !allowed.contains(name))
+                    .collect();
+                (
+                    "SCMP_ACT_ALLOW",
+                    json!({"names": names, "action": "SCMP_ACT_ERRNO", "errnoRet": EPERM}),
+                )
+            }
+        };
+        json!({
+            "defaultAction": default_action,
+            // unknown syscalls look unimplemented, so libc falls back to
+            // older ones
+            "defaultErrnoRet": ENOSYS,
+            "archMap": [
+                {"architecture": "SCMP_ARCH_X86_64", "subArchitectures": ["SCMP_ARCH_X86", "SCMP_ARCH_X32"]},
+                {"architecture": "SCMP_ARCH_AARCH64", "subArchitectures": ["SCMP_ARCH_ARM"]},
+            ],
+            "syscalls": [rule],
+        })
+    }
+}
+
+/// FNV-1a, to name profiles after their content.
+fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Writes `profile` to the seccomp directory of the cache, unless it is
+/// already there, and returns its path.
+pub fn install(profile: &Value) -> Result<PathBuf> {
+    let cache = crate::artifacts::cache_dir()
+        .and_then(|artifacts| artifacts.parent().map(|semcp| semcp.join("seccomp")))
+        .context("Cannot locate the seccomp profile cache: HOME is not set")?;
+    let content = serde_json::to_vec_pretty(profile)?;
+    let path = cache.join(format!("{:016x}.json", fingerprint(&content)));
+    if std::fs::read(&path).is_ok_and(|existing| existing == content) {
+        return Ok(path);
+    }
+    std::fs::create_dir_all(&cache)
+        .with_context(|| format!("Failed to create {}", cache.display()))?;
+    let partial = cache.join(format!(
+        "{:016x}.partial-{}",
+        fingerprint(&content),
+        std::process::id()
+    ));
+    std::fs::write(&partial, &content)
+        .with_context(|| format!("Failed to write {}", partial.display()))?;
+    std::fs::rename(&partial, &path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(profile: &Value) -> Vec<&str> {
+        profile["syscalls"][0]["names"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|name| name.as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_standard_profile() {
+        let spec: SeccompSpec = serde_yaml::from_str("deny: [personality]").unwrap();
+        let profile = spec.profile(&[]);
+        assert_eq!(profile["defaultAction"], "SCMP_ACT_ALLOW");
+        let denied = names(&profile);
+        assert!(denied.contains(&"ptrace"));
+        assert!(denied.contains(&"personality"));
+        // an added capability unlocks its syscalls
+        let profile = spec.profile(&["SysPtrace".to_string()]);
+        let denied = names(&profile);
+        assert!(!denied.contains(&"ptrace"));
+        assert!(denied.contains(&"mount"));
+    }
+
+    #[test]
+    fn test_strict_profile() {
+        let spec: SeccompSpec =
+            serde_yaml::from_str("preset: strict\nallow: [io_uring_setup]\ndeny: [fork]").unwrap();
+        let profile = spec.profile(&[]);
+        assert_eq!(profile["defaultAction"], "SCMP_ACT_ERRNO");
+        let allowed = names(&profile);
+        assert!(allowed.contains(&"execve"));
+        assert!(allowed.contains(&"io_uring_setup"));
+        assert!(!allowed.contains(&"fork"));
+        assert!(!allowed.contains(&"ptrace"));
+        assert!(names(&spec.profile(&["All".to_string()])).contains(&"ptrace"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let spec: SeccompSpec = serde_yaml::from_str("allow: [ptrace]\ndeny: [ptrace]").unwrap();
+        assert!(spec.validate().is_err());
+        let spec: SeccompSpec = serde_yaml::from_str("deny: [\"rm -rf\"]").unwrap();
+        assert!(spec.validate().is_err());
+        assert!(SeccompSpec::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,81 @@
+//! Images of the DNS filter and firewall sidecars.
+//!
+//! The default images are built on first use from the Dockerfiles in
+//! `data/sidecars`, on the official Alpine image, and tagged under
+//! `localhost/` with the semcp version, so a run never pulls a sidecar from
+//! a third-party account. A policy can still name an image of its own with
+//! `filter_image` or `firewall_image`.
+
+use crate::scratch::ScratchDir;
+use crate::ContainerExecutor;
+use anyhow::{Context, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SidecarImage {
+    pub tag: &'static str,
+    pub dockerfile: &'static str,
+}
+
+pub const DNS_FILTER: SidecarImage = SidecarImage {
+    tag: concat!("localhost/semcp-dns-filter:", env!("CARGO_PKG_VERSION")),
+    dockerfile: include_str!("../data/sidecars/dns-filter.Dockerfile"),
+};
+
+pub const FIREWALL: SidecarImage = SidecarImage {
+    tag: concat!("localhost/semcp-firewall:", env!("CARGO_PKG_VERSION")),
+    dockerfile: include_str!("../data/sidecars/firewall.Dockerfile"),
+};
+
+const BUILTIN: &[SidecarImage] = &[DNS_FILTER, FIREWALL];
+
+impl SidecarImage {
+    /// The built-in image tagged `image`, if it is one.
+    pub fn builtin(image: &str) -> Option<Self> {
+        BUILTIN.iter().find(|sidecar| sidecar.tag == image).copied()
+    }
+}
+
+impl ContainerExecutor {
+    /// Builds `image` when it is a built-in sidecar image the runtime does
+    /// not have yet. Other images are pulled by `run` as usual.
+    pub(crate) async fn ensure_sidecar_image(&self, image: &str) -> Result<()> {
+        let Some(sidecar) = SidecarImage::builtin(image) else {
+            return Ok(());
+        };
+        if self
+            .runtime_step(&["image", "inspect", image])
+            .await
+            .is_ok()
+        {
+            return Ok(());
+        }
+        let scratch = ScratchDir::new("semcp-sidecar")?;
+        scratch.write("Dockerfile", sidecar.dockerfile.as_bytes())?;
+        let context = scratch.path().to_string_lossy().into_owned();
+        if self.verbose {
+            eprintln!("Building sidecar image {}", image);
+        }
+        self.runtime_step(&["build", "-t", image, &context])
+            .await
+            .with_context(|| format!("Failed to build sidecar image {}", image))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_images_are_built_locally() {
+        for sidecar in BUILTIN {
+            assert!(sidecar.tag.starts_with("localhost/semcp-"));
+            assert!(sidecar
+                .dockerfile
+                .lines()
+                .any(|line| line.starts_with("FROM alpine:3.")));
+            assert_eq!(SidecarImage::builtin(sidecar.tag), Some(*sidecar));
+        }
+        assert_eq!(SidecarImage::builtin("4km3/dnsmasq"), None);
+    }
+}
@@ -0,0 +1,226 @@
+//! Signatures of policy files, verified with `cosign` before a policy is
+//! honored.
+//!
+//! A policy `base.yaml` is signed by a sigstore bundle `base.yaml.sigstore.json`
+//! or a detached signature `base.yaml.sig` next to it:
+//!
+//! ```text
+//! cosign sign-blob --bundle base.yaml.sigstore.json base.yaml
+//! cosign sign-blob --key cosign.key --output-signature base.yaml.sig base.yaml
+//! ```
+//!
+//! Who may sign is set in the user's config, with a public key or, for
+//! keyless signing, the identity of the signer's certificate:
+//!
+//! ```yaml
+//! policy_signing:
+//!   key: /etc/semcp/policy.pub
+//!   # or
+//!   identity: security@example.com
+//!   issuer: https://accounts.google.com
+//!   require: true   # like --require-signed-policy
+//! ```
+//!
+//! A signature is verified whenever a signer is configured, and a policy
+//! whose signature does not verify is refused. Unsigned policies are
+//! refused only when signatures are required, so a compromised repository
+//! cannot swap its policy for a weaker, unsigned one. Policies that are
+//! extended or fetched are checked the same way: signatures are fetched
+//! next to `https://` policies and pushed with `oci://` ones.
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Extension of a sigstore bundle, after the policy's own name.
+pub const BUNDLE_EXTENSION: &str = "sigstore.json";
+/// Extension of a detached signature, after the policy's own name.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SigningSpec {
+    /// Public key policies are signed with.
+    pub key: Option<PathBuf>,
+    /// Identity in the certificate of keyless signatures, e.g. an email.
+    pub identity: Option<String>,
+    /// OIDC issuer of that certificate.
+    pub issuer: Option<String>,
+    /// Refuse policies without a signature.
+    pub require: bool,
+}
+
+/// A signature found next to a policy file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Signature {
+    Bundle(PathBuf),
+    Detached(PathBuf),
+}
+
+/// `path` with `extension` appended to its whole name.
+pub fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// The signature of the policy at `path`, a bundle before a detached one.
+pub fn find(path: &Path) -> Option<Signature> {
+    let bundle = sibling(path, BUNDLE_EXTENSION);
+    if bundle.is_file() {
+        return Some(Signature::Bundle(bundle));
+    }
+    let detached = sibling(path, SIGNATURE_EXTENSION);
+    detached.is_file().then_some(Signature::Detached(detached))
+}
+
+impl SigningSpec {
+    pub fn has_signer(&self) -> bool {
+        self.key.is_some() || self.identity.is_some()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.key.is_some() && self.identity.is_some() {
+            bail!("policy_signing takes a key or an identity, not both");
+        }
+        if self.identity.is_some() != self.issuer.is_some() {
+            bail!("policy_signing.identity and policy_signing.issuer go together");
+        }
+        if self.require && !self.has_signer() {
+            bail!("Signed policies are required, but policy_signing names no key or identity to verify them with");
+        }
+        Ok(())
+    }
+
+    /// The `cosign` arguments that verify `policy` with `signature`.
+    pub fn verify_args(&self, policy: &Path, signature: &Signature) -> Result<Vec<String>> {
+        let mut args = vec!["verify-blob".to_string()];
+        match signature {
+            Signature::Bundle(bundle) => {
+                args.push("--bundle".to_string());
+                args.push(bundle.to_string_lossy().into_owned());
+            }
+            Signature::Detached(detached) => {
+                if self.key.is_none() {
+                    bail!(
+                        "{} is a detached signature, which needs policy_signing.key to verify",
+                        detached.display()
+                    );
+                }
+                args.push("--signature".to_string());
+                args.push(detached.to_string_lossy().into_owned());
+            }
+        }
+        match (&self.key, &self.identity, &self.issuer) {
+            (Some(key), _, _) => {
+                args.push("--key".to_string());
+                args.push(key.to_string_lossy().into_owned());
+            }
+            (None, Some(identity), Some(issuer)) => {
+                args.push("--certificate-identity".to_string());
+                args.push(identity.clone());
+                args.push("--certificate-oidc-issuer".to_string());
+                args.push(issuer.clone());
+            }
+            _ => bail!("policy_signing names no key or identity to verify with"),
+        }
+        args.push(policy.to_string_lossy().into_owned());
+        Ok(args)
+    }
+
+    /// Verifies the signature of the policy at `path`. Fails when it does
+    /// not verify, or when there is none and one is required.
+    pub fn check(&self, path: &Path) -> Result<()> {
+        let signature = find(path);
+        let signature = match signature {
+            Some(signature) if self.has_signer() => signature,
+            Some(_) => return Ok(()),
+            None if self.require => bail!(
+                "Policy file {} is not signed, and signed policies are required",
+                path.display()
+            ),
+            None => return Ok(()),
+        };
+        which::which("cosign").context("cosign is required to verify signed policies")?;
+        let output = Command::new("cosign")
+            .args(self.verify_args(path, &signature)?)
+            .output()
+            .context("Failed to execute cosign")?;
+        if !output.status.success() {
+            bail!(
+                "The signature of policy file {} does not verify: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_args() {
+        let policy = Path::new("policies/base.yaml");
+        let bundle = Signature::Bundle(sibling(policy, BUNDLE_EXTENSION));
+        let keyless = SigningSpec {
+            identity: Some("security@example.com".to_string()),
+            issuer: Some("https://accounts.google.com".to_string()),
+            ..SigningSpec::default()
+        };
+        assert!(keyless.validate().is_ok());
+        assert_eq!(
+            keyless.verify_args(policy, &bundle).unwrap(),
+            [
+                "verify-blob",
+                "--bundle",
+                "policies/base.yaml.sigstore.json",
+                "--certificate-identity",
+                "security@example.com",
+                "--certificate-oidc-issuer",
+                "https://accounts.google.com",
+                "policies/base.yaml",
+            ]
+        );
+        let detached = Signature::Detached(sibling(policy, SIGNATURE_EXTENSION));
+        assert!(keyless.verify_args(policy, &detached).is_err());
+
+        let keyed = SigningSpec {
+            key: Some(PathBuf::from("policy.pub")),
+            ..SigningSpec::default()
+        };
+        assert_eq!(
+            keyed.verify_args(policy, &detached).unwrap(),
+            [
+                "verify-blob",
+                "--signature",
+                "policies/base.yaml.sig",
+                "--key",
+                "policy.pub",
+                "policies/base.yaml",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsigned_policies() {
+        let policy = Path::new("testdata/policy.yaml");
+        assert!(SigningSpec::default().check(policy).is_ok());
+        let required = SigningSpec {
+            key: Some(PathBuf::from("policy.pub")),
+            require: true,
+            ..SigningSpec::default()
+        };
+        assert!(required.validate().is_ok());
+        assert!(required.check(policy).is_err());
+        let no_signer = SigningSpec {
+            require: true,
+            ..SigningSpec::default()
+        };
+        assert!(no_signer.validate().is_err());
+    }
+}
@@ -0,0 +1,196 @@
+//! Docker sockets of VM-based runtimes on macOS and Linux desktops.
+//!
+//! Colima, OrbStack and Lima run the Docker daemon in a VM and expose its
+//! socket under the user's home instead of `/var/run/docker.sock`. They
+//! normally register a docker context too, but when the context was never
+//! switched (or was reset) the docker CLI finds no daemon. So when no
+//! context or `DOCKER_HOST` is chosen and the default socket does not
+//! answer, semcp looks for a listening VM socket and points Docker at it.
+
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_SOCKET: &str = "/var/run/docker.sock";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// The product owning the socket, e.g. `Colima`.
+    pub provider: &'static str,
+    pub path: PathBuf,
+}
+
+impl Candidate {
+    pub fn url(&self) -> String {
+        format!("unix://{}", self.path.display())
+    }
+}
+
+/// `dir/*/suffix` for every entry of `dir`, sorted.
+fn instances(dir: &Path, suffix: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(suffix))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Sockets the VM runtimes may have created under `home`, in order of
+/// preference. Default instances come first.
+pub fn candidates(home: &Path) -> Vec<Candidate> {
+    let mut candidates = vec![Candidate {
+        provider: "OrbStack",
+        path: home.join(".orbstack/run/docker.sock"),
+    }];
+    for colima in [home.join(".colima"), home.join(".config/colima")] {
+        let default = colima.join("default/docker.sock");
+        let mut paths = vec![default.clone()];
+        paths.extend(
+            instances(&colima, "docker.sock")
+                .into_iter()
+                .filter(|path| *path != default),
+        );
+        candidates.extend(paths.into_iter().map(|path| Candidate {
+            provider: "Colima",
+            path,
+        }));
+    }
+    let lima = home.join(".lima");
+    let default = lima.join("docker/sock/docker.sock");
+    candidates.push(Candidate {
+        provider: "Lima",
+        path: default.clone(),
+    });
+    candidates.extend(
+        instances(&lima, "sock/docker.sock")
+            .into_iter()
+            .filter(|path| *path != default)
+            .map(|path| Candidate {
+                provider: "Lima",
+                path,
+            }),
+    );
+    candidates
+}
+
+/// Whether a daemon accepts connections on the socket.
+#[cfg(unix)]
+pub fn is_listening(path: &Path) -> bool {
+    std::os::unix::net::UnixStream::connect(path).is_ok()
+}
+
+#[cfg(not(unix))]
+pub fn is_listening(_path: &Path) -> bool {
+    false
+}
+
+/// The docker context the user chose, if not `default`.
+pub fn chosen_context(docker_config: Option<&Value>) -> Option<String> {
+    let context = std::env::var("DOCKER_CONTEXT").ok().or_else(|| {
+        docker_config?
+            .get("currentContext")?
+            .as_str()
+            .map(str::to_string)
+    })?;
+    (!context.is_empty() && context != "default").then_some(context)
+}
+
+fn docker_config() -> Option<Value> {
+    let dir = std::env::var_os("DOCKER_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker")))?;
+    let content = std::fs::read_to_string(dir.join("config.json")).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// The VM socket Docker should use, when the user has not chosen a daemon
+/// and the default socket does not answer.
+pub fn vm_socket() -> Option<Candidate> {
+    if std::env::var_os("DOCKER_HOST").is_some()
+        || chosen_context(docker_config().as_ref()).is_some()
+        || is_listening(Path::new(DEFAULT_SOCKET))
+    {
+        return None;
+    }
+    let home = PathBuf::from(std::env::var_os("HOME")?);
+    candidates(&home)
+        .into_iter()
+        .find(|candidate| is_listening(&candidate.path))
+}
+
+/// One line per known socket and whether it answers, for `semcp doctor`.
+pub fn report() -> Vec<String> {
+    let mut sockets = vec![Candidate {
+        provider: "Docker",
+        path: PathBuf::from(DEFAULT_SOCKET),
+    }];
+    if let Some(home) = std::env::var_os("HOME") {
+        sockets.extend(candidates(Path::new(&home)));
+    }
+    sockets
+        .into_iter()
+        .filter(|candidate| candidate.path.exists())
+        .map(|candidate| {
+            format!(
+                "{} {} ({})",
+                candidate.provider,
+                candidate.path.display(),
+                if is_listening(&candidate.path) {
+                    "listening"
+                } else {
+                    "not answering"
+                }
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_candidates_prefer_default_instances() {
+        let home = std::env::temp_dir().join(format!("semcp-sockets-{}", std::process::id()));
+        for dir in [".colima/work", ".colima/default", ".lima/dev/sock"] {
+            std::fs::create_dir_all(home.join(dir)).unwrap();
+        }
+        let paths: Vec<PathBuf> = candidates(&home)
+            .into_iter()
+            .map(|candidate| candidate.path.strip_prefix(&home).unwrap().to_path_buf())
+            .collect();
+        std::fs::remove_dir_all(&home).unwrap();
+        assert_eq!(
+            paths,
+            [
+                ".orbstack/run/docker.sock",
+                ".colima/default/docker.sock",
+                ".colima/work/docker.sock",
+                ".config/colima/default/docker.sock",
+                ".lima/docker/sock/docker.sock",
+                ".lima/dev/sock/docker.sock",
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_default_context_is_not_a_choice() {
+        if std::env::var_os("DOCKER_CONTEXT").is_some() {
+            return;
+        }
+        assert_eq!(chosen_context(None), None);
+        assert_eq!(
+            chosen_context(Some(&json!({"currentContext": "default"}))),
+            None
+        );
+        assert_eq!(
+            chosen_context(Some(&json!({"currentContext": "colima"}))),
+            Some("colima".to_string())
+        );
+    }
+}
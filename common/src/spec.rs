@@ -0,0 +1,978 @@
+//! The policy model.
+//!
+//! [`PermissionsSpec`] is what semcp reads of a policy's `permissions`, the
+//! portable sections as well as the semcp-specific ones under `runtime`.
+//! `policy_mcp` still validates every file, but docker arguments, checks
+//! and exports are all derived from these structs, which also make up the
+//! schema of `snpx policy schema`.
+
+use crate::audit::AuditSpec;
+use crate::budget::BudgetSpec;
+use crate::daemon::DaemonSpec;
+use crate::envmap::EnvMapping;
+use crate::seccomp::SeccompSpec;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct PermissionsSpec {
+    pub storage: StorageSpec,
+    pub network: NetworkSpec,
+    pub environment: EnvironmentSpec,
+    pub runtime: RuntimeSpec,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct StorageSpec {
+    /// Host paths mounted into the container.
+    pub allow: Vec<StorageGrant>,
+    /// Host paths mounted at the same path, read-only unless they end in
+    /// `:rw`, e.g. `~/notes` or `/srv/data:rw`.
+    pub allowed_paths: Vec<String>,
+    /// Host paths hidden inside the mounts, absolute or under `~/`, e.g.
+    /// `~/.ssh`.
+    pub blocked_paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+pub struct StorageGrant {
+    /// `fs://` and the absolute host path, e.g. `fs:///home/me/notes`.
+    pub uri: String,
+    /// Mounted writable when it includes `write`, read-only otherwise.
+    pub access: Vec<Access>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct NetworkSpec {
+    /// Hosts the server may reach.
+    pub allow: Vec<HostGrant>,
+    /// Network to put the container on: `none`, `bridge`, `host` or a
+    /// custom network.
+    pub policy: Option<String>,
+    /// Resolvers for the container, IP addresses.
+    pub dns_servers: Vec<String>,
+    pub dns_search: Vec<String>,
+    pub dns_options: Vec<String>,
+    /// Resolve only the allowed hosts, through a resolver sidecar.
+    pub filter_dns: bool,
+    /// Image of the resolver sidecar.
+    pub filter_image: Option<String>,
+    /// Image of the firewall sidecar.
+    pub firewall_image: Option<String>,
+    /// Outgoing ports the firewall sidecar blocks, e.g. `25` or `53/udp`.
+    pub blocked_ports: Vec<PortSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+pub struct HostGrant {
+    /// Host name, `*.` wildcard, IP address or CIDR range, with an
+    /// optional port.
+    pub host: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum PortSpec {
+    Number(u16),
+    WithProtocol(String),
+}
+
+impl PortSpec {
+    pub fn text(&self) -> String {
+        match self {
+            PortSpec::Number(port) => port.to_string(),
+            PortSpec::WithProtocol(text) => text.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct EnvironmentSpec {
+    /// Variables that may be set with `-e`.
+    pub allow: Vec<EnvGrant>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+pub struct EnvGrant {
+    pub key: String,
+}
+
+/// `permissions.runtime.docker.security`.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SecuritySpec {
+    /// `false` adds `--security-opt no-new-privileges`.
+    pub privileged: Option<bool>,
+    /// `true` adds `--security-opt no-new-privileges` too.
+    pub no_new_privileges: Option<bool>,
+    pub capabilities: CapabilitiesSpec,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CapabilitiesSpec {
+    /// Capabilities to drop, e.g. `ALL`.
+    pub drop: Vec<String>,
+    /// Capabilities to add back, e.g. `NET_BIND_SERVICE`.
+    pub add: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct RuntimeSpec {
+    pub docker: DockerSpec,
+    /// Stop the container when no MCP traffic has passed through the proxy
+    /// for this long.
+    pub idle_timeout: Option<DurationSpec>,
+    /// Stop the container once it has run this long.
+    pub timeout: Option<DurationSpec>,
+    /// How long a stopped container gets to exit before it is killed.
+    pub graceful_shutdown_timeout: Option<DurationSpec>,
+    /// How long stopping may take in all before the container is killed
+    /// outright, for runtimes that do not answer.
+    pub force_kill_timeout: Option<DurationSpec>,
+    /// How many times a crashed server is restarted before it is reported
+    /// as crash-looping.
+    pub max_restart_attempts: Option<u32>,
+    /// Replay the session to a restarted server: the `initialize` exchange
+    /// and requests of the listed setup methods. Enables the proxy.
+    pub replay_on_restart: Option<Vec<String>>,
+    /// Host environment variables passed into the container, by name or by
+    /// prefix such as `GITHUB_*`. No other host variable reaches it.
+    pub environment_whitelist: Vec<String>,
+    /// Host variables renamed and transformed for the container, see
+    /// [`crate::envmap`].
+    pub env_mapping: Vec<EnvMapping>,
+    pub cache: CacheSpec,
+    /// Cost weights of tools and what a session may spend on them.
+    pub budget: Option<BudgetSpec>,
+    /// Hardening the container daemon must have, see [`crate::daemon`].
+    pub daemon: DaemonSpec,
+    /// What the audit log records of runs, see [`crate::audit`].
+    pub audit: AuditSpec,
+}
+
+/// Who may share the package manager cache used by `--split-phases`.
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct CacheSpec {
+    /// Publishers whose packages use the shared cache: an npm scope such as
+    /// `@modelcontextprotocol`, or a package name prefix such as
+    /// `mcp-server-`.
+    pub trusted_publishers: Vec<String>,
+    /// Give packages from any other publisher a cache of their own that is
+    /// removed after the run, so they cannot poison the shared one.
+    pub isolate_untrusted: bool,
+}
+
+impl CacheSpec {
+    pub fn is_trusted(&self, package: &str) -> bool {
+        let identity = crate::state::package_identity(package);
+        self.trusted_publishers.iter().any(|publisher| {
+            if publisher.starts_with('@') {
+                identity.starts_with(&format!("{}/", publisher.trim_end_matches('/')))
+            } else {
+                identity.starts_with(publisher.as_str())
+            }
+        })
+    }
+
+    /// Whether `package` must not touch the shared cache.
+    pub fn is_isolated(&self, package: Option<&str>) -> bool {
+        self.isolate_untrusted && !package.is_some_and(|package| self.is_trusted(package))
+    }
+}
+
+/// What `docker stop` waits by default.
+pub const DEFAULT_GRACEFUL_SHUTDOWN: Duration = Duration::from_secs(10);
+pub(crate) const FORCE_KILL_MARGIN: Duration = Duration::from_secs(5);
+
+/// A duration written as seconds (`600`) or with a unit (`90s`, `10m`,
+/// `2h`).
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum DurationSpec {
+    Seconds(u64),
+    Text(String),
+}
+
+impl DurationSpec {
+    pub fn to_duration(&self) -> Result<Duration> {
+        let text = match self {
+            DurationSpec::Seconds(seconds) => return Ok(Duration::from_secs(*seconds)),
+            DurationSpec::Text(text) => text.trim(),
+        };
+        let split = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        let (number, unit) = text.split_at(split);
+        let Ok(number) = number.parse::<u64>() else {
+            bail!("Invalid duration `{}`", text);
+        };
+        let multiplier = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            _ => bail!("Invalid duration unit in `{}`, use s, m or h", text),
+        };
+        Ok(Duration::from_secs(number * multiplier))
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct DockerSpec {
+    pub security: SecuritySpec,
+    pub volumes: Vec<VolumeSpec>,
+    /// IANA timezone for the container, or `host` to follow the host.
+    pub timezone: Option<String>,
+    /// Locale exported as `LANG` and `LC_ALL`, e.g. `en_US.UTF-8`.
+    pub locale: Option<String>,
+    /// OCI runtime isolating the container from the host kernel.
+    pub runtime_class: Option<RuntimeClass>,
+    /// Run an init process as PID 1 that reaps zombies and forwards
+    /// signals. On unless set to `false`.
+    pub init: Option<bool>,
+    /// Memory limit, e.g. `512m`, or bytes.
+    pub memory_limit: Option<LimitSpec>,
+    /// CPUs the container may use, e.g. `1.5`.
+    pub cpu_limit: Option<LimitSpec>,
+    pub pids_limit: Option<u32>,
+    /// Resource limits by name, e.g. `nofile: {soft: 1024, hard: 2048}` or
+    /// `nproc: 512` for equal soft and hard limits.
+    pub ulimits: BTreeMap<String, UlimitSpec>,
+    /// User (and group) to run as, e.g. `1000:1000` or `node`.
+    pub user: Option<String>,
+    pub read_only_root_filesystem: bool,
+    /// Writable in-memory mounts, e.g. `/tmp` or `/run:size=16m`.
+    pub tmpfs: Vec<String>,
+    /// Options of every tmpfs mount, e.g. `[noexec, nosuid, nodev]`. A
+    /// mount setting the opposite keeps it, so `/tmp:exec` stays
+    /// executable.
+    pub mount_options: Vec<MountOption>,
+    /// Extra `--security-opt` values, e.g. `seccomp=profile.json`.
+    pub security_opts: Vec<String>,
+    /// Seccomp profile generated from a preset, see [`crate::seccomp`].
+    pub seccomp: Option<SeccompSpec>,
+    /// SELinux labels of the container and its bind mounts.
+    pub selinux: Option<SelinuxSpec>,
+    /// Labels of the containers, volumes and networks semcp creates, e.g.
+    /// `owner: data-platform`, for chargeback and ownership tracking.
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MountOption {
+    Ro,
+    Noexec,
+    Nosuid,
+    Nodev,
+}
+
+impl MountOption {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MountOption::Ro => "ro",
+            MountOption::Noexec => "noexec",
+            MountOption::Nosuid => "nosuid",
+            MountOption::Nodev => "nodev",
+        }
+    }
+
+    /// The option a single mount turns this one off with.
+    fn opposite(&self) -> &'static str {
+        match self {
+            MountOption::Ro => "rw",
+            MountOption::Noexec => "exec",
+            MountOption::Nosuid => "suid",
+            MountOption::Nodev => "dev",
+        }
+    }
+}
+
+/// SELinux settings for hosts running SELinux enforcing, like Fedora and
+/// RHEL, where a bind mount is unreadable in the container until relabeled.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct SelinuxSpec {
+    pub user: Option<String>,
+    pub role: Option<String>,
+    /// Process type, e.g. `container_t`.
+    #[serde(rename = "type")]
+    pub process_type: Option<String>,
+    /// MCS level, e.g. `s0:c100,c200`.
+    pub level: Option<String>,
+    /// Run the container without SELinux separation.
+    pub disable: bool,
+    /// Relabel the bind mounts of the policy: `shared` (`:z`) or `private`
+    /// (`:Z`).
+    pub relabel: Option<Relabel>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Relabel {
+    /// Label the content for every container, `:z`.
+    Shared,
+    /// Label the content for this container only, `:Z`.
+    Private,
+}
+
+impl Relabel {
+    pub fn mount_option(&self) -> &'static str {
+        match self {
+            Relabel::Shared => "z",
+            Relabel::Private => "Z",
+        }
+    }
+}
+
+/// Label keys semcp sets itself, which clean-up and tenancy rely on.
+const RESERVED_LABEL_PREFIX: &str = "semcp.";
+
+/// Checks user-defined labels, which must not pass for semcp's own.
+pub fn validate_labels(labels: &BTreeMap<String, String>) -> Result<()> {
+    for key in labels.keys() {
+        if key.is_empty() || key.contains(|c: char| c == '=' || c.is_whitespace()) {
+            bail!("Invalid label `{}`", key);
+        }
+        if key.starts_with(RESERVED_LABEL_PREFIX) {
+            bail!(
+                "Label `{}` is reserved, semcp sets the {}* labels itself",
+                key,
+                RESERVED_LABEL_PREFIX
+            );
+        }
+    }
+    Ok(())
+}
+
+impl SelinuxSpec {
+    /// The `--security-opt label=...` values.
+    pub fn labels(&self) -> Vec<String> {
+        if self.disable {
+            return vec!["label=disable".to_string()];
+        }
+        [
+            ("user", &self.user),
+            ("role", &self.role),
+            ("type", &self.process_type),
+            ("level", &self.level),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            value
+                .as_ref()
+                .map(|value| format!("label={}:{}", key, value))
+        })
+        .collect()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let labelled = self.user.is_some()
+            || self.role.is_some()
+            || self.process_type.is_some()
+            || self.level.is_some();
+        if self.disable && (labelled || self.relabel.is_some()) {
+            bail!(
+                "selinux: `disable` turns labels off, it cannot be combined with labels or relabel"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A limit written as a number (`2`, `0.5`) or as text with a unit (`512m`).
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum LimitSpec {
+    Number(f64),
+    Text(String),
+}
+
+/// Parses a docker style memory size (`1024`, `512k`, `256m`, `2g`).
+pub fn parse_memory(value: &str) -> Result<u64> {
+    let value = value.trim().to_lowercase();
+    let value = value.strip_suffix('b').unwrap_or(&value);
+    let (number, multiplier) = match value.chars().last() {
+        Some('k') => (&value[..value.len() - 1], 1 << 10),
+        Some('m') => (&value[..value.len() - 1], 1 << 20),
+        Some('g') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(number) if number > 0 => Ok(number * multiplier),
+        _ => bail!("Invalid memory size `{}`", value),
+    }
+}
+
+impl std::fmt::Display for LimitSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitSpec::Number(number) => write!(f, "{}", number),
+            LimitSpec::Text(text) => write!(f, "{}", text.trim()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum UlimitSpec {
+    Single(i64),
+    Range { soft: i64, hard: i64 },
+}
+
+impl UlimitSpec {
+    /// The value as `--ulimit` takes it: `soft:hard`.
+    pub fn value(&self) -> String {
+        match self {
+            UlimitSpec::Single(limit) => limit.to_string(),
+            UlimitSpec::Range { soft, hard } => format!("{}:{}", soft, hard),
+        }
+    }
+}
+
+impl DockerSpec {
+    /// A `tmpfs` entry with the `mount_options` it does not override.
+    pub fn tmpfs_entry(&self, tmpfs: &str) -> String {
+        let (path, options) = tmpfs.split_once(':').unwrap_or((tmpfs, ""));
+        let mut options: Vec<&str> = options.split(',').filter(|o| !o.is_empty()).collect();
+        for option in &self.mount_options {
+            if !options.contains(&option.as_str()) && !options.contains(&option.opposite()) {
+                options.push(option.as_str());
+            }
+        }
+        if options.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}:{}", path, options.join(","))
+        }
+    }
+
+    /// Run options for the limits and hardening settings.
+    pub fn run_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |flag: &str, value: String| {
+            args.push(flag.to_string());
+            args.push(value);
+        };
+        if let Some(ref memory) = self.memory_limit {
+            push("--memory", memory.to_string());
+        }
+        if let Some(ref cpus) = self.cpu_limit {
+            push("--cpus", cpus.to_string());
+        }
+        if let Some(pids) = self.pids_limit {
+            push("--pids-limit", pids.to_string());
+        }
+        for (name, limit) in &self.ulimits {
+            push("--ulimit", format!("{}={}", name, limit.value()));
+        }
+        if let Some(ref user) = self.user {
+            push("--user", user.clone());
+        }
+        for tmpfs in &self.tmpfs {
+            push("--tmpfs", self.tmpfs_entry(tmpfs));
+        }
+        for option in &self.security_opts {
+            push("--security-opt", option.clone());
+        }
+        for label in self.selinux.iter().flat_map(SelinuxSpec::labels) {
+            push("--security-opt", label);
+        }
+        if self.read_only_root_filesystem {
+            args.push("--read-only".to_string());
+        }
+        args
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RuntimeClass {
+    /// The runtime's default OCI runtime, normally runc.
+    Runc,
+    /// gVisor: system calls are served by a user space kernel (runsc).
+    Gvisor,
+    /// Kata Containers: each container runs in a lightweight VM.
+    Kata,
+    /// Kata Containers on the Firecracker VMM: a microVM per container.
+    Firecracker,
+}
+
+impl RuntimeClass {
+    /// Name of the OCI runtime to pass as `--runtime`, if not the default.
+    pub fn oci_runtime(&self) -> Option<&'static str> {
+        match self {
+            RuntimeClass::Runc => None,
+            RuntimeClass::Gvisor => Some("runsc"),
+            RuntimeClass::Kata => Some("kata-runtime"),
+            RuntimeClass::Firecracker => Some("kata-fc"),
+        }
+    }
+
+    /// Whether containers run in a VM, which needs `/dev/kvm`.
+    pub fn needs_kvm(&self) -> bool {
+        matches!(self, RuntimeClass::Kata | RuntimeClass::Firecracker)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RuntimeClass::Runc => "runc",
+            RuntimeClass::Gvisor => "gvisor",
+            RuntimeClass::Kata => "kata",
+            RuntimeClass::Firecracker => "firecracker",
+        }
+    }
+
+    pub fn install_guide(&self) -> &'static str {
+        match self {
+            RuntimeClass::Runc => "",
+            RuntimeClass::Gvisor => "https://gvisor.dev/docs/user_guide/install/",
+            RuntimeClass::Kata => {
+                "https://github.com/kata-containers/kata-containers/tree/main/docs/install"
+            }
+            RuntimeClass::Firecracker => {
+                "https://github.com/kata-containers/kata-containers/blob/main/docs/how-to/how-to-use-kata-containers-with-firecracker.md"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VolumeMode {
+    Ro,
+    #[default]
+    Rw,
+}
+
+impl VolumeMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VolumeMode::Ro => "ro",
+            VolumeMode::Rw => "rw",
+        }
+    }
+}
+
+/// A named docker volume that persists server data across runs.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+pub struct VolumeSpec {
+    pub name: String,
+    pub mount: String,
+    #[serde(default)]
+    pub mode: VolumeMode,
+    /// Informational size, recorded as a volume label. The local volume
+    /// driver does not enforce quotas.
+    #[serde(default)]
+    pub size: Option<String>,
+}
+
+pub fn is_valid_volume_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(first) if first.is_ascii_alphanumeric())
+        && name.len() > 1
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+impl RuntimeSpec {
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+            .as_ref()
+            .and_then(|timeout| timeout.to_duration().ok())
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+            .as_ref()
+            .and_then(|timeout| timeout.to_duration().ok())
+    }
+
+    pub fn graceful_shutdown_timeout(&self) -> Duration {
+        self.graceful_shutdown_timeout
+            .as_ref()
+            .and_then(|timeout| timeout.to_duration().ok())
+            .unwrap_or(DEFAULT_GRACEFUL_SHUTDOWN)
+    }
+
+    /// Defaults to five seconds past `graceful_shutdown_timeout`.
+    pub fn force_kill_timeout(&self) -> Duration {
+        self.force_kill_timeout
+            .as_ref()
+            .and_then(|timeout| timeout.to_duration().ok())
+            .unwrap_or_else(|| self.graceful_shutdown_timeout() + FORCE_KILL_MARGIN)
+    }
+
+    pub fn max_restart_attempts(&self) -> u32 {
+        self.max_restart_attempts
+            .unwrap_or(crate::crashloop::DEFAULT_MAX_RESTART_ATTEMPTS)
+    }
+
+    /// Whether the host variable `key` may be passed into the container.
+    pub fn whitelists_env(&self, key: &str) -> bool {
+        self.environment_whitelist
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == pattern,
+            })
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for pattern in &self.environment_whitelist {
+            let name = pattern.strip_suffix('*').unwrap_or(pattern);
+            if name.is_empty() || name.contains(['*', '=']) {
+                bail!(
+                    "Invalid environment_whitelist entry `{}`, use a variable name or a prefix ending in *",
+                    pattern
+                );
+            }
+        }
+        for mapping in &self.env_mapping {
+            mapping.validate()?;
+        }
+        validate_labels(&self.docker.labels)?;
+        for volume in &self.docker.volumes {
+            if !is_valid_volume_name(&volume.name) {
+                bail!("Invalid volume name `{}`", volume.name);
+            }
+            if !volume.mount.starts_with('/') {
+                bail!(
+                    "Volume `{}` must be mounted at an absolute path, got `{}`",
+                    volume.name,
+                    volume.mount
+                );
+            }
+        }
+        if let Some(ref idle_timeout) = self.idle_timeout {
+            if idle_timeout.to_duration()?.is_zero() {
+                bail!("idle_timeout must be greater than zero");
+            }
+        }
+        if let Some(ref timeout) = self.timeout {
+            if timeout.to_duration()?.is_zero() {
+                bail!("timeout must be greater than zero");
+            }
+        }
+        if let Some(ref grace) = self.graceful_shutdown_timeout {
+            grace.to_duration()?;
+        }
+        if let Some(ref force_kill) = self.force_kill_timeout {
+            if force_kill.to_duration()? < self.graceful_shutdown_timeout() {
+                bail!("force_kill_timeout must not be shorter than graceful_shutdown_timeout");
+            }
+        }
+        if self
+            .budget
+            .as_ref()
+            .is_some_and(|budget| budget.per_session == 0)
+        {
+            bail!("budget.per_session must be greater than zero");
+        }
+        if let Some(ref memory) = self.docker.memory_limit {
+            parse_memory(&memory.to_string()).context("memory_limit")?;
+        }
+        if let Some(ref cpus) = self.docker.cpu_limit {
+            match cpus.to_string().parse::<f64>() {
+                Ok(cpus) if cpus > 0.0 => {}
+                _ => bail!(
+                    "cpu_limit must be a number greater than zero, got `{}`",
+                    cpus
+                ),
+            }
+        }
+        if let Some(ref selinux) = self.docker.selinux {
+            selinux.validate()?;
+        }
+        if let Some(ref seccomp) = self.docker.seccomp {
+            seccomp.validate()?;
+            if self
+                .docker
+                .security_opts
+                .iter()
+                .any(|option| option.starts_with("seccomp"))
+            {
+                bail!("docker.seccomp and a seccomp entry in security_opts cannot be combined");
+            }
+        }
+        for tmpfs in &self.docker.tmpfs {
+            if !tmpfs.starts_with('/') {
+                bail!("tmpfs must be mounted at an absolute path, got `{}`", tmpfs);
+            }
+        }
+        for (name, limit) in &self.docker.ulimits {
+            if let UlimitSpec::Range { soft, hard } = limit {
+                if soft > hard {
+                    bail!("The soft {} ulimit is above the hard one", name);
+                }
+            }
+        }
+        if let Some(ref timezone) = self.docker.timezone {
+            if !crate::locale::is_valid_timezone(timezone) {
+                bail!("Invalid timezone `{}`", timezone);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_volume_defaults() {
+        let volume: VolumeSpec = serde_yaml::from_str("{name: data, mount: /data}").unwrap();
+        assert_eq!(volume.mode, VolumeMode::Rw);
+        assert!(volume.size.is_none());
+    }
+
+    #[test]
+    fn test_cache_trust() {
+        let cache: CacheSpec = serde_yaml::from_str(
+            "{isolate_untrusted: true, trusted_publishers: ['@modelcontextprotocol', mcp-server-]}",
+        )
+        .unwrap();
+        assert!(cache.is_trusted("@modelcontextprotocol/server-memory@1.0.0"));
+        assert!(cache.is_trusted("mcp-server-time"));
+        assert!(!cache.is_trusted("@modelcontextprotocol-fake/server"));
+        assert!(cache.is_isolated(Some("left-pad")));
+        assert!(cache.is_isolated(None));
+        assert!(!CacheSpec::default().is_isolated(Some("left-pad")));
+    }
+
+    #[test]
+    fn test_volume_name_validation() {
+        assert!(is_valid_volume_name("memory-data"));
+        assert!(is_valid_volume_name("kb_v1.2"));
+        assert!(!is_valid_volume_name("-data"));
+        assert!(!is_valid_volume_name("d"));
+        assert!(!is_valid_volume_name("data/../etc"));
+    }
+
+    #[test]
+    fn test_validate_rejects_relative_mount() {
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("docker: {volumes: [{name: data, mount: data}]}").unwrap();
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_labels() {
+        let labels = |key: &str| BTreeMap::from([(key.to_string(), "x".to_string())]);
+        assert!(validate_labels(&labels("cost-center")).is_ok());
+        assert!(validate_labels(&labels("com.example.owner")).is_ok());
+        for invalid in ["", "a=b", "cost center", "semcp.user", "semcp.managed"] {
+            assert!(validate_labels(&labels(invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_duration_spec() {
+        let spec: RuntimeSpec = serde_yaml::from_str("idle_timeout: 600").unwrap();
+        assert_eq!(
+            spec.idle_timeout.unwrap().to_duration().unwrap(),
+            Duration::from_secs(600)
+        );
+        let minutes = DurationSpec::Text("15m".to_string());
+        assert_eq!(minutes.to_duration().unwrap(), Duration::from_secs(900));
+        assert!(DurationSpec::Text("soon".to_string())
+            .to_duration()
+            .is_err());
+        assert!(DurationSpec::Text("5d".to_string()).to_duration().is_err());
+    }
+
+    #[test]
+    fn test_run_timeout() {
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("{timeout: 300s, graceful_shutdown_timeout: 5}").unwrap();
+        assert_eq!(spec.timeout(), Some(Duration::from_secs(300)));
+        assert_eq!(spec.graceful_shutdown_timeout(), Duration::from_secs(5));
+        assert_eq!(
+            RuntimeSpec::default().graceful_shutdown_timeout(),
+            DEFAULT_GRACEFUL_SHUTDOWN
+        );
+        assert_eq!(spec.force_kill_timeout(), Duration::from_secs(10));
+        let spec: RuntimeSpec = serde_yaml::from_str("timeout: 0").unwrap();
+        assert!(spec.validate().is_err());
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("{graceful_shutdown_timeout: 30, force_kill_timeout: 10}")
+                .unwrap();
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_environment_whitelist() {
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("environment_whitelist: [OPENAI_API_KEY, 'GITHUB_*']").unwrap();
+        assert!(spec.whitelists_env("OPENAI_API_KEY"));
+        assert!(spec.whitelists_env("GITHUB_TOKEN"));
+        assert!(!spec.whitelists_env("OPENAI_API_KEY_2"));
+        assert!(!spec.whitelists_env("AWS_SECRET_ACCESS_KEY"));
+        assert!(spec.validate().is_ok());
+
+        let spec: RuntimeSpec = serde_yaml::from_str("environment_whitelist: ['*']").unwrap();
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_selinux_labels() {
+        let spec: RuntimeSpec = serde_yaml::from_str(
+            "docker: {selinux: {type: container_t, level: 's0:c1,c2', relabel: shared}}",
+        )
+        .unwrap();
+        assert!(spec.validate().is_ok());
+        assert_eq!(
+            spec.docker.run_args(),
+            [
+                "--security-opt",
+                "label=type:container_t",
+                "--security-opt",
+                "label=level:s0:c1,c2"
+            ]
+        );
+        let spec: RuntimeSpec = serde_yaml::from_str("docker: {selinux: {disable: true}}").unwrap();
+        assert_eq!(spec.docker.run_args(), ["--security-opt", "label=disable"]);
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("docker: {selinux: {disable: true, relabel: private}}").unwrap();
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_seccomp_replaces_security_opts() {
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("docker: {seccomp: {preset: strict}}").unwrap();
+        assert!(spec.validate().is_ok());
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("docker: {seccomp: {}, security_opts: [seccomp=profile.json]}")
+                .unwrap();
+        assert!(spec.validate().is_err());
+    }
+
+    #[test]
+    fn test_docker_spec_run_args() {
+        let spec: RuntimeSpec = serde_yaml::from_str(
+            "docker:
+  memory_limit: 512m
+  cpu_limit: 1.5
+  pids_limit: 100
+  ulimits:
+    nofile: {soft: 1024, hard: 2048}
+    nproc: 512
+  user: '1000:1000'
+  read_only_root_filesystem: true
+  tmpfs: [/tmp, '/run:size=16m']
+  security_opts: [seccomp=profile.json]",
+        )
+        .unwrap();
+        spec.validate().unwrap();
+        assert_eq!(
+            spec.docker.run_args(),
+            [
+                "--memory",
+                "512m",
+                "--cpus",
+                "1.5",
+                "--pids-limit",
+                "100",
+                "--ulimit",
+                "nofile=1024:2048",
+                "--ulimit",
+                "nproc=512",
+                "--user",
+                "1000:1000",
+                "--tmpfs",
+                "/tmp",
+                "--tmpfs",
+                "/run:size=16m",
+                "--security-opt",
+                "seccomp=profile.json",
+                "--read-only",
+            ]
+        );
+        assert!(DockerSpec::default().run_args().is_empty());
+    }
+
+    #[test]
+    fn test_mount_options_apply_to_tmpfs() {
+        let spec = DockerSpec {
+            tmpfs: ["/tmp:exec", "/run:size=16m", "/cache", "/scratch:rw,suid"]
+                .map(String::from)
+                .to_vec(),
+            mount_options: vec![
+                MountOption::Ro,
+                MountOption::Noexec,
+                MountOption::Nosuid,
+                MountOption::Nodev,
+            ],
+            ..DockerSpec::default()
+        };
+        assert_eq!(spec.tmpfs_entry("/tmp:exec"), "/tmp:exec,ro,nosuid,nodev");
+        assert_eq!(
+            spec.tmpfs_entry("/run:size=16m"),
+            "/run:size=16m,ro,noexec,nosuid,nodev"
+        );
+        assert_eq!(spec.tmpfs_entry("/cache"), "/cache:ro,noexec,nosuid,nodev");
+        assert_eq!(
+            spec.tmpfs_entry("/scratch:rw,suid"),
+            "/scratch:rw,suid,noexec,nodev"
+        );
+        assert_eq!(DockerSpec::default().tmpfs_entry("/tmp"), "/tmp");
+        assert_eq!(spec.run_args()[1], "/tmp:exec,ro,nosuid,nodev");
+    }
+
+    #[test]
+    fn test_numeric_memory_limit_is_bytes() {
+        let spec: DockerSpec =
+            serde_yaml::from_str("{memory_limit: 268435456, cpu_limit: 2}").unwrap();
+        assert_eq!(spec.run_args(), ["--memory", "268435456", "--cpus", "2"]);
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("1024").unwrap(), 1024);
+        assert_eq!(parse_memory("512m").unwrap(), 512 << 20);
+        assert_eq!(parse_memory("2GB").unwrap(), 2 << 30);
+        assert!(parse_memory("0").is_err());
+        assert!(parse_memory("lots").is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_limits() {
+        for yaml in [
+            "docker: {cpu_limit: 0}",
+            "docker: {cpu_limit: many}",
+            "docker: {memory_limit: lots}",
+            "docker: {tmpfs: [tmp]}",
+            "docker: {ulimits: {nofile: {soft: 4096, hard: 1024}}}",
+        ] {
+            let spec: RuntimeSpec = serde_yaml::from_str(yaml).unwrap();
+            assert!(spec.validate().is_err(), "{}", yaml);
+        }
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_timezone() {
+        let spec: RuntimeSpec =
+            serde_yaml::from_str("docker: {timezone: \"../../etc/passwd\"}").unwrap();
+        assert!(spec.validate().is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! Per-package state volumes.
+//!
+//! Every package gets a docker volume keyed by the invoking user and the
+//! package name (without version), mounted at [`STATE_DIR`]. Containers are
+//! ephemeral, the volume is not, so servers that keep a database or memory
+//! file find it again on the next run.
+
+use crate::runtime::{self, ContainerRuntime};
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::process::Stdio;
+
+pub const STATE_DIR: &str = "/var/lib/semcp/state";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateVolume {
+    pub name: String,
+    pub package: String,
+    pub user: String,
+}
+
+/// Strips version and extras from a package spec so that upgrades keep
+/// using the same state: `@scope/pkg@1.2` -> `@scope/pkg`,
+/// `pkg[extra]==1.0` -> `pkg`.
+pub fn package_identity(spec: &str) -> String {
+    let (prefix, rest) = match spec.strip_prefix('@') {
+        Some(rest) => ("@", rest),
+        None => ("", spec),
+    };
+    let end = rest
+        .find(['@', '=', '<', '>', '~', '!', '[', ' '])
+        .unwrap_or(rest.len());
+    format!("{}{}", prefix, &rest[..end])
+}
+
+fn sanitize(value: &str) -> String {
+    let mut out = String::new();
+    for c in value.chars() {
+        if c.is_ascii_alphanumeric() {
+            out.push(c.to_ascii_lowercase());
+        } else if !out.is_empty() && !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    out.trim_end_matches('-').to_string()
+}
+
+pub fn current_user() -> String {
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+    match sanitize(&user) {
+        user if user.is_empty() => "user".to_string(),
+        user => user,
+    }
+}
+
+/// Directory for semcp's own host-side state: `$XDG_STATE_HOME/semcp`,
+/// else `~/.local/state/semcp`.
+pub fn state_home() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state"))
+        })?;
+    Some(state.join("semcp"))
+}
+
+impl StateVolume {
+    pub fn for_package(package: &str) -> Self {
+        Self::for_user(package, &current_user())
+    }
+
+    /// The volume of `package` for `user`. The name ends in a digest of
+    /// both as given, since names that sanitize alike, like `@foo/bar-baz`
+    /// and `foo-bar-baz`, must not share state.
+    pub fn for_user(package: &str, user: &str) -> Self {
+        let package = package_identity(package);
+        let digest = format!("{:x}", Sha256::digest(format!("{}\0{}", user, package)));
+        Self {
+            name: format!(
+                "semcp-state-{}-{}-{}",
+                sanitize(user),
+                sanitize(&package),
+                &digest[..8]
+            ),
+            package,
+            user: user.to_string(),
+        }
+    }
+
+    /// The name of the volume before names ended in a digest.
+    pub fn legacy_name(&self) -> String {
+        format!(
+            "semcp-state-{}-{}",
+            sanitize(&self.user),
+            sanitize(&self.package)
+        )
+    }
+
+    /// This volume, or the one under its legacy name when only that
+    /// exists, so state kept before the rename is used and not orphaned.
+    pub async fn resolve(mut self, runtime: &dyn ContainerRuntime) -> Result<Self> {
+        let legacy = self.legacy_name();
+        if !volume_exists(runtime, &self.name).await? && volume_exists(runtime, &legacy).await? {
+            self.name = legacy;
+        }
+        Ok(self)
+    }
+
+    pub fn labels(&self) -> Vec<String> {
+        vec![
+            "semcp.state=true".to_string(),
+            format!("semcp.package={}", self.package),
+            format!("semcp.user={}", self.user),
+        ]
+    }
+
+    pub fn docker_args(&self) -> Vec<String> {
+        vec![
+            "-v".to_string(),
+            format!("{}:{}", self.name, STATE_DIR),
+            "-e".to_string(),
+            format!("SEMCP_STATE_DIR={}", STATE_DIR),
+        ]
+    }
+}
+
+/// Lists the state volumes owned by `user`.
+pub async fn list_state_volumes(user: &str) -> Result<Vec<StateVolume>> {
+    let runtime = runtime::current();
+    let output = runtime
+        .command()
+        .args([
+            "volume",
+            "ls",
+            "--filter",
+            "label=semcp.state=true",
+            "--filter",
+            &format!("label=semcp.user={}", user),
+            "--format",
+            "{{.Name}}\t{{.Label \"semcp.package\"}}",
+        ])
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} volume ls", runtime))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to list state volumes: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(name, package)| StateVolume {
+            name: name.to_string(),
+            package: package.to_string(),
+            user: user.to_string(),
+        })
+        .collect())
+}
+
+pub async fn volume_exists(runtime: &dyn ContainerRuntime, name: &str) -> Result<bool> {
+    let status = runtime
+        .command()
+        .args(["volume", "inspect", name])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .with_context(|| format!("Failed to execute {} volume inspect", runtime))?;
+    Ok(status.success())
+}
+
+pub async fn remove_state_volume(name: &str) -> Result<()> {
+    let runtime = runtime::current();
+    let output = runtime
+        .command()
+        .args(["volume", "rm", name])
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} volume rm", runtime))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to remove {}: {}",
+            name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_identity() {
+        assert_eq!(
+            package_identity("@modelcontextprotocol/server-memory@0.6.2"),
+            "@modelcontextprotocol/server-memory"
+        );
+        assert_eq!(package_identity("mcp-server-git"), "mcp-server-git");
+        assert_eq!(package_identity("mcp-server-time==0.6"), "mcp-server-time");
+        assert_eq!(package_identity("mcp-server[cli]>=1"), "mcp-server");
+    }
+
+    #[test]
+    fn test_state_volume_name_is_stable_across_versions() {
+        let a = StateVolume::for_user("@modelcontextprotocol/server-memory@0.6.2", "alice");
+        let b = StateVolume::for_user("@modelcontextprotocol/server-memory", "alice");
+
+        assert_eq!(a, b);
+        assert_eq!(
+            a.name,
+            "semcp-state-alice-modelcontextprotocol-server-memory-e846569f"
+        );
+        assert_ne!(
+            a,
+            StateVolume::for_user("@modelcontextprotocol/server-memory", "bob")
+        );
+    }
+
+    #[test]
+    fn test_state_volume_names_do_not_collide() {
+        let scoped = StateVolume::for_user("@foo/bar-baz", "alice");
+        let unscoped = StateVolume::for_user("foo-bar-baz", "alice");
+        assert_ne!(scoped.name, unscoped.name);
+        assert!(scoped.name.starts_with("semcp-state-alice-foo-bar-baz-"));
+        assert_ne!(
+            StateVolume::for_user("x", "a-b").name,
+            StateVolume::for_user("x", "a.b").name
+        );
+    }
+
+    #[test]
+    fn test_legacy_name() {
+        let volume = StateVolume::for_user("@modelcontextprotocol/server-memory@0.6.2", "alice");
+        assert_eq!(
+            volume.legacy_name(),
+            "semcp-state-alice-modelcontextprotocol-server-memory"
+        );
+    }
+
+    #[test]
+    fn test_state_volume_docker_args() {
+        let volume = StateVolume::for_user("mcp-server-sqlite", "alice");
+        assert_eq!(
+            volume.docker_args(),
+            vec![
+                "-v",
+                "semcp-state-alice-mcp-server-sqlite-e453ddfa:/var/lib/semcp/state",
+                "-e",
+                "SEMCP_STATE_DIR=/var/lib/semcp/state"
+            ]
+        );
+        assert!(volume
+            .labels()
+            .contains(&"semcp.package=mcp-server-sqlite".to_string()));
+    }
+}
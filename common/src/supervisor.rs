@@ -0,0 +1,135 @@
+//! Restarting crashed servers.
+//!
+//! When the server exits with an error, snpx and suvx start it again after
+//! a backoff that doubles with every consecutive crash, up to
+//! `permissions.runtime.max_restart_attempts` times. Each restart is written
+//! to the audit log. Once the attempts are used up, the crash-loop report is
+//! printed and the last exit status is what the run returns.
+//!
+//! When the proxy relays the session, a restarted server gets the client's
+//! `initialize` exchange again, and the setup requests listed in
+//! `runtime.replay_on_restart`, before any new request. Requests the crashed
+//! server had not answered fail with an error the client can retry.
+
+use crate::crashloop::{Crash, CrashTracker, CrashVerdict, StderrTail};
+use crate::proxy::{ProxyExit, ProxyOptions, Relay};
+use crate::ContainerExecutor;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::process::{ExitStatus, Stdio};
+
+fn describe_exit(code: Option<i32>) -> String {
+    code.map_or("a signal".to_string(), |code| format!("code {}", code))
+}
+
+impl ContainerExecutor {
+    /// Starts the container from `run_args` and restarts it while it
+    /// crashes. Returns the status of the last start.
+    pub(crate) async fn supervise(
+        &self,
+        run_args: &[String],
+        proxy_options: Option<&ProxyOptions>,
+    ) -> Result<ExitStatus> {
+        let server = self
+            .package
+            .clone()
+            .unwrap_or_else(|| self.container_name.clone());
+        let mut tracker =
+            CrashTracker::new(&server, self.policy_config.runtime.max_restart_attempts());
+        // one relay for every start, so the client keeps its session
+        let mut relay = proxy_options.map(Relay::start);
+        let status = loop {
+            let mut command = self.run_command(run_args);
+            command.stderr(Stdio::piped());
+            if proxy_options.is_some() {
+                command.stdin(Stdio::piped()).stdout(Stdio::piped());
+            }
+            let mut child = command
+                .spawn()
+                .with_context(|| format!("Failed to spawn {} command", self.runtime))?;
+            let tail = StderrTail::default();
+            let capture = tail.capture(
+                child
+                    .stderr
+                    .take()
+                    .context("Failed to open server stderr")?,
+            );
+
+            // an idle stop is deliberate, not a crash
+            let mut idle = false;
+            if let Some(ref mut relay) = relay {
+                let exit = relay.serve(&mut child, self.verbose).await?;
+                if exit == ProxyExit::Idle {
+                    self.cleanup().await?;
+                    idle = true;
+                }
+            }
+            let status = child
+                .wait()
+                .await
+                .with_context(|| format!("Failed to wait for {} command", self.runtime))?;
+            let _ = capture.await;
+            if status.success() || idle {
+                break status;
+            }
+
+            let crash = Crash {
+                exit_code: status.code(),
+                stderr: tail.lines(),
+            };
+            match tracker.record_crash(crash) {
+                CrashVerdict::Restart { attempt, backoff } => {
+                    eprintln!(
+                        "{}: server exited with {}, restart {} of {} in {}s",
+                        server,
+                        describe_exit(status.code()),
+                        attempt,
+                        tracker.max_restart_attempts(),
+                        backoff.as_secs()
+                    );
+                    self.audit_supervision(
+                        "server_restarted",
+                        json!({
+                            "attempt": attempt,
+                            "exit_code": status.code(),
+                            "backoff_secs": backoff.as_secs(),
+                        }),
+                    );
+                    tokio::time::sleep(backoff).await;
+                    // `--rm` may not have removed the crashed container yet
+                    let _ = self.runtime_step(&["rm", "-f", &self.container_name]).await;
+                }
+                CrashVerdict::CrashLoop => {
+                    let crashes = tracker.report().map(|report| {
+                        eprintln!("{}", report);
+                        report.crashes
+                    });
+                    self.audit_supervision(
+                        "server_crash_loop",
+                        json!({"crashes": crashes, "exit_code": status.code()}),
+                    );
+                    break status;
+                }
+            }
+        };
+        if let Some(relay) = relay {
+            relay.finish().await?;
+        }
+        Ok(status)
+    }
+
+    fn audit_supervision(&self, event: &str, fields: Value) {
+        self.audit(event, event == "server_crash_loop", fields);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_exit() {
+        assert_eq!(describe_exit(Some(3)), "code 3");
+        assert_eq!(describe_exit(None), "a signal");
+    }
+}
@@ -0,0 +1,182 @@
+//! Trace context of proxied requests.
+//!
+//! With `--trace`, every request from the client carries a W3C trace context
+//! in `params._meta.traceparent`, where MCP leaves room for metadata. A
+//! client that sends one keeps its trace id and the request gets a span of
+//! semcp's own; otherwise a new trace starts. Servers that export OTLP
+//! traces can continue it, and semcp puts the same ids in the audit records
+//! and refusals of the request, so a tool call can be followed from semcp's
+//! logs to the server's traces and to alerts that quote them.
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceContext {
+    /// 32 lowercase hex digits.
+    pub trace_id: String,
+    /// 16 lowercase hex digits, the span of the request.
+    pub span_id: String,
+}
+
+/// `len` hex digits that differ between calls and processes. They only
+/// need to be unique, not secret.
+fn unique_hex(len: usize) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos())
+        .unwrap_or_default();
+    let seed = format!(
+        "{}-{}-{}",
+        std::process::id(),
+        nanos,
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    format!("{:x}", Sha256::digest(seed))[..len].to_string()
+}
+
+fn is_hex_id(id: &str, len: usize) -> bool {
+    id.len() == len
+        && id
+            .chars()
+            .all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+        && id.chars().any(|c| c != '0')
+}
+
+impl TraceContext {
+    pub fn new_trace() -> Self {
+        Self {
+            trace_id: unique_hex(32),
+            span_id: unique_hex(16),
+        }
+    }
+
+    /// A context from a `traceparent` value, `None` when it is malformed.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let mut parts = traceparent.trim().split('-');
+        let (version, trace_id, span_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        let valid = version.len() == 2
+            && version != "ff"
+            && flags.len() == 2
+            && is_hex_id(trace_id, 32)
+            && is_hex_id(span_id, 16);
+        valid.then(|| Self {
+            trace_id: trace_id.to_string(),
+            span_id: span_id.to_string(),
+        })
+    }
+
+    /// A new span in the same trace.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: unique_hex(16),
+        }
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+
+    /// The context for the request `message`: a span in the client's trace
+    /// when it sent one, else a new trace.
+    pub fn for_request(message: &Value) -> Self {
+        message
+            .pointer("/params/_meta/traceparent")
+            .and_then(Value::as_str)
+            .and_then(Self::parse)
+            .map_or_else(Self::new_trace, |parent| parent.child())
+    }
+
+    /// Puts the context in the `_meta` of `message`. Requests whose params
+    /// are a list have no room for it and are left alone.
+    pub fn inject(&self, message: &mut Value) {
+        let Some(request) = message.as_object_mut() else {
+            return;
+        };
+        let params = request
+            .entry("params".to_string())
+            .or_insert_with(|| json!({}));
+        let Some(params) = params.as_object_mut() else {
+            return;
+        };
+        let meta = params
+            .entry("_meta".to_string())
+            .or_insert_with(|| json!({}));
+        if let Some(meta) = meta.as_object_mut() {
+            meta.insert("traceparent".to_string(), json!(self.traceparent()));
+        }
+    }
+
+    /// The ids as fields of an audit record or error data.
+    pub fn fields(&self) -> Value {
+        json!({"trace_id": self.trace_id, "span_id": self.span_id})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_traceparent() {
+        let context =
+            TraceContext::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.span_id, "00f067aa0ba902b7");
+        for invalid in [
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-01",
+        ] {
+            assert!(TraceContext::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_new_contexts_are_valid_and_distinct() {
+        let first = TraceContext::new_trace();
+        let second = TraceContext::new_trace();
+        assert_ne!(first, second);
+        assert_eq!(
+            TraceContext::parse(&first.traceparent()),
+            Some(first.clone())
+        );
+
+        let child = first.child();
+        assert_eq!(child.trace_id, first.trace_id);
+        assert_ne!(child.span_id, first.span_id);
+    }
+
+    #[test]
+    fn test_for_request_continues_the_client_trace() {
+        let mut message = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": {
+                "name": "read",
+                "_meta": {"traceparent": "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01", "progressToken": 7}
+            }
+        });
+        let context = TraceContext::for_request(&message);
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_ne!(context.span_id, "00f067aa0ba902b7");
+
+        context.inject(&mut message);
+        assert_eq!(
+            message["params"]["_meta"]["traceparent"],
+            json!(context.traceparent())
+        );
+        assert_eq!(message["params"]["_meta"]["progressToken"], json!(7));
+
+        let mut list = json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"});
+        TraceContext::new_trace().inject(&mut list);
+        assert!(list["params"]["_meta"]["traceparent"].is_string());
+    }
+}
@@ -0,0 +1,673 @@
+//! Trust on first use for packages.
+//!
+//! The first time snpx or suvx runs a package, it shows what is about to
+//! run: the version the registry resolves, who published it, its weekly
+//! downloads and the permissions the run grants. It asks for confirmation on
+//! the terminal, or takes `--approve` for it. The approval is recorded in
+//! `$XDG_STATE_HOME/semcp/trusted.json`, and later runs of the package
+//! without a version, with a tag such as `latest`, or with a range the
+//! approved version satisfies, are pinned to the approved version. Asking
+//! for another version, or granting more permissions, asks again, like SSH
+//! does when a host key changes.
+
+use crate::cli::ContainerArgs;
+use crate::state::{package_identity, state_home};
+use crate::{audit, runtime, PolicyConfig};
+use anyhow::{bail, Context, Result};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prints the version, publisher and weekly downloads of the package spec in
+/// `argv[1]`, named `argv[2]`, as JSON.
+const NPM_LOOKUP: &str = "const [spec, name] = process.argv.slice(1); \
+    let view = JSON.parse(require('child_process').execFileSync('npm', \
+        ['view', spec, 'version', '_npmUser', '--json'], {stdio: ['ignore', 'pipe', 'ignore']})); \
+    if (Array.isArray(view)) view = view[view.length - 1]; \
+    const user = view._npmUser; \
+    fetch('https://api.npmjs.org/downloads/point/last-week/' + name) \
+        .then(r => r.json()).catch(() => ({})) \
+        .then(d => console.log(JSON.stringify({version: view.version, \
+            publisher: typeof user === 'string' ? user.split(' <')[0] : user && user.name, \
+            weekly_downloads: d.downloads})));";
+
+/// The same for PyPI, with the version to look up in `argv[2]`, empty for
+/// the latest.
+const PYPI_LOOKUP: &str = r#"import json, sys, urllib.request
+def get(url):
+    try:
+        return json.load(urllib.request.urlopen(url))
+    except Exception:
+        return {}
+name, version = sys.argv[1], sys.argv[2]
+info = get('https://pypi.org/pypi/' + name + ('/' + version if version else '') + '/json').get('info') or {}
+recent = get('https://pypistats.org/api/packages/' + name.lower() + '/recent').get('data') or {}
+print(json.dumps({'version': info.get('version'), 'publisher': info.get('author') or info.get('maintainer') or None, 'weekly_downloads': recent.get('last_week')}))
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Registry {
+    Npm,
+    Pypi,
+}
+
+impl Registry {
+    /// The registry the packages of a runner command come from.
+    pub fn of_command(command: &str) -> Option<Self> {
+        match command {
+            "npx" => Some(Registry::Npm),
+            "uvx" => Some(Registry::Pypi),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Registry::Npm => "npm",
+            Registry::Pypi => "PyPI",
+        }
+    }
+
+    /// `name` pinned to `version`: `pkg@1.2.3` or `pkg==1.2.3`.
+    pub fn pin(&self, name: &str, version: &str) -> String {
+        match self {
+            Registry::Npm => format!("{}@{}", name, version),
+            Registry::Pypi => format!("{}=={}", name, version),
+        }
+    }
+
+    /// Splits a package spec into the package name and the version asked
+    /// for, if any: `1.2.3`, a range, or a tag such as `latest`.
+    pub fn split(&self, spec: &str) -> (String, Option<String>) {
+        let name = package_identity(spec);
+        let rest = spec[name.len()..].trim();
+        let version = match self {
+            Registry::Npm => rest.strip_prefix('@').unwrap_or(rest),
+            Registry::Pypi => rest.strip_prefix("==").unwrap_or(rest),
+        };
+        (name, Some(version.to_string()).filter(|v| !v.is_empty()))
+    }
+}
+
+/// How a run asks for a version of a package.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Requested {
+    /// A tag such as `latest`, which the approved version stands in for.
+    Tag,
+    /// A release, which has to be the approved one.
+    Release,
+    /// A range, which the approved version has to satisfy.
+    Range,
+}
+
+impl Registry {
+    fn requested(&self, version: &str) -> Requested {
+        if version.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Requested::Tag;
+        }
+        let release = match self {
+            Registry::Npm => Version::parse(version).is_ok(),
+            Registry::Pypi => {
+                version.starts_with(|c: char| c.is_ascii_digit())
+                    && version
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || ".!+-_".contains(c))
+            }
+        };
+        if release {
+            Requested::Release
+        } else {
+            Requested::Range
+        }
+    }
+
+    /// The requirements `range` stands for, any of which it accepts. `None`
+    /// when semver cannot express it, such as PyPI's `!=`.
+    fn requirements(&self, range: &str) -> Option<Vec<VersionReq>> {
+        let alternatives: Vec<Vec<String>> = match self {
+            Registry::Npm => range
+                .split("||")
+                .map(|alternative| npm_comparators(alternative.trim()))
+                .collect(),
+            Registry::Pypi => vec![range
+                .split(',')
+                .map(|specifier| pypi_comparator(specifier.trim()))
+                .collect::<Option<Vec<_>>>()?
+                .concat()],
+        };
+        alternatives
+            .iter()
+            .map(|comparators| VersionReq::parse(&comparators.join(", ")).ok())
+            .collect()
+    }
+
+    /// Whether `version` is within the requested `range`.
+    fn satisfies(&self, range: &str, version: &str) -> bool {
+        let version = match self {
+            Registry::Npm => Version::parse(version),
+            Registry::Pypi => Version::parse(&pad_release(version)),
+        };
+        match (version, self.requirements(range)) {
+            (Ok(version), Some(requirements)) => {
+                requirements.iter().any(|req| req.matches(&version))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The comparators of one `||` alternative of an npm range, in the syntax
+/// of [`VersionReq`].
+fn npm_comparators(alternative: &str) -> Vec<String> {
+    if let Some((low, high)) = alternative.split_once(" - ") {
+        return vec![format!(">={}", low.trim()), format!("<={}", high.trim())];
+    }
+    // `>= 1.2` is one comparator, written with a space
+    let mut tokens: Vec<String> = Vec::new();
+    for token in alternative.split_whitespace() {
+        match tokens.last_mut() {
+            Some(last) if last.chars().all(|c| "<>=~^".contains(c)) => last.push_str(token),
+            _ => tokens.push(token.to_string()),
+        }
+    }
+    if tokens.is_empty() {
+        return vec!["*".to_string()];
+    }
+    tokens
+        .iter()
+        .map(|token| {
+            let version = token.trim_start_matches(|c: char| "<>=~^".contains(c));
+            let op = &token[..token.len() - version.len()];
+            let version = version.strip_prefix('v').unwrap_or(version);
+            match (op, version) {
+                (_, "*" | "x" | "X" | "") => "*".to_string(),
+                // a bare version means that release, or with fewer parts
+                // any release starting with them, as `=` does in semver
+                ("", _) => format!("={}", version),
+                _ => format!("{}{}", op, version),
+            }
+        })
+        .collect()
+}
+
+/// A PEP 440 version specifier as [`VersionReq`] comparators.
+fn pypi_comparator(specifier: &str) -> Option<Vec<String>> {
+    let (op, version) = ["~=", "===", "==", "!=", ">=", "<=", ">", "<"]
+        .iter()
+        .find_map(|op| Some((*op, specifier.strip_prefix(op)?.trim())))?;
+    Some(match op {
+        "~=" => {
+            // ~=1.4.2 is >=1.4.2, ==1.4.*; ~=1.4 is >=1.4, ==1.*
+            let mut parts: Vec<&str> = version.split('.').collect();
+            if parts.len() < 2 {
+                return None;
+            }
+            parts.pop();
+            vec![
+                format!(">={}", pad_release(version)),
+                format!("={}", parts.join(".")),
+            ]
+        }
+        "==" => match version.strip_suffix(".*") {
+            Some(prefix) => vec![format!("={}", prefix)],
+            None => vec![format!("={}", pad_release(version))],
+        },
+        "!=" | "===" => return None,
+        _ => vec![format!("{}{}", op, pad_release(version))],
+    })
+}
+
+/// `2024.1` as `2024.1.0`: PyPI releases need not have three parts.
+fn pad_release(version: &str) -> String {
+    let parts = version.split('.').count();
+    let mut padded = version.to_string();
+    for _ in parts..3 {
+        padded.push_str(".0");
+    }
+    padded
+}
+
+/// What the registry says about a package.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct PackageInfo {
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    #[serde(default)]
+    pub weekly_downloads: Option<u64>,
+}
+
+/// A package the user approved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Approval {
+    pub registry: Registry,
+    pub name: String,
+    /// The version later runs are pinned to, when it could be resolved.
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub publisher: Option<String>,
+    /// The permissions granted when it was approved, see
+    /// [`requested_permissions`].
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    /// Unix time of the approval.
+    pub approved_at: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustStore {
+    pub packages: Vec<Approval>,
+}
+
+impl TrustStore {
+    pub fn path() -> Option<PathBuf> {
+        Some(state_home()?.join("trusted.json"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path().filter(|path| path.exists()) else {
+            return Ok(Self::default());
+        };
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().context("Cannot locate the trust store: HOME is not set")?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let partial = path.with_extension(format!("partial-{}", std::process::id()));
+        std::fs::write(&partial, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        std::fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn find(&self, registry: Registry, name: &str) -> Option<&Approval> {
+        self.packages
+            .iter()
+            .find(|approval| approval.registry == registry && approval.name == name)
+    }
+
+    /// Records `approval`, replacing an earlier one of the package.
+    pub fn approve(&mut self, approval: Approval) {
+        self.packages
+            .retain(|old| old.registry != approval.registry || old.name != approval.name);
+        self.packages.push(approval);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// Approved before: run the approved version, if it is known.
+    Trusted(Option<String>),
+    /// Never approved.
+    New,
+    /// Approved, but the run differs from the approval in these ways.
+    Changed(Vec<String>),
+}
+
+/// Compares a run of `requested` with `permissions` to the earlier
+/// approval of the package.
+pub fn verdict(
+    approval: Option<&Approval>,
+    requested: Option<&str>,
+    permissions: &[String],
+) -> Verdict {
+    let Some(approval) = approval else {
+        return Verdict::New;
+    };
+    let mut changes = Vec::new();
+    let approved = approval.version.as_deref();
+    if let Some(requested) = requested {
+        let matches = match approval.registry.requested(requested) {
+            Requested::Tag => true,
+            Requested::Release => approved == Some(requested),
+            Requested::Range => {
+                approved.is_some_and(|approved| approval.registry.satisfies(requested, approved))
+            }
+        };
+        if !matches {
+            changes.push(format!(
+                "version {} instead of the approved {}",
+                requested,
+                approved.unwrap_or("unknown")
+            ));
+        }
+    }
+    for permission in permissions {
+        if !approval.permissions.contains(permission) {
+            changes.push(format!("new permission: {}", permission));
+        }
+    }
+    if changes.is_empty() {
+        Verdict::Trusted(approval.version.clone())
+    } else {
+        Verdict::Changed(changes)
+    }
+}
+
+/// The permissions a run grants, as shown for approval: those of the
+/// policy, and those the command line adds.
+pub fn requested_permissions(policy: &PolicyConfig, args: &ContainerArgs) -> Vec<String> {
+    let mut permissions = Vec::new();
+    for (path, writable) in policy.storage_grants() {
+        let access = if writable { "read-write" } else { "read" };
+        permissions.push(format!("{} {}", access, path));
+    }
+    for volume in &args.volumes {
+        permissions.push(format!("mount {}", volume));
+    }
+    let networks = policy
+        .network_mode()
+        .into_iter()
+        .chain(policy.allowed_network_hosts())
+        .chain(args.network.clone());
+    for network in networks {
+        permissions.push(format!("network {}", network));
+    }
+    let env_keys = policy
+        .allowed_env_keys()
+        .into_iter()
+        .chain(policy.runtime.environment_whitelist.iter().cloned())
+        .chain(
+            args.env
+                .iter()
+                .map(|env| env.split('=').next().unwrap_or(env).to_string()),
+        );
+    for key in env_keys {
+        permissions.push(format!("env {}", key));
+    }
+    for cap in policy.added_capabilities().iter().chain(&args.cap_add) {
+        permissions.push(format!("capability {}", cap));
+    }
+    permissions.sort();
+    permissions.dedup();
+    permissions
+}
+
+/// Asks the registry about `name`, from inside `image` like the run will.
+pub async fn lookup(
+    registry: Registry,
+    image: &str,
+    name: &str,
+    requested: Option<&str>,
+) -> Result<PackageInfo> {
+    let mut args = vec!["--rm".to_string(), "--entrypoint".to_string()];
+    match registry {
+        Registry::Npm => args.extend([
+            "node".to_string(),
+            image.to_string(),
+            "-e".to_string(),
+            NPM_LOOKUP.to_string(),
+            requested.map_or(name.to_string(), |version| registry.pin(name, version)),
+            name.to_string(),
+        ]),
+        Registry::Pypi => args.extend([
+            "python3".to_string(),
+            image.to_string(),
+            "-c".to_string(),
+            PYPI_LOOKUP.to_string(),
+            name.to_string(),
+            requested
+                .filter(|version| registry.requested(version) == Requested::Release)
+                .unwrap_or_default()
+                .to_string(),
+        ]),
+    }
+    let output = runtime::current()
+        .run(&args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} run", runtime::current()))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to look up {} in the {} registry: {}",
+            name,
+            registry.name(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Unexpected registry answer for {}", name))
+}
+
+/// What is shown before asking for approval.
+pub fn describe(
+    name: &str,
+    info: &PackageInfo,
+    permissions: &[String],
+    changes: &[String],
+) -> String {
+    let mut text = if changes.is_empty() {
+        format!("{} has not been run before:\n", name)
+    } else {
+        let mut text = format!("{} differs from what was approved:\n", name);
+        for change in changes {
+            text.push_str(&format!("  ! {}\n", change));
+        }
+        text
+    };
+    let unknown = || "unknown".to_string();
+    text.push_str(&format!(
+        "  version:          {}\n",
+        info.version.clone().unwrap_or_else(unknown)
+    ));
+    text.push_str(&format!(
+        "  publisher:        {}\n",
+        info.publisher.clone().unwrap_or_else(unknown)
+    ));
+    text.push_str(&format!(
+        "  weekly downloads: {}\n",
+        info.weekly_downloads
+            .map_or_else(unknown, |downloads| downloads.to_string())
+    ));
+    match permissions {
+        [] => text.push_str("  permissions:      none"),
+        [first, rest @ ..] => {
+            text.push_str(&format!("  permissions:      {}", first));
+            for permission in rest {
+                text.push_str(&format!("\n                    {}", permission));
+            }
+        }
+    }
+    text
+}
+
+/// Asks on the terminal, since stdin carries the MCP session.
+fn confirm(name: &str) -> Result<bool> {
+    let terminal = if cfg!(windows) { "CONIN$" } else { "/dev/tty" };
+    let Ok(tty) = std::fs::File::open(terminal) else {
+        bail!(
+            "{} has to be approved before it runs, but there is no terminal to ask on. Run it once interactively, or pass --approve",
+            name
+        );
+    };
+    eprint!("Run it and pin this version? [y/N] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    BufReader::new(tty).read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Checks `package` against the trust store, asking for approval when it
+/// is new or changed, unless `approve`. Returns the package spec to run,
+/// pinned to the approved version.
+pub async fn approve_package(
+    registry: Registry,
+    image: &str,
+    package: &str,
+    permissions: &[String],
+    approve: bool,
+) -> Result<String> {
+    let (name, requested) = registry.split(package);
+    let mut store = TrustStore::load()?;
+    let changes = match verdict(
+        store.find(registry, &name),
+        requested.as_deref(),
+        permissions,
+    ) {
+        Verdict::Trusted(Some(version)) => return Ok(registry.pin(&name, &version)),
+        Verdict::Trusted(None) => return Ok(package.to_string()),
+        Verdict::New => Vec::new(),
+        Verdict::Changed(changes) => changes,
+    };
+
+    let info = match lookup(registry, image, &name, requested.as_deref()).await {
+        Ok(info) => info,
+        Err(e) => {
+            eprintln!("Warning: {:#}", e);
+            PackageInfo::default()
+        }
+    };
+    eprintln!("{}", describe(&name, &info, permissions, &changes));
+    if !approve && !confirm(&name)? {
+        bail!("{} was not approved", name);
+    }
+
+    let version = info.version.clone().or(requested);
+    let approval = Approval {
+        registry,
+        name: name.clone(),
+        version: version.clone(),
+        publisher: info.publisher,
+        permissions: permissions.to_vec(),
+        approved_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_secs())
+            .unwrap_or_default(),
+    };
+    let _ = audit::record(
+        "package_approved",
+        json!({
+            "registry": registry,
+            "package": name,
+            "version": version,
+            "permissions": permissions,
+        }),
+    );
+    store.approve(approval);
+    store.save()?;
+    Ok(version.map_or(package.to_string(), |version| registry.pin(&name, &version)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approval(version: &str, permissions: &[&str]) -> Approval {
+        Approval {
+            registry: Registry::Npm,
+            name: "@scope/server".to_string(),
+            version: Some(version.to_string()),
+            publisher: None,
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            approved_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_split_package_spec() {
+        assert_eq!(
+            Registry::Npm.split("@scope/server@1.2.3"),
+            ("@scope/server".to_string(), Some("1.2.3".to_string()))
+        );
+        assert_eq!(Registry::Npm.split("server"), ("server".to_string(), None));
+        assert_eq!(
+            Registry::Pypi.split("mcp-server-git==0.6.2"),
+            ("mcp-server-git".to_string(), Some("0.6.2".to_string()))
+        );
+        assert_eq!(Registry::Pypi.pin("git", "0.6.2"), "git==0.6.2");
+    }
+
+    #[test]
+    fn test_verdict() {
+        let permissions = vec!["read /data".to_string()];
+        let approved = approval("1.2.3", &["read /data"]);
+        assert_eq!(verdict(None, None, &permissions), Verdict::New);
+        for requested in [None, Some("latest"), Some("1.2.3")] {
+            assert_eq!(
+                verdict(Some(&approved), requested, &permissions),
+                Verdict::Trusted(Some("1.2.3".to_string()))
+            );
+        }
+        assert_eq!(
+            verdict(Some(&approved), Some("1.3.0"), &[]),
+            Verdict::Changed(vec![
+                "version 1.3.0 instead of the approved 1.2.3".to_string()
+            ])
+        );
+        for range in [
+            "^1.2.0",
+            "~1.2",
+            "1.x",
+            ">= 1.0.0 < 2",
+            "1.0.0 - 1.5.0",
+            "^0.9 || ^1",
+        ] {
+            assert_eq!(
+                verdict(Some(&approved), Some(range), &[]),
+                Verdict::Trusted(Some("1.2.3".to_string())),
+                "{}",
+                range
+            );
+        }
+        for range in ["^2.0.0", "~1.3", ">1.2.3", "<1.2"] {
+            assert_eq!(
+                verdict(Some(&approved), Some(range), &[]),
+                Verdict::Changed(vec![format!(
+                    "version {} instead of the approved 1.2.3",
+                    range
+                )]),
+            );
+        }
+        let wider = vec!["read /data".to_string(), "network example.com".to_string()];
+        assert_eq!(
+            verdict(Some(&approved), None, &wider),
+            Verdict::Changed(vec!["new permission: network example.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_pypi_ranges() {
+        let pypi = Registry::Pypi;
+        assert_eq!(pypi.requested("0.6.2"), Requested::Release);
+        assert_eq!(pypi.requested("1.0rc1"), Requested::Release);
+        assert_eq!(pypi.requested(">=0.6,<1"), Requested::Range);
+        for range in [">=0.6,<1", "~=0.6.0", "~=0.6", "==0.6.*", ">0.6"] {
+            assert!(pypi.satisfies(range, "0.6.2"), "{}", range);
+        }
+        for range in ["~=0.6.3", "==0.5.*", "<0.6", "!=0.6.1"] {
+            assert!(!pypi.satisfies(range, "0.6.2"), "{}", range);
+        }
+        assert!(pypi.satisfies(">=2024.1", "2024.3"));
+    }
+
+    #[test]
+    fn test_store_replaces_earlier_approval() {
+        let mut store = TrustStore::default();
+        store.approve(approval("1.2.3", &[]));
+        store.approve(approval("1.3.0", &[]));
+        assert_eq!(store.packages.len(), 1);
+        assert_eq!(
+            store
+                .find(Registry::Npm, "@scope/server")
+                .and_then(|approval| approval.version.as_deref()),
+            Some("1.3.0")
+        );
+        assert!(store.find(Registry::Pypi, "@scope/server").is_none());
+    }
+}
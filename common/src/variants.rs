@@ -0,0 +1,118 @@
+//! What each image variant can do.
+//!
+//! Distroless images ship the language runtime only: no shell and no
+//! package manager, so `npx`/`uvx` cannot run in them at all. Checking the
+//! variant up front replaces the confusing "exec: not found" failures with
+//! an error that says what to do instead.
+
+use crate::{ContainerExecutor, ImageVariants, Runner};
+use anyhow::{bail, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VariantCapabilities {
+    pub has_shell: bool,
+    pub has_package_manager: bool,
+}
+
+impl VariantCapabilities {
+    pub const FULL: Self = Self {
+        has_shell: true,
+        has_package_manager: true,
+    };
+    pub const RUNTIME_ONLY: Self = Self {
+        has_shell: false,
+        has_package_manager: false,
+    };
+
+    /// Lists the capabilities required by `needed` that are missing here.
+    pub fn missing(&self, needed: &Self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if needed.has_shell && !self.has_shell {
+            missing.push("shell");
+        }
+        if needed.has_package_manager && !self.has_package_manager {
+            missing.push("package manager");
+        }
+        missing
+    }
+}
+
+impl ImageVariants {
+    /// Capabilities of a known variant. Custom images are assumed to be
+    /// complete, whatever their name, as one built from a distroless base
+    /// may well have the package installed.
+    pub fn capabilities(image: &str) -> VariantCapabilities {
+        let known_distroless = [Self::NODE_DISTROLESS];
+        if known_distroless.contains(&image) {
+            VariantCapabilities::RUNTIME_ONLY
+        } else {
+            VariantCapabilities::FULL
+        }
+    }
+}
+
+impl ContainerExecutor {
+    /// Fails early when the image cannot run the runner's command with the
+    /// given flags.
+    pub fn check_variant<R: Runner>(&self, runner: &R, flags: &[String]) -> Result<()> {
+        let available = ImageVariants::capabilities(&self.docker_image);
+        let needed = runner.required_capabilities(flags);
+        let missing = available.missing(&needed);
+        if missing.is_empty() {
+            return Ok(());
+        }
+        bail!(
+            "Image `{}` has no {}, which `{}` needs. Prebuild an image with the \
+             package already installed (for example from a slim variant) and pass \
+             it with --image, or choose the alpine, slim or standard variant",
+            self.docker_image,
+            missing.join(" or "),
+            runner.command()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_variants() {
+        assert_eq!(
+            ImageVariants::capabilities(ImageVariants::NODE_DISTROLESS),
+            VariantCapabilities::RUNTIME_ONLY
+        );
+        assert_eq!(
+            ImageVariants::capabilities(ImageVariants::NODE_ALPINE),
+            VariantCapabilities::FULL
+        );
+    }
+
+    #[test]
+    fn test_custom_images_are_complete() {
+        for image in [
+            "registry.example.com/mcp/server-memory-distroless:1.2",
+            "gcr.io/distroless/python3-debian12",
+        ] {
+            assert_eq!(
+                ImageVariants::capabilities(image),
+                VariantCapabilities::FULL,
+                "{}",
+                image
+            );
+        }
+    }
+
+    #[test]
+    fn test_missing_capabilities() {
+        let needed = VariantCapabilities {
+            has_shell: false,
+            has_package_manager: true,
+        };
+        assert_eq!(
+            VariantCapabilities::RUNTIME_ONLY.missing(&needed),
+            ["package manager"]
+        );
+        assert!(VariantCapabilities::FULL.missing(&needed).is_empty());
+    }
+}
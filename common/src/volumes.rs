@@ -0,0 +1,77 @@
+use crate::ContainerExecutor;
+use anyhow::{bail, Context, Result};
+
+impl ContainerExecutor {
+    /// Creates the named volumes declared by the policy, and the package
+    /// state volume, if they do not exist yet. A state volume still under
+    /// its legacy name is used as it is. Volumes are labelled so they
+    /// can be traced back to semcp, and get the labels of the run.
+    pub async fn ensure_named_volumes(&self) -> Result<()> {
+        for volume in &self.policy_config.runtime.docker.volumes {
+            let mut labels = vec!["semcp.managed=true".to_string()];
+            if let Some(ref size) = volume.size {
+                labels.push(format!("semcp.size-hint={}", size));
+            }
+            labels.extend(self.labels());
+            self.ensure_volume(&volume.name, &labels).await?;
+        }
+        let state = self.state_volume.lock().unwrap().clone();
+        if let Some(state) = state {
+            let state = state.resolve(self.runtime.as_ref()).await?;
+            if self.verbose && state.name == state.legacy_name() {
+                eprintln!(
+                    "Using the state volume {} under its legacy name",
+                    state.name
+                );
+            }
+            let mut labels = state.labels();
+            labels.extend(self.labels());
+            self.ensure_volume(&state.name, &labels).await?;
+            *self.state_volume.lock().unwrap() = Some(state);
+        }
+        Ok(())
+    }
+
+    async fn ensure_volume(&self, name: &str, labels: &[String]) -> Result<()> {
+        let inspect = self
+            .runtime
+            .command()
+            .args(["volume", "inspect", name])
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {} volume inspect", self.runtime))?;
+        if inspect.status.success() {
+            return Ok(());
+        }
+
+        let mut create_args = vec!["volume".to_string(), "create".to_string()];
+        for label in labels {
+            create_args.push("--label".to_string());
+            create_args.push(label.clone());
+        }
+        create_args.push(name.to_string());
+
+        if self.verbose {
+            eprintln!(
+                "Creating volume: {} {}",
+                self.runtime,
+                create_args.join(" ")
+            );
+        }
+        let output = self
+            .runtime
+            .command()
+            .args(&create_args)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute {} volume create", self.runtime))?;
+        if !output.status.success() {
+            bail!(
+                "Failed to create volume {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+}
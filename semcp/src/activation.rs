@@ -0,0 +1,99 @@
+//! On-demand containers for `semcp serve`.
+//!
+//! Nothing runs for an on-demand server until a client connects. The first
+//! connection starts an idle container, every connection execs its own
+//! server process in it, and the container is removed once no connection
+//! has been open for the server's idle timeout.
+
+use crate::pool::{remove_container, start_idle_container, Profile};
+use anyhow::Result;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+pub const ON_DEMAND_LABEL: &str = "semcp.on-demand=true";
+
+#[derive(Default)]
+struct State {
+    container: Option<String>,
+    connections: usize,
+    /// Bumped whenever the last connection closes, so that an idle timer
+    /// started earlier can tell the container has been used since.
+    idle_since: u64,
+}
+
+pub struct OnDemand {
+    name: String,
+    profile: Profile,
+    idle_timeout: Duration,
+    verbose: bool,
+    state: Mutex<State>,
+}
+
+impl OnDemand {
+    pub fn new(name: &str, profile: Profile, idle_timeout: Duration, verbose: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            profile,
+            idle_timeout,
+            verbose,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the running container, starting it if needed, and counts the
+    /// caller as a connection until `release` is called.
+    pub async fn acquire(&self) -> Result<String> {
+        let mut state = self.state.lock().await;
+        let container = match state.container {
+            Some(ref container) => container.clone(),
+            None => {
+                let container = format!("semcp-{}-{}", self.name, std::process::id());
+                if self.verbose {
+                    eprintln!("{}: starting on first connection", self.name);
+                }
+                start_idle_container(&container, ON_DEMAND_LABEL, &self.profile, self.verbose)
+                    .await?;
+                state.container = Some(container.clone());
+                container
+            }
+        };
+        state.connections += 1;
+        Ok(container)
+    }
+
+    /// Ends a connection. When it was the last one, the container is
+    /// stopped after the idle timeout unless another client connects first.
+    pub async fn release(self: &Arc<Self>) {
+        let idle_since = {
+            let mut state = self.state.lock().await;
+            state.connections = state.connections.saturating_sub(1);
+            if state.connections > 0 {
+                return;
+            }
+            state.idle_since += 1;
+            state.idle_since
+        };
+
+        let this = Arc::clone(self);
+        tokio::spawn(async move {
+            tokio::time::sleep(this.idle_timeout).await;
+            let mut state = this.state.lock().await;
+            if state.connections > 0 || state.idle_since != idle_since {
+                return;
+            }
+            if let Some(container) = state.container.take() {
+                if this.verbose {
+                    eprintln!("{}: idle, stopping {}", this.name, container);
+                }
+                remove_container(&container).await;
+            }
+        });
+    }
+
+    pub async fn stop(&self) {
+        if let Some(container) = self.state.lock().await.container.take() {
+            remove_container(&container).await;
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! Image bundles for air-gapped networks.
+//!
+//! `semcp bundle image` writes one server of a manifest to a tar file: the
+//! saved image and a `bundle.yaml` with the server's unit (see
+//! [`crate::unit`]) and the ID of the image. `semcp bundle load` loads the
+//! image on a machine without registry access, checks that it is the one
+//! that was saved, and adds the server to the manifest there.
+
+use crate::manifest::Manifest;
+use crate::unit::{self, ImportUnitArgs, Unit};
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use semcp_common::{pull, runtime};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+pub const BUNDLE_VERSION: u32 = 1;
+
+const META_FILE: &str = "bundle.yaml";
+const IMAGE_FILE: &str = "image.tar";
+
+#[derive(Subcommand, Debug)]
+pub enum BundleCommand {
+    #[command(about = "Save a server of a manifest with its image to a tar file")]
+    Image(BundleImageArgs),
+
+    #[command(about = "Load a bundle and add its server to a manifest")]
+    Load(BundleLoadArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct BundleImageArgs {
+    #[arg(help = "Name of the server to bundle")]
+    pub server: String,
+
+    #[arg(short = 'o', long = "out", help = "Path of the tar file to write")]
+    pub out: String,
+
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers"
+    )]
+    pub config: String,
+}
+
+#[derive(Args, Debug)]
+pub struct BundleLoadArgs {
+    #[arg(help = "The bundle to load")]
+    pub bundle: String,
+
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest to add the server to (created if missing)"
+    )]
+    pub config: String,
+
+    #[arg(
+        long = "name",
+        help = "Name to give the server instead of the bundled one"
+    )]
+    pub name: Option<String>,
+
+    #[arg(long = "port", help = "Port to serve it on instead of the bundled one")]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleMeta {
+    pub version: u32,
+    /// ID of the saved image, which survives save and load.
+    pub image_id: String,
+    pub unit: Unit,
+}
+
+impl BundleMeta {
+    pub fn parse(content: &str) -> Result<Self> {
+        let meta: BundleMeta = serde_yaml::from_str(content).context("Not a semcp bundle")?;
+        if meta.version != BUNDLE_VERSION {
+            bail!(
+                "Unsupported bundle version {} (this semcp reads version {})",
+                meta.version,
+                BUNDLE_VERSION
+            );
+        }
+        if meta.unit.version != unit::UNIT_VERSION {
+            bail!("Unsupported unit version {}", meta.unit.version);
+        }
+        Ok(meta)
+    }
+}
+
+pub async fn run(command: BundleCommand) -> Result<()> {
+    match command {
+        BundleCommand::Image(args) => image(args).await,
+        BundleCommand::Load(args) => load(args).await,
+    }
+}
+
+/// Scratch directory for the files of a bundle, removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new() -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("semcp-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `args` of the container runtime and returns its output.
+async fn runtime_step(args: &[&str]) -> Result<String> {
+    let runtime = runtime::current();
+    let output = runtime
+        .command()
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} {}", runtime, args[0]))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            runtime,
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+async fn tar(args: &[&str]) -> Result<()> {
+    let output = Command::new("tar")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to execute tar")?;
+    if !output.status.success() {
+        bail!(
+            "tar failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+async fn image(args: BundleImageArgs) -> Result<()> {
+    let manifest = Manifest::from_file(&args.config)?;
+    let spec = unit::find_server(&manifest, &args.server, &args.config)?;
+    if !runtime::current().inspect_image(&spec.image).await? {
+        eprintln!("Pulling {}", spec.image);
+        pull::pull(runtime::current().as_ref(), &spec.image, false)
+            .await
+            .with_context(|| format!("Failed to pull {}", spec.image))?;
+    }
+    let unit = Unit::of(spec).await?;
+    let image_id = runtime_step(&["image", "inspect", "--format", "{{.Id}}", &spec.image]).await?;
+
+    let scratch = Scratch::new()?;
+    let image_file = scratch.0.join(IMAGE_FILE);
+    runtime_step(&["save", "-o", &image_file.to_string_lossy(), &spec.image]).await?;
+    let meta = BundleMeta {
+        version: BUNDLE_VERSION,
+        image_id,
+        unit,
+    };
+    std::fs::write(scratch.0.join(META_FILE), serde_yaml::to_string(&meta)?)
+        .context("Failed to write the bundle metadata")?;
+    tar(&[
+        "-cf",
+        &args.out,
+        "-C",
+        &scratch.0.to_string_lossy(),
+        META_FILE,
+        IMAGE_FILE,
+    ])
+    .await?;
+    eprintln!(
+        "Bundled {} with {} to {}, load it with `semcp bundle load`",
+        spec.name, spec.image, args.out
+    );
+    Ok(())
+}
+
+async fn load(args: BundleLoadArgs) -> Result<()> {
+    if !Path::new(&args.bundle).is_file() {
+        bail!("{} does not exist", args.bundle);
+    }
+    let scratch = Scratch::new()?;
+    tar(&["-xf", &args.bundle, "-C", &scratch.0.to_string_lossy()]).await?;
+    let content = std::fs::read_to_string(scratch.0.join(META_FILE))
+        .with_context(|| format!("{} is not a semcp bundle", args.bundle))?;
+    let mut meta =
+        BundleMeta::parse(&content).with_context(|| format!("Failed to parse {}", args.bundle))?;
+
+    runtime_step(&["load", "-i", &scratch.0.join(IMAGE_FILE).to_string_lossy()]).await?;
+    let image = &meta.unit.server.image;
+    let loaded = runtime_step(&["image", "inspect", "--format", "{{.Id}}", image]).await;
+    match loaded {
+        Ok(id) if id == meta.image_id => {}
+        Ok(id) => bail!(
+            "{} was loaded as {} but bundled as {}",
+            image,
+            id,
+            meta.image_id
+        ),
+        // a load keeps tags but not registry digests, run the image by ID
+        Err(_) => {
+            if !runtime::current().inspect_image(&meta.image_id).await? {
+                bail!("{} does not contain image {}", args.bundle, meta.image_id);
+            }
+            eprintln!(
+                "{} is not known here, running it as {}",
+                image, meta.image_id
+            );
+            meta.unit.server.image = meta.image_id.clone();
+        }
+    }
+    let import = ImportUnitArgs {
+        file: args.bundle,
+        config: args.config,
+        name: args.name,
+        port: args.port,
+    };
+    unit::add_unit(meta.unit, &import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bundle_checks_version() {
+        let meta = "version: 1\nimage_id: sha256:ab\nunit:\n  version: 1\n  \
+                    server: {name: time, image: img, command: [uvx, t], port: 7302}\n";
+        let parsed = BundleMeta::parse(meta).unwrap();
+        assert_eq!(parsed.image_id, "sha256:ab");
+        assert_eq!(parsed.unit.server.name, "time");
+        assert!(BundleMeta::parse(&meta.replacen("version: 1", "version: 2", 1)).is_err());
+    }
+}
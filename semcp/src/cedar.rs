@@ -0,0 +1,29 @@
+use anyhow::Result;
+use clap::Subcommand;
+use semcp_common::cedar;
+use std::path::Path;
+
+#[derive(Subcommand)]
+pub enum CedarCommand {
+    #[command(about = "Print the Cedar schema of the entities and actions semcp asks about")]
+    Schema,
+
+    #[command(about = "Validate Cedar policies against the semcp schema")]
+    Validate {
+        #[arg(help = "Cedar policy file")]
+        policies: String,
+    },
+}
+
+pub async fn run(command: CedarCommand) -> Result<()> {
+    match command {
+        CedarCommand::Schema => {
+            println!("{}", serde_json::to_string_pretty(&cedar::schema())?);
+        }
+        CedarCommand::Validate { policies } => {
+            cedar::validate(&cedar::binary()?, Path::new(&policies))?;
+            eprintln!("{} is valid", policies);
+        }
+    }
+    Ok(())
+}
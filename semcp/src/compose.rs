@@ -0,0 +1,496 @@
+//! `semcp export compose`: the servers of a manifest as a Compose file.
+//!
+//! Each server becomes a service with the run options `semcp serve` gives
+//! its containers, translated to Compose keys, and the DNS filter and
+//! firewall sidecars of its policy become services of their own. Servers
+//! speak MCP over stdio, so a client starts one per connection with
+//! `docker compose run --rm -T <server>`. Options Compose has no key for
+//! are listed in a comment at the top of the file.
+
+use crate::manifest::{Manifest, ServerSpec};
+use crate::unit::find_server;
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use semcp_common::firewall::rules_script;
+use semcp_common::sidecar::SidecarImage;
+use semcp_common::{dnsfilter, engine, ContainerExecutor, PolicyConfig};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Args, Debug)]
+pub struct ExportComposeArgs {
+    #[arg(help = "Servers to export, all of the manifest without any")]
+    pub servers: Vec<String>,
+
+    #[arg(
+        short = 'o',
+        long = "out",
+        default_value = "docker-compose.yaml",
+        help = "Path of the Compose file to write"
+    )]
+    pub out: String,
+
+    #[arg(long = "force", help = "Overwrite an existing file")]
+    pub force: bool,
+
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers"
+    )]
+    pub config: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ComposeFile {
+    pub services: BTreeMap<String, Service>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, Network>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub volumes: BTreeMap<String, Volume>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Service {
+    pub image: String,
+    /// How Compose builds `image`, for the built-in sidecar images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<Build>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub entrypoint: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stdin_open: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub init: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub privileged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_parent: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mem_limit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpus: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pids_limit: Option<i64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub ulimits: BTreeMap<String, Ulimit>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cap_drop: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub cap_add: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub security_opt: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tmpfs: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+    /// Names only: Compose passes the values from its own environment.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub environment: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub labels: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dns: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dns_search: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dns_opt: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub networks: BTreeMap<String, ServiceNetwork>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Build {
+    pub context: String,
+    pub dockerfile_inline: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Ulimit {
+    Single(i64),
+    Range { soft: i64, hard: i64 },
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ServiceNetwork {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv4_address: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Network {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipam: Option<Ipam>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Ipam {
+    pub config: Vec<Subnet>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Subnet {
+    pub subnet: String,
+}
+
+/// A named volume, under its own name rather than one prefixed with the
+/// project's, so it is the volume `semcp serve` uses.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Volume {
+    pub name: String,
+}
+
+/// `soft` or `soft:hard` of `--ulimit name=soft:hard`.
+fn parse_ulimit(value: &str) -> Option<Ulimit> {
+    match value.split_once(':') {
+        Some((soft, hard)) => Some(Ulimit::Range {
+            soft: soft.parse().ok()?,
+            hard: hard.parse().ok()?,
+        }),
+        None => Some(Ulimit::Single(value.parse().ok()?)),
+    }
+}
+
+/// Flags without a value.
+const SWITCHES: [&str; 6] = ["--init", "--read-only", "--privileged", "--rm", "-i", "-t"];
+
+/// Sets the Compose keys of the `run` options `args` on `service`, and
+/// returns the options Compose has no key for.
+pub fn apply_run_args(service: &mut Service, args: &[String]) -> Vec<String> {
+    let mut unmapped = Vec::new();
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        let (flag, value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ if SWITCHES.contains(&arg.as_str()) => (arg.as_str(), None),
+            _ => (
+                arg.as_str(),
+                args.next_if(|next| !next.starts_with('-')).cloned(),
+            ),
+        };
+        match (flag, value) {
+            ("--init", None) => service.init = true,
+            ("--read-only", None) => service.read_only = true,
+            ("--privileged", None) => service.privileged = true,
+            // every service container is run with these
+            ("--rm" | "-i" | "-t", None) => {}
+            ("--memory", Some(value)) => service.mem_limit = Some(value),
+            ("--cpus", Some(value)) => service.cpus = Some(value),
+            ("--user", Some(value)) => service.user = Some(value),
+            ("--runtime", Some(value)) => service.runtime = Some(value),
+            ("--network", Some(value)) => service.network_mode = Some(value),
+            ("--cgroup-parent", Some(value)) => service.cgroup_parent = Some(value),
+            ("--cap-drop", Some(value)) => service.cap_drop.push(value),
+            ("--cap-add", Some(value)) => service.cap_add.push(value),
+            ("--security-opt", Some(value)) => service.security_opt.push(value),
+            ("--tmpfs", Some(value)) => service.tmpfs.push(value),
+            ("-v" | "--volume", Some(value)) => service.volumes.push(value),
+            ("-e" | "--env", Some(value)) => service.environment.push(value),
+            ("--label", Some(value)) => service.labels.push(value),
+            ("--dns", Some(value)) => service.dns.push(value),
+            ("--dns-search", Some(value)) => service.dns_search.push(value),
+            ("--dns-option", Some(value)) => service.dns_opt.push(value),
+            ("--pids-limit", Some(value)) if value.parse::<i64>().is_ok() => {
+                service.pids_limit = value.parse().ok();
+            }
+            ("--ulimit", Some(value)) => {
+                let parsed = value
+                    .split_once('=')
+                    .and_then(|(name, limit)| Some((name.to_string(), parse_ulimit(limit)?)));
+                match parsed {
+                    Some((name, limit)) => {
+                        service.ulimits.insert(name, limit);
+                    }
+                    None => unmapped.push(format!("--ulimit {}", value)),
+                }
+            }
+            (flag, Some(value)) => unmapped.push(format!("{} {}", flag, value)),
+            (flag, None) => unmapped.push(flag.to_string()),
+        }
+    }
+    unmapped
+}
+
+/// Named volumes among `-v` values: those whose source is not a path.
+fn named_volumes(volumes: &[String]) -> Vec<String> {
+    volumes
+        .iter()
+        .filter_map(|volume| volume.split_once(':').map(|(source, _)| source))
+        .filter(|source| !source.starts_with(['/', '.', '~']))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Subnet of the sidecar network of the `index`th server, so the DNS
+/// filter has a fixed address to give as the server's resolver.
+fn sidecar_subnet(index: usize) -> (String, String) {
+    (
+        format!("10.213.{}.0/24", index),
+        format!("10.213.{}.2", index),
+    )
+}
+
+/// Adds the DNS filter and firewall sidecars `policy` asks for, wired to
+/// `name` as `semcp serve` wires them to its containers.
+fn add_sidecars(
+    compose: &mut ComposeFile,
+    name: &str,
+    index: usize,
+    policy: &PolicyConfig,
+) -> Result<()> {
+    let filter = policy.filter_dns();
+    let ports = policy.blocked_ports();
+    if !filter && ports.is_empty() {
+        return Ok(());
+    }
+    if policy.network_mode().is_some() {
+        bail!(
+            "`{}`: the DNS filter and the firewall put the server on a network of its own; drop permissions.network.policy",
+            name
+        );
+    }
+    let network = format!("{}-net", name);
+    let mut dns = Vec::new();
+    let mut sidecars = Vec::new();
+    let mut ipam = None;
+    if filter {
+        let (subnet, address) = sidecar_subnet(index);
+        ipam = Some(Ipam {
+            config: vec![Subnet { subnet }],
+        });
+        let sidecar = format!("{}-dns", name);
+        compose.services.insert(
+            sidecar.clone(),
+            Service {
+                build: sidecar_build(&policy.dns_filter_image()),
+                image: policy.dns_filter_image(),
+                entrypoint: vec!["dnsmasq".to_string()],
+                command: dnsfilter::dnsmasq_args(
+                    &dnsfilter::domains(policy),
+                    &dnsfilter::upstreams(policy),
+                ),
+                networks: BTreeMap::from([(
+                    network.clone(),
+                    ServiceNetwork {
+                        ipv4_address: Some(address.clone()),
+                    },
+                )]),
+                ..Service::default()
+            },
+        );
+        dns.push(address);
+        sidecars.push(sidecar);
+    }
+    compose.networks.insert(network.clone(), Network { ipam });
+
+    let server = compose
+        .services
+        .get_mut(name)
+        .expect("the server is added before its sidecars");
+    if ports.is_empty() {
+        server.networks.insert(network, ServiceNetwork::default());
+        server.dns = dns;
+    } else {
+        let sidecar = format!("{}-fw", name);
+        // the server shares the firewall's network stack, resolver included
+        server.network_mode = Some(format!("service:{}", sidecar));
+        server.dns.clear();
+        server.dns_search.clear();
+        server.dns_opt.clear();
+        sidecars.push(sidecar.clone());
+        let script = format!("{}\nexec tail -f /dev/null", rules_script(&ports));
+        let firewall = Service {
+            build: sidecar_build(&policy.firewall_image()),
+            image: policy.firewall_image(),
+            entrypoint: vec!["sh".to_string(), "-c".to_string()],
+            command: vec![script],
+            cap_drop: vec!["ALL".to_string()],
+            cap_add: vec!["NET_ADMIN".to_string()],
+            networks: BTreeMap::from([(network, ServiceNetwork::default())]),
+            dns,
+            ..Service::default()
+        };
+        compose.services.insert(sidecar, firewall);
+    }
+    let server = compose.services.get_mut(name).expect("added above");
+    server.depends_on.extend(sidecars);
+    Ok(())
+}
+
+/// The build of a built-in sidecar image, which no registry has.
+fn sidecar_build(image: &str) -> Option<Build> {
+    SidecarImage::builtin(image).map(|sidecar| Build {
+        context: ".".to_string(),
+        dockerfile_inline: sidecar.dockerfile.to_string(),
+    })
+}
+
+/// The service of `spec`, and the options of it Compose cannot express.
+fn service_of(spec: &ServerSpec, policy: PolicyConfig) -> Result<(Service, Vec<String>)> {
+    let engine = engine::from_policy(&policy)?;
+    // the profile is written to the cache, where the file points
+    policy.seccomp_profile()?;
+    let executor = ContainerExecutor::with_policy(spec.image.clone(), false, policy)
+        .with_engine(engine)
+        .with_labels(spec.labels.clone());
+    let mut args = executor.init_args();
+    args.extend(executor.container_options());
+    for key in &spec.env {
+        args.extend(["-e".to_string(), key.clone()]);
+    }
+    let mut service = Service {
+        image: spec.image.clone(),
+        command: spec.command.clone(),
+        stdin_open: true,
+        depends_on: spec.depends_on.clone(),
+        ..Service::default()
+    };
+    let unmapped = apply_run_args(&mut service, &args);
+    Ok((service, unmapped))
+}
+
+/// The Compose file of `servers`, with the notes to write above it.
+pub fn compose(servers: &[&ServerSpec]) -> Result<(ComposeFile, Vec<String>)> {
+    let mut compose = ComposeFile::default();
+    let mut notes = Vec::new();
+    for (index, spec) in servers.iter().enumerate() {
+        let policy = PolicyConfig::from_files(&spec.policy)
+            .with_context(|| format!("Failed to load policy for `{}`", spec.name))?;
+        if !policy.runtime.env_mapping.is_empty() {
+            notes.push(format!(
+                "{}: runtime.env_mapping is not applied, set the variables it makes before `docker compose`",
+                spec.name
+            ));
+        }
+        let (service, unmapped) = service_of(spec, policy.clone())?;
+        for option in unmapped {
+            notes.push(format!("{}: {}", spec.name, option));
+        }
+        for volume in named_volumes(&service.volumes) {
+            compose.volumes.insert(
+                volume.clone(),
+                Volume {
+                    name: volume.clone(),
+                },
+            );
+        }
+        compose.services.insert(spec.name.clone(), service);
+        add_sidecars(&mut compose, &spec.name, index, &policy)?;
+    }
+    Ok((compose, notes))
+}
+
+pub async fn export(args: ExportComposeArgs) -> Result<()> {
+    if Path::new(&args.out).exists() && !args.force {
+        bail!("{} already exists, pass --force to overwrite it", args.out);
+    }
+    let manifest = Manifest::from_file(&args.config)?;
+    let servers: Vec<&ServerSpec> = if args.servers.is_empty() {
+        manifest.servers.iter().collect()
+    } else {
+        args.servers
+            .iter()
+            .map(|name| find_server(&manifest, name, &args.config))
+            .collect::<Result<_>>()?
+    };
+    let (compose, notes) = compose(&servers)?;
+    let mut content = format!(
+        "# MCP servers of {}, start one per connection with `docker compose run --rm -T <server>`\n",
+        args.config
+    );
+    if !notes.is_empty() {
+        content.push_str("# Options without a Compose key, left out:\n");
+        for note in &notes {
+            content.push_str(&format!("#   {}\n", note));
+        }
+    }
+    content.push_str(&serde_yaml::to_string(&compose)?);
+    std::fs::write(&args.out, content).with_context(|| format!("Failed to write {}", args.out))?;
+    eprintln!("Exported {} servers to {}", servers.len(), args.out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_run_args() {
+        let mut service = Service::default();
+        let unmapped = apply_run_args(
+            &mut service,
+            &strings(&[
+                "--init",
+                "--label",
+                "semcp.managed=true",
+                "--runtime=runsc",
+                "--cap-drop",
+                "ALL",
+                "--memory",
+                "1g",
+                "--pids-limit",
+                "256",
+                "--ulimit",
+                "nofile=64:128",
+                "--ulimit",
+                "nproc=32",
+                "--read-only",
+                "--tmpfs",
+                "/tmp:exec",
+                "-v",
+                "notes:/data:rw",
+                "-e",
+                "TOKEN",
+                "--oom-score-adj",
+                "500",
+                "--no-healthcheck",
+            ]),
+        );
+        assert!(service.init && service.read_only);
+        assert_eq!(service.labels, ["semcp.managed=true"]);
+        assert_eq!(service.runtime.as_deref(), Some("runsc"));
+        assert_eq!(service.cap_drop, ["ALL"]);
+        assert_eq!(service.mem_limit.as_deref(), Some("1g"));
+        assert_eq!(service.pids_limit, Some(256));
+        assert_eq!(
+            service.ulimits["nofile"],
+            Ulimit::Range {
+                soft: 64,
+                hard: 128
+            }
+        );
+        assert_eq!(service.ulimits["nproc"], Ulimit::Single(32));
+        assert_eq!(service.tmpfs, ["/tmp:exec"]);
+        assert_eq!(service.environment, ["TOKEN"]);
+        assert_eq!(unmapped, ["--oom-score-adj 500", "--no-healthcheck"]);
+        assert_eq!(named_volumes(&service.volumes), ["notes"]);
+        assert!(named_volumes(&strings(&["/srv:/srv:ro", "./data:/data"])).is_empty());
+    }
+
+    #[test]
+    fn test_builtin_sidecars_are_built() {
+        let build = sidecar_build(semcp_common::dnsfilter::DEFAULT_IMAGE).unwrap();
+        assert!(build.dockerfile_inline.contains("dnsmasq"));
+        let build = sidecar_build(semcp_common::firewall::DEFAULT_IMAGE).unwrap();
+        assert!(build.dockerfile_inline.contains("iptables"));
+        assert!(sidecar_build("registry.example.com/dnsmasq").is_none());
+    }
+}
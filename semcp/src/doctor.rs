@@ -0,0 +1,52 @@
+use anyhow::Result;
+use clap::Args;
+use semcp_common::runtime;
+use semcp_common::sockets;
+use std::process::Stdio;
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {}
+
+/// Prints which runtime and daemon runs would use, and whether it answers.
+pub async fn run(_args: DoctorArgs) -> Result<()> {
+    let runtime = runtime::current();
+    println!(
+        "Runtime:  {} ({})",
+        runtime.name(),
+        if runtime.is_available() {
+            "installed"
+        } else {
+            "not installed"
+        }
+    );
+    println!(
+        "Daemon:   {}",
+        runtime
+            .describe_endpoint()
+            .unwrap_or_else(|| "default".to_string())
+    );
+    let reachable = runtime
+        .command()
+        .args(["info"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await
+        .is_ok_and(|status| status.success());
+    println!(
+        "Status:   {}",
+        if reachable {
+            "reachable"
+        } else {
+            "not reachable"
+        }
+    );
+    let found = sockets::report();
+    if !found.is_empty() {
+        println!("Sockets:");
+        for line in found {
+            println!("  {}", line);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+//! `semcp exec`: runs any image and command as snpx and suvx run packages.
+//!
+//! In-house servers that ship as their own images get the same pipeline:
+//! policy layers and engine, hardening, mounts, network rules, the proxy,
+//! canaries and the audit log. There is no package manager in the way, so
+//! the command is run as given and nothing is installed first.
+
+use anyhow::{bail, Result};
+use clap::Args;
+use semcp_common::overrides::format_denials;
+use semcp_common::{
+    ContainerArgs, ContainerExecutor, ProxyArgs, Runner, Transport, VariantCapabilities,
+};
+
+#[derive(Args)]
+pub struct ExecArgs {
+    #[arg(long, help = "Use verbose output")]
+    pub verbose: bool,
+
+    #[arg(long = "image", help = "Image to run")]
+    pub image: String,
+
+    #[arg(
+        long = "policy",
+        help = "Path to policy file, repeat to layer policies (later files take precedence)"
+    )]
+    pub policy: Vec<String>,
+
+    #[command(flatten)]
+    pub container: ContainerArgs,
+
+    #[arg(
+        long = "state",
+        help = "Keep state in a volume under this name, as snpx does per package"
+    )]
+    pub state: Option<String>,
+
+    #[command(flatten)]
+    pub proxy: ProxyArgs,
+
+    #[arg(
+        trailing_var_arg = true,
+        allow_hyphen_values = true,
+        required = true,
+        help = "The command and its arguments"
+    )]
+    pub command: Vec<String>,
+}
+
+/// Runs the command of the image as is.
+struct ExecRunner {
+    command: String,
+}
+
+impl Runner for ExecRunner {
+    fn command(&self) -> &str {
+        &self.command
+    }
+
+    fn default_image(&self) -> &str {
+        ""
+    }
+
+    fn default_flags(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn detect_transport(&self, _package: &str) -> Transport {
+        Transport::Stdio
+    }
+
+    fn requires_tty(&self, _transport: &Transport) -> bool {
+        false
+    }
+
+    fn required_capabilities(&self, _flags: &[String]) -> VariantCapabilities {
+        VariantCapabilities {
+            has_shell: false,
+            has_package_manager: false,
+        }
+    }
+}
+
+/// Runs `args` and returns the exit code of the command.
+pub async fn run(args: ExecArgs) -> Result<i32> {
+    let Some((command, command_args)) = args.command.split_first() else {
+        bail!("No command specified");
+    };
+    if args.container.native_sandbox || args.container.sandbox_fallback {
+        bail!("semcp exec runs images, it needs a container runtime");
+    }
+    args.container.select_runtime()?;
+    if args.verbose {
+        for policy_path in &args.policy {
+            eprintln!("Loading policy from: {}", policy_path);
+        }
+    }
+
+    let startup = args.container.preflight(&args.policy, &[]).await?;
+    if !startup.runtime_available {
+        bail!("No container runtime (docker, podman or nerdctl) is available or running");
+    }
+    let denials = startup
+        .engine
+        .check_overrides(&args.container, args.state.as_deref());
+    if !denials.is_empty() {
+        bail!(format_denials(&denials, args.container.explain_denial));
+    }
+
+    let executor = ContainerExecutor::with_policy(args.image, args.verbose, startup.policy)
+        .with_engine(startup.engine);
+    let executor = args.container.configure(executor, args.state.as_ref());
+    let executor = args.proxy.configure(executor);
+    let runner = ExecRunner {
+        command: command.clone(),
+    };
+    let status = executor
+        .run_containerized(&runner, &[], command_args)
+        .await?;
+    Ok(status.code().unwrap_or(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exec_runner_runs_the_command_as_given() {
+        let runner = ExecRunner {
+            command: "/usr/local/bin/acme-mcp".to_string(),
+        };
+        assert_eq!(
+            runner.build_command_args(&[], &["--stdio".to_string()]),
+            ["/usr/local/bin/acme-mcp", "--stdio"]
+        );
+        assert!(runner
+            .build_install_args(&[], &["--stdio".to_string()])
+            .is_none());
+        let needed = runner.required_capabilities(&[]);
+        assert!(!needed.has_shell && !needed.has_package_manager);
+    }
+}
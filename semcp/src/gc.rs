@@ -0,0 +1,51 @@
+use anyhow::Result;
+use clap::Args;
+use semcp_common::cleanup::orphaned_sets;
+use semcp_common::runtime;
+use semcp_common::spec::DEFAULT_GRACEFUL_SHUTDOWN;
+
+#[derive(Args, Debug)]
+pub struct GcArgs {
+    #[arg(
+        long = "dry-run",
+        help = "List what would be removed without removing it"
+    )]
+    dry_run: bool,
+}
+
+/// Removes what runs killed before they could clean up left behind.
+pub async fn run(args: GcArgs) -> Result<()> {
+    let runtime = runtime::current();
+    let sets = orphaned_sets()?;
+    if sets.is_empty() {
+        eprintln!("Nothing to clean up");
+        return Ok(());
+    }
+    for (path, set) in sets {
+        if set.runtime != runtime.name() {
+            eprintln!(
+                "Skipping {}: it was created with {}, pass --runtime to clean it up",
+                path.display(),
+                set.runtime
+            );
+            continue;
+        }
+        for resource in set.resources.iter().rev() {
+            eprintln!(
+                "{} {}",
+                if args.dry_run {
+                    "Would remove"
+                } else {
+                    "Removing"
+                },
+                resource
+            );
+        }
+        if !args.dry_run {
+            set.remove_all(runtime.as_ref(), DEFAULT_GRACEFUL_SHUTDOWN)
+                .await;
+            std::fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,222 @@
+//! Resource groups for `semcp serve`.
+//!
+//! Servers assigned to a group share one memory and CPU budget. Each group
+//! becomes a cgroup that the group's containers are started under with
+//! `--cgroup-parent`, so a runaway server can exhaust its group but not the
+//! host. With the systemd cgroup driver the group is a transient slice
+//! (`semcp-<group>.slice`); with cgroupfs it is a directory under
+//! `/sys/fs/cgroup` (cgroup v2 only). Either way this needs root.
+
+use anyhow::{bail, Context, Result};
+use semcp_common::runtime;
+use semcp_common::spec::parse_memory;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::process::Command as AsyncCommand;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const SYSTEMD_UNIT_DIR: &str = "/run/systemd/system";
+const CPU_PERIOD_US: u64 = 100_000;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ResourceGroup {
+    /// Memory shared by the group, e.g. `2g` or `512m`.
+    #[serde(default)]
+    pub memory: Option<String>,
+    /// CPUs shared by the group, e.g. `1.5`.
+    #[serde(default)]
+    pub cpus: Option<f64>,
+}
+
+impl ResourceGroup {
+    pub fn validate(&self, name: &str) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            bail!("Invalid resource group name `{}`", name);
+        }
+        if let Some(ref memory) = self.memory {
+            parse_memory(memory).with_context(|| format!("In resource group `{}`", name))?;
+        }
+        if let Some(cpus) = self.cpus {
+            if !cpus.is_finite() || cpus <= 0.0 {
+                bail!("Resource group `{}` needs a positive cpus value", name);
+            }
+        }
+        Ok(())
+    }
+
+    fn cpu_quota_us(&self) -> Option<u64> {
+        self.cpus.map(|cpus| (cpus * CPU_PERIOD_US as f64) as u64)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupDriver {
+    Systemd,
+    Cgroupfs,
+}
+
+impl CgroupDriver {
+    pub fn cgroup_parent(&self, group: &str) -> String {
+        match self {
+            CgroupDriver::Systemd => format!("semcp-{}.slice", group),
+            CgroupDriver::Cgroupfs => format!("/semcp-{}", group),
+        }
+    }
+}
+
+async fn cgroup_driver() -> CgroupDriver {
+    let runtime = runtime::current();
+    // docker and nerdctl, then podman
+    for format in ["{{.CgroupDriver}}", "{{.Host.CgroupManager}}"] {
+        let Ok(output) = runtime
+            .command()
+            .args(["info", "--format", format])
+            .output()
+            .await
+        else {
+            continue;
+        };
+        match String::from_utf8_lossy(&output.stdout).trim() {
+            "systemd" => return CgroupDriver::Systemd,
+            "cgroupfs" => return CgroupDriver::Cgroupfs,
+            _ => {}
+        }
+    }
+    CgroupDriver::Systemd
+}
+
+/// Unit file of the transient slice for `group`.
+pub fn slice_unit(group: &ResourceGroup) -> String {
+    let mut unit = String::from("[Slice]\n");
+    if let Some(ref memory) = group.memory {
+        if let Ok(bytes) = parse_memory(memory) {
+            unit.push_str(&format!("MemoryMax={}\n", bytes));
+        }
+    }
+    if let Some(cpus) = group.cpus {
+        unit.push_str(&format!("CPUQuota={}%\n", (cpus * 100.0).round() as u64));
+    }
+    unit
+}
+
+async fn systemctl(args: &[&str]) -> Result<()> {
+    let output = AsyncCommand::new("systemctl")
+        .args(args)
+        .output()
+        .await
+        .context("Failed to execute systemctl")?;
+    if !output.status.success() {
+        bail!(
+            "systemctl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+async fn apply_systemd(slices: &[(String, &ResourceGroup)]) -> Result<()> {
+    for (slice, group) in slices {
+        let path = Path::new(SYSTEMD_UNIT_DIR).join(slice);
+        std::fs::write(&path, slice_unit(group)).with_context(|| {
+            format!(
+                "Failed to write {} (resource groups need root)",
+                path.display()
+            )
+        })?;
+    }
+    systemctl(&["daemon-reload"]).await?;
+    for (slice, _) in slices {
+        systemctl(&["start", slice]).await?;
+    }
+    Ok(())
+}
+
+fn apply_cgroupfs(parent: &str, group: &ResourceGroup) -> Result<()> {
+    let dir = Path::new(CGROUP_ROOT).join(parent.trim_start_matches('/'));
+    std::fs::create_dir_all(&dir).with_context(|| {
+        format!(
+            "Failed to create {} (resource groups need root)",
+            dir.display()
+        )
+    })?;
+    if let Some(ref memory) = group.memory {
+        std::fs::write(dir.join("memory.max"), parse_memory(memory)?.to_string())
+            .with_context(|| format!("Failed to set memory.max in {}", dir.display()))?;
+    }
+    if let Some(quota) = group.cpu_quota_us() {
+        std::fs::write(dir.join("cpu.max"), format!("{} {}", quota, CPU_PERIOD_US))
+            .with_context(|| format!("Failed to set cpu.max in {}", dir.display()))?;
+    }
+    Ok(())
+}
+
+/// Creates a cgroup with the budget of each group and returns the cgroup
+/// parent to start its containers under.
+pub async fn apply(
+    groups: &BTreeMap<String, ResourceGroup>,
+    verbose: bool,
+) -> Result<BTreeMap<String, String>> {
+    let mut parents = BTreeMap::new();
+    if groups.is_empty() {
+        return Ok(parents);
+    }
+    let driver = cgroup_driver().await;
+    for name in groups.keys() {
+        parents.insert(name.clone(), driver.cgroup_parent(name));
+    }
+    match driver {
+        CgroupDriver::Systemd => {
+            let slices: Vec<(String, &ResourceGroup)> = groups
+                .iter()
+                .map(|(name, group)| (parents[name].clone(), group))
+                .collect();
+            apply_systemd(&slices).await?;
+        }
+        CgroupDriver::Cgroupfs => {
+            for (name, group) in groups {
+                apply_cgroupfs(&parents[name], group)?;
+            }
+        }
+    }
+    if verbose {
+        for (name, parent) in &parents {
+            eprintln!("Resource group {}: cgroup parent {}", name, parent);
+        }
+    }
+    Ok(parents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slice_unit() {
+        let group = ResourceGroup {
+            memory: Some("1g".to_string()),
+            cpus: Some(1.5),
+        };
+        assert_eq!(
+            slice_unit(&group),
+            "[Slice]\nMemoryMax=1073741824\nCPUQuota=150%\n"
+        );
+        assert_eq!(group.cpu_quota_us(), Some(150_000));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ResourceGroup::default().validate("tools").is_ok());
+        assert!(ResourceGroup::default().validate("../etc").is_err());
+        let group = ResourceGroup {
+            memory: None,
+            cpus: Some(0.0),
+        };
+        assert!(group.validate("tools").is_err());
+    }
+}
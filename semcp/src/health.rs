@@ -0,0 +1,222 @@
+//! Health probes for `semcp serve` sessions.
+//!
+//! A server with `health_check` in the manifest gets an MCP `ping` whenever
+//! its session has been quiet for a while. Right after traffic the probes
+//! come every `min_interval`, so a server that hangs mid-session is noticed
+//! quickly; while the session stays quiet the interval doubles up to
+//! `max_interval`, so many idle servers cost little. Every interval is
+//! jittered by up to a fifth so probes of servers started together spread
+//! out. No probe is sent while a client request is outstanding, since
+//! servers that handle one request at a time would not answer it. A server
+//! that does not answer a probe within `timeout` is treated as crashed.
+
+use crate::manifest::HealthCheck;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const PROBE_ID_PREFIX: &str = "semcp-health-";
+
+/// Exponential probe intervals with jitter.
+#[derive(Debug)]
+pub struct Schedule {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    seed: u64,
+}
+
+impl Schedule {
+    pub fn new(min: Duration, max: Duration, seed: u64) -> Self {
+        Self {
+            min,
+            max: max.max(min),
+            current: min,
+            // xorshift must not start at zero
+            seed: seed | 1,
+        }
+    }
+
+    /// Starts over at the shortest interval.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+
+    /// The next delay, after which the interval doubles.
+    pub fn next_delay(&mut self) -> Duration {
+        let base = self.current;
+        self.current = (self.current * 2).min(self.max);
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        // a factor between 0.8 and 1.2
+        let factor = 0.8 + (self.seed % 1000) as f64 / 2500.0;
+        base.mul_f64(factor)
+    }
+}
+
+/// What the session should do when the probe deadline passes.
+#[derive(Debug, PartialEq)]
+pub enum Tick {
+    Wait,
+    /// Send this request to the server.
+    Probe(String),
+    /// The server did not answer the last probe in time.
+    Hung,
+}
+
+/// Probe state of one session. Like the proxy session it performs no IO.
+#[derive(Debug)]
+pub struct Prober {
+    schedule: Schedule,
+    timeout: Duration,
+    /// Ids of client requests the server has not answered yet.
+    outstanding: HashSet<String>,
+    /// The probe in flight and when it was sent.
+    probe: Option<(String, Instant)>,
+    next_probe: Instant,
+    probes_sent: u64,
+}
+
+impl Prober {
+    pub fn new(check: &HealthCheck, now: Instant) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        let mut schedule = Schedule::new(check.min_interval(), check.max_interval(), seed);
+        let next_probe = now + schedule.next_delay();
+        Self {
+            schedule,
+            timeout: check.timeout(),
+            outstanding: HashSet::new(),
+            probe: None,
+            next_probe,
+            probes_sent: 0,
+        }
+    }
+
+    fn activity(&mut self, now: Instant) {
+        self.schedule.reset();
+        if self.probe.is_none() {
+            self.next_probe = now + self.schedule.next_delay();
+        }
+    }
+
+    pub fn on_client_line(&mut self, line: &str, now: Instant) {
+        if let Ok(message) = serde_json::from_str::<Value>(line) {
+            if let (Some(id), Some(_)) = (message.get("id"), message.get("method")) {
+                self.outstanding.insert(id.to_string());
+            }
+        }
+        self.activity(now);
+    }
+
+    /// Returns whether the line answers a probe, in which case it must not
+    /// reach the client.
+    pub fn on_server_line(&mut self, line: &str, now: Instant) -> bool {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            self.activity(now);
+            return false;
+        };
+        let id = message.get("id").and_then(Value::as_str);
+        if message.get("method").is_none() && id.is_some_and(|id| id.starts_with(PROBE_ID_PREFIX)) {
+            if self.probe.as_ref().map(|(probe, _)| probe.as_str()) == id {
+                self.probe = None;
+                self.next_probe = now + self.schedule.next_delay();
+            }
+            return true;
+        }
+        if message.get("method").is_none() {
+            if let Some(id) = message.get("id") {
+                self.outstanding.remove(&id.to_string());
+            }
+        }
+        self.activity(now);
+        false
+    }
+
+    /// When [`Prober::on_tick`] has something to do next.
+    pub fn deadline(&self) -> Instant {
+        match self.probe {
+            Some((_, sent)) => sent + self.timeout,
+            None => self.next_probe,
+        }
+    }
+
+    pub fn on_tick(&mut self, now: Instant) -> Tick {
+        if let Some((_, sent)) = self.probe {
+            return if now >= sent + self.timeout {
+                Tick::Hung
+            } else {
+                Tick::Wait
+            };
+        }
+        if now < self.next_probe {
+            return Tick::Wait;
+        }
+        if !self.outstanding.is_empty() {
+            self.next_probe = now + self.schedule.next_delay();
+            return Tick::Wait;
+        }
+        self.probes_sent += 1;
+        let id = format!("{}{}", PROBE_ID_PREFIX, self.probes_sent);
+        self.probe = Some((id.clone(), now));
+        Tick::Probe(json!({"jsonrpc": "2.0", "id": id, "method": "ping"}).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check() -> HealthCheck {
+        HealthCheck {
+            min_interval: 5,
+            max_interval: 60,
+            timeout: 10,
+        }
+    }
+
+    #[test]
+    fn test_schedule_backs_off_with_jitter() {
+        let mut schedule = Schedule::new(Duration::from_secs(5), Duration::from_secs(60), 42);
+        let delays: Vec<Duration> = (0..6).map(|_| schedule.next_delay()).collect();
+        for (delay, base) in delays.iter().zip([5, 10, 20, 40, 60, 60]) {
+            let base = Duration::from_secs(base);
+            assert!(*delay >= base.mul_f64(0.8) && *delay <= base.mul_f64(1.2));
+        }
+        schedule.reset();
+        assert!(schedule.next_delay() <= Duration::from_secs(6));
+    }
+
+    #[test]
+    fn test_unanswered_probe_means_hung() {
+        let start = Instant::now();
+        let mut prober = Prober::new(&check(), start);
+        let due = prober.deadline();
+        let Tick::Probe(probe) = prober.on_tick(due) else {
+            panic!("expected a probe");
+        };
+        let id = serde_json::from_str::<Value>(&probe).unwrap()["id"].clone();
+        let answer = json!({"jsonrpc": "2.0", "id": id, "result": {}}).to_string();
+        assert!(prober.on_server_line(&answer, due));
+
+        let due = prober.deadline();
+        assert!(matches!(prober.on_tick(due), Tick::Probe(_)));
+        assert_eq!(prober.on_tick(due + Duration::from_secs(5)), Tick::Wait);
+        assert_eq!(prober.on_tick(due + Duration::from_secs(10)), Tick::Hung);
+    }
+
+    #[test]
+    fn test_no_probe_while_a_request_is_outstanding() {
+        let start = Instant::now();
+        let mut prober = Prober::new(&check(), start);
+        prober.on_client_line(r#"{"jsonrpc":"2.0","id":1,"method":"tools/call"}"#, start);
+        assert_eq!(prober.on_tick(prober.deadline()), Tick::Wait);
+
+        let now = prober.deadline();
+        assert!(!prober.on_server_line(r#"{"jsonrpc":"2.0","id":1,"result":{}}"#, now));
+        assert!(matches!(prober.on_tick(prober.deadline()), Tick::Probe(_)));
+    }
+}
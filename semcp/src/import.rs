@@ -0,0 +1,258 @@
+//! `semcp import`: rewrites plain `npx`/`uvx` servers in an MCP client
+//! config to their containerized `snpx`/`suvx` equivalents. `semcp import
+//! unit` adds an exported server definition instead, see [`crate::unit`].
+
+use crate::unit::{self, ImportUnitArgs};
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Client {
+    Claude,
+    Cursor,
+    Vscode,
+}
+
+impl Client {
+    /// Key of the server map in the client's config.
+    fn servers_key(&self) -> &'static str {
+        match self {
+            Client::Claude | Client::Cursor => "mcpServers",
+            Client::Vscode => "servers",
+        }
+    }
+
+    fn default_config(&self) -> Option<PathBuf> {
+        let home = std::env::var_os("HOME").map(PathBuf::from);
+        match self {
+            Client::Claude => {
+                if cfg!(target_os = "macos") {
+                    Some(
+                        home?.join("Library/Application Support/Claude/claude_desktop_config.json"),
+                    )
+                } else if cfg!(windows) {
+                    let appdata = std::env::var_os("APPDATA")?;
+                    Some(
+                        PathBuf::from(appdata)
+                            .join("Claude")
+                            .join("claude_desktop_config.json"),
+                    )
+                } else {
+                    Some(home?.join(".config/Claude/claude_desktop_config.json"))
+                }
+            }
+            Client::Cursor => Some(home?.join(".cursor").join("mcp.json")),
+            Client::Vscode => Some(PathBuf::from(".vscode").join("mcp.json")),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct ImportArgs {
+    #[command(subcommand)]
+    pub command: Option<ImportCommand>,
+
+    #[arg(value_enum, help = "MCP client whose config should be imported")]
+    pub client: Option<Client>,
+
+    #[arg(
+        long = "config",
+        help = "Path of the client config (default: the client's usual location)"
+    )]
+    pub config: Option<String>,
+
+    #[arg(long = "dry-run", help = "Show the changes without writing them")]
+    pub dry_run: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ImportCommand {
+    #[command(about = "Add a server exported with `semcp export unit` to a manifest")]
+    Unit(ImportUnitArgs),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub server: String,
+    pub before: String,
+    pub after: String,
+}
+
+fn command_line(server: &Value) -> String {
+    let mut parts = vec![server["command"].as_str().unwrap_or_default().to_string()];
+    if let Some(args) = server["args"].as_array() {
+        parts.extend(args.iter().filter_map(Value::as_str).map(str::to_string));
+    }
+    parts.join(" ")
+}
+
+/// Returns the containerized form of a server entry, or `None` when it is
+/// not started with plain `npx` or `uvx`.
+pub fn convert_server(server: &Value) -> Option<Value> {
+    let runner = match server.get("command")?.as_str()? {
+        "npx" => "snpx",
+        "uvx" => "suvx",
+        _ => return None,
+    };
+    let args: Vec<String> = server
+        .get("args")
+        .and_then(Value::as_array)
+        .map(|args| {
+            args.iter()
+                .filter_map(Value::as_str)
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    // runner flags have to come before the package
+    let mut converted = Vec::new();
+    if let Some(env) = server.get("env").and_then(Value::as_object) {
+        for key in env.keys() {
+            converted.push("-e".to_string());
+            converted.push(key.clone());
+        }
+    }
+    for arg in &args {
+        if arg.starts_with('/') && Path::new(arg).exists() {
+            converted.push("-v".to_string());
+            converted.push(format!("{}:{}", arg, arg));
+        }
+    }
+    converted.extend(args);
+
+    let mut server = server.clone();
+    server["command"] = Value::from(runner);
+    server["args"] = Value::from(converted);
+    Some(server)
+}
+
+/// Converts every eligible server under `key` in place.
+pub fn convert_config(config: &mut Value, key: &str) -> Vec<Change> {
+    let mut changes = Vec::new();
+    let Some(servers) = config.get_mut(key).and_then(Value::as_object_mut) else {
+        return changes;
+    };
+    for (name, server) in servers.iter_mut() {
+        if let Some(converted) = convert_server(server) {
+            changes.push(Change {
+                server: name.clone(),
+                before: command_line(server),
+                after: command_line(&converted),
+            });
+            *server = converted;
+        }
+    }
+    changes
+}
+
+pub async fn run(args: ImportArgs) -> Result<()> {
+    if let Some(ImportCommand::Unit(unit_args)) = args.command {
+        return unit::import(unit_args).await;
+    }
+    let Some(client) = args.client else {
+        bail!("Name the MCP client to import from, or use `semcp import unit <file>`");
+    };
+    let path = match args.config {
+        Some(ref config) => PathBuf::from(config),
+        None => client
+            .default_config()
+            .context("Cannot locate the client config, pass --config")?,
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut config: Value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    if config.get(client.servers_key()).is_none() {
+        bail!(
+            "{} has no `{}` section",
+            path.display(),
+            client.servers_key()
+        );
+    }
+
+    let changes = convert_config(&mut config, client.servers_key());
+    if changes.is_empty() {
+        eprintln!("No npx or uvx servers to import in {}", path.display());
+        return Ok(());
+    }
+    for change in &changes {
+        println!("{}:", change.server);
+        println!("- {}", change.before);
+        println!("+ {}", change.after);
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let backup = PathBuf::from(format!("{}.{}.bak", path.display(), timestamp));
+    std::fs::copy(&path, &backup)
+        .with_context(|| format!("Failed to back up {}", path.display()))?;
+    let updated = serde_json::to_string_pretty(&config)?;
+    std::fs::write(&path, updated + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    eprintln!(
+        "Imported {} server(s) into {} (backup: {})",
+        changes.len(),
+        path.display(),
+        backup.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_convert_npx_with_env() {
+        let server = json!({
+            "command": "npx",
+            "args": ["-y", "@modelcontextprotocol/server-github"],
+            "env": { "GITHUB_PERSONAL_ACCESS_TOKEN": "ghp_x" }
+        });
+        let converted = convert_server(&server).unwrap();
+        assert_eq!(converted["command"], json!("snpx"));
+        assert_eq!(
+            converted["args"],
+            json!([
+                "-e",
+                "GITHUB_PERSONAL_ACCESS_TOKEN",
+                "-y",
+                "@modelcontextprotocol/server-github"
+            ])
+        );
+        assert_eq!(converted["env"], server["env"]);
+    }
+
+    #[test]
+    fn test_convert_config_skips_other_commands() {
+        let mut config = json!({
+            "mcpServers": {
+                "time": { "command": "uvx", "args": ["mcp-server-time"] },
+                "local": { "command": "node", "args": ["server.js"] }
+            }
+        });
+        let changes = convert_config(&mut config, "mcpServers");
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].after, "suvx mcp-server-time");
+        assert_eq!(config["mcpServers"]["local"]["command"], json!("node"));
+    }
+
+    #[test]
+    fn test_mounts_existing_paths() {
+        let server = json!({ "command": "npx", "args": ["-y", "@modelcontextprotocol/server-filesystem", "/"] });
+        let converted = convert_server(&server).unwrap();
+        assert_eq!(converted["args"][0], json!("-v"));
+        assert_eq!(converted["args"][1], json!("/:/"));
+    }
+}
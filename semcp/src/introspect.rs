@@ -0,0 +1,431 @@
+//! Read-only view of a running `semcp serve`, for dashboards and editor
+//! extensions.
+//!
+//! With `introspection` in the manifest, semcp answers HTTP `GET` requests
+//! on a Unix socket with JSON:
+//!
+//! - `/v1/servers`, `/v1/servers/<name>`: state, open sessions and the
+//!   SHA-256 of each policy file as it was read at startup
+//! - `/v1/calls`: recent requests to the servers, method and tool name only
+//! - `/v1/alerts`: recent crashes, crash loops and hung servers
+//!
+//! The kernel reports the uid of every client. Only root, the user semcp
+//! runs as and `allowed_uids` get answers; nothing can be changed. Each
+//! connection is answered on a task of its own and dropped if its request
+//! takes longer than [`REQUEST_TIMEOUT`].
+
+use crate::manifest::Activation;
+use anyhow::{Context, Result};
+use semcp_common::policy::LoadedFile;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Calls and alerts kept for the API.
+const RECENT: usize = 200;
+const MAX_REQUEST: usize = 8192;
+/// How long a client has to send its request and take the answer.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct Introspection {
+    /// Path of the socket, `introspect.sock` in the state directory unless
+    /// set.
+    pub socket: Option<String>,
+    /// Users besides root and semcp's own who may query the API.
+    pub allowed_uids: Vec<u32>,
+}
+
+impl Introspection {
+    pub fn socket_path(&self) -> Result<PathBuf> {
+        match self.socket {
+            Some(ref socket) => Ok(PathBuf::from(socket)),
+            None => semcp_common::state::state_home()
+                .map(|home| home.join("introspect.sock"))
+                .context(
+                    "No state directory for the introspection socket, set introspection.socket",
+                ),
+        }
+    }
+
+    /// Whether `uid` may query the API of a semcp running as `owner`.
+    pub fn admits(&self, uid: u32, owner: u32) -> bool {
+        uid == 0 || uid == owner || self.allowed_uids.contains(&uid)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Call {
+    pub time: u64,
+    pub server: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<u32>,
+    pub method: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Alert {
+    pub time: u64,
+    pub server: String,
+    /// `crash`, `crash-loop` or `hung`.
+    pub kind: &'static str,
+    pub message: String,
+}
+
+/// Recent calls and alerts of every server.
+#[derive(Debug, Default)]
+pub struct Activity {
+    calls: Mutex<VecDeque<Call>>,
+    alerts: Mutex<VecDeque<Alert>>,
+}
+
+fn push_recent<T>(queue: &Mutex<VecDeque<T>>, item: T) {
+    let mut queue = queue.lock().unwrap();
+    if queue.len() == RECENT {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+impl Activity {
+    /// Records `line` from a client of `server` when it is a request.
+    pub fn record_client_line(&self, server: &str, user: Option<u32>, line: &str) {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            return;
+        };
+        let (Some(method), false) = (message["method"].as_str(), message["id"].is_null()) else {
+            return;
+        };
+        let tool = (method == "tools/call")
+            .then(|| message["params"]["name"].as_str())
+            .flatten()
+            .map(str::to_string);
+        push_recent(
+            &self.calls,
+            Call {
+                time: now(),
+                server: server.to_string(),
+                user,
+                method: method.to_string(),
+                tool,
+            },
+        );
+    }
+
+    pub fn record_alert(&self, server: &str, kind: &'static str, message: String) {
+        push_recent(
+            &self.alerts,
+            Alert {
+                time: now(),
+                server: server.to_string(),
+                kind,
+                message,
+            },
+        );
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().iter().cloned().collect()
+    }
+
+    pub fn alerts(&self) -> Vec<Alert> {
+        self.alerts.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Records the requests of one client session.
+#[derive(Clone, Copy)]
+pub struct CallRecorder<'a> {
+    pub activity: &'a Activity,
+    pub server: &'a str,
+    pub user: Option<u32>,
+}
+
+impl CallRecorder<'_> {
+    pub fn on_client_line(&self, line: &str) {
+        self.activity
+            .record_client_line(self.server, self.user, line);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PolicyHash {
+    /// The path, or the `oci://` or `https://` reference, of the file.
+    pub path: String,
+    pub sha256: String,
+}
+
+impl From<&LoadedFile> for PolicyHash {
+    fn from(file: &LoadedFile) -> Self {
+        Self {
+            path: file.source.clone(),
+            sha256: file.sha256.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerStatus {
+    pub name: String,
+    pub image: String,
+    pub activation: Activation,
+    /// Unset with tenancy, where servers listen on sockets.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// `idle`, `active`, `backing-off` or `crash-looping`.
+    pub state: &'static str,
+    pub sessions: usize,
+    pub policies: Vec<PolicyHash>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Labels of the server's containers, from its policies and the
+    /// manifest.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Status code and JSON body answering `request`, the head of an HTTP
+/// request.
+pub fn respond(request: &str, servers: &[ServerStatus], activity: &Activity) -> (u16, Value) {
+    let mut parts = request.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next());
+    let Some(target) = target else {
+        return (400, json!({"error": "malformed request"}));
+    };
+    if method != "GET" {
+        return (405, json!({"error": "the API is read-only, use GET"}));
+    }
+    let path = target.split('?').next().unwrap_or_default();
+    match path.trim_end_matches('/') {
+        "/v1/servers" => (200, json!(servers)),
+        "/v1/calls" => (200, json!(activity.calls())),
+        "/v1/alerts" => (200, json!(activity.alerts())),
+        path => match path.strip_prefix("/v1/servers/") {
+            Some(name) => match servers.iter().find(|server| server.name == name) {
+                Some(server) => (200, json!(server)),
+                None => (404, json!({"error": format!("no server `{}`", name)})),
+            },
+            None => (404, json!({"error": format!("no endpoint {}", path)})),
+        },
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Error",
+    }
+}
+
+pub fn http_response(status: u16, body: &Value) -> String {
+    let body = body.to_string();
+    format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )
+}
+
+/// Reads the head of an HTTP request, up to [`MAX_REQUEST`] bytes.
+#[cfg(unix)]
+async fn read_request(stream: &mut tokio::net::UnixStream) -> Vec<u8> {
+    use tokio::io::AsyncReadExt;
+
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(read) => request.extend_from_slice(&buffer[..read]),
+        }
+    }
+    request
+}
+
+/// Answers requests on the socket until semcp stops. `servers` gives the
+/// current state of the servers for each request.
+#[cfg(unix)]
+pub fn serve<F>(
+    config: &Introspection,
+    activity: std::sync::Arc<Activity>,
+    servers: F,
+) -> Result<()>
+where
+    F: Fn() -> Vec<ServerStatus> + Send + Sync + 'static,
+{
+    use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+    use std::sync::Arc;
+    use tokio::io::AsyncWriteExt;
+
+    let path = config.socket_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    // a socket left behind by an earlier run, but nothing else
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!(
+                "{} exists and is not a socket; move it, or set introspection.socket",
+                path.display()
+            );
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    // who may ask is checked per connection
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    let owner = std::fs::metadata(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?
+        .uid();
+    eprintln!("Introspection API on {}", path.display());
+
+    let config = Arc::new(config.clone());
+    let servers = Arc::new(servers);
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                continue;
+            };
+            let config = Arc::clone(&config);
+            let servers = Arc::clone(&servers);
+            let activity = Arc::clone(&activity);
+            // a client that never finishes its request holds up only its
+            // own connection, and not for long
+            tokio::spawn(tokio::time::timeout(REQUEST_TIMEOUT, async move {
+                let admitted = stream
+                    .peer_cred()
+                    .is_ok_and(|credentials| config.admits(credentials.uid(), owner));
+                let (status, body) = if admitted {
+                    let request = read_request(&mut stream).await;
+                    respond(&String::from_utf8_lossy(&request), &servers(), &activity)
+                } else {
+                    (403, json!({"error": "not allowed to query this semcp"}))
+                };
+                let _ = stream
+                    .write_all(http_response(status, &body).as_bytes())
+                    .await;
+                let _ = stream.shutdown().await;
+            }));
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve<F>(
+    _config: &Introspection,
+    _activity: std::sync::Arc<Activity>,
+    _servers: F,
+) -> Result<()>
+where
+    F: Fn() -> Vec<ServerStatus> + Send + Sync + 'static,
+{
+    anyhow::bail!("the introspection API needs Unix sockets, which this platform does not have")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(name: &str) -> ServerStatus {
+        ServerStatus {
+            name: name.to_string(),
+            image: "node:24-alpine".to_string(),
+            activation: Activation::PerConnection,
+            port: Some(7301),
+            state: "idle",
+            sessions: 0,
+            policies: vec![],
+            depends_on: vec![],
+            labels: BTreeMap::from([("owner".to_string(), "data-platform".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_records_requests_only() {
+        let activity = Activity::default();
+        let call = r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{"name":"read","arguments":{"path":"/secret"}}}"#;
+        activity.record_client_line("files", Some(1000), call);
+        activity.record_client_line(
+            "files",
+            None,
+            r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#,
+        );
+        activity.record_client_line("files", None, r#"{"jsonrpc":"2.0","id":1,"result":{}}"#);
+        activity.record_client_line("files", None, "not json");
+
+        let calls = activity.calls();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].tool.as_deref(), Some("read"));
+        assert_eq!(calls[0].user, Some(1000));
+        assert!(!json!(calls).to_string().contains("/secret"));
+
+        for _ in 0..RECENT + 5 {
+            activity.record_client_line("files", None, call);
+        }
+        assert_eq!(activity.calls().len(), RECENT);
+    }
+
+    #[test]
+    fn test_respond() {
+        let activity = Activity::default();
+        activity.record_alert("memory", "crash-loop", "exited 5 times".to_string());
+        let servers = [status("memory"), status("files")];
+
+        let (code, body) = respond("GET /v1/servers HTTP/1.1\r\n\r\n", &servers, &activity);
+        assert_eq!(code, 200);
+        assert_eq!(body[1]["name"], "files");
+        assert_eq!(body[0]["activation"], "per-connection");
+
+        let (code, body) = respond("GET /v1/servers/files HTTP/1.1\r\n", &servers, &activity);
+        assert_eq!((code, body["port"].as_u64()), (200, Some(7301)));
+        assert_eq!(
+            respond("GET /v1/servers/db HTTP/1.1", &servers, &activity).0,
+            404
+        );
+
+        let (code, body) = respond("GET /v1/alerts HTTP/1.1", &servers, &activity);
+        assert_eq!((code, body[0]["kind"].as_str()), (200, Some("crash-loop")));
+
+        assert_eq!(
+            respond("POST /v1/servers HTTP/1.1", &servers, &activity).0,
+            405
+        );
+        assert_eq!(respond("", &servers, &activity).0, 400);
+    }
+
+    #[test]
+    fn test_admits() {
+        let config = Introspection {
+            socket: None,
+            allowed_uids: vec![1001],
+        };
+        assert!(config.admits(0, 1000));
+        assert!(config.admits(1000, 1000));
+        assert!(config.admits(1001, 1000));
+        assert!(!config.admits(1002, 1000));
+    }
+}
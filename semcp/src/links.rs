@@ -0,0 +1,110 @@
+//! Servers of `semcp serve` that other servers depend on.
+//!
+//! A server named in another's `depends_on` is not served to clients. It
+//! runs once, detached, with its command as the container's main process,
+//! on a private network where its name resolves to it, so a proxy server
+//! reaches a database server at e.g. `http://db:8080`. Dependents join the
+//! network too. Servers start after the ones they depend on, and the
+//! network and its servers are removed when `semcp serve` stops.
+
+use crate::pool::{docker, docker_with_env, remove_container, Profile};
+use anyhow::{bail, Result};
+use semcp_common::{audit, runtime};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+pub const LINKED_LABEL: &str = "semcp.linked=true";
+
+pub struct Links {
+    network: String,
+    /// Containers of the servers depended on, in start order.
+    containers: Mutex<Vec<String>>,
+    verbose: bool,
+}
+
+impl Links {
+    /// Creates the private network, with the manifest's `labels`.
+    pub async fn create(labels: &BTreeMap<String, String>, verbose: bool) -> Result<Self> {
+        let network = format!("semcp-links-{}", std::process::id());
+        let mut create_args = ["network", "create", "--label", "semcp.managed=true"]
+            .map(String::from)
+            .to_vec();
+        for (key, value) in labels {
+            create_args.extend(["--label".to_string(), format!("{}={}", key, value)]);
+        }
+        create_args.push(network.clone());
+        docker(&create_args).await?;
+        Ok(Self {
+            network,
+            containers: Mutex::new(Vec::new()),
+            verbose,
+        })
+    }
+
+    /// `--network` for the containers of `server`, which must not choose a
+    /// network of their own.
+    pub fn join_args(&self, server: &str, docker_args: &[String]) -> Result<Vec<String>> {
+        if docker_args.iter().any(|arg| arg == "--network") {
+            bail!(
+                "Server `{}` sets its own network, so it cannot join the network of depends_on; \
+                 drop permissions.network.policy",
+                server
+            );
+        }
+        Ok(vec!["--network".to_string(), self.network.clone()])
+    }
+
+    /// Starts the server `name` depended on, reachable as `name` on the
+    /// network. `profile` has already joined it.
+    pub async fn start(
+        &self,
+        name: &str,
+        profile: &Profile,
+        command: &[String],
+        policies: &[String],
+    ) -> Result<()> {
+        let container = format!("semcp-link-{}-{}", name, std::process::id());
+        let mut args = vec![
+            "run".to_string(),
+            "-d".to_string(),
+            "--name".to_string(),
+            container.clone(),
+            "--label".to_string(),
+            LINKED_LABEL.to_string(),
+            "--network-alias".to_string(),
+            name.to_string(),
+        ];
+        args.extend(profile.docker_args.iter().cloned());
+        args.push(profile.image.clone());
+        args.extend(command.iter().cloned());
+        if self.verbose {
+            eprintln!(
+                "Starting {} for its dependents: {} {}",
+                name,
+                runtime::current(),
+                args.join(" ")
+            );
+        }
+        docker_with_env(&args, &profile.env).await?;
+        self.containers.lock().unwrap().push(container.clone());
+        let fields = json!({
+            "container": container,
+            "image": profile.image,
+            "server": name,
+            "policies": policies,
+        });
+        audit::record_run(runtime::current().as_ref(), fields, self.verbose).await;
+        eprintln!("Started {} as `{}` on network {}", name, name, self.network);
+        Ok(())
+    }
+
+    /// Removes the servers, dependents of others first, and the network.
+    pub async fn remove(&self) {
+        let containers: Vec<String> = self.containers.lock().unwrap().drain(..).collect();
+        for container in containers.iter().rev() {
+            remove_container(container).await;
+        }
+        let _ = docker(&["network", "rm", &self.network].map(String::from)).await;
+    }
+}
@@ -0,0 +1,230 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use semcp_common::runtime::{self, Endpoint, RuntimeKind};
+
+mod activation;
+mod bundle;
+mod cedar;
+mod compose;
+mod doctor;
+mod exec;
+mod gc;
+mod groups;
+mod health;
+mod import;
+mod introspect;
+mod links;
+mod manifest;
+mod multiplex;
+mod outdated;
+mod pool;
+mod serve;
+mod service;
+mod snapshot;
+mod state;
+mod tenancy;
+mod unit;
+mod wrap;
+
+#[derive(Parser)]
+#[command(
+    name = "semcp",
+    about = "Manage containerized MCP servers",
+    version = env!("CARGO_PKG_VERSION")
+)]
+struct Args {
+    #[arg(
+        long = "runtime",
+        global = true,
+        value_enum,
+        help = "Container runtime (default: the first installed of docker, podman, nerdctl, then apple on macOS)"
+    )]
+    runtime: Option<RuntimeKind>,
+
+    #[arg(
+        long = "context",
+        global = true,
+        help = "Docker context (or Podman connection) of the daemon to run on"
+    )]
+    context: Option<String>,
+
+    #[arg(
+        long = "docker-host",
+        global = true,
+        help = "Daemon to run on, e.g. ssh://user@host or tcp://host:2376 (bind mounts are refused when it is remote)"
+    )]
+    docker_host: Option<String>,
+
+    #[arg(
+        long = "tls-cert-path",
+        global = true,
+        help = "Directory with ca.pem, cert.pem and key.pem for a TLS docker daemon"
+    )]
+    tls_cert_path: Option<String>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl Args {
+    /// The global options as given, for commands that start semcp again.
+    fn global_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(runtime) = self.runtime {
+            args.extend(["--runtime".to_string(), runtime.name().to_string()]);
+        }
+        for (flag, value) in [
+            ("--context", &self.context),
+            ("--docker-host", &self.docker_host),
+            ("--tls-cert-path", &self.tls_cert_path),
+        ] {
+            if let Some(value) = value {
+                args.extend([flag.to_string(), value.clone()]);
+            }
+        }
+        args
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(subcommand, about = "Manage persistent per-package state volumes")]
+    State(state::StateCommand),
+
+    #[command(about = "Archive the state volume of a package, pausing the servers using it")]
+    Snapshot(snapshot::SnapshotArgs),
+
+    #[command(about = "Replace the state volume of a package with a snapshot")]
+    Restore(snapshot::RestoreArgs),
+
+    #[command(subcommand, about = "Export server definitions to share them")]
+    Export(unit::ExportCommand),
+
+    #[command(
+        subcommand,
+        about = "Carry servers with their images into networks without registry access"
+    )]
+    Bundle(bundle::BundleCommand),
+
+    #[command(about = "Rewrite npx/uvx servers in an MCP client config to snpx/suvx")]
+    Import(import::ImportArgs),
+
+    #[command(about = "List servers of a manifest with newer pinned images or packages")]
+    Outdated(outdated::OutdatedArgs),
+
+    #[command(about = "Re-pin a server of a manifest after verifying its updates")]
+    Upgrade(outdated::UpgradeArgs),
+
+    #[command(about = "Serve the MCP servers of a manifest over TCP")]
+    Serve(serve::ServeArgs),
+
+    #[command(
+        subcommand,
+        about = "Run the gateway of a manifest as a Windows service"
+    )]
+    Service(service::ServiceCommand),
+
+    #[command(about = "Write an executable wrapper that runs a package with fixed settings")]
+    Wrap(wrap::WrapArgs),
+
+    #[command(about = "Remove containers, networks and volumes left behind by killed runs")]
+    Gc(gc::GcArgs),
+
+    #[command(about = "Show which container runtime and daemon runs use")]
+    Doctor(doctor::DoctorArgs),
+
+    #[command(subcommand, about = "Work with Cedar policies for the cedar engine")]
+    Cedar(cedar::CedarCommand),
+
+    // the container options of exec include --runtime and the daemon
+    // options, which take the global values given before `exec`
+    #[command(about = "Run a command in an image with the policy pipeline of snpx and suvx")]
+    Exec(Box<exec::ExecArgs>),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    if let Command::Exec(args) = args.command {
+        match exec::run(*args).await {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let endpoint = Endpoint {
+        context: args.context.clone(),
+        host: args.docker_host.clone(),
+        tls_cert_path: args.tls_cert_path.clone(),
+    };
+    if args.runtime.is_some() || !endpoint.is_default() {
+        runtime::set_current(args.runtime, endpoint)?;
+    }
+
+    let global = args.global_args();
+    let result = match args.command {
+        Command::State(command) => state::run(command).await,
+        Command::Snapshot(args) => snapshot::snapshot(args).await,
+        Command::Restore(args) => snapshot::restore(args).await,
+        Command::Export(unit::ExportCommand::Unit(args)) => unit::export(args).await,
+        Command::Export(unit::ExportCommand::Compose(args)) => compose::export(args).await,
+        Command::Bundle(command) => bundle::run(command).await,
+        Command::Import(args) => import::run(args).await,
+        Command::Outdated(args) => outdated::run_outdated(args).await,
+        Command::Upgrade(args) => outdated::run_upgrade(args).await,
+        Command::Serve(args) => serve::run(args).await,
+        Command::Service(command) => service::run(command, &global),
+        Command::Wrap(args) => wrap::run(args).await,
+        Command::Gc(args) => gc::run(args).await,
+        Command::Doctor(args) => doctor::run(args).await,
+        Command::Cedar(command) => cedar::run(command).await,
+        Command::Exec(_) => unreachable!("exec is run above"),
+    };
+
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn test_exec_takes_global_options() {
+        let args = Args::try_parse_from([
+            "semcp",
+            "--runtime",
+            "podman",
+            "exec",
+            "--image",
+            "acme-mcp:1.4",
+            "acme-mcp",
+            "--stdio",
+        ])
+        .unwrap();
+        let Command::Exec(exec) = args.command else {
+            panic!("expected exec");
+        };
+        assert_eq!(exec.container.runtime, Some(RuntimeKind::Podman));
+        assert_eq!(exec.command, vec!["acme-mcp", "--stdio"]);
+
+        let args = Args::try_parse_from(["semcp", "exec", "--image", "x", "--", "x", "--verbose"])
+            .unwrap();
+        let Command::Exec(exec) = args.command else {
+            panic!("expected exec");
+        };
+        assert!(!exec.verbose);
+        assert_eq!(exec.command, vec!["x", "--verbose"]);
+    }
+}
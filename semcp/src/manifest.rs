@@ -0,0 +1,479 @@
+//! The `semcp serve` manifest: which servers to expose and how.
+//!
+//! ```yaml
+//! listen: 127.0.0.1
+//! labels:
+//!   cost-center: cc-1042
+//!   owner: data-platform
+//! warm_pool:
+//!   node:24-alpine: 2
+//! resource_groups:
+//!   tools: {memory: 2g, cpus: 2}
+//! tenancy:
+//!   socket_dir: /run/semcp
+//!   max_sessions_per_user: 4
+//! introspection:
+//!   allowed_uids: [1001]
+//! servers:
+//!   - name: memory
+//!     image: node:24-alpine
+//!     command: [npx, -y, "@modelcontextprotocol/server-memory"]
+//!     policy: [policy.yaml]
+//!     port: 7301
+//!     activation: on-demand
+//!     idle_timeout: 300
+//!     resource_group: tools
+//!     env: [MEMORY_FILE_PATH]
+//!     health_check: {min_interval: 5, max_interval: 300, timeout: 10}
+//!     depends_on: [db]
+//!     labels: {environment: prod}
+//!   - name: db
+//!     image: node:24-alpine
+//!     command: [npx, -y, mcp-db-server, --port, "8080"]
+//!     port: 7302
+//! ```
+
+use crate::groups::ResourceGroup;
+use crate::introspect::Introspection;
+use crate::tenancy::Tenancy;
+use anyhow::{bail, Context, Result};
+use semcp_common::spec::validate_labels;
+use semcp_common::ImageVariants;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
+
+pub const DEFAULT_LISTEN: &str = "127.0.0.1";
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub listen: Option<String>,
+    /// Labels of every container, volume and network of the servers, on
+    /// top of those their policies set.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Number of paused containers to keep ready per image.
+    #[serde(default)]
+    pub warm_pool: BTreeMap<String, usize>,
+    /// Memory and CPU budgets shared by the servers assigned to them.
+    #[serde(default)]
+    pub resource_groups: BTreeMap<String, ResourceGroup>,
+    /// Serve local users over Unix sockets, see [`crate::tenancy`].
+    #[serde(default)]
+    pub tenancy: Option<Tenancy>,
+    /// Answer read-only queries about the servers, see
+    /// [`crate::introspect`].
+    #[serde(default)]
+    pub introspection: Option<Introspection>,
+    pub servers: Vec<ServerSpec>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ServerSpec {
+    pub name: String,
+    pub image: String,
+    /// Command started in the container for each connection.
+    pub command: Vec<String>,
+    /// Policy files, relative to the manifest, layered in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub policy: Vec<String>,
+    /// Environment variables passed on from semcp's own environment.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// TCP port, unused with `tenancy`.
+    pub port: u16,
+    #[serde(default)]
+    pub activation: Activation,
+    /// Seconds without connections before an on-demand container is
+    /// stopped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resource_group: Option<String>,
+    /// Ping the server during quiet periods, see [`crate::health`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// Servers this one reaches by name, see [`crate::links`].
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Labels of this server's containers and volumes, replacing the
+    /// manifest's with the same key.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// Health probe timing, in seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HealthCheck {
+    /// Interval right after traffic.
+    pub min_interval: u64,
+    /// Longest interval, reached by doubling while the session is quiet.
+    pub max_interval: u64,
+    /// How long the server may take to answer a probe.
+    pub timeout: u64,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            min_interval: 5,
+            max_interval: 300,
+            timeout: 10,
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn min_interval(&self) -> Duration {
+        Duration::from_secs(self.min_interval.max(1))
+    }
+
+    pub fn max_interval(&self) -> Duration {
+        Duration::from_secs(self.max_interval)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.max(1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Activation {
+    /// A fresh container per connection, removed when it closes.
+    #[default]
+    PerConnection,
+    /// One container started by the first connection and shared by later
+    /// ones, each running its own server process, until it has been idle
+    /// for `idle_timeout`.
+    OnDemand,
+    /// One server process shared by every connection, for stateless
+    /// servers, see [`crate::multiplex`].
+    Shared,
+}
+
+impl ServerSpec {
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT_SECS))
+    }
+}
+
+impl Manifest {
+    pub fn from_file(path: &str) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest {}", path))?;
+        let mut manifest: Manifest = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse manifest {}", path))?;
+        manifest.validate()?;
+        manifest.inherit_labels();
+
+        let base = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        for server in &mut manifest.servers {
+            for policy in &mut server.policy {
+                *policy = base.join(&*policy).to_string_lossy().into_owned();
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// Gives every server the manifest's labels it does not set itself.
+    fn inherit_labels(&mut self) {
+        for server in &mut self.servers {
+            let mut labels = self.labels.clone();
+            labels.append(&mut server.labels);
+            server.labels = labels;
+        }
+    }
+
+    pub fn listen(&self) -> &str {
+        self.listen.as_deref().unwrap_or(DEFAULT_LISTEN)
+    }
+
+    /// Names of the servers other servers depend on.
+    pub fn dependencies(&self) -> HashSet<&str> {
+        self.servers
+            .iter()
+            .flat_map(|server| &server.depends_on)
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// The servers in the order they start: every server after the ones it
+    /// depends on, otherwise in manifest order.
+    pub fn start_order(&self) -> Result<Vec<&ServerSpec>> {
+        let by_name: HashMap<&str, &ServerSpec> = self
+            .servers
+            .iter()
+            .map(|server| (server.name.as_str(), server))
+            .collect();
+        let mut order = Vec::new();
+        let mut done = HashSet::new();
+        for server in &self.servers {
+            visit(server, &by_name, &mut done, &mut Vec::new(), &mut order)?;
+        }
+        Ok(order)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        validate_labels(&self.labels)?;
+        for (name, group) in &self.resource_groups {
+            group.validate(name)?;
+        }
+        let dependencies = self.dependencies();
+        if self.tenancy.is_some() && !dependencies.is_empty() {
+            bail!("depends_on cannot be combined with tenancy: users would share the servers depended on");
+        }
+        let mut names = HashSet::new();
+        let mut ports = HashSet::new();
+        for server in &self.servers {
+            if !names.insert(&server.name) {
+                bail!("Server `{}` is declared more than once", server.name);
+            }
+            validate_labels(&server.labels)
+                .with_context(|| format!("Server `{}` has invalid labels", server.name))?;
+            // servers others depend on are not served on their port
+            if self.tenancy.is_none()
+                && !dependencies.contains(server.name.as_str())
+                && !ports.insert(server.port)
+            {
+                bail!(
+                    "Server `{}` uses port {} which is already taken",
+                    server.name,
+                    server.port
+                );
+            }
+            if let Some(ref group) = server.resource_group {
+                if !self.resource_groups.contains_key(group) {
+                    bail!(
+                        "Server `{}` is assigned to unknown resource group `{}`",
+                        server.name,
+                        group
+                    );
+                }
+            }
+            if server.command.is_empty() {
+                bail!("Server `{}` has an empty command", server.name);
+            }
+            if server.activation == Activation::OnDemand
+                && !ImageVariants::capabilities(&server.image).has_shell
+            {
+                bail!(
+                    "Server `{}` cannot be started on demand: image `{}` has no shell",
+                    server.name,
+                    server.image
+                );
+            }
+            if server.activation == Activation::Shared && server.health_check.is_some() {
+                bail!(
+                    "Server `{}` is shared between connections, which health checks do not support",
+                    server.name
+                );
+            }
+        }
+        self.start_order()?;
+        Ok(())
+    }
+}
+
+/// Appends `server` to `order` after its dependencies. `path` holds the
+/// servers being visited, to report cycles.
+fn visit<'a>(
+    server: &'a ServerSpec,
+    by_name: &HashMap<&str, &'a ServerSpec>,
+    done: &mut HashSet<&'a str>,
+    path: &mut Vec<&'a str>,
+    order: &mut Vec<&'a ServerSpec>,
+) -> Result<()> {
+    if done.contains(server.name.as_str()) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|name| *name == server.name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(&server.name);
+        bail!("Servers depend on each other: {}", cycle.join(" -> "));
+    }
+    path.push(&server.name);
+    for dependency in &server.depends_on {
+        let Some(target) = by_name.get(dependency.as_str()) else {
+            bail!(
+                "Server `{}` depends on unknown server `{}`",
+                server.name,
+                dependency
+            );
+        };
+        visit(target, by_name, done, path, order)?;
+    }
+    path.pop();
+    done.insert(&server.name);
+    order.push(server);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Manifest {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse(
+            "warm_pool: {node:24-alpine: 2}\n\
+             servers:\n\
+             - {name: memory, image: node:24-alpine, command: [npx, -y, pkg], port: 7301}\n",
+        );
+        assert_eq!(manifest.listen(), DEFAULT_LISTEN);
+        assert_eq!(manifest.warm_pool.get("node:24-alpine"), Some(&2));
+        assert!(manifest.servers[0].policy.is_empty());
+        assert_eq!(manifest.servers[0].activation, Activation::PerConnection);
+        assert!(manifest.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_duplicate_ports() {
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301}\n\
+             - {name: b, image: img, command: [b], port: 7301}\n",
+        );
+        assert!(manifest.validate().is_err());
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut manifest = parse(
+            "labels: {owner: data-platform, environment: dev}
+             servers:
+             - {name: a, image: img, command: [a], port: 7301, labels: {environment: prod}}
+             - {name: b, image: img, command: [b], port: 7302}
+",
+        );
+        assert!(manifest.validate().is_ok());
+        manifest.inherit_labels();
+        assert_eq!(manifest.servers[0].labels["environment"], "prod");
+        assert_eq!(manifest.servers[0].labels["owner"], "data-platform");
+        assert_eq!(manifest.servers[1].labels["environment"], "dev");
+
+        let reserved = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, labels: {semcp.user: '0'}}\n",
+        );
+        assert!(reserved.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_resource_groups() {
+        let manifest = parse(
+            "resource_groups: {tools: {memory: 1g, cpus: 1}}\n\
+             servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, resource_group: tools}\n\
+             - {name: b, image: img, command: [b], port: 7302, resource_group: other}\n",
+        );
+        let error = manifest.validate().unwrap_err().to_string();
+        assert!(error.contains("unknown resource group `other`"));
+    }
+
+    #[test]
+    fn test_on_demand_activation() {
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, activation: on-demand, idle_timeout: 60}\n",
+        );
+        let server = &manifest.servers[0];
+        assert_eq!(server.activation, Activation::OnDemand);
+        assert_eq!(server.idle_timeout(), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_shared_activation() {
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, activation: shared}\n",
+        );
+        assert_eq!(manifest.servers[0].activation, Activation::Shared);
+        assert!(manifest.validate().is_ok());
+
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, activation: shared, health_check: {}}\n",
+        );
+        assert!(manifest.validate().is_err());
+    }
+
+    #[test]
+    fn test_tenancy_ignores_ports() {
+        let manifest = parse(
+            "tenancy: {socket_dir: /run/semcp-mcp}\n\
+             servers:\n\
+             - {name: a, image: img, command: [a], port: 7301}\n\
+             - {name: b, image: img, command: [b], port: 7301}\n",
+        );
+        assert!(manifest.validate().is_ok());
+        assert_eq!(
+            manifest.tenancy.unwrap().socket_path("b"),
+            Path::new("/run/semcp-mcp/b.sock")
+        );
+    }
+
+    #[test]
+    fn test_start_order_follows_dependencies() {
+        let manifest = parse(
+            "servers:\n\
+             - {name: proxy, image: img, command: [a], port: 7301, depends_on: [db, cache]}\n\
+             - {name: db, image: img, command: [b], port: 7301, depends_on: [cache]}\n\
+             - {name: cache, image: img, command: [c], port: 7301}\n",
+        );
+        assert!(manifest.validate().is_ok());
+        let order: Vec<&str> = manifest
+            .start_order()
+            .unwrap()
+            .iter()
+            .map(|server| server.name.as_str())
+            .collect();
+        assert_eq!(order, ["cache", "db", "proxy"]);
+        assert_eq!(manifest.dependencies(), HashSet::from(["db", "cache"]));
+    }
+
+    #[test]
+    fn test_dependency_errors() {
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, depends_on: [b]}\n\
+             - {name: b, image: img, command: [b], port: 7302, depends_on: [a]}\n",
+        );
+        let error = manifest.validate().unwrap_err().to_string();
+        assert!(error.contains("a -> b -> a"), "{}", error);
+
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, depends_on: [db]}\n",
+        );
+        let error = manifest.validate().unwrap_err().to_string();
+        assert!(error.contains("unknown server `db`"));
+
+        let manifest = parse(
+            "tenancy: {socket_dir: /run/semcp-mcp}\n\
+             servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, depends_on: [b]}\n\
+             - {name: b, image: img, command: [b], port: 7302}\n",
+        );
+        assert!(manifest.validate().is_err());
+    }
+
+    #[test]
+    fn test_health_check_defaults() {
+        let manifest = parse(
+            "servers:\n\
+             - {name: a, image: img, command: [a], port: 7301, health_check: {timeout: 30}}\n",
+        );
+        let check = manifest.servers[0].health_check.clone().unwrap();
+        assert_eq!(check.min_interval(), Duration::from_secs(5));
+        assert_eq!(check.timeout(), Duration::from_secs(30));
+    }
+}
@@ -0,0 +1,467 @@
+//! Shared server processes for `semcp serve`.
+//!
+//! A stateless server with `activation: shared` runs one server process
+//! that every connection talks to. Requests are tagged with the session
+//! they came from: their ids, and the progress tokens they carry, are
+//! rewritten to `semcp-s<session>-<id>` on the way in and restored on the
+//! way out, so two clients using the same ids never see each other's
+//! answers. The server is initialized once; later sessions get its answer
+//! to the first `initialize`. List-changed notifications go to every
+//! session, progress to the session that asked, and anything else the
+//! server sends unprompted, including requests to the client, is dropped
+//! or refused, since it cannot be attributed to one session. A session that
+//! disconnects has its outstanding requests cancelled, and one that falls
+//! too far behind is disconnected instead of holding up the others.
+
+use crate::introspect::CallRecorder;
+use crate::serve::write_line;
+use anyhow::{Context, Result};
+use semcp_common::pump::Pump;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
+use tokio::process::Child;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+pub type SessionId = u64;
+
+const TAG_PREFIX: &str = "semcp-s";
+/// Messages queued for a session before it counts as stalled.
+const SESSION_BACKLOG: usize = 256;
+
+fn tag(session: SessionId, id: &Value) -> String {
+    format!("{}{}-{}", TAG_PREFIX, session, id)
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Routed {
+    pub to_server: Vec<String>,
+    pub to_sessions: Vec<(SessionId, String)>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    session: SessionId,
+    id: Value,
+    progress: Option<String>,
+    initialize: bool,
+}
+
+/// Routes messages between sessions and the shared server process.
+#[derive(Debug, Default)]
+pub struct Router {
+    next_session: SessionId,
+    sessions: BTreeSet<SessionId>,
+    /// Requests sent to the server, by tagged id.
+    pending: HashMap<String, Pending>,
+    /// Progress tokens of pending requests, by tagged token.
+    progress: HashMap<String, (SessionId, Value)>,
+    /// The server's result for the first `initialize`.
+    initialize_result: Option<Value>,
+    /// Whether an `initialize` is on its way to the server.
+    initializing: bool,
+    /// Sessions whose `initialize` waits for the one in flight.
+    waiting: Vec<(SessionId, Value)>,
+    /// Whether the server got `notifications/initialized`.
+    initialized: bool,
+}
+
+impl Router {
+    pub fn open(&mut self) -> SessionId {
+        self.next_session += 1;
+        self.sessions.insert(self.next_session);
+        self.next_session
+    }
+
+    /// Forgets `session` and cancels what it left outstanding.
+    pub fn close(&mut self, session: SessionId) -> Vec<String> {
+        self.sessions.remove(&session);
+        self.waiting.retain(|(waiting, _)| *waiting != session);
+        // an initialize in flight is still answered for the waiting sessions
+        let abandoned: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.session == session && !pending.initialize)
+            .map(|(tagged, _)| tagged.clone())
+            .collect();
+        abandoned
+            .into_iter()
+            .map(|tagged| {
+                if let Some(token) = self.pending.remove(&tagged).and_then(|p| p.progress) {
+                    self.progress.remove(&token);
+                }
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "notifications/cancelled",
+                    "params": {"requestId": tagged, "reason": "client disconnected"}
+                })
+                .to_string()
+            })
+            .collect()
+    }
+
+    pub fn on_client_line(&mut self, session: SessionId, line: &str) -> Routed {
+        let mut routed = Routed::default();
+        let Ok(mut message) = serde_json::from_str::<Value>(line) else {
+            return routed;
+        };
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        match (method.as_deref(), message.get("id").cloned()) {
+            (Some("initialize"), Some(id)) => {
+                if let Some(ref result) = self.initialize_result {
+                    routed.to_sessions.push((
+                        session,
+                        json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string(),
+                    ));
+                } else if self.initializing {
+                    self.waiting.push((session, id));
+                } else {
+                    self.initializing = true;
+                    routed
+                        .to_server
+                        .push(self.forward(session, message, id, true));
+                }
+            }
+            (Some(_), Some(id)) => {
+                routed
+                    .to_server
+                    .push(self.forward(session, message, id, false));
+            }
+            (Some("notifications/initialized"), None) => {
+                if !self.initialized {
+                    self.initialized = true;
+                    routed.to_server.push(line.to_string());
+                }
+            }
+            (Some("notifications/cancelled"), None) => {
+                let request = message
+                    .pointer("/params/requestId")
+                    .cloned()
+                    .unwrap_or_default();
+                let tagged = tag(session, &request);
+                if self.pending.contains_key(&tagged) {
+                    message["params"]["requestId"] = json!(tagged);
+                    routed.to_server.push(message.to_string());
+                }
+            }
+            (Some(_), None) => routed.to_server.push(line.to_string()),
+            // answers to server requests, which are never relayed
+            (None, _) => {}
+        }
+        routed
+    }
+
+    /// Tags a request of `session` and remembers where its answer goes.
+    fn forward(
+        &mut self,
+        session: SessionId,
+        mut message: Value,
+        id: Value,
+        initialize: bool,
+    ) -> String {
+        let tagged = tag(session, &id);
+        message["id"] = json!(tagged);
+        let progress = message
+            .pointer("/params/_meta/progressToken")
+            .cloned()
+            .map(|token| {
+                let tagged_token = tag(session, &token);
+                message["params"]["_meta"]["progressToken"] = json!(tagged_token);
+                self.progress.insert(tagged_token.clone(), (session, token));
+                tagged_token
+            });
+        self.pending.insert(
+            tagged,
+            Pending {
+                session,
+                id,
+                progress,
+                initialize,
+            },
+        );
+        message.to_string()
+    }
+
+    pub fn on_server_line(&mut self, line: &str) -> Routed {
+        let mut routed = Routed::default();
+        let Ok(mut message) = serde_json::from_str::<Value>(line) else {
+            return routed;
+        };
+        let method = message
+            .get("method")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        match (method.as_deref(), message.get("id").cloned()) {
+            (None, Some(id)) => {
+                let Some(pending) = id.as_str().and_then(|id| self.pending.remove(id)) else {
+                    return routed;
+                };
+                if let Some(ref token) = pending.progress {
+                    self.progress.remove(token);
+                }
+                let mut sessions = vec![(pending.session, pending.id)];
+                if pending.initialize {
+                    self.initializing = false;
+                    self.initialize_result = message.get("result").cloned();
+                    sessions.append(&mut self.waiting);
+                }
+                for (session, id) in sessions {
+                    message["id"] = id;
+                    routed.to_sessions.push((session, message.to_string()));
+                }
+            }
+            (Some(_), Some(id)) => routed.to_server.push(
+                json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": -32601,
+                        "message": "this server is shared between clients, semcp does not relay its requests"
+                    }
+                })
+                .to_string(),
+            ),
+            (Some("notifications/progress"), None) => {
+                let owner = message
+                    .pointer("/params/progressToken")
+                    .and_then(Value::as_str)
+                    .and_then(|token| self.progress.get(token))
+                    .cloned();
+                if let Some((session, token)) = owner {
+                    message["params"]["progressToken"] = token;
+                    routed.to_sessions.push((session, message.to_string()));
+                }
+            }
+            (Some(method), None) if method.ends_with("/list_changed") => {
+                for session in &self.sessions {
+                    routed.to_sessions.push((*session, line.to_string()));
+                }
+            }
+            _ => {}
+        }
+        routed
+    }
+}
+
+/// One server process and the sessions sharing it.
+pub struct Shared {
+    pub container: String,
+    router: Mutex<Router>,
+    to_server: Pump,
+    sessions: Mutex<HashMap<SessionId, mpsc::Sender<String>>>,
+    closed: AtomicBool,
+    name: String,
+}
+
+impl Shared {
+    /// Takes over the stdio of `child`. The returned task ends when the
+    /// server closes its stdout.
+    pub fn start(
+        name: &str,
+        container: String,
+        child: &mut Child,
+    ) -> Result<(Arc<Self>, JoinHandle<()>)> {
+        let stdin = child.stdin.take().context("Failed to open server stdin")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("Failed to open server stdout")?;
+        let (to_server, _) = Pump::spawn(stdin);
+        let shared = Arc::new(Self {
+            container,
+            router: Mutex::new(Router::default()),
+            to_server,
+            sessions: Mutex::new(HashMap::new()),
+            closed: AtomicBool::new(false),
+            name: name.to_string(),
+        });
+
+        let this = Arc::clone(&shared);
+        let reader = tokio::spawn(async move {
+            let mut lines = BufReader::new(stdout).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let routed = this.router.lock().unwrap().on_server_line(&line);
+                this.dispatch(routed).await;
+            }
+            this.closed.store(true, Ordering::SeqCst);
+            // ends every session
+            this.sessions.lock().unwrap().clear();
+        });
+        Ok((shared, reader))
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    async fn dispatch(&self, routed: Routed) {
+        self.to_server.send_all(routed.to_server).await;
+        let mut sessions = self.sessions.lock().unwrap();
+        for (session, line) in routed.to_sessions {
+            let Some(sender) = sessions.get(&session) else {
+                continue;
+            };
+            if let Err(mpsc::error::TrySendError::Full(_)) = sender.try_send(line) {
+                eprintln!(
+                    "{}: session {} is not reading its messages, disconnecting it",
+                    self.name, session
+                );
+                sessions.remove(&session);
+            }
+        }
+    }
+
+    /// Relays one connection until it or the server closes.
+    pub async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        recorder: CallRecorder<'_>,
+    ) -> Result<()> {
+        let (session, mut messages) = {
+            let (sender, messages) = mpsc::channel(SESSION_BACKLOG);
+            let session = self.router.lock().unwrap().open();
+            self.sessions.lock().unwrap().insert(session, sender);
+            (session, messages)
+        };
+        let (reader, mut writer) = tokio::io::split(stream);
+        let mut client = BufReader::new(reader).lines();
+        let result = loop {
+            tokio::select! {
+                line = client.next_line() => {
+                    let line = match line {
+                        Ok(Some(line)) => line,
+                        Ok(None) => break Ok(()),
+                        Err(e) => break Err(e.into()),
+                    };
+                    recorder.on_client_line(&line);
+                    let routed = self.router.lock().unwrap().on_client_line(session, &line);
+                    self.dispatch(routed).await;
+                }
+                line = messages.recv() => {
+                    let Some(line) = line else {
+                        break Ok(());
+                    };
+                    if let Err(e) = write_line(&mut writer, &line).await {
+                        break Err(e.into());
+                    }
+                }
+            }
+        };
+
+        self.sessions.lock().unwrap().remove(&session);
+        let cancelled = self.router.lock().unwrap().close(session);
+        self.to_server.send_all(cancelled).await;
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INITIALIZE: &str = r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#;
+
+    fn parse(line: &str) -> Value {
+        serde_json::from_str(line).unwrap()
+    }
+
+    #[test]
+    fn test_sessions_with_the_same_ids_are_kept_apart() {
+        let mut router = Router::default();
+        let (a, b) = (router.open(), router.open());
+        let call = r#"{"jsonrpc":"2.0","id":7,"method":"tools/call","params":{"name":"now"}}"#;
+
+        let to_a = parse(&router.on_client_line(a, call).to_server[0]);
+        let to_b = parse(&router.on_client_line(b, call).to_server[0]);
+        assert_ne!(to_a["id"], to_b["id"]);
+
+        let answer = json!({"jsonrpc": "2.0", "id": to_b["id"], "result": {}}).to_string();
+        let routed = router.on_server_line(&answer);
+        assert_eq!(routed.to_sessions.len(), 1);
+        assert_eq!(routed.to_sessions[0].0, b);
+        assert_eq!(parse(&routed.to_sessions[0].1)["id"], json!(7));
+        assert!(router.on_server_line(&answer).to_sessions.is_empty());
+    }
+
+    #[test]
+    fn test_server_is_initialized_once() {
+        let mut router = Router::default();
+        let (a, b, c) = (router.open(), router.open(), router.open());
+
+        let forwarded = router.on_client_line(a, INITIALIZE).to_server;
+        assert_eq!(forwarded.len(), 1);
+        assert!(router.on_client_line(b, INITIALIZE).to_server.is_empty());
+
+        let answer = json!({
+            "jsonrpc": "2.0",
+            "id": parse(&forwarded[0])["id"],
+            "result": {"protocolVersion": "2025-03-26"}
+        });
+        let routed = router.on_server_line(&answer.to_string());
+        let sessions: Vec<SessionId> = routed.to_sessions.iter().map(|(s, _)| *s).collect();
+        assert_eq!(sessions, [a, b]);
+
+        let routed = router.on_client_line(c, INITIALIZE);
+        assert!(routed.to_server.is_empty());
+        assert_eq!(
+            parse(&routed.to_sessions[0].1)["result"]["protocolVersion"],
+            "2025-03-26"
+        );
+
+        let initialized = r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#;
+        assert_eq!(router.on_client_line(a, initialized).to_server.len(), 1);
+        assert!(router.on_client_line(b, initialized).to_server.is_empty());
+    }
+
+    #[test]
+    fn test_progress_goes_to_the_requesting_session() {
+        let mut router = Router::default();
+        let (a, _b) = (router.open(), router.open());
+        let call = r#"{"jsonrpc":"2.0","id":1,"method":"tools/call","params":{"_meta":{"progressToken":"p"}}}"#;
+        let forwarded = parse(&router.on_client_line(a, call).to_server[0]);
+        let token = forwarded["params"]["_meta"]["progressToken"].clone();
+        assert_ne!(token, json!("p"));
+
+        let progress = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": {"progressToken": token, "progress": 1}
+        });
+        let routed = router.on_server_line(&progress.to_string());
+        assert_eq!(routed.to_sessions.len(), 1);
+        assert_eq!(routed.to_sessions[0].0, a);
+        assert_eq!(
+            parse(&routed.to_sessions[0].1)["params"]["progressToken"],
+            "p"
+        );
+
+        let log =
+            r#"{"jsonrpc":"2.0","method":"notifications/message","params":{"data":"secret"}}"#;
+        assert!(router.on_server_line(log).to_sessions.is_empty());
+        let changed = r#"{"jsonrpc":"2.0","method":"notifications/tools/list_changed"}"#;
+        assert_eq!(router.on_server_line(changed).to_sessions.len(), 2);
+    }
+
+    #[test]
+    fn test_closed_sessions_cancel_their_requests() {
+        let mut router = Router::default();
+        let a = router.open();
+        let call = r#"{"jsonrpc":"2.0","id":3,"method":"tools/call","params":{}}"#;
+        let tagged = parse(&router.on_client_line(a, call).to_server[0])["id"].clone();
+
+        let cancelled = router.close(a);
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(parse(&cancelled[0])["params"]["requestId"], tagged);
+
+        let sampling = r#"{"jsonrpc":"2.0","id":9,"method":"sampling/createMessage"}"#;
+        let routed = router.on_server_line(sampling);
+        assert!(routed.to_sessions.is_empty());
+        assert_eq!(parse(&routed.to_server[0])["error"]["code"], json!(-32601));
+    }
+}
@@ -0,0 +1,538 @@
+//! `semcp outdated` and `semcp upgrade`: keeping the pins of a manifest
+//! current without letting them drift on their own.
+//!
+//! A server is pinned when its image carries a digest
+//! (`node:24-alpine@sha256:...`) and its package a version
+//! (`pkg@1.2.3` for npx, `pkg==1.2.3` for uvx). `outdated` reports images
+//! whose tag now points to another digest and packages with a newer patch
+//! release of the same minor version. `upgrade` starts the server with the
+//! new pins, and only rewrites the manifest when it answers `initialize`.
+
+use crate::manifest::{Manifest, ServerSpec};
+use crate::wrap::pin_image;
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use semcp_common::spec::DEFAULT_GRACEFUL_SHUTDOWN;
+use semcp_common::trust::Registry;
+use semcp_common::{engine, runtime, ContainerExecutor, PolicyConfig};
+use serde_json::Value;
+use std::ops::Range;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+/// How long a server may take to install and answer `initialize`.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(180);
+
+const PYPI_RELEASES: &str = "import json, sys, urllib.request; \
+    url = 'https://pypi.org/pypi/' + sys.argv[1] + '/json'; \
+    print(json.dumps(list(json.load(urllib.request.urlopen(url))['releases'])))";
+
+#[derive(Args, Debug)]
+pub struct OutdatedArgs {
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers to check"
+    )]
+    pub config: String,
+
+    #[arg(long, help = "Use verbose output")]
+    pub verbose: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct UpgradeArgs {
+    #[arg(help = "Name of the server to upgrade")]
+    pub server: String,
+
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers"
+    )]
+    pub config: String,
+
+    #[arg(long = "dry-run", help = "Verify the upgrade without writing it")]
+    pub dry_run: bool,
+
+    #[arg(long, help = "Use verbose output")]
+    pub verbose: bool,
+}
+
+/// A versioned package argument in a server command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackagePin {
+    pub registry: Registry,
+    pub name: String,
+    pub version: String,
+    /// Position of the argument in the command.
+    pub index: usize,
+}
+
+impl PackagePin {
+    pub fn with_version(&self, version: &str) -> String {
+        self.registry.pin(&self.name, version)
+    }
+}
+
+/// The pinned package of an `npx` or `uvx` command, if it has one.
+pub fn package_pin(command: &[String]) -> Option<PackagePin> {
+    let registry = Registry::of_command(command.first()?)?;
+    let (index, arg) = command
+        .iter()
+        .enumerate()
+        .skip(1)
+        .find(|(_, arg)| !arg.starts_with('-'))?;
+    let (name, version) = match registry {
+        // a leading @ belongs to the scope, not the version
+        Registry::Npm => arg.rsplit_once('@').filter(|(name, _)| !name.is_empty())?,
+        Registry::Pypi => arg.split_once("==")?,
+    };
+    parse_version(version)?;
+    Some(PackagePin {
+        registry,
+        name: name.to_string(),
+        version: version.to_string(),
+        index,
+    })
+}
+
+/// Splits an image into the reference to follow and its pinned digest.
+pub fn split_image(image: &str) -> (&str, Option<&str>) {
+    match image.split_once('@') {
+        Some((reference, digest)) => (reference, Some(digest)),
+        None => (image, None),
+    }
+}
+
+/// Parses a plain `major.minor.patch` release; pre-releases are skipped.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+    let version = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(version)
+}
+
+/// The newest release with the same major and minor version as `current`.
+pub fn newest_patch(current: &str, available: &[String]) -> Option<String> {
+    let (major, minor, patch) = parse_version(current)?;
+    available
+        .iter()
+        .filter_map(|version| Some((parse_version(version)?, version)))
+        .filter(|((ma, mi, pa), _)| *ma == major && *mi == minor && *pa > patch)
+        .max_by_key(|(version, _)| *version)
+        .map(|(_, version)| version.clone())
+}
+
+/// Asks the registry, from inside the server's image, which versions of the
+/// package exist.
+async fn available_versions(image: &str, pin: &PackagePin) -> Result<Vec<String>> {
+    let mut args = vec!["--rm".to_string(), "--entrypoint".to_string()];
+    match pin.registry {
+        Registry::Npm => args.extend([
+            "npm".to_string(),
+            image.to_string(),
+            "view".to_string(),
+            pin.name.clone(),
+            "versions".to_string(),
+            "--json".to_string(),
+        ]),
+        Registry::Pypi => args.extend([
+            "python3".to_string(),
+            image.to_string(),
+            "-c".to_string(),
+            PYPI_RELEASES.to_string(),
+            pin.name.clone(),
+        ]),
+    }
+    let output = runtime::current()
+        .run(&args)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} run", runtime::current()))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to list versions of {}: {}",
+            pin.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let versions: Value = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Unexpected version list for {}", pin.name))?;
+    Ok(match versions {
+        Value::Array(versions) => versions
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        // npm prints a single version as a plain string
+        Value::String(version) => vec![version],
+        _ => vec![],
+    })
+}
+
+/// The image the tag of `image` points to now, if that is another digest.
+async fn image_update(image: &str) -> Result<Option<String>> {
+    let (reference, Some(digest)) = split_image(image) else {
+        return Ok(None);
+    };
+    runtime::current()
+        .pull(reference)
+        .await
+        .with_context(|| format!("Failed to pull {}", reference))?;
+    let Some(pinned) = pin_image(reference).await else {
+        bail!("{} has no registry digest", reference);
+    };
+    let (_, latest) = split_image(&pinned);
+    Ok(latest
+        .filter(|latest| *latest != digest)
+        .map(|latest| format!("{}@{}", reference, latest)))
+}
+
+/// Newer pins for one server.
+#[derive(Debug, Clone, Default)]
+pub struct Updates {
+    pub image: Option<String>,
+    pub package: Option<(PackagePin, String)>,
+}
+
+impl Updates {
+    pub fn is_empty(&self) -> bool {
+        self.image.is_none() && self.package.is_none()
+    }
+
+    /// The server with the new pins applied.
+    pub fn apply(&self, spec: &ServerSpec) -> ServerSpec {
+        let mut spec = spec.clone();
+        if let Some(ref image) = self.image {
+            spec.image = image.clone();
+        }
+        if let Some((ref pin, ref version)) = self.package {
+            spec.command[pin.index] = pin.with_version(version);
+        }
+        spec
+    }
+
+    /// `(old, new)` pairs of the values that change in the manifest.
+    pub fn replacements(&self, spec: &ServerSpec) -> Vec<(String, String)> {
+        let mut replacements = Vec::new();
+        if let Some(ref image) = self.image {
+            replacements.push((spec.image.clone(), image.clone()));
+        }
+        if let Some((ref pin, ref version)) = self.package {
+            replacements.push((spec.command[pin.index].clone(), pin.with_version(version)));
+        }
+        replacements
+    }
+}
+
+async fn check(spec: &ServerSpec, verbose: bool) -> Result<Updates> {
+    let mut updates = Updates::default();
+    match split_image(&spec.image) {
+        (_, Some(_)) => updates.image = image_update(&spec.image).await?,
+        _ if verbose => eprintln!("{}: image {} is not pinned", spec.name, spec.image),
+        _ => {}
+    }
+    match package_pin(&spec.command) {
+        Some(pin) => {
+            let versions = available_versions(&spec.image, &pin).await?;
+            if let Some(version) = newest_patch(&pin.version, &versions) {
+                updates.package = Some((pin, version));
+            }
+        }
+        None if verbose => eprintln!("{}: package version is not pinned", spec.name),
+        None => {}
+    }
+    Ok(updates)
+}
+
+fn print_updates(spec: &ServerSpec, updates: &Updates) {
+    if let Some(ref image) = updates.image {
+        println!("{}: image {} -> {}", spec.name, spec.image, image);
+    }
+    if let Some((ref pin, ref version)) = updates.package {
+        println!(
+            "{}: package {} {} -> {}",
+            spec.name, pin.name, pin.version, version
+        );
+    }
+}
+
+pub async fn run_outdated(args: OutdatedArgs) -> Result<()> {
+    let manifest = Manifest::from_file(&args.config)?;
+    let mut outdated = 0;
+    for spec in &manifest.servers {
+        match check(spec, args.verbose).await {
+            Ok(updates) => {
+                print_updates(spec, &updates);
+                if !updates.is_empty() {
+                    outdated += 1;
+                }
+            }
+            Err(e) => eprintln!("{}: {:#}", spec.name, e),
+        }
+    }
+    if outdated == 0 {
+        eprintln!("All pinned servers are up to date");
+    } else {
+        eprintln!(
+            "{} server(s) can be upgraded with `semcp upgrade <server>`",
+            outdated
+        );
+    }
+    Ok(())
+}
+
+fn is_initialize_response(line: &str) -> Result<bool> {
+    let Ok(message) = serde_json::from_str::<Value>(line) else {
+        return Ok(false);
+    };
+    if message.get("id") != Some(&Value::from(1)) {
+        return Ok(false);
+    }
+    if let Some(error) = message.get("error") {
+        bail!("initialize failed: {}", error);
+    }
+    Ok(message.get("result").is_some())
+}
+
+/// Starts the server under its policy and waits for it to answer
+/// `initialize`.
+async fn verify(spec: &ServerSpec, verbose: bool) -> Result<()> {
+    let policy = PolicyConfig::from_files(&spec.policy)
+        .with_context(|| format!("Failed to load policy for `{}`", spec.name))?;
+    policy.seccomp_profile()?;
+    let engine = engine::from_policy(&policy)?;
+    let executor =
+        ContainerExecutor::with_policy(spec.image.clone(), verbose, policy).with_engine(engine);
+    executor.ensure_named_volumes().await?;
+    let container = format!("semcp-upgrade-{}-{}", spec.name, std::process::id());
+
+    let mut command = executor.run_command(&[
+        "--rm".to_string(),
+        "-i".to_string(),
+        "--name".to_string(),
+        container.clone(),
+    ]);
+    command
+        .args(executor.container_options())
+        .arg(&spec.image)
+        .args(&spec.command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(if verbose {
+            Stdio::inherit()
+        } else {
+            Stdio::null()
+        })
+        .kill_on_drop(true);
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Failed to spawn {} command", executor.runtime()))?;
+
+    let initialize = format!(
+        "{{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"initialize\",\"params\":{{\
+         \"protocolVersion\":\"2025-06-18\",\"capabilities\":{{}},\
+         \"clientInfo\":{{\"name\":\"semcp\",\"version\":\"{}\"}}}}}}\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    let mut stdin = child.stdin.take().context("Server has no stdin")?;
+    let stdout = child.stdout.take().context("Server has no stdout")?;
+    let handshake = async {
+        stdin.write_all(initialize.as_bytes()).await?;
+        stdin.flush().await?;
+        let mut lines = BufReader::new(stdout).lines();
+        while let Some(line) = lines.next_line().await? {
+            if is_initialize_response(&line)? {
+                return Ok(());
+            }
+        }
+        bail!("Server exited before answering initialize")
+    };
+    let result = match tokio::time::timeout(VERIFY_TIMEOUT, handshake).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!(
+            "Server did not answer initialize within {}s",
+            VERIFY_TIMEOUT.as_secs()
+        )),
+    };
+    let _ = executor
+        .runtime()
+        .stop(&container, DEFAULT_GRACEFUL_SHUTDOWN)
+        .await;
+    result
+}
+
+/// Byte ranges of each server's entry, in manifest order. An entry runs
+/// from its `name:` to the next server's.
+fn server_spans(content: &str, names: &[&str]) -> Option<Vec<Range<usize>>> {
+    let mut starts = Vec::new();
+    let mut from = 0;
+    for name in names {
+        let start = loop {
+            let at = from + content[from..].find("name:")?;
+            from = at + "name:".len();
+            let standalone = content[..at]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !(c.is_ascii_alphanumeric() || c == '_'));
+            let value = content[from..]
+                .split(['\n', ',', '}', '#'])
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .trim_matches(['"', '\'']);
+            if standalone && value == *name {
+                break at;
+            }
+        };
+        starts.push(start);
+    }
+    let ends = starts.iter().skip(1).copied().chain([content.len()]);
+    Some(starts.iter().zip(ends).map(|(&s, e)| s..e).collect())
+}
+
+/// Rewrites the values of one server's entry, leaving the rest of the
+/// manifest (and its comments) untouched.
+pub fn repin(
+    content: &str,
+    names: &[&str],
+    server: &str,
+    replacements: &[(String, String)],
+) -> Result<String> {
+    let index = names
+        .iter()
+        .position(|name| *name == server)
+        .with_context(|| format!("No server named `{}`", server))?;
+    let spans =
+        server_spans(content, names).context("Cannot locate the servers in the manifest")?;
+    let span = spans[index].clone();
+    let mut entry = content[span.clone()].to_string();
+    for (old, new) in replacements {
+        if !entry.contains(old.as_str()) {
+            bail!(
+                "Cannot find `{}` in the entry of `{}`, update it by hand",
+                old,
+                server
+            );
+        }
+        entry = entry.replacen(old.as_str(), new, 1);
+    }
+    Ok(format!(
+        "{}{}{}",
+        &content[..span.start],
+        entry,
+        &content[span.end..]
+    ))
+}
+
+pub async fn run_upgrade(args: UpgradeArgs) -> Result<()> {
+    let manifest = Manifest::from_file(&args.config)?;
+    let Some(spec) = manifest.servers.iter().find(|s| s.name == args.server) else {
+        bail!("No server named `{}` in {}", args.server, args.config);
+    };
+    let updates = check(spec, args.verbose).await?;
+    if updates.is_empty() {
+        eprintln!("{} is up to date", spec.name);
+        return Ok(());
+    }
+    print_updates(spec, &updates);
+
+    let upgraded = updates.apply(spec);
+    eprintln!("Verifying {} with the new pins...", spec.name);
+    verify(&upgraded, args.verbose)
+        .await
+        .with_context(|| format!("Not upgrading {}", spec.name))?;
+    if args.dry_run {
+        eprintln!("{} answered initialize, not writing (dry run)", spec.name);
+        return Ok(());
+    }
+
+    let content = std::fs::read_to_string(&args.config)
+        .with_context(|| format!("Failed to read manifest {}", args.config))?;
+    let names: Vec<&str> = manifest.servers.iter().map(|s| s.name.as_str()).collect();
+    let updated = repin(&content, &names, &spec.name, &updates.replacements(spec))?;
+    std::fs::write(&args.config, updated)
+        .with_context(|| format!("Failed to write manifest {}", args.config))?;
+    eprintln!("Upgraded {} in {}", spec.name, args.config);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_package_pin() {
+        let pin = package_pin(&command(&[
+            "npx",
+            "-y",
+            "@modelcontextprotocol/server-memory@0.6.2",
+        ]))
+        .unwrap();
+        assert_eq!(pin.registry, Registry::Npm);
+        assert_eq!(pin.name, "@modelcontextprotocol/server-memory");
+        assert_eq!(pin.version, "0.6.2");
+        assert_eq!(pin.index, 2);
+        assert_eq!(
+            pin.with_version("0.6.3"),
+            "@modelcontextprotocol/server-memory@0.6.3"
+        );
+
+        let pin = package_pin(&command(&["uvx", "mcp-server-time==1.2.0"])).unwrap();
+        assert_eq!(pin.with_version("1.2.1"), "mcp-server-time==1.2.1");
+
+        assert!(package_pin(&command(&["npx", "-y", "@scope/unpinned"])).is_none());
+        assert!(package_pin(&command(&["npx", "pkg@latest"])).is_none());
+        assert!(package_pin(&command(&["node", "server.js"])).is_none());
+    }
+
+    #[test]
+    fn test_newest_patch() {
+        let available = command(&["1.2.0", "1.2.3", "1.2.10", "1.2.11-rc.1", "1.3.0", "2.0.0"]);
+        assert_eq!(newest_patch("1.2.3", &available).as_deref(), Some("1.2.10"));
+        assert_eq!(newest_patch("1.3.0", &available), None);
+    }
+
+    #[test]
+    fn test_split_image() {
+        assert_eq!(
+            split_image("node:24-alpine@sha256:abc"),
+            ("node:24-alpine", Some("sha256:abc"))
+        );
+        assert_eq!(split_image("node:24-alpine"), ("node:24-alpine", None));
+    }
+
+    #[test]
+    fn test_repin_only_touches_the_server() {
+        let content = "servers:\n\
+            - name: memory  # notes\n  image: node:24-alpine@sha256:old\n  command: [npx, -y, pkg@1.0.0]\n\
+            - {name: other, image: node:24-alpine@sha256:old, command: [npx, pkg@1.0.0]}\n";
+        let updated = repin(
+            content,
+            &["memory", "other"],
+            "memory",
+            &[
+                (
+                    "node:24-alpine@sha256:old".to_string(),
+                    "node:24-alpine@sha256:new".to_string(),
+                ),
+                ("pkg@1.0.0".to_string(), "pkg@1.0.1".to_string()),
+            ],
+        )
+        .unwrap();
+        assert!(updated.contains("- name: memory  # notes\n  image: node:24-alpine@sha256:new\n"));
+        assert!(updated.contains("[npx, -y, pkg@1.0.1]"));
+        assert!(updated.contains(
+            "{name: other, image: node:24-alpine@sha256:old, command: [npx, pkg@1.0.0]}"
+        ));
+
+        let missing = [("absent".to_string(), "x".to_string())];
+        assert!(repin(content, &["memory", "other"], "memory", &missing).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Warm containers for `semcp serve`.
+//!
+//! A cold `docker run` per connection costs container creation and start
+//! before the server even begins to load. The pool keeps containers that
+//! are already created, started and paused with a server's options; a
+//! connection unpauses one and execs the server command in it, and the pool
+//! is topped up in the background.
+
+use anyhow::{bail, Context, Result};
+use semcp_common::{runtime, ImageVariants};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub const WARM_LABEL: &str = "semcp.warm=true";
+
+/// Containers can only be shared between servers that run the same image
+/// with the same mounts, security options and environment.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Profile {
+    pub image: String,
+    pub docker_args: Vec<String>,
+    /// Values of mapped environment variables, which the runtime CLI copies
+    /// into the container.
+    pub env: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct Slot {
+    idle: Vec<String>,
+    /// Containers being created, counted so concurrent refills do not
+    /// overshoot the target.
+    pending: usize,
+}
+
+pub struct WarmPool {
+    sizes: BTreeMap<String, usize>,
+    slots: Mutex<HashMap<Profile, Slot>>,
+    next_id: AtomicU64,
+    verbose: bool,
+}
+
+pub(crate) async fn docker(args: &[String]) -> Result<()> {
+    docker_with_env(args, &[]).await
+}
+
+pub(crate) async fn docker_with_env(args: &[String], env: &[(String, String)]) -> Result<()> {
+    let runtime = runtime::current();
+    let output = runtime
+        .command()
+        .args(args)
+        .envs(env.iter().cloned())
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {}", runtime))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            runtime,
+            args.first().map(String::as_str).unwrap_or_default(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub async fn remove_container(name: &str) {
+    let _ = docker(&["rm".to_string(), "-f".to_string(), name.to_string()]).await;
+}
+
+/// Starts a container for `profile` that idles until commands are exec'd
+/// in it.
+pub async fn start_idle_container(
+    name: &str,
+    label: &str,
+    profile: &Profile,
+    verbose: bool,
+) -> Result<()> {
+    let mut args = vec![
+        "run".to_string(),
+        "-d".to_string(),
+        "--name".to_string(),
+        name.to_string(),
+        "--label".to_string(),
+        label.to_string(),
+    ];
+    args.extend(profile.docker_args.iter().cloned());
+    args.extend([
+        "--entrypoint".to_string(),
+        "sleep".to_string(),
+        profile.image.clone(),
+        "infinity".to_string(),
+    ]);
+    if verbose {
+        eprintln!(
+            "Starting idle container: {} {}",
+            runtime::current(),
+            args.join(" ")
+        );
+    }
+    docker_with_env(&args, &profile.env).await
+}
+
+impl WarmPool {
+    pub fn new(sizes: BTreeMap<String, usize>, verbose: bool) -> Self {
+        Self {
+            sizes,
+            slots: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            verbose,
+        }
+    }
+
+    /// Number of warm containers to keep for `profile`. Images without a
+    /// shell have no `sleep` to keep an idle container alive.
+    pub fn target(&self, profile: &Profile) -> usize {
+        if !ImageVariants::capabilities(&profile.image).has_shell {
+            return 0;
+        }
+        self.sizes.get(&profile.image).copied().unwrap_or(0)
+    }
+
+    fn next_name(&self) -> String {
+        format!(
+            "semcp-warm-{}-{}",
+            std::process::id(),
+            self.next_id.fetch_add(1, Ordering::Relaxed)
+        )
+    }
+
+    async fn create(&self, profile: &Profile) -> Result<String> {
+        let name = self.next_name();
+        start_idle_container(&name, WARM_LABEL, profile, self.verbose).await?;
+
+        if let Err(e) = docker(&["pause".to_string(), name.clone()]).await {
+            remove_container(&name).await;
+            return Err(e);
+        }
+        Ok(name)
+    }
+
+    /// Creates containers until `profile` has its target number idle.
+    pub async fn fill(&self, profile: &Profile) -> Result<()> {
+        let missing = {
+            let mut slots = self.slots.lock().unwrap();
+            let slot = slots.entry(profile.clone()).or_default();
+            let missing = self
+                .target(profile)
+                .saturating_sub(slot.idle.len() + slot.pending);
+            slot.pending += missing;
+            missing
+        };
+        for created_so_far in 0..missing {
+            let created = self.create(profile).await;
+            let mut slots = self.slots.lock().unwrap();
+            let slot = slots.entry(profile.clone()).or_default();
+            match created {
+                Ok(name) => {
+                    slot.pending -= 1;
+                    slot.idle.push(name);
+                }
+                Err(e) => {
+                    slot.pending -= missing - created_so_far;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Refills in the background after a container was handed out.
+    pub fn replenish(self: &Arc<Self>, profile: Profile) {
+        let pool = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = pool.fill(&profile).await {
+                eprintln!("Failed to warm a container for {}: {}", profile.image, e);
+            }
+        });
+    }
+
+    /// Takes an idle container and unpauses it, or returns `None` when the
+    /// caller has to start a cold one.
+    pub async fn acquire(&self, profile: &Profile) -> Option<String> {
+        let name = self.slots.lock().unwrap().get_mut(profile)?.idle.pop()?;
+        match docker(&["unpause".to_string(), name.clone()]).await {
+            Ok(()) => Some(name),
+            Err(e) => {
+                if self.verbose {
+                    eprintln!("Discarding warm container {}: {}", name, e);
+                }
+                remove_container(&name).await;
+                None
+            }
+        }
+    }
+
+    /// Removes every idle container.
+    pub async fn drain(&self) {
+        let names: Vec<String> = self
+            .slots
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, slot)| slot.idle)
+            .collect();
+        for name in names {
+            remove_container(&name).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(image: &str) -> Profile {
+        Profile {
+            image: image.to_string(),
+            docker_args: vec![],
+            env: vec![],
+        }
+    }
+
+    #[test]
+    fn test_target() {
+        let sizes = BTreeMap::from([
+            (ImageVariants::NODE_ALPINE.to_string(), 2),
+            (ImageVariants::NODE_DISTROLESS.to_string(), 2),
+        ]);
+        let pool = WarmPool::new(sizes, false);
+
+        assert_eq!(pool.target(&profile(ImageVariants::NODE_ALPINE)), 2);
+        assert_eq!(pool.target(&profile(ImageVariants::NODE_SLIM)), 0);
+        assert_eq!(pool.target(&profile(ImageVariants::NODE_DISTROLESS)), 0);
+    }
+
+    #[test]
+    fn test_names_are_unique() {
+        let pool = WarmPool::new(BTreeMap::new(), false);
+        assert_ne!(pool.next_name(), pool.next_name());
+    }
+}
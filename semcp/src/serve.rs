@@ -0,0 +1,797 @@
+use crate::activation::OnDemand;
+use crate::groups;
+use crate::health::{Prober, Tick};
+use crate::introspect::{self, Activity, CallRecorder, PolicyHash, ServerStatus};
+use crate::links::Links;
+use crate::manifest::{Activation, HealthCheck, Manifest, ServerSpec};
+use crate::multiplex::Shared;
+use crate::pool::{remove_container, Profile, WarmPool};
+use crate::tenancy::{user_label, Quotas, Tenancy};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use semcp_common::crashloop::{Crash, CrashTracker, CrashVerdict, StderrTail};
+use semcp_common::{audit, engine, runtime, ContainerExecutor, PolicyConfig};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio::process::{Child, Command as AsyncCommand};
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers to serve"
+    )]
+    pub config: String,
+
+    #[arg(long, help = "Use verbose output")]
+    pub verbose: bool,
+}
+
+/// How long a crash-looping server refuses connections before it is tried
+/// again.
+const CRASH_LOOP_COOLDOWN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+enum Hold {
+    /// Delay the next start until then.
+    Backoff(Instant),
+    /// Refuse connections until then.
+    CrashLoop(Instant),
+}
+
+struct Server {
+    spec: ServerSpec,
+    profile: Profile,
+    on_demand: Option<Arc<OnDemand>>,
+    /// On-demand containers per user, with tenancy.
+    tenants: Mutex<HashMap<u32, Arc<OnDemand>>>,
+    /// Shared server processes, one per user with tenancy.
+    shared: tokio::sync::Mutex<HashMap<Option<u32>, Arc<Shared>>>,
+    crashes: Mutex<CrashTracker>,
+    hold: Mutex<Option<Hold>>,
+    /// The policy files as read at startup, for the introspection API.
+    policy_hashes: Vec<PolicyHash>,
+    /// Labels of the server's containers, for the introspection API.
+    labels: BTreeMap<String, String>,
+    sessions: AtomicUsize,
+    activity: Arc<Activity>,
+    verbose: bool,
+}
+
+impl Server {
+    /// Loads the server's policy and options. With `links` its containers
+    /// join the network of `depends_on`.
+    async fn load(
+        spec: ServerSpec,
+        cgroup_parent: Option<&String>,
+        links: Option<&Links>,
+        activity: Arc<Activity>,
+        verbose: bool,
+    ) -> Result<Self> {
+        let policy = PolicyConfig::from_files(&spec.policy)
+            .with_context(|| format!("Failed to load policy for `{}`", spec.name))?;
+        let policy_hashes = policy.files().iter().map(PolicyHash::from).collect();
+        let crashes = CrashTracker::new(&spec.name, policy.runtime.max_restart_attempts());
+        let engine = engine::from_policy(&policy)?;
+        let env = policy.mapped_env()?;
+        policy.seccomp_profile()?;
+        let mut labels = policy.runtime.docker.labels.clone();
+        labels.extend(spec.labels.clone());
+        let executor = ContainerExecutor::with_policy(spec.image.clone(), verbose, policy)
+            .with_engine(engine)
+            .with_labels(spec.labels.clone());
+        executor.check_remote_mounts()?;
+        executor.ensure_named_volumes().await?;
+        let mut docker_args = executor.init_args();
+        docker_args.extend(executor.container_options());
+        for key in &spec.env {
+            docker_args.push("-e".to_string());
+            docker_args.push(key.clone());
+        }
+        if let Some(parent) = cgroup_parent {
+            let adapted = executor
+                .runtime()
+                .adapt_options(vec!["--cgroup-parent".to_string(), parent.clone()]);
+            for note in adapted.downgraded {
+                eprintln!("{}: {}", spec.name, note);
+            }
+            docker_args.extend(adapted.options);
+        }
+        if let Some(links) = links {
+            let join_args = links.join_args(&spec.name, &docker_args)?;
+            docker_args.extend(join_args);
+        }
+        let profile = Profile {
+            image: spec.image.clone(),
+            docker_args,
+            env,
+        };
+        let on_demand = match spec.activation {
+            Activation::OnDemand => Some(Arc::new(OnDemand::new(
+                &spec.name,
+                profile.clone(),
+                spec.idle_timeout(),
+                verbose,
+            ))),
+            Activation::PerConnection | Activation::Shared => None,
+        };
+        Ok(Self {
+            spec,
+            profile,
+            on_demand,
+            tenants: Mutex::new(HashMap::new()),
+            shared: tokio::sync::Mutex::new(HashMap::new()),
+            crashes: Mutex::new(crashes),
+            hold: Mutex::new(None),
+            policy_hashes,
+            labels,
+            sessions: AtomicUsize::new(0),
+            activity,
+            verbose,
+        })
+    }
+
+    fn status(&self, tenancy: bool) -> ServerStatus {
+        let now = Instant::now();
+        let state = match *self.hold.lock().unwrap() {
+            Some(Hold::Backoff(until)) if now < until => "backing-off",
+            Some(Hold::CrashLoop(until)) if now < until => "crash-looping",
+            _ if self.sessions.load(Ordering::Relaxed) > 0 => "active",
+            _ => "idle",
+        };
+        ServerStatus {
+            name: self.spec.name.clone(),
+            image: self.spec.image.clone(),
+            activation: self.spec.activation,
+            port: (!tenancy).then_some(self.spec.port),
+            state,
+            sessions: self.sessions.load(Ordering::Relaxed),
+            policies: self.policy_hashes.clone(),
+            depends_on: self.spec.depends_on.clone(),
+            labels: self.labels.clone(),
+        }
+    }
+
+    fn recorder(&self, user: Option<u32>) -> CallRecorder<'_> {
+        CallRecorder {
+            activity: &self.activity,
+            server: &self.spec.name,
+            user,
+        }
+    }
+
+    /// The server's options, labelled for `uid` when it is known.
+    fn profile_for(&self, user: Option<u32>) -> Profile {
+        let mut profile = self.profile.clone();
+        if let Some(uid) = user {
+            profile
+                .docker_args
+                .extend(["--label".to_string(), user_label(uid)]);
+        }
+        profile
+    }
+
+    /// The on-demand container that sessions of `user` share. Every user
+    /// gets one of their own.
+    fn on_demand_for(&self, user: Option<u32>) -> Option<Arc<OnDemand>> {
+        let shared = self.on_demand.as_ref()?;
+        let Some(uid) = user else {
+            return Some(Arc::clone(shared));
+        };
+        let mut tenants = self.tenants.lock().unwrap();
+        let on_demand = tenants.entry(uid).or_insert_with(|| {
+            Arc::new(OnDemand::new(
+                &format!("{}-u{}", self.spec.name, uid),
+                self.profile_for(user),
+                self.spec.idle_timeout(),
+                self.verbose,
+            ))
+        });
+        Some(Arc::clone(on_demand))
+    }
+
+    /// Stops the on-demand containers of every user.
+    async fn stop(&self) {
+        let tenants: Vec<Arc<OnDemand>> = self.tenants.lock().unwrap().values().cloned().collect();
+        for on_demand in self.on_demand.iter().chain(&tenants) {
+            on_demand.stop().await;
+        }
+        for shared in self.shared.lock().await.values() {
+            remove_container(&shared.container).await;
+        }
+    }
+
+    /// The server process that sessions of `user` share, started if none
+    /// is running. Its exit is recorded like that of a per-connection
+    /// server.
+    async fn shared_for(
+        self: &Arc<Self>,
+        pool: &Arc<WarmPool>,
+        user: Option<u32>,
+    ) -> Result<Arc<Shared>> {
+        let mut running = self.shared.lock().await;
+        if let Some(shared) = running.get(&user).filter(|shared| !shared.is_closed()) {
+            return Ok(Arc::clone(shared));
+        }
+        let (mut child, container) = self.start(pool, user).await?;
+        let tail = StderrTail::default();
+        let stderr = child
+            .stderr
+            .take()
+            .context("Failed to open server stderr")?;
+        let capture = tail.capture(stderr);
+        let (shared, reader) = Shared::start(&self.spec.name, container.clone(), &mut child)?;
+        running.insert(user, Arc::clone(&shared));
+
+        let server = Arc::clone(self);
+        tokio::spawn(async move {
+            let _ = reader.await;
+            let status = child.wait().await;
+            let _ = capture.await;
+            match status {
+                Ok(status) if !status.success() => server.record_crash(Crash {
+                    exit_code: status.code(),
+                    stderr: tail.lines(),
+                }),
+                Ok(_) => server.record_success(),
+                Err(_) => {}
+            }
+            remove_container(&container).await;
+        });
+        Ok(shared)
+    }
+
+    fn container_name(&self) -> String {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        format!("semcp-{}-{}", self.spec.name, timestamp)
+    }
+
+    fn spawn(&self, mut command: AsyncCommand) -> Result<Child> {
+        command
+            .args(&self.spec.command)
+            .envs(self.profile.env.iter().cloned())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to spawn {} command", runtime::current()))
+    }
+
+    fn exec(&self, container: &str) -> Result<Child> {
+        let mut command = runtime::current().command();
+        command.args(["exec", "-i", container]);
+        self.spawn(command)
+    }
+
+    /// Starts the server command, in a warm container when one is ready.
+    /// Warm containers are created before the user is known and cannot be
+    /// labelled, so sessions of a known `user` always start a fresh one.
+    async fn start(&self, pool: &Arc<WarmPool>, user: Option<u32>) -> Result<(Child, String)> {
+        if user.is_none() {
+            if let Some(container) = pool.acquire(&self.profile).await {
+                pool.replenish(self.profile.clone());
+                return Ok((self.exec(&container)?, container));
+            }
+        }
+
+        let container = self.container_name();
+        let mut command = runtime::current().run(&[
+            "--rm".to_string(),
+            "-i".to_string(),
+            "--name".to_string(),
+            container.clone(),
+        ]);
+        command.args(&self.profile_for(user).docker_args);
+        command.arg(&self.profile.image);
+        let child = self.spawn(command)?;
+        let mut fields = json!({
+            "container": container,
+            "image": self.profile.image,
+            "server": self.spec.name,
+            "policies": self.spec.policy,
+        });
+        if let Some(uid) = user {
+            fields["user"] = json!(uid);
+        }
+        audit::record_run(runtime::current().as_ref(), fields, false).await;
+        Ok((child, container))
+    }
+}
+
+impl Server {
+    /// Waits out the restart backoff after a crash, or fails while the
+    /// server is crash-looping.
+    async fn wait_for_restart(&self) -> Result<()> {
+        let hold = *self.hold.lock().unwrap();
+        match hold {
+            Some(Hold::Backoff(until)) => {
+                tokio::time::sleep(until.saturating_duration_since(Instant::now())).await;
+            }
+            Some(Hold::CrashLoop(until)) if Instant::now() < until => bail!(
+                "{} is crash-looping, not starting it for another {}s",
+                self.spec.name,
+                until.saturating_duration_since(Instant::now()).as_secs()
+            ),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn record_crash(&self, crash: Crash) {
+        let mut tracker = self.crashes.lock().unwrap();
+        let exit_code = crash.exit_code;
+        let hold = match tracker.record_crash(crash) {
+            CrashVerdict::Restart { attempt, backoff } => {
+                eprintln!(
+                    "{}: server exited with {}, restart {} of {} in {}s",
+                    self.spec.name,
+                    exit_code.map_or("a signal".to_string(), |code| format!("code {}", code)),
+                    attempt,
+                    tracker.max_restart_attempts(),
+                    backoff.as_secs()
+                );
+                self.activity.record_alert(
+                    &self.spec.name,
+                    "crash",
+                    format!(
+                        "exited with {}, restart {} of {}",
+                        exit_code.map_or("a signal".to_string(), |code| format!("code {}", code)),
+                        attempt,
+                        tracker.max_restart_attempts()
+                    ),
+                );
+                Hold::Backoff(Instant::now() + backoff)
+            }
+            CrashVerdict::CrashLoop => {
+                let report = tracker.report();
+                if let Some(ref report) = report {
+                    eprintln!("{}", report);
+                }
+                self.activity.record_alert(
+                    &self.spec.name,
+                    "crash-loop",
+                    report.map_or_else(|| "crash-looping".to_string(), |report| report.to_string()),
+                );
+                tracker.record_success();
+                Hold::CrashLoop(Instant::now() + CRASH_LOOP_COOLDOWN)
+            }
+        };
+        *self.hold.lock().unwrap() = Some(hold);
+    }
+
+    fn record_success(&self) {
+        self.crashes.lock().unwrap().record_success();
+        *self.hold.lock().unwrap() = None;
+    }
+
+    /// Relays one client session and records whether the server crashed.
+    async fn session<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: S,
+        mut child: Child,
+        user: Option<u32>,
+    ) -> Result<()> {
+        let tail = StderrTail::default();
+        let stderr = child
+            .stderr
+            .take()
+            .context("Failed to open server stderr")?;
+        let capture = tail.capture(stderr);
+
+        let bridged = bridge(
+            stream,
+            &mut child,
+            self.spec.health_check.as_ref(),
+            self.recorder(user),
+        )
+        .await;
+        if matches!(bridged, Ok(BridgeEnd::Hung)) {
+            let message = format!(
+                "no answer to a health probe within {}s, stopping the server",
+                self.spec
+                    .health_check
+                    .as_ref()
+                    .map_or(0, |check| check.timeout().as_secs())
+            );
+            eprintln!("{}: {}", self.spec.name, message);
+            self.activity.record_alert(&self.spec.name, "hung", message);
+        }
+        if !matches!(bridged, Ok(BridgeEnd::Server)) {
+            let _ = child.kill().await;
+        }
+        let status = child
+            .wait()
+            .await
+            .with_context(|| format!("Failed to wait for {} command", runtime::current()))?;
+        let _ = capture.await;
+
+        match bridged {
+            Ok(BridgeEnd::Server) if !status.success() => self.record_crash(Crash {
+                exit_code: status.code(),
+                stderr: tail.lines(),
+            }),
+            Ok(BridgeEnd::Hung) => self.record_crash(Crash {
+                exit_code: None,
+                stderr: tail.lines(),
+            }),
+            Ok(_) => self.record_success(),
+            Err(_) => {}
+        }
+        bridged.map(|_| ())
+    }
+}
+
+enum BridgeEnd {
+    Client,
+    Server,
+    /// The server stopped answering health probes.
+    Hung,
+}
+
+pub(crate) async fn write_line<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    line: &str,
+) -> std::io::Result<()> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await
+}
+
+/// Relays lines between the connection and the server until either side
+/// closes, probing the server's health when `health_check` is set.
+async fn bridge<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    child: &mut Child,
+    health_check: Option<&HealthCheck>,
+    recorder: CallRecorder<'_>,
+) -> Result<BridgeEnd> {
+    let mut stdin = child.stdin.take().context("Failed to open server stdin")?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("Failed to open server stdout")?;
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut client = BufReader::new(reader).lines();
+    let mut server = BufReader::new(stdout).lines();
+    let mut prober = health_check.map(|check| Prober::new(check, Instant::now()));
+
+    loop {
+        let deadline = prober
+            .as_ref()
+            .map(|prober| prober.deadline())
+            .unwrap_or_else(Instant::now);
+        tokio::select! {
+            line = client.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(BridgeEnd::Client);
+                };
+                if let Some(ref mut prober) = prober {
+                    prober.on_client_line(&line, Instant::now());
+                }
+                recorder.on_client_line(&line);
+                write_line(&mut stdin, &line).await?;
+            }
+            line = server.next_line() => {
+                let Some(line) = line? else {
+                    return Ok(BridgeEnd::Server);
+                };
+                if prober.as_mut().is_some_and(|prober| prober.on_server_line(&line, Instant::now())) {
+                    continue;
+                }
+                write_line(&mut writer, &line).await?;
+            }
+            _ = tokio::time::sleep(deadline.saturating_duration_since(Instant::now())), if prober.is_some() => {
+                let Some(ref mut prober) = prober else {
+                    continue;
+                };
+                match prober.on_tick(Instant::now()) {
+                    Tick::Wait => {}
+                    Tick::Probe(probe) => write_line(&mut stdin, &probe).await?,
+                    Tick::Hung => return Ok(BridgeEnd::Hung),
+                }
+            }
+        }
+    }
+}
+
+/// Counts a session of a server while it is open.
+struct OpenSession<'a>(&'a AtomicUsize);
+
+impl<'a> OpenSession<'a> {
+    fn new(sessions: &'a AtomicUsize) -> Self {
+        sessions.fetch_add(1, Ordering::Relaxed);
+        Self(sessions)
+    }
+}
+
+impl Drop for OpenSession<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Serves one connection, from `user` when it is known.
+async fn handle<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    server: Arc<Server>,
+    pool: Arc<WarmPool>,
+    user: Option<u32>,
+) -> Result<()> {
+    server.wait_for_restart().await?;
+    let _open = OpenSession::new(&server.sessions);
+
+    if server.spec.activation == Activation::Shared {
+        let shared = server.shared_for(&pool, user).await?;
+        return shared.session(stream, server.recorder(user)).await;
+    }
+
+    if let Some(on_demand) = server.on_demand_for(user) {
+        let container = on_demand.acquire().await?;
+        let result = match server.exec(&container) {
+            Ok(child) => server.session(stream, child, user).await,
+            Err(e) => Err(e),
+        };
+        on_demand.release().await;
+        return result;
+    }
+
+    let (child, container) = server.start(&pool, user).await?;
+    let result = server.session(stream, child, user).await;
+    remove_container(&container).await;
+    result
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    server: Arc<Server>,
+    pool: Arc<WarmPool>,
+    verbose: bool,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!(
+                    "Failed to accept connection for {}: {}",
+                    server.spec.name, e
+                );
+                continue;
+            }
+        };
+        if verbose {
+            eprintln!("{}: connection from {}", server.spec.name, peer);
+        }
+        let server = Arc::clone(&server);
+        let pool = Arc::clone(&pool);
+        tokio::spawn(async move {
+            let name = server.spec.name.clone();
+            if let Err(e) = handle(stream, server, pool, None).await {
+                eprintln!("{}: {}", name, e);
+            }
+        });
+    }
+}
+
+/// Listens on the server's socket under the tenancy's `socket_dir`. Who may
+/// connect is up to the directory's permissions, so the socket itself is
+/// open to everyone.
+#[cfg(unix)]
+fn serve_unix(
+    tenancy: &Tenancy,
+    server: Arc<Server>,
+    pool: Arc<WarmPool>,
+    quotas: Arc<Quotas>,
+    verbose: bool,
+) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = tenancy.socket_path(&server.spec.name);
+    std::fs::create_dir_all(&tenancy.socket_dir)
+        .with_context(|| format!("Failed to create {}", tenancy.socket_dir))?;
+    // a socket left behind by an earlier run
+    let _ = std::fs::remove_file(&path);
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o666))
+        .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+    eprintln!("Serving {} on {}", server.spec.name, path.display());
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to accept connection for {}: {}",
+                        server.spec.name, e
+                    );
+                    continue;
+                }
+            };
+            let uid = match stream.peer_cred() {
+                Ok(credentials) => credentials.uid(),
+                Err(e) => {
+                    eprintln!(
+                        "{}: refusing a connection from an unknown user: {}",
+                        server.spec.name, e
+                    );
+                    continue;
+                }
+            };
+            let admission = match quotas.admit(uid) {
+                Ok(admission) => admission,
+                Err(e) => {
+                    eprintln!("{}: refusing connection: {}", server.spec.name, e);
+                    continue;
+                }
+            };
+            if verbose {
+                eprintln!(
+                    "{}: connection from user {}, {} sessions open",
+                    server.spec.name,
+                    uid,
+                    quotas.open_sessions(uid)
+                );
+            }
+            let server = Arc::clone(&server);
+            let pool = Arc::clone(&pool);
+            tokio::spawn(async move {
+                let _admission = admission;
+                let name = server.spec.name.clone();
+                if let Err(e) = handle(stream, server, pool, Some(uid)).await {
+                    eprintln!("{}: user {}: {}", name, uid, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_unix(
+    _tenancy: &Tenancy,
+    _server: Arc<Server>,
+    _pool: Arc<WarmPool>,
+    _quotas: Arc<Quotas>,
+    _verbose: bool,
+) -> Result<()> {
+    bail!("tenancy needs Unix sockets, which this platform does not have")
+}
+
+/// Loads the servers in dependency order, starts the ones depended on and
+/// listens for the others.
+async fn start_servers(
+    manifest: &Manifest,
+    pool: &Arc<WarmPool>,
+    quotas: &Arc<Quotas>,
+    cgroup_parents: &BTreeMap<String, String>,
+    links: Option<&Links>,
+    activity: &Arc<Activity>,
+    verbose: bool,
+) -> Result<Vec<Arc<Server>>> {
+    let dependencies = manifest.dependencies();
+    let mut servers = Vec::new();
+
+    for spec in manifest.start_order()? {
+        let cgroup_parent = spec
+            .resource_group
+            .as_ref()
+            .and_then(|group| cgroup_parents.get(group));
+        let depended_on = dependencies.contains(spec.name.as_str());
+        let linked = links.filter(|_| depended_on || !spec.depends_on.is_empty());
+        let server = Arc::new(
+            Server::load(
+                spec.clone(),
+                cgroup_parent,
+                linked,
+                Arc::clone(activity),
+                verbose,
+            )
+            .await?,
+        );
+        if let (true, Some(links)) = (depended_on, links) {
+            links
+                .start(&spec.name, &server.profile, &spec.command, &spec.policy)
+                .await?;
+            continue;
+        }
+        servers.push(Arc::clone(&server));
+        if let Some(ref tenancy) = manifest.tenancy {
+            serve_unix(
+                tenancy,
+                server,
+                Arc::clone(pool),
+                Arc::clone(quotas),
+                verbose,
+            )?;
+            continue;
+        }
+        let address = format!("{}:{}", manifest.listen(), server.spec.port);
+        let listener = TcpListener::bind(&address)
+            .await
+            .with_context(|| format!("Failed to listen on {}", address))?;
+        eprintln!("Serving {} on {}", server.spec.name, address);
+
+        if server.on_demand.is_none() {
+            pool.replenish(server.profile.clone());
+        }
+        tokio::spawn(accept_loop(listener, server, Arc::clone(pool), verbose));
+    }
+    Ok(servers)
+}
+
+pub async fn run(args: ServeArgs) -> Result<()> {
+    let manifest = Manifest::from_file(&args.config)?;
+    let pool = Arc::new(WarmPool::new(manifest.warm_pool.clone(), args.verbose));
+    let cgroup_parents = groups::apply(&manifest.resource_groups, args.verbose).await?;
+    let quotas = Arc::new(Quotas::new(
+        manifest
+            .tenancy
+            .as_ref()
+            .and_then(|tenancy| tenancy.max_sessions_per_user),
+    ));
+    let links = if manifest.dependencies().is_empty() {
+        None
+    } else {
+        Some(Links::create(&manifest.labels, args.verbose).await?)
+    };
+    let activity = Arc::new(Activity::default());
+    let started = start_servers(
+        &manifest,
+        &pool,
+        &quotas,
+        &cgroup_parents,
+        links.as_ref(),
+        &activity,
+        args.verbose,
+    )
+    .await;
+    let started = match (started, manifest.introspection.as_ref()) {
+        (Ok(servers), Some(config)) => {
+            let listed = servers.clone();
+            let tenancy = manifest.tenancy.is_some();
+            introspect::serve(config, Arc::clone(&activity), move || {
+                listed.iter().map(|server| server.status(tenancy)).collect()
+            })
+            .map(|_| servers)
+        }
+        (started, _) => started,
+    };
+    let servers = match started {
+        Ok(servers) => servers,
+        Err(e) => {
+            pool.drain().await;
+            if let Some(ref links) = links {
+                links.remove().await;
+            }
+            return Err(e);
+        }
+    };
+
+    tokio::signal::ctrl_c().await?;
+    eprintln!("Shutting down, removing containers...");
+    pool.drain().await;
+    for server in servers {
+        server.stop().await;
+        if let Some(ref tenancy) = manifest.tenancy {
+            let _ = std::fs::remove_file(tenancy.socket_path(&server.spec.name));
+        }
+    }
+    if let Some(links) = links {
+        links.remove().await;
+    }
+    if let Some(ref config) = manifest.introspection {
+        let _ = std::fs::remove_file(config.socket_path()?);
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! `semcp service`: running `semcp serve` as a Windows service.
+//!
+//! A service has to answer the service control manager, which `semcp`
+//! itself does not, so the service is registered with NSSM, the
+//! Non-Sucking Service Manager, wrapping `semcp serve --config <manifest>`.
+//! NSSM is told to exit with the gateway instead of restarting it, and the
+//! restart policy becomes the service's recovery actions: up to
+//! `--max-restart-attempts` restarts with the doubling backoff of a crashed
+//! server, after which the service stays down. The failure count starts over
+//! after an hour without a crash.
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Subcommand};
+use semcp_common::crashloop;
+use std::path::Path;
+use std::process::Command;
+
+/// Seconds without a failure after which the failure count is reset.
+const FAILURE_RESET_SECS: u64 = 60 * 60;
+
+#[derive(Subcommand, Debug)]
+pub enum ServiceCommand {
+    #[command(about = "Register `semcp serve` for a manifest as a Windows service")]
+    Install(InstallArgs),
+
+    #[command(about = "Stop and remove a service registered with `semcp service install`")]
+    Uninstall {
+        #[arg(long = "name", default_value = "semcp", help = "Name of the service")]
+        name: String,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct InstallArgs {
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers to serve"
+    )]
+    pub config: String,
+
+    #[arg(long = "name", default_value = "semcp", help = "Name of the service")]
+    pub name: String,
+
+    #[arg(
+        long = "max-restart-attempts",
+        default_value_t = crashloop::DEFAULT_MAX_RESTART_ATTEMPTS,
+        help = "Restarts after the gateway fails before the service stays down"
+    )]
+    pub max_restart_attempts: u32,
+
+    #[arg(
+        long = "log",
+        help = "File for the output of the gateway, relative to the manifest (default: <name>.log)"
+    )]
+    pub log: Option<String>,
+}
+
+/// The `actions=` of `sc.exe failure`: a restart per attempt, then nothing.
+pub fn recovery_actions(max_restart_attempts: u32) -> String {
+    let mut actions: Vec<String> = (1..=max_restart_attempts)
+        .map(|attempt| format!("restart/{}", crashloop::backoff(attempt).as_millis()))
+        .collect();
+    // the last action repeats for later failures, so end with an empty one
+    actions.push("/0".to_string());
+    actions.join("/")
+}
+
+fn step(program: &str, args: &[&str]) -> Result<()> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to execute {}", program))?;
+    if !output.status.success() {
+        // nssm writes UTF-16 on some versions, show what can be read
+        let message = String::from_utf8_lossy(&output.stderr).replace('\0', "");
+        let stdout = String::from_utf8_lossy(&output.stdout).replace('\0', "");
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.first().copied().unwrap_or_default(),
+            if message.trim().is_empty() {
+                stdout.trim().to_string()
+            } else {
+                message.trim().to_string()
+            }
+        );
+    }
+    Ok(())
+}
+
+fn require_windows() -> Result<()> {
+    if !cfg!(windows) {
+        bail!("semcp service registers Windows services, use a systemd unit or launchd job to run `semcp serve` elsewhere");
+    }
+    // without arguments nssm prints its usage, the point is that it starts
+    Command::new("nssm")
+        .output()
+        .context("nssm is required to run semcp as a service, see https://nssm.cc")?;
+    Ok(())
+}
+
+/// `global` are the options of `semcp` itself, such as `--runtime`, for the
+/// service to run with.
+pub fn run(command: ServiceCommand, global: &[String]) -> Result<()> {
+    require_windows()?;
+    match command {
+        ServiceCommand::Install(args) => install(args, global),
+        ServiceCommand::Uninstall { name } => {
+            // stopping a stopped service fails, removing it is what matters
+            let _ = step("nssm", &["stop", &name]);
+            step("nssm", &["remove", &name, "confirm"])?;
+            eprintln!("Removed service {}", name);
+            Ok(())
+        }
+    }
+}
+
+fn install(args: InstallArgs, global: &[String]) -> Result<()> {
+    let config = Path::new(&args.config)
+        .canonicalize()
+        .with_context(|| format!("Failed to find manifest {}", args.config))?;
+    crate::manifest::Manifest::from_file(&config.to_string_lossy())?;
+    let directory = config.parent().unwrap_or_else(|| Path::new("."));
+    let log = args.log.map_or_else(
+        || directory.join(format!("{}.log", args.name)),
+        |log| directory.join(log),
+    );
+    let exe = std::env::current_exe().context("Failed to locate the semcp executable")?;
+
+    let config = config.to_string_lossy();
+    let mut command: Vec<&str> = vec!["install", &args.name];
+    let exe = exe.to_string_lossy();
+    command.push(&exe);
+    command.extend(global.iter().map(String::as_str));
+    command.extend(["serve", "--config", &config]);
+    step("nssm", &command)?;
+
+    let directory = directory.to_string_lossy();
+    let log = log.to_string_lossy();
+    for setting in [
+        ["AppDirectory", &directory],
+        ["AppStdout", &log],
+        ["AppStderr", &log],
+        ["Start", "SERVICE_AUTO_START"],
+        ["Description", "MCP servers of a semcp manifest"],
+    ] {
+        step("nssm", &["set", &args.name, setting[0], setting[1]])?;
+    }
+    // the recovery actions decide about restarts, not nssm
+    step("nssm", &["set", &args.name, "AppExit", "Default", "Exit"])?;
+    step(
+        "sc.exe",
+        &[
+            "failure",
+            &args.name,
+            "reset=",
+            &FAILURE_RESET_SECS.to_string(),
+            "actions=",
+            &recovery_actions(args.max_restart_attempts),
+        ],
+    )?;
+    // a gateway exiting with an error counts as a failure, not only a crash
+    step("sc.exe", &["failureflag", &args.name, "1"])?;
+
+    eprintln!(
+        "Installed service {} for {}, logging to {}. Start it with `sc.exe start {}`",
+        args.name, config, log, args.name
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recovery_actions_follow_the_backoff() {
+        assert_eq!(
+            recovery_actions(3),
+            "restart/1000/restart/2000/restart/4000//0"
+        );
+        assert_eq!(recovery_actions(0), "/0");
+    }
+}
@@ -0,0 +1,294 @@
+//! `semcp snapshot` and `semcp restore`: backups of a package's state
+//! volume.
+//!
+//! A snapshot is a gzipped tar of the volume, streamed out of a throwaway
+//! container that mounts it read-only, so it works with remote daemons too.
+//! Containers using the volume are paused while it is read, so a server is
+//! not caught halfway through writing its files. Restoring replaces the
+//! volume's contents once the archive has been extracted, and is refused
+//! while a container uses it.
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use semcp_common::runtime;
+use semcp_common::state::volume_exists;
+use semcp_common::StateVolume;
+use std::process::Stdio;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Image of the container that reads and writes the volume.
+const HELPER_IMAGE: &str = "busybox:1.36";
+/// Where the helper mounts the volume.
+const MOUNT: &str = "/data";
+/// Extracts the archive next to the old contents and only then swaps them,
+/// so a broken archive leaves the volume as it was.
+const EXTRACT_SCRIPT: &str =
+    "set -e; staging=/data/.semcp-restore; rm -rf $staging; mkdir $staging; \
+     tar xzf - -C $staging || { rm -rf $staging; exit 1; }; \
+     find /data -mindepth 1 -maxdepth 1 ! -name .semcp-restore -exec rm -rf {} +; \
+     find $staging -mindepth 1 -maxdepth 1 -exec mv {} /data/ \\; ; rmdir $staging";
+
+#[derive(Args)]
+pub struct SnapshotArgs {
+    #[arg(help = "Package whose state volume to archive")]
+    pub package: String,
+
+    #[arg(
+        long = "out",
+        short = 'o',
+        help = "Archive to write (default: <volume>-<unix time>.tar.gz)"
+    )]
+    pub out: Option<String>,
+
+    #[arg(long, help = "Use verbose output")]
+    pub verbose: bool,
+}
+
+#[derive(Args)]
+pub struct RestoreArgs {
+    #[arg(help = "Package whose state volume to restore")]
+    pub package: String,
+
+    #[arg(help = "Archive written by semcp snapshot")]
+    pub archive: String,
+
+    #[arg(long, help = "Use verbose output")]
+    pub verbose: bool,
+}
+
+fn default_archive(volume: &StateVolume, unix_time: u64) -> String {
+    format!("{}-{}.tar.gz", volume.name, unix_time)
+}
+
+fn archive_args(volume: &StateVolume) -> Vec<String> {
+    [
+        "run",
+        "--rm",
+        "--network",
+        "none",
+        "-v",
+        &format!("{}:{}:ro", volume.name, MOUNT),
+        HELPER_IMAGE,
+        "tar",
+        "czf",
+        "-",
+        "-C",
+        MOUNT,
+        ".",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn extract_args(volume: &StateVolume) -> Vec<String> {
+    [
+        "run",
+        "--rm",
+        "-i",
+        "--network",
+        "none",
+        "-v",
+        &format!("{}:{}", volume.name, MOUNT),
+        HELPER_IMAGE,
+        "sh",
+        "-c",
+        EXTRACT_SCRIPT,
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// Runs the runtime with `args` and returns its output.
+async fn runtime_output(args: &[String], verbose: bool) -> Result<String> {
+    let runtime = runtime::current();
+    if verbose {
+        eprintln!("Running: {} {}", runtime, args.join(" "));
+    }
+    let output = runtime
+        .command()
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} {}", runtime, args[0]))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            runtime,
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Running containers that mount the volume.
+async fn users_of(volume: &StateVolume, verbose: bool) -> Result<Vec<String>> {
+    let args = ["ps", "-q", "--filter", &format!("volume={}", volume.name)].map(String::from);
+    let output = runtime_output(&args, verbose).await?;
+    Ok(output.lines().map(str::to_string).collect())
+}
+
+/// Streams the runtime's output for `args` into `path`.
+async fn run_to_file(args: &[String], path: &str, verbose: bool) -> Result<()> {
+    let runtime = runtime::current();
+    if verbose {
+        eprintln!("Running: {} {} > {}", runtime, args.join(" "), path);
+    }
+    let file = std::fs::File::create(path).with_context(|| format!("Failed to create {}", path))?;
+    let output = runtime
+        .command()
+        .args(args)
+        .stdout(Stdio::from(file))
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} run", runtime))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to archive the volume: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub async fn snapshot(args: SnapshotArgs) -> Result<()> {
+    let runtime = runtime::current();
+    let volume = StateVolume::for_package(&args.package)
+        .resolve(runtime.as_ref())
+        .await?;
+    if !volume_exists(runtime.as_ref(), &volume.name).await? {
+        bail!(
+            "No state volume for {} ({}), see `semcp state ls`",
+            volume.package,
+            volume.name
+        );
+    }
+    let unix_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    let out = args
+        .out
+        .unwrap_or_else(|| default_archive(&volume, unix_time));
+    let partial = format!("{}.partial", out);
+
+    let users = users_of(&volume, args.verbose).await?;
+    let mut paused = Vec::new();
+    let mut result = Ok(());
+    for container in &users {
+        let pause = ["pause".to_string(), container.clone()];
+        match runtime_output(&pause, args.verbose).await {
+            Ok(_) => paused.push(container.clone()),
+            Err(e) => {
+                result = Err(e.context(format!("Failed to pause {}", container)));
+                break;
+            }
+        }
+    }
+    if result.is_ok() {
+        if !paused.is_empty() {
+            eprintln!("Paused {} while reading {}", paused.join(", "), volume.name);
+        }
+        result = run_to_file(&archive_args(&volume), &partial, args.verbose).await;
+    }
+    for container in &paused {
+        let unpause = ["unpause".to_string(), container.clone()];
+        if let Err(e) = runtime_output(&unpause, args.verbose).await {
+            eprintln!("Warning: failed to unpause {}: {}", container, e);
+        }
+    }
+    if let Err(e) = result {
+        let _ = std::fs::remove_file(&partial);
+        return Err(e);
+    }
+    std::fs::rename(&partial, &out).with_context(|| format!("Failed to write {}", out))?;
+    eprintln!("Saved the state of {} to {}", volume.package, out);
+    Ok(())
+}
+
+pub async fn restore(args: RestoreArgs) -> Result<()> {
+    let volume = StateVolume::for_package(&args.package)
+        .resolve(runtime::current().as_ref())
+        .await?;
+    let archive = std::fs::File::open(&args.archive)
+        .with_context(|| format!("Failed to open {}", args.archive))?;
+    let users = users_of(&volume, args.verbose).await?;
+    if !users.is_empty() {
+        bail!(
+            "{} is in use by {}, stop its servers before restoring",
+            volume.name,
+            users.join(", ")
+        );
+    }
+    if !volume_exists(runtime::current().as_ref(), &volume.name).await? {
+        let mut create = vec!["volume".to_string(), "create".to_string()];
+        for label in volume.labels() {
+            create.extend(["--label".to_string(), label]);
+        }
+        create.push(volume.name.clone());
+        runtime_output(&create, args.verbose).await?;
+    }
+
+    let runtime = runtime::current();
+    let extract = extract_args(&volume);
+    if args.verbose {
+        eprintln!(
+            "Running: {} {} < {}",
+            runtime,
+            extract.join(" "),
+            args.archive
+        );
+    }
+    let output = runtime
+        .command()
+        .args(&extract)
+        .stdin(Stdio::from(archive))
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {} run", runtime))?;
+    if !output.status.success() {
+        bail!(
+            "Failed to restore {}: {}",
+            volume.name,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    eprintln!(
+        "Restored the state of {} from {}",
+        volume.package, args.archive
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helper_args() {
+        let volume = StateVolume::for_user("@modelcontextprotocol/server-memory", "alice");
+        assert_eq!(
+            default_archive(&volume, 1700000000),
+            "semcp-state-alice-modelcontextprotocol-server-memory-e846569f-1700000000.tar.gz"
+        );
+
+        let archive = archive_args(&volume);
+        assert!(archive.contains(
+            &"semcp-state-alice-modelcontextprotocol-server-memory-e846569f:/data:ro".to_string()
+        ));
+        assert_eq!(
+            archive[archive.len() - 5..],
+            ["czf", "-", "-C", "/data", "."]
+        );
+
+        let extract = extract_args(&volume);
+        assert!(extract.contains(&"-i".to_string()));
+        assert!(extract.contains(
+            &"semcp-state-alice-modelcontextprotocol-server-memory-e846569f:/data".to_string()
+        ));
+        assert_eq!(extract.last().unwrap(), EXTRACT_SCRIPT);
+    }
+}
@@ -0,0 +1,59 @@
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use semcp_common::runtime;
+use semcp_common::state::{current_user, list_state_volumes, remove_state_volume};
+use semcp_common::StateVolume;
+
+#[derive(Subcommand)]
+pub enum StateCommand {
+    #[command(about = "List the state volumes of the current user")]
+    Ls,
+
+    #[command(about = "Remove the state volume of a package")]
+    Rm {
+        #[arg(help = "Package whose state should be removed")]
+        package: Option<String>,
+
+        #[arg(long = "all", help = "Remove every state volume of the current user")]
+        all: bool,
+    },
+}
+
+pub async fn run(command: StateCommand) -> Result<()> {
+    let user = current_user();
+
+    match command {
+        StateCommand::Ls => {
+            let volumes = list_state_volumes(&user).await?;
+            if volumes.is_empty() {
+                eprintln!("No state volumes for {}", user);
+                return Ok(());
+            }
+            println!("{:<40} VOLUME", "PACKAGE");
+            for volume in volumes {
+                println!("{:<40} {}", volume.package, volume.name);
+            }
+        }
+        StateCommand::Rm { package, all } => {
+            let names = match (package, all) {
+                (_, true) => list_state_volumes(&user)
+                    .await?
+                    .into_iter()
+                    .map(|volume| volume.name)
+                    .collect(),
+                (Some(package), false) => vec![
+                    StateVolume::for_user(&package, &user)
+                        .resolve(runtime::current().as_ref())
+                        .await?
+                        .name,
+                ],
+                (None, false) => bail!("Specify a package or --all"),
+            };
+            for name in names {
+                remove_state_volume(&name).await?;
+                eprintln!("Removed {}", name);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,136 @@
+//! Serving several users from one system-wide `semcp serve`.
+//!
+//! A TCP connection does not tell which local user opened it. With
+//! `tenancy` in the manifest, servers listen on Unix sockets under
+//! `socket_dir` instead, and the kernel reports the uid of every client.
+//! Containers are labelled with that uid, on-demand containers are kept per
+//! user so a client never reaches a server process another user started,
+//! and each user may only hold so many sessions at once. Who may connect at
+//! all is up to the permissions of `socket_dir`.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+pub const USER_LABEL: &str = "semcp.user";
+pub const DEFAULT_SOCKET_DIR: &str = "/run/semcp";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Tenancy {
+    /// Directory of the per-server sockets, `<server>.sock`.
+    pub socket_dir: String,
+    /// Sessions one user may have open at once, over all servers.
+    pub max_sessions_per_user: Option<usize>,
+}
+
+impl Default for Tenancy {
+    fn default() -> Self {
+        Self {
+            socket_dir: DEFAULT_SOCKET_DIR.to_string(),
+            max_sessions_per_user: None,
+        }
+    }
+}
+
+impl Tenancy {
+    pub fn socket_path(&self, server: &str) -> PathBuf {
+        PathBuf::from(&self.socket_dir).join(format!("{}.sock", server))
+    }
+}
+
+/// The label marking a container as started for `uid`.
+pub fn user_label(uid: u32) -> String {
+    format!("{}={}", USER_LABEL, uid)
+}
+
+/// Open sessions per user, checked against the quota.
+#[derive(Debug, Default)]
+pub struct Quotas {
+    max_sessions: Option<usize>,
+    sessions: Mutex<HashMap<u32, usize>>,
+}
+
+/// An admitted session. Dropping it gives the slot back.
+#[derive(Debug)]
+pub struct Admission {
+    quotas: Arc<Quotas>,
+    uid: u32,
+}
+
+impl Quotas {
+    pub fn new(max_sessions: Option<usize>) -> Self {
+        Self {
+            max_sessions,
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a new session of `uid`, unless the user is at the quota.
+    pub fn admit(self: &Arc<Self>, uid: u32) -> Result<Admission> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let open = sessions.entry(uid).or_default();
+        if let Some(max) = self.max_sessions {
+            if *open >= max {
+                bail!("user {} already has {} sessions open", uid, max);
+            }
+        }
+        *open += 1;
+        Ok(Admission {
+            quotas: Arc::clone(self),
+            uid,
+        })
+    }
+
+    pub fn open_sessions(&self, uid: u32) -> usize {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(&uid)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        let mut sessions = self.quotas.sessions.lock().unwrap();
+        if let Some(open) = sessions.get_mut(&self.uid) {
+            *open -= 1;
+            if *open == 0 {
+                sessions.remove(&self.uid);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_is_per_user() {
+        let quotas = Arc::new(Quotas::new(Some(2)));
+        let first = quotas.admit(1000).unwrap();
+        let _second = quotas.admit(1000).unwrap();
+        assert!(quotas.admit(1000).is_err());
+        let _other = quotas.admit(1001).unwrap();
+
+        drop(first);
+        assert_eq!(quotas.open_sessions(1000), 1);
+        assert!(quotas.admit(1000).is_ok());
+    }
+
+    #[test]
+    fn test_tenancy_defaults() {
+        let tenancy: Tenancy = serde_yaml::from_str("{max_sessions_per_user: 4}").unwrap();
+        assert_eq!(
+            tenancy.socket_path("memory"),
+            PathBuf::from("/run/semcp/memory.sock")
+        );
+        assert_eq!(tenancy.max_sessions_per_user, Some(4));
+        assert_eq!(user_label(1000), "semcp.user=1000");
+    }
+}
@@ -0,0 +1,302 @@
+//! Portable server definitions.
+//!
+//! `semcp export unit` writes one server of a manifest to a `.semcp` file:
+//! the server entry with its image pinned to a digest, its policy layers
+//! merged into one document, and the names (never the values) of the
+//! environment variables it needs. `semcp import unit` adds such a file to
+//! the manifest on another machine.
+
+use crate::manifest::{Manifest, ServerSpec};
+use crate::outdated::{package_pin, split_image};
+use crate::wrap::pin_image;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Subcommand};
+use semcp_common::PolicyConfig;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+pub const UNIT_VERSION: u32 = 1;
+
+#[derive(Subcommand, Debug)]
+pub enum ExportCommand {
+    #[command(about = "Export one server of a manifest as a portable .semcp file")]
+    Unit(ExportUnitArgs),
+
+    #[command(
+        about = "Write a Compose file running servers of a manifest with their policies and sidecars"
+    )]
+    Compose(crate::compose::ExportComposeArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ExportUnitArgs {
+    #[arg(help = "Name of the server to export")]
+    pub server: String,
+
+    #[arg(short = 'o', long = "out", help = "Path of the .semcp file to write")]
+    pub out: String,
+
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest listing the servers"
+    )]
+    pub config: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ImportUnitArgs {
+    #[arg(help = "The .semcp file to import")]
+    pub file: String,
+
+    #[arg(
+        long = "config",
+        default_value = "semcp.yaml",
+        help = "Manifest to add the server to (created if missing)"
+    )]
+    pub config: String,
+
+    #[arg(
+        long = "name",
+        help = "Name to give the server instead of the exported one"
+    )]
+    pub name: Option<String>,
+
+    #[arg(
+        long = "port",
+        help = "Port to serve it on instead of the exported one"
+    )]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Unit {
+    pub version: u32,
+    pub server: ServerSpec,
+    /// All policy layers of the server, merged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<serde_yaml::Value>,
+}
+
+impl Unit {
+    pub fn parse(content: &str) -> Result<Self> {
+        let unit: Unit = serde_yaml::from_str(content).context("Not a semcp unit")?;
+        if unit.version != UNIT_VERSION {
+            bail!(
+                "Unsupported unit version {} (this semcp reads version {})",
+                unit.version,
+                UNIT_VERSION
+            );
+        }
+        Ok(unit)
+    }
+
+    /// The unit of `spec`, with its image pinned if it is pulled.
+    pub async fn of(spec: &ServerSpec) -> Result<Self> {
+        let policy = PolicyConfig::from_files(&spec.policy)
+            .with_context(|| format!("Failed to load policy for `{}`", spec.name))?;
+
+        let mut server = spec.clone();
+        server.policy = Vec::new();
+        // groups belong to the machine, not the server
+        server.resource_group = None;
+        if split_image(&server.image).1.is_none() {
+            match pin_image(&server.image).await {
+                Some(pinned) => {
+                    let (_, digest) = split_image(&pinned);
+                    server.image = format!("{}@{}", server.image, digest.unwrap_or_default());
+                }
+                None => eprintln!(
+                    "Warning: {} is not pulled locally, exporting it unpinned",
+                    server.image
+                ),
+            }
+        }
+        if package_pin(&server.command).is_none() {
+            eprintln!(
+                "Warning: the package of `{}` is not pinned to a version",
+                server.name
+            );
+        }
+
+        Ok(Unit {
+            version: UNIT_VERSION,
+            server,
+            policy: policy.document().cloned(),
+        })
+    }
+}
+
+/// The server of a manifest named `name`.
+pub fn find_server<'a>(manifest: &'a Manifest, name: &str, config: &str) -> Result<&'a ServerSpec> {
+    match manifest.servers.iter().find(|s| s.name == name) {
+        Some(spec) => Ok(spec),
+        None => bail!("No server named `{}` in {}", name, config),
+    }
+}
+
+pub async fn export(args: ExportUnitArgs) -> Result<()> {
+    let manifest = Manifest::from_file(&args.config)?;
+    let spec = find_server(&manifest, &args.server, &args.config)?;
+    let unit = Unit::of(spec).await?;
+    let content = format!(
+        "# semcp unit `{}`, add it to a manifest with `semcp import unit`\n{}",
+        spec.name,
+        serde_yaml::to_string(&unit)?
+    );
+    std::fs::write(&args.out, content).with_context(|| format!("Failed to write {}", args.out))?;
+    eprintln!("Exported {} to {}", spec.name, args.out);
+    Ok(())
+}
+
+/// Appends a server entry to the `servers:` list of a manifest, keeping the
+/// rest of the file as it is.
+pub fn append_server(content: &str, entry: &str) -> Result<String> {
+    if content.trim().is_empty() {
+        return append_server("servers:\n", entry);
+    }
+    let lines: Vec<&str> = content.lines().collect();
+    let Some(start) = lines.iter().position(|line| line.starts_with("servers:")) else {
+        bail!("The manifest has no top-level `servers:` list");
+    };
+    if !lines[start]["servers:".len()..].trim().is_empty() {
+        bail!(
+            "`servers:` is written inline, add the server by hand:\n{}",
+            entry
+        );
+    }
+    let block_end = lines[start + 1..]
+        .iter()
+        .position(|line| !line.is_empty() && !line.starts_with([' ', '\t', '-', '#']))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    // insert after the last item, not after trailing blank lines or comments
+    let insert_at = (start + 1..block_end)
+        .rev()
+        .find(|&i| !lines[i].trim().is_empty() && !lines[i].starts_with('#'))
+        .map_or(start + 1, |i| i + 1);
+    let indent: String = lines[start + 1..block_end]
+        .iter()
+        .find(|line| line.trim_start().starts_with('-'))
+        .map(|line| line.chars().take_while(|c| c.is_whitespace()).collect())
+        .unwrap_or_else(|| "  ".to_string());
+
+    let mut updated: Vec<String> = lines[..insert_at].iter().map(|l| l.to_string()).collect();
+    for (i, line) in entry.lines().enumerate() {
+        let marker = if i == 0 { "- " } else { "  " };
+        updated.push(format!("{}{}{}", indent, marker, line));
+    }
+    updated.extend(lines[insert_at..].iter().map(|l| l.to_string()));
+    Ok(updated.join("\n") + "\n")
+}
+
+pub async fn import(args: ImportUnitArgs) -> Result<()> {
+    let content = std::fs::read_to_string(&args.file)
+        .with_context(|| format!("Failed to read {}", args.file))?;
+    let unit = Unit::parse(&content).with_context(|| format!("Failed to parse {}", args.file))?;
+    add_unit(unit, &args)
+}
+
+/// Adds `unit` to the manifest of `args`, with the policy next to it.
+pub fn add_unit(unit: Unit, args: &ImportUnitArgs) -> Result<()> {
+    let mut server = unit.server;
+    if let Some(ref name) = args.name {
+        server.name = name.clone();
+    }
+    if let Some(port) = args.port {
+        server.port = port;
+    }
+
+    let manifest_path = Path::new(&args.config);
+    let base = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+    let existing = if manifest_path.exists() {
+        Some(Manifest::from_file(&args.config)?)
+    } else {
+        None
+    };
+
+    let policy_file = format!("{}.policy.yaml", server.name);
+    let policy_path = base.join(&policy_file);
+    if unit.policy.is_some() {
+        if policy_path.exists() {
+            bail!(
+                "{} already exists, pick another name with --name",
+                policy_path.display()
+            );
+        }
+        server.policy = vec![policy_file];
+    }
+
+    // check name and port clashes before touching anything
+    let mut combined = existing.unwrap_or(Manifest {
+        listen: None,
+        labels: Default::default(),
+        warm_pool: Default::default(),
+        resource_groups: Default::default(),
+        tenancy: None,
+        introspection: None,
+        servers: Vec::new(),
+    });
+    combined.servers.push(server.clone());
+    combined
+        .validate()
+        .map_err(|e| anyhow!("{} (use --name or --port to import it differently)", e))?;
+
+    let manifest_content = if manifest_path.exists() {
+        std::fs::read_to_string(manifest_path)
+            .with_context(|| format!("Failed to read manifest {}", args.config))?
+    } else {
+        String::new()
+    };
+    let updated = append_server(&manifest_content, &serde_yaml::to_string(&server)?)?;
+    if let Some(ref policy) = unit.policy {
+        std::fs::write(&policy_path, serde_yaml::to_string(policy)?)
+            .with_context(|| format!("Failed to write {}", policy_path.display()))?;
+    }
+    std::fs::write(manifest_path, updated)
+        .with_context(|| format!("Failed to write manifest {}", args.config))?;
+
+    eprintln!(
+        "Added {} to {} on port {}",
+        server.name, args.config, server.port
+    );
+    if !server.env.is_empty() {
+        eprintln!(
+            "Set these variables before `semcp serve`: {}",
+            server.env.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = "name: time\nimage: img\ncommand:\n- uvx\nport: 7302\n";
+
+    #[test]
+    fn test_append_server_keeps_the_manifest() {
+        let content =
+            "# fleet\nservers:\n  - name: memory\n    port: 7301\n\n# more soon\nwarm_pool: {}\n";
+        let updated = append_server(content, ENTRY).unwrap();
+        assert_eq!(
+            updated,
+            "# fleet\nservers:\n  - name: memory\n    port: 7301\n  - name: time\n    image: img\n    \
+             command:\n    - uvx\n    port: 7302\n\n# more soon\nwarm_pool: {}\n"
+        );
+    }
+
+    #[test]
+    fn test_append_server_to_new_manifest() {
+        let updated = append_server("", ENTRY).unwrap();
+        assert!(updated.starts_with("servers:\n  - name: time\n    image: img\n"));
+        assert!(append_server("servers: []\n", ENTRY).is_err());
+    }
+
+    #[test]
+    fn test_parse_unit_checks_version() {
+        let unit = "version: 1\nserver: {name: time, image: img, command: [uvx, t], port: 7302}\n";
+        assert_eq!(Unit::parse(unit).unwrap().server.name, "time");
+        assert!(Unit::parse(&unit.replace("version: 1", "version: 9")).is_err());
+    }
+}
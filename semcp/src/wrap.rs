@@ -0,0 +1,189 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use semcp_common::{runtime, ImageVariants};
+use std::path::Path;
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapRunner {
+    Snpx,
+    Suvx,
+}
+
+impl WrapRunner {
+    fn command(&self) -> &'static str {
+        match self {
+            WrapRunner::Snpx => "snpx",
+            WrapRunner::Suvx => "suvx",
+        }
+    }
+
+    fn default_image(&self) -> &'static str {
+        match self {
+            WrapRunner::Snpx => ImageVariants::get_node_recommended(),
+            WrapRunner::Suvx => ImageVariants::get_python_recommended(),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct WrapArgs {
+    #[arg(long = "out", help = "Path of the wrapper to write")]
+    pub out: String,
+
+    #[arg(
+        long = "runner",
+        value_enum,
+        default_value = "snpx",
+        help = "Runner that starts the package"
+    )]
+    pub runner: WrapRunner,
+
+    #[arg(long = "image", help = "Docker image (default: the runner's default)")]
+    pub image: Option<String>,
+
+    #[arg(
+        long = "no-pin",
+        help = "Keep the image tag instead of pinning the local image digest"
+    )]
+    pub no_pin: bool,
+
+    #[arg(long = "policy", help = "Policy file, repeat to layer policies")]
+    pub policy: Vec<String>,
+
+    #[arg(
+        short = 'e',
+        long = "env",
+        help = "Environment variable to pass (KEY or KEY=VALUE)"
+    )]
+    pub env: Vec<String>,
+
+    #[arg(help = "The package and arguments to wrap")]
+    pub package_args: Vec<String>,
+}
+
+/// Quotes `value` for a POSIX shell.
+fn shell_quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Resolves a tag to the digest of the locally pulled image so the wrapper
+/// keeps running the image it was generated for.
+pub(crate) async fn pin_image(image: &str) -> Option<String> {
+    let output = runtime::current()
+        .command()
+        .args([
+            "image",
+            "inspect",
+            "--format",
+            "{{index .RepoDigests 0}}",
+            image,
+        ])
+        .output()
+        .await
+        .ok()?;
+    let digest = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (output.status.success() && digest.contains("@sha256:")).then_some(digest)
+}
+
+pub fn render_wrapper(image: &str, args: &WrapArgs) -> String {
+    let mut command = vec![
+        args.runner.command().to_string(),
+        "--image".to_string(),
+        image.to_string(),
+    ];
+    for policy in &args.policy {
+        command.push("--policy".to_string());
+        command.push(policy.clone());
+    }
+    for env in &args.env {
+        command.push("-e".to_string());
+        command.push(env.clone());
+    }
+    command.extend(args.package_args.iter().cloned());
+
+    let quoted: Vec<String> = command.iter().map(|arg| shell_quote(arg)).collect();
+    format!(
+        "#!/bin/sh\n\
+         # Generated by `semcp wrap`. Run it again to update this wrapper.\n\
+         exec {} \"$@\"\n",
+        quoted.join(" ")
+    )
+}
+
+pub async fn run(mut args: WrapArgs) -> Result<()> {
+    if args.package_args.is_empty() {
+        bail!("No package specified");
+    }
+    for policy in &mut args.policy {
+        let absolute = std::fs::canonicalize(&*policy)
+            .with_context(|| format!("Failed to resolve policy {}", policy))?;
+        *policy = absolute.to_string_lossy().into_owned();
+    }
+
+    let image = args
+        .image
+        .clone()
+        .unwrap_or_else(|| args.runner.default_image().to_string());
+    let image = if args.no_pin {
+        image
+    } else if let Some(pinned) = pin_image(&image).await {
+        pinned
+    } else {
+        eprintln!("{} is not pulled locally, keeping the tag", image);
+        image
+    };
+
+    let script = render_wrapper(&image, &args);
+    let out = Path::new(&args.out);
+    std::fs::write(out, script).with_context(|| format!("Failed to write {}", args.out))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(out, std::fs::Permissions::from_mode(0o755))
+            .with_context(|| format!("Failed to make {} executable", args.out))?;
+    }
+    eprintln!("Wrote {}", args.out);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("@scope/pkg@1.0"), "@scope/pkg@1.0");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_render_wrapper() {
+        let args = WrapArgs {
+            out: "/tmp/mcp-fs".to_string(),
+            runner: WrapRunner::Snpx,
+            image: None,
+            no_pin: true,
+            policy: vec!["/etc/semcp/fs policy.yaml".to_string()],
+            env: vec!["DEBUG".to_string()],
+            package_args: vec![
+                "@modelcontextprotocol/server-filesystem".to_string(),
+                "/data".to_string(),
+            ],
+        };
+        let script = render_wrapper("node:24-alpine", &args);
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.ends_with(
+            "exec snpx --image node:24-alpine --policy '/etc/semcp/fs policy.yaml' -e DEBUG \
+             @modelcontextprotocol/server-filesystem /data \"$@\"\n"
+        ));
+    }
+}
@@ -1,7 +1,16 @@
 use anyhow::Result;
 use clap::Parser;
-use semcp_common::{ContainerExecutor, ImageVariants, PolicyConfig, Runner, Transport};
+use semcp_common::gateway;
+use semcp_common::overrides::format_denials;
+use semcp_common::trust::{self, Registry};
+use semcp_common::{
+    ContainerArgs, ContainerExecutor, ImageVariants, PolicyConfig, ProbeReport, ProxyArgs, Runner,
+    Transport, VariantCapabilities,
+};
 use std::env;
+use std::sync::Arc;
+
+mod policy;
 
 #[derive(Parser)]
 #[command(
@@ -25,12 +34,25 @@ struct Args {
     #[arg(long = "standard", help = "Use standard image (~1.1GB)")]
     standard: bool,
 
-    #[arg(long = "distroless", help = "Use distroless image (~200MB)")]
+    #[arg(
+        long = "distroless",
+        help = "Use distroless image (~200MB, no shell or npm: needs a prebuilt --image)"
+    )]
     distroless: bool,
 
-    #[arg(short = 'y', help = "Automatically answer yes when prompted")]
+    #[arg(
+        short = 'y',
+        long = "yes",
+        help = "Automatically answer yes when npx prompts"
+    )]
     yes: bool,
 
+    #[arg(
+        long = "approve",
+        help = "Approve new or changed packages without asking"
+    )]
+    approve: bool,
+
     #[arg(short = 'p', long = "package", help = "Package to execute from")]
     package: Option<String>,
 
@@ -49,10 +71,33 @@ struct Args {
     #[arg(long = "shell", help = "Use custom shell")]
     shell: Option<String>,
 
-    #[arg(long = "policy", help = "Path to policy file")]
-    policy: Option<String>,
-
-    #[arg(help = "The package and arguments to execute")]
+    #[arg(
+        long = "policy",
+        help = "Path to policy file, repeat to layer policies (later files take precedence)"
+    )]
+    policy: Vec<String>,
+
+    #[arg(
+        long = "probe",
+        help = "Verify the image, policy and entry command without running a package"
+    )]
+    probe: bool,
+
+    #[arg(
+        long = "multi",
+        help = "Run each package as its own MCP server behind one gateway that prefixes tool names with the server name"
+    )]
+    multi: bool,
+
+    #[command(flatten)]
+    container: ContainerArgs,
+
+    #[command(flatten)]
+    proxy: ProxyArgs,
+
+    #[arg(
+        help = "The package and arguments to execute, or `policy init|validate|explain|schema|push` to work with policy files"
+    )]
     package_args: Vec<String>,
 }
 
@@ -61,14 +106,36 @@ struct SnpxRunner {
 }
 
 impl SnpxRunner {
-    pub fn with_policy(docker_image: String, verbose: bool, policy_config: PolicyConfig) -> Self {
-        Self {
-            executor: ContainerExecutor::with_policy(docker_image, verbose, policy_config),
-        }
+    pub fn new(executor: ContainerExecutor) -> Self {
+        Self { executor }
+    }
+
+    pub async fn probe(&self) -> Result<ProbeReport> {
+        self.executor.probe(self).await
     }
 
-    pub fn check_docker_available(&self) -> Result<bool> {
-        self.executor.check_docker_available()
+    pub async fn run_native_npx_with_flags(
+        &self,
+        npx_flags: &[String],
+        npx_args: &[String],
+    ) -> Result<std::process::ExitStatus> {
+        self.executor.run_native(self, npx_flags, npx_args).await
+    }
+
+    pub async fn run_sandboxed_npx_with_flags(
+        &self,
+        npx_flags: &[String],
+        npx_args: &[String],
+    ) -> Result<std::process::ExitStatus> {
+        self.executor.run_fallback(self, npx_flags, npx_args).await
+    }
+
+    pub async fn run_bundle_npx_with_flags(
+        &self,
+        npx_flags: &[String],
+        servers: Vec<(String, ContainerExecutor)>,
+    ) -> Result<std::process::ExitStatus> {
+        gateway::run_bundle(self, npx_flags, servers).await
     }
 
     pub async fn run_containerized_npx_with_flags(
@@ -103,6 +170,52 @@ impl Runner for SnpxRunner {
     fn requires_tty(&self, transport: &Transport) -> bool {
         matches!(transport, Transport::Http | Transport::SSE)
     }
+
+    fn supports_fallback(&self) -> bool {
+        true
+    }
+
+    fn cache_dir(&self) -> Option<&str> {
+        Some("/root/.npm")
+    }
+
+    fn build_install_args(&self, flags: &[String], args: &[String]) -> Option<Vec<String>> {
+        let mut install_args = vec!["npx".to_string(), "-y".to_string()];
+
+        // with -p the packages come from the flags and args start with the command
+        let packages: Vec<&String> = flags
+            .windows(2)
+            .filter(|pair| pair[0] == "-p")
+            .map(|pair| &pair[1])
+            .collect();
+        if packages.is_empty() {
+            install_args.push("--package".to_string());
+            install_args.push(args.first()?.clone());
+        }
+        for package in packages {
+            install_args.push("--package".to_string());
+            install_args.push(package.clone());
+        }
+
+        install_args.push("true".to_string());
+        Some(install_args)
+    }
+
+    fn required_capabilities(&self, flags: &[String]) -> VariantCapabilities {
+        VariantCapabilities {
+            // -c and --shell run the command through a shell
+            has_shell: flags.iter().any(|flag| flag == "-c" || flag == "--shell"),
+            has_package_manager: true,
+        }
+    }
+
+    fn offline_env(&self) -> Vec<String> {
+        vec![
+            "npm_config_offline=true".to_string(),
+            "npm_config_update_notifier=false".to_string(),
+            "npm_config_logs_max=0".to_string(),
+        ]
+    }
 }
 
 fn determine_image(args: &Args) -> String {
@@ -121,11 +234,35 @@ fn determine_image(args: &Args) -> String {
     }
 }
 
+/// Asks to approve the packages of this run on their first use and pins
+/// them to the approved versions, see [`trust`].
+async fn approve_packages(args: &mut Args, image: &str, policy: &PolicyConfig) -> Result<()> {
+    let permissions = trust::requested_permissions(policy, &args.container);
+    let packages = if let Some(ref mut package) = args.package {
+        std::slice::from_mut(package)
+    } else if args.multi {
+        &mut args.package_args[..]
+    } else {
+        &mut args.package_args[..1]
+    };
+    for package in packages {
+        *package =
+            trust::approve_package(Registry::Npm, image, package, &permissions, args.approve)
+                .await?;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    // `snpx policy ...` works on policy files instead of running a package
+    let argv: Vec<String> = env::args().collect();
+    if argv.get(1).is_some_and(|arg| arg == "policy") {
+        std::process::exit(policy::run(policy::PolicyArgs::parse_from(&argv[1..])));
+    }
+    let mut args = Args::parse();
 
-    if args.package_args.is_empty() {
+    if args.package_args.is_empty() && !args.probe {
         eprintln!("Error: No package specified");
         std::process::exit(1);
     }
@@ -136,16 +273,103 @@ async fn main() -> Result<()> {
         eprintln!("Using Docker image: {}", docker_image);
     }
 
-    let policy_config = if let Some(ref policy_path) = args.policy {
-        if args.verbose {
+    if args.verbose {
+        for policy_path in &args.policy {
             eprintln!("Loading policy from: {}", policy_path);
         }
-        PolicyConfig::from_file(policy_path)?
+    }
+    if let Err(e) = args.container.select_runtime() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    // with --multi every argument is a package of its own
+    let checked = if args.multi {
+        args.package_args.len()
     } else {
-        PolicyConfig::new()
+        args.package_args.len().min(1)
     };
+    let startup = match args
+        .container
+        .preflight(&args.policy, &args.package_args[..checked])
+        .await
+    {
+        Ok(startup) => startup,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let (policy_config, engine) = (startup.policy, startup.engine);
+    let runtime_available = startup.runtime_available;
+
+    let denials = engine.check_overrides(
+        &args.container,
+        args.package_args.first().map(String::as_str),
+    );
+    if !denials.is_empty() {
+        eprintln!(
+            "Error: {}",
+            format_denials(&denials, args.container.explain_denial)
+        );
+        std::process::exit(1);
+    }
 
-    let runner = SnpxRunner::with_policy(docker_image, args.verbose, policy_config);
+    if !args.probe {
+        if let Err(e) = approve_packages(&mut args, &docker_image, &policy_config).await {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let servers: Vec<(String, ContainerExecutor)> = if args.multi {
+        args.package_args
+            .iter()
+            .map(|package| {
+                let executor = ContainerExecutor::with_policy(
+                    docker_image.clone(),
+                    args.verbose,
+                    policy_config.clone(),
+                )
+                .with_engine(Arc::clone(&engine));
+                (
+                    package.clone(),
+                    args.container.configure(executor, Some(package)),
+                )
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let executor = ContainerExecutor::with_policy(docker_image, args.verbose, policy_config)
+        .with_engine(engine);
+    let executor = args
+        .container
+        .configure(executor, args.package_args.first());
+    let package_args = if args.container.skip_arg_check || args.multi {
+        args.package_args.clone()
+    } else {
+        match executor.check_server_args(&args.package_args) {
+            Ok(package_args) => package_args,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    let runner = SnpxRunner::new(args.proxy.configure(executor));
+
+    if args.probe {
+        if !runtime_available {
+            eprintln!("No container runtime (docker, podman or nerdctl) is available or running");
+            eprintln!("snpx requires Docker, Podman, nerdctl or Apple's container CLI to be installed and running");
+            std::process::exit(1);
+        }
+        let report = runner.probe().await?;
+        eprintln!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
 
     let mut npx_flags = Vec::new();
 
@@ -182,16 +406,36 @@ async fn main() -> Result<()> {
         npx_flags.push(shell.clone());
     }
 
-    let result = if runner.check_docker_available()? {
+    let result = if args.multi {
+        if !runtime_available {
+            eprintln!("--multi needs Docker, Podman or nerdctl to be installed and running");
+            std::process::exit(1);
+        }
+        runner.run_bundle_npx_with_flags(&npx_flags, servers).await
+    } else if args.container.native_sandbox {
+        runner
+            .run_native_npx_with_flags(&npx_flags, &package_args)
+            .await
+    } else if runtime_available {
         if args.verbose {
-            eprintln!("Docker is available, using containerized execution");
+            eprintln!(
+                "{} is available, using containerized execution",
+                runner.executor.runtime().name()
+            );
         }
         runner
-            .run_containerized_npx_with_flags(&npx_flags, &args.package_args)
+            .run_containerized_npx_with_flags(&npx_flags, &package_args)
+            .await
+    } else if args.container.sandbox_fallback {
+        runner
+            .run_sandboxed_npx_with_flags(&npx_flags, &package_args)
             .await
     } else {
-        eprintln!("Docker is not available or not running");
-        eprintln!("snpx requires Docker to be installed and running");
+        eprintln!("No container runtime (docker, podman or nerdctl) is available or running");
+        eprintln!("snpx requires Docker, Podman, nerdctl or Apple's container CLI to be installed and running");
+        eprintln!(
+            "--sandbox-fallback runs the package in a bubblewrap (Linux) or sandbox-exec (macOS) sandbox instead"
+        );
         std::process::exit(1);
     };
 
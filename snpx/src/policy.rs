@@ -0,0 +1,311 @@
+//! `snpx policy`: creating and checking policy files.
+
+use clap::{Args, Parser, Subcommand};
+use semcp_common::artifacts::{self, ArtifactRef};
+use semcp_common::export::{self, ExportFormat, Workload};
+use semcp_common::lint::{self, Severity};
+use semcp_common::presets::Preset;
+use semcp_common::{engine, explain, policytest, schema, ImageVariants, PolicyConfig};
+use std::path::Path;
+
+#[derive(Parser)]
+#[command(name = "snpx policy", about = "Create and check snpx policy files")]
+pub struct PolicyArgs {
+    #[command(subcommand)]
+    pub command: PolicyCommand,
+}
+
+#[derive(Subcommand)]
+pub enum PolicyCommand {
+    #[command(about = "Write a commented policy file to start from")]
+    Init(InitArgs),
+
+    #[command(about = "Check policy files, exiting with 1 when one has errors")]
+    Validate {
+        #[arg(required = true, help = "Policy files to check")]
+        files: Vec<String>,
+    },
+
+    #[command(
+        about = "Show the run options, mounts, limits and sidecars a policy produces, and what it does not enforce"
+    )]
+    Explain {
+        #[arg(help = "Policy files, layered in order; the built-in baseline without any")]
+        files: Vec<String>,
+    },
+
+    #[command(
+        about = "Show what changes in the run options, mounts, hosts, limits and sidecars between two policies, exiting with 1 when any does"
+    )]
+    Diff {
+        #[arg(
+            help = "The policy before the change, or the only policy, compared to the built-in baseline"
+        )]
+        old: String,
+
+        #[arg(help = "The policy after the change")]
+        new: Option<String>,
+    },
+
+    #[command(
+        about = "Write manifests that run a server under a policy on another platform, listing what they cannot enforce"
+    )]
+    Export(ExportArgs),
+
+    #[command(
+        about = "Run test cases against the policies they name, exiting with 1 when one fails"
+    )]
+    Test {
+        #[arg(required = true, help = "Files of test cases")]
+        files: Vec<String>,
+    },
+
+    #[command(about = "Print the JSON Schema of policy files, for editors and other tools")]
+    Schema,
+
+    #[command(
+        about = "Check a policy file and push it, with its signature if any, to a registry as an OCI artifact"
+    )]
+    Push {
+        #[arg(help = "Policy file to push")]
+        file: String,
+
+        #[arg(help = "Where to push it, e.g. oci://ghcr.io/acme/policies/mcp:prod")]
+        reference: String,
+    },
+}
+
+#[derive(Args)]
+pub struct InitArgs {
+    #[arg(
+        long = "preset",
+        value_enum,
+        default_value = "standard",
+        help = "strict: no network once installed; standard: the npm registry only; dev: for developing a server"
+    )]
+    pub preset: Preset,
+
+    #[arg(
+        short = 'o',
+        long = "out",
+        default_value = "snpx.yaml",
+        help = "Path of the policy file to write"
+    )]
+    pub out: String,
+
+    #[arg(long = "force", help = "Overwrite an existing file")]
+    pub force: bool,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(help = "Policy files, layered in order; the built-in baseline without any")]
+    pub files: Vec<String>,
+
+    #[arg(long = "format", value_enum, help = "k8s: a Pod and a NetworkPolicy")]
+    pub format: ExportFormat,
+
+    #[arg(
+        long = "name",
+        default_value = "mcp-server",
+        help = "Name of the Pod and of the objects that go with it"
+    )]
+    pub name: String,
+
+    #[arg(
+        long = "image",
+        help = "Image of the server, the Node.js image snpx uses without it"
+    )]
+    pub image: Option<String>,
+
+    #[arg(
+        last = true,
+        help = "Command of the server, e.g. -- npx -y @modelcontextprotocol/server-filesystem /data"
+    )]
+    pub command: Vec<String>,
+}
+
+/// Runs the command and returns the exit code.
+pub fn run(args: PolicyArgs) -> i32 {
+    match args.command {
+        PolicyCommand::Init(args) => init(&args),
+        PolicyCommand::Validate { files } => validate(&files),
+        PolicyCommand::Explain { files } => explain(&files),
+        PolicyCommand::Diff { old, new } => diff(&old, new.as_deref()),
+        PolicyCommand::Export(args) => export(&args),
+        PolicyCommand::Test { files } => test(&files),
+        PolicyCommand::Schema => {
+            println!("{:#}", schema::policy_schema());
+            0
+        }
+        PolicyCommand::Push { file, reference } => push(&file, &reference),
+    }
+}
+
+fn init(args: &InitArgs) -> i32 {
+    if Path::new(&args.out).exists() && !args.force {
+        eprintln!(
+            "Error: {} already exists, pass --force to overwrite it",
+            args.out
+        );
+        return 1;
+    }
+    if let Err(e) = std::fs::write(&args.out, args.preset.template()) {
+        eprintln!("Error: Failed to write {}: {}", args.out, e);
+        return 1;
+    }
+    eprintln!(
+        "Wrote the {} policy to {}, use it with `snpx --policy {} <package>`",
+        args.preset.name(),
+        args.out,
+        args.out
+    );
+    0
+}
+
+/// Prints the findings for each policy file. Returns 1 when a file has
+/// errors; warnings alone pass.
+fn validate(files: &[String]) -> i32 {
+    let mut failed = false;
+    for file in files {
+        match lint::check_file(file) {
+            Ok(diagnostics) => {
+                for diagnostic in &diagnostics {
+                    eprintln!("{}:{}", file, diagnostic);
+                }
+                failed |= diagnostics
+                    .iter()
+                    .any(|diagnostic| diagnostic.severity == Severity::Error);
+            }
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                failed = true;
+            }
+        }
+    }
+    i32::from(failed)
+}
+
+/// Pushes `file` once it validates, so a broken policy never reaches the
+/// developers pulling the tag.
+fn diff(old: &str, new: Option<&str>) -> i32 {
+    let (old, new) = match new {
+        Some(new) => (vec![old.to_string()], new),
+        None => (vec![], old),
+    };
+    let result = explanation(&old)
+        .and_then(|old| Ok(explain::diff(&old, &explanation(&[new.to_string()])?)));
+    match result {
+        Ok(diff) => {
+            print!("{}", diff);
+            i32::from(!diff.is_empty())
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            2
+        }
+    }
+}
+
+fn export(args: &ExportArgs) -> i32 {
+    let policy = if args.files.is_empty() {
+        Ok(PolicyConfig::baseline())
+    } else {
+        PolicyConfig::from_files(&args.files)
+    };
+    let workload = Workload {
+        name: args.name.clone(),
+        image: args
+            .image
+            .clone()
+            .unwrap_or_else(|| ImageVariants::get_node_recommended().to_string()),
+        command: args.command.clone(),
+    };
+    let manifests =
+        policy.and_then(|policy| export::export(args.format, &policy, &workload)?.to_yaml());
+    match manifests {
+        Ok(manifests) => {
+            print!("{}", manifests);
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            1
+        }
+    }
+}
+
+/// Prints each case as it passes or fails. Returns 1 when one fails and 2
+/// when a file cannot be run.
+fn test(files: &[String]) -> i32 {
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let outcomes = match policytest::run_file(file) {
+            Ok(outcomes) => outcomes,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                return 2;
+            }
+        };
+        for outcome in outcomes {
+            if outcome.passed() {
+                passed += 1;
+                println!("ok    {}: {}", file, outcome.name);
+                continue;
+            }
+            failed += 1;
+            println!("FAIL  {}: {}", file, outcome.name);
+            for failure in &outcome.failures {
+                println!("      {}", failure);
+            }
+        }
+    }
+    println!("{} passed, {} failed", passed, failed);
+    i32::from(failed > 0)
+}
+
+fn push(file: &str, reference: &str) -> i32 {
+    if validate(&[file.to_string()]) != 0 {
+        eprintln!("Error: {} has errors, not pushing it", file);
+        return 1;
+    }
+    let pushed = ArtifactRef::parse(reference)
+        .and_then(|parsed| Ok((artifacts::push_policy(Path::new(file), &parsed)?, parsed)));
+    match pushed {
+        Ok((digest, parsed)) => {
+            eprintln!(
+                "Pushed {} to {}, pin it as oci://{}@{}",
+                file, reference, parsed.repository, digest
+            );
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            1
+        }
+    }
+}
+
+/// The explanation of `files` layered, or of the baseline without any.
+fn explanation(files: &[String]) -> anyhow::Result<explain::Explanation> {
+    let policy = if files.is_empty() {
+        PolicyConfig::baseline()
+    } else {
+        PolicyConfig::from_files(files)?
+    };
+    let engine = engine::from_policy(&policy)?;
+    Ok(explain::explain(&policy, engine))
+}
+
+fn explain(files: &[String]) -> i32 {
+    match explanation(files) {
+        Ok(explanation) => {
+            print!("{}", explanation);
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            1
+        }
+    }
+}
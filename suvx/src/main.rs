@@ -1,7 +1,14 @@
 use anyhow::Result;
 use clap::Parser;
-use semcp_common::{ContainerExecutor, ImageVariants, PolicyConfig, Runner, Transport};
+use semcp_common::gateway;
+use semcp_common::overrides::format_denials;
+use semcp_common::trust::{self, Registry};
+use semcp_common::{
+    ContainerArgs, ContainerExecutor, ImageVariants, PolicyConfig, ProbeReport, ProxyArgs, Runner,
+    Transport,
+};
 use std::env;
+use std::sync::Arc;
 
 #[derive(Parser)]
 #[command(
@@ -73,8 +80,35 @@ struct Args {
     #[arg(long = "no-deps", help = "Don't install dependencies")]
     no_deps: bool,
 
-    #[arg(long = "policy", help = "Path to policy file")]
-    policy: Option<String>,
+    #[arg(
+        long = "policy",
+        help = "Path to policy file, repeat to layer policies (later files take precedence)"
+    )]
+    policy: Vec<String>,
+
+    #[arg(
+        long = "approve",
+        help = "Approve new or changed packages without asking"
+    )]
+    approve: bool,
+
+    #[arg(
+        long = "probe",
+        help = "Verify the image, policy and entry command without running a package"
+    )]
+    probe: bool,
+
+    #[arg(
+        long = "multi",
+        help = "Run each package as its own MCP server behind one gateway that prefixes tool names with the server name"
+    )]
+    multi: bool,
+
+    #[command(flatten)]
+    container: ContainerArgs,
+
+    #[command(flatten)]
+    proxy: ProxyArgs,
 
     #[arg(trailing_var_arg = true, help = "arguments to execute")]
     package_args: Vec<String>,
@@ -85,14 +119,36 @@ struct SuvxRunner {
 }
 
 impl SuvxRunner {
-    pub fn with_policy(docker_image: String, verbose: bool, policy_config: PolicyConfig) -> Self {
-        Self {
-            executor: ContainerExecutor::with_policy(docker_image, verbose, policy_config),
-        }
+    pub fn new(executor: ContainerExecutor) -> Self {
+        Self { executor }
+    }
+
+    pub async fn probe(&self) -> Result<ProbeReport> {
+        self.executor.probe(self).await
     }
 
-    pub fn check_docker_available(&self) -> Result<bool> {
-        self.executor.check_docker_available()
+    pub async fn run_native_uvx_with_flags(
+        &self,
+        uvx_flags: &[String],
+        uvx_args: &[String],
+    ) -> Result<std::process::ExitStatus> {
+        self.executor.run_native(self, uvx_flags, uvx_args).await
+    }
+
+    pub async fn run_sandboxed_uvx_with_flags(
+        &self,
+        uvx_flags: &[String],
+        uvx_args: &[String],
+    ) -> Result<std::process::ExitStatus> {
+        self.executor.run_fallback(self, uvx_flags, uvx_args).await
+    }
+
+    pub async fn run_bundle_uvx_with_flags(
+        &self,
+        uvx_flags: &[String],
+        servers: Vec<(String, ContainerExecutor)>,
+    ) -> Result<std::process::ExitStatus> {
+        gateway::run_bundle(self, uvx_flags, servers).await
     }
 
     pub async fn run_containerized_uvx_with_flags(
@@ -126,6 +182,32 @@ impl Runner for SuvxRunner {
     fn requires_tty(&self, transport: &Transport) -> bool {
         matches!(transport, Transport::Http | Transport::SSE)
     }
+
+    fn supports_fallback(&self) -> bool {
+        true
+    }
+
+    fn cache_dir(&self) -> Option<&str> {
+        Some("/root/.cache/uv")
+    }
+
+    fn build_install_args(&self, flags: &[String], args: &[String]) -> Option<Vec<String>> {
+        let command = args.first()?;
+        let mut install_args = vec!["uvx".to_string()];
+        install_args.extend(flags.iter().cloned());
+        if !flags.iter().any(|flag| flag == "--from") {
+            install_args.push("--from".to_string());
+            install_args.push(command.clone());
+        }
+
+        // resolve the tool environment without starting the server
+        install_args.extend(["python".to_string(), "-c".to_string(), "pass".to_string()]);
+        Some(install_args)
+    }
+
+    fn offline_env(&self) -> Vec<String> {
+        vec!["UV_OFFLINE=1".to_string()]
+    }
 }
 
 fn determine_image(args: &Args) -> String {
@@ -209,11 +291,30 @@ fn build_uvx_flags(args: &Args) -> Vec<String> {
     flags
 }
 
+/// Asks to approve the packages of this run on their first use and pins
+/// them to the approved versions, see [`trust`].
+async fn approve_packages(args: &mut Args, image: &str, policy: &PolicyConfig) -> Result<()> {
+    let permissions = trust::requested_permissions(policy, &args.container);
+    let packages = if let Some(ref mut package) = args.from_package {
+        std::slice::from_mut(package)
+    } else if args.multi {
+        &mut args.package_args[..]
+    } else {
+        &mut args.package_args[..1]
+    };
+    for package in packages {
+        *package =
+            trust::approve_package(Registry::Pypi, image, package, &permissions, args.approve)
+                .await?;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
 
-    if args.package_args.is_empty() {
+    if args.package_args.is_empty() && !args.probe {
         eprintln!("Error: No package specified");
         std::process::exit(1);
     }
@@ -224,28 +325,132 @@ async fn main() -> Result<()> {
         eprintln!("Using Docker image: {}", docker_image);
     }
 
-    let policy_config = if let Some(ref policy_path) = args.policy {
-        if args.verbose {
+    if args.verbose {
+        for policy_path in &args.policy {
             eprintln!("Loading policy from: {}", policy_path);
         }
-        PolicyConfig::from_file(policy_path)?
+    }
+    if let Err(e) = args.container.select_runtime() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    // with --multi every argument is a package of its own
+    let checked = if args.multi {
+        args.package_args.len()
     } else {
-        PolicyConfig::new()
+        args.package_args.len().min(1)
     };
+    let startup = match args
+        .container
+        .preflight(&args.policy, &args.package_args[..checked])
+        .await
+    {
+        Ok(startup) => startup,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
+    let (policy_config, engine) = (startup.policy, startup.engine);
+    let runtime_available = startup.runtime_available;
+
+    let denials = engine.check_overrides(
+        &args.container,
+        args.package_args.first().map(String::as_str),
+    );
+    if !denials.is_empty() {
+        eprintln!(
+            "Error: {}",
+            format_denials(&denials, args.container.explain_denial)
+        );
+        std::process::exit(1);
+    }
 
-    let runner = SuvxRunner::with_policy(docker_image, args.verbose, policy_config);
+    if !args.probe {
+        if let Err(e) = approve_packages(&mut args, &docker_image, &policy_config).await {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    }
 
-    let uvx_flags = build_uvx_flags(&args);
+    let servers: Vec<(String, ContainerExecutor)> = if args.multi {
+        args.package_args
+            .iter()
+            .map(|package| {
+                let executor = ContainerExecutor::with_policy(
+                    docker_image.clone(),
+                    args.verbose,
+                    policy_config.clone(),
+                )
+                .with_engine(Arc::clone(&engine));
+                (
+                    package.clone(),
+                    args.container.configure(executor, Some(package)),
+                )
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
 
-    if !runner.check_docker_available()? {
-        eprintln!("Docker is not available or not running");
-        eprintln!("suvx requires Docker to be installed and running");
-        std::process::exit(1);
+    let executor = ContainerExecutor::with_policy(docker_image, args.verbose, policy_config)
+        .with_engine(engine);
+    let executor = args
+        .container
+        .configure(executor, args.package_args.first());
+    let package_args = if args.container.skip_arg_check || args.multi {
+        args.package_args.clone()
+    } else {
+        match executor.check_server_args(&args.package_args) {
+            Ok(package_args) => package_args,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+    let runner = SuvxRunner::new(args.proxy.configure(executor));
+
+    if args.probe {
+        if !runtime_available {
+            eprintln!("No container runtime (docker, podman or nerdctl) is available or running");
+            eprintln!("suvx requires Docker, Podman, nerdctl or Apple's container CLI to be installed and running");
+            std::process::exit(1);
+        }
+        let report = runner.probe().await?;
+        eprintln!("{}", report);
+        std::process::exit(if report.passed() { 0 } else { 1 });
     }
 
-    let result = runner
-        .run_containerized_uvx_with_flags(&uvx_flags, &args.package_args)
-        .await;
+    let uvx_flags = build_uvx_flags(&args);
+
+    let result = if args.multi {
+        if !runtime_available {
+            eprintln!("--multi needs Docker, Podman or nerdctl to be installed and running");
+            std::process::exit(1);
+        }
+        runner.run_bundle_uvx_with_flags(&uvx_flags, servers).await
+    } else if args.container.native_sandbox {
+        runner
+            .run_native_uvx_with_flags(&uvx_flags, &package_args)
+            .await
+    } else if runtime_available {
+        runner
+            .run_containerized_uvx_with_flags(&uvx_flags, &package_args)
+            .await
+    } else if args.container.sandbox_fallback {
+        runner
+            .run_sandboxed_uvx_with_flags(&uvx_flags, &package_args)
+            .await
+    } else {
+        eprintln!("No container runtime (docker, podman or nerdctl) is available or running");
+        eprintln!("suvx requires Docker, Podman, nerdctl or Apple's container CLI to be installed and running");
+        eprintln!(
+            "--sandbox-fallback runs the package in a bubblewrap (Linux) or sandbox-exec (macOS) sandbox instead"
+        );
+        std::process::exit(1);
+    };
 
     match result {
         Ok(status) => {
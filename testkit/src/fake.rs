@@ -0,0 +1,145 @@
+//! A container runtime that runs scripted servers instead of containers.
+
+use crate::run::RunArgs;
+use crate::server::ScriptedServer;
+use anyhow::anyhow;
+use semcp_common::runtime::RuntimeFuture;
+use semcp_common::ContainerRuntime;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::process::Command;
+
+/// What a [`FakeRuntime`] was asked to do, in order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Calls {
+    pub pulls: Vec<String>,
+    pub runs: Vec<RunArgs>,
+    pub stops: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct FakeRuntime {
+    server: ScriptedServer,
+    missing_images: HashSet<String>,
+    pull_error: Option<String>,
+    remote: bool,
+    calls: Mutex<Calls>,
+}
+
+impl FakeRuntime {
+    /// A runtime that has every image, and whose containers exit at once.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `server` as every container.
+    pub fn with_server(mut self, server: ScriptedServer) -> Self {
+        self.server = server;
+        self
+    }
+
+    /// Reports `image` as not present, so it is pulled.
+    pub fn with_missing_image(mut self, image: &str) -> Self {
+        self.missing_images.insert(image.to_string());
+        self
+    }
+
+    /// Fails every pull with `error`, e.g. a registry's rate limit message.
+    pub fn with_pull_error(mut self, error: &str) -> Self {
+        self.pull_error = Some(error.to_string());
+        self
+    }
+
+    /// Acts as a daemon on another machine, which refuses bind mounts.
+    pub fn with_remote(mut self, remote: bool) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    /// Done configuring, ready for [`crate::executor`].
+    pub fn build(self) -> Arc<Self> {
+        Arc::new(self)
+    }
+
+    pub fn calls(&self) -> Calls {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// The arguments of the last container run, without the leading `run`.
+    pub fn last_run(&self) -> Option<RunArgs> {
+        self.calls.lock().unwrap().runs.last().cloned()
+    }
+}
+
+impl ContainerRuntime for FakeRuntime {
+    fn name(&self) -> &str {
+        "fake"
+    }
+
+    fn is_available(&self) -> bool {
+        true
+    }
+
+    /// Subcommands other than `run`, like creating volumes, succeed without
+    /// doing anything.
+    fn command(&self) -> Command {
+        Command::new("true")
+    }
+
+    fn run(&self, args: &[String]) -> Command {
+        self.calls.lock().unwrap().runs.push(RunArgs(args.to_vec()));
+        self.server.command()
+    }
+
+    fn is_remote(&self) -> bool {
+        self.remote
+    }
+
+    fn stop<'a>(&'a self, container: &'a str, _grace: Duration) -> RuntimeFuture<'a, ()> {
+        self.calls.lock().unwrap().stops.push(container.to_string());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn inspect_image<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, bool> {
+        let pulled = self.calls.lock().unwrap().pulls.iter().any(|p| p == image);
+        let present = pulled || !self.missing_images.contains(image);
+        Box::pin(async move { Ok(present) })
+    }
+
+    fn pull<'a>(&'a self, image: &'a str) -> RuntimeFuture<'a, ()> {
+        let result = match self.pull_error {
+            Some(ref error) => Err(anyhow!("{}", error)),
+            None => {
+                self.calls.lock().unwrap().pulls.push(image.to_string());
+                Ok(())
+            }
+        };
+        Box::pin(async move { result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semcp_common::ContainerExecutor;
+
+    #[test]
+    fn test_records_runs_and_stops() {
+        let runtime = FakeRuntime::new()
+            .with_missing_image("node:24-alpine")
+            .build();
+        let executor = ContainerExecutor::new("node:24-alpine".to_string(), false)
+            .with_runtime(runtime.clone());
+        let _run = executor.runtime().run(&["--rm".to_string()]);
+        drop(
+            executor
+                .runtime()
+                .stop(executor.container_name(), Duration::ZERO),
+        );
+        let calls = runtime.calls();
+        assert_eq!(calls.runs, [RunArgs(vec!["--rm".to_string()])]);
+        assert_eq!(calls.stops, [executor.container_name()]);
+        assert!(calls.pulls.is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+//! Test doubles for code built on `semcp-common`, so policies and runners
+//! can be tested end to end without a container runtime.
+//!
+//! A [`FakeRuntime`] stands in for docker: it records what it is asked to
+//! pull, run and stop, and every container it runs is a
+//! [`ScriptedServer`], a small `sh` program that answers MCP requests in
+//! order. [`executor`] puts the two together with a policy, and
+//! [`McpSession`] talks to the server the executor starts:
+//!
+//! ```no_run
+//! # async fn example() -> anyhow::Result<()> {
+//! use semcp_common::PolicyConfig;
+//! use semcp_testkit::{executor, FakeRuntime, McpSession, ScriptedServer, TestRunner};
+//! use serde_json::json;
+//!
+//! let runtime = FakeRuntime::new()
+//!     .with_server(
+//!         ScriptedServer::new()
+//!             .initialize("files")
+//!             .result(json!({"tools": [{"name": "read_file"}]})),
+//!     )
+//!     .build();
+//! // a policy with read_only: true and memory_limit: 512m
+//! let policy = PolicyConfig::from_file("policy.yaml")?;
+//! let executor = executor(&runtime, "node:24-alpine", policy)?;
+//!
+//! let mut child = executor
+//!     .spawn_piped(&TestRunner::new("mcp-server"), &[], &[])
+//!     .await?;
+//! let mut session = McpSession::new(&mut child)?;
+//! session.initialize().await?;
+//! let tools = session.request("tools/list", json!({})).await?;
+//! assert_eq!(tools["result"]["tools"][0]["name"], "read_file");
+//!
+//! let run = runtime.last_run().unwrap();
+//! run.assert_has("--read-only");
+//! assert_eq!(run.values("--memory"), ["536870912"]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The executor runs in test mode, see
+//! [`semcp_common::ContainerExecutor::with_test_mode`], so the daemon is
+//! not asked about itself and no DNS filter or firewall is started.
+
+pub mod fake;
+pub mod run;
+pub mod server;
+pub mod session;
+
+pub use fake::FakeRuntime;
+pub use run::{RunArgs, TestRunner};
+pub use server::ScriptedServer;
+pub use session::McpSession;
+
+use anyhow::Result;
+use semcp_common::{engine, ContainerExecutor, PolicyConfig};
+use std::sync::Arc;
+
+/// An executor for `image` under `policy` and the engine it selects, which
+/// runs its containers on `runtime`, in test mode.
+pub fn executor(
+    runtime: &Arc<FakeRuntime>,
+    image: &str,
+    policy: PolicyConfig,
+) -> Result<ContainerExecutor> {
+    let engine = engine::from_policy(&policy)?;
+    Ok(
+        ContainerExecutor::with_policy(image.to_string(), false, policy)
+            .with_engine(engine)
+            .with_runtime(runtime.clone())
+            .with_test_mode(true),
+    )
+}
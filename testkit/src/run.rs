@@ -0,0 +1,158 @@
+//! Assertions on the arguments a container was run with, and a runner for
+//! servers that are not npm or PyPI packages.
+
+use semcp_common::{Runner, Transport, VariantCapabilities};
+
+/// Flags that mount something into the container.
+const MOUNT_FLAGS: [&str; 3] = ["-v", "--volume", "--mount"];
+/// Flags that set an environment variable.
+const ENV_FLAGS: [&str; 2] = ["-e", "--env"];
+
+/// The arguments of one `run`, as the executor built them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunArgs(pub Vec<String>);
+
+impl RunArgs {
+    pub fn has(&self, arg: &str) -> bool {
+        self.0.iter().any(|a| a == arg)
+    }
+
+    /// Every value of `flag`, given as `--flag value` or `--flag=value`.
+    pub fn values(&self, flag: &str) -> Vec<&str> {
+        let prefix = format!("{}=", flag);
+        let mut values = Vec::new();
+        let mut args = self.0.iter();
+        while let Some(arg) = args.next() {
+            if arg == flag {
+                if let Some(value) = args.next() {
+                    values.push(value.as_str());
+                }
+            } else if let Some(value) = arg.strip_prefix(&prefix) {
+                values.push(value);
+            }
+        }
+        values
+    }
+
+    /// What the container's `key` is set to, `None` when it is not set or
+    /// copied from the host with `-e KEY`.
+    pub fn env(&self, key: &str) -> Option<&str> {
+        ENV_FLAGS
+            .iter()
+            .flat_map(|flag| self.values(flag))
+            .filter_map(|entry| entry.split_once('='))
+            .filter(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+            .next_back()
+    }
+
+    /// The values of `-v`, `--volume` and `--mount`.
+    pub fn mounts(&self) -> Vec<&str> {
+        MOUNT_FLAGS
+            .iter()
+            .flat_map(|flag| self.values(flag))
+            .collect()
+    }
+
+    /// Panics, listing the arguments, unless `arg` is one of them.
+    #[track_caller]
+    pub fn assert_has(&self, arg: &str) {
+        assert!(
+            self.has(arg),
+            "{} is not in the run arguments {:?}",
+            arg,
+            self.0
+        );
+    }
+
+    /// Panics, listing the arguments, if `arg` is one of them.
+    #[track_caller]
+    pub fn assert_lacks(&self, arg: &str) {
+        assert!(
+            !self.has(arg),
+            "{} is in the run arguments {:?}",
+            arg,
+            self.0
+        );
+    }
+}
+
+/// Runs `command` in the image as it is, without a package manager.
+#[derive(Debug, Clone)]
+pub struct TestRunner {
+    command: String,
+}
+
+impl TestRunner {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl Runner for TestRunner {
+    fn command(&self) -> &str {
+        &self.command
+    }
+
+    fn default_image(&self) -> &str {
+        ""
+    }
+
+    fn default_flags(&self) -> Vec<String> {
+        vec![]
+    }
+
+    fn detect_transport(&self, _package: &str) -> Transport {
+        Transport::Stdio
+    }
+
+    fn requires_tty(&self, _transport: &Transport) -> bool {
+        false
+    }
+
+    fn required_capabilities(&self, _flags: &[String]) -> VariantCapabilities {
+        VariantCapabilities {
+            has_shell: false,
+            has_package_manager: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_args() {
+        let run = RunArgs(
+            [
+                "-i",
+                "--rm",
+                "--memory",
+                "268435456",
+                "-e",
+                "TOKEN=abc",
+                "--env=MODE=ci",
+                "-e",
+                "HOME",
+                "-v",
+                "/data:/data:ro",
+                "--mount=type=volume,src=state,dst=/state",
+            ]
+            .map(String::from)
+            .to_vec(),
+        );
+        run.assert_has("--rm");
+        run.assert_lacks("--privileged");
+        assert_eq!(run.values("--memory"), ["268435456"]);
+        assert_eq!(run.env("TOKEN"), Some("abc"));
+        assert_eq!(run.env("MODE"), Some("ci"));
+        assert_eq!(run.env("HOME"), None);
+        assert_eq!(
+            run.mounts(),
+            ["/data:/data:ro", "type=volume,src=state,dst=/state"]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! MCP servers that answer from a script.
+//!
+//! A [`ScriptedServer`] is a POSIX `sh` program, so it runs wherever the
+//! tests do and needs no binary of its own. It reads JSON-RPC messages a
+//! line at a time and answers each request with the next scripted reply,
+//! under the request's `id` (the first `id` in the line). Notifications
+//! are read and not answered. When the script is done, it reads until
+//! stdin closes and exits with the scripted code.
+
+use serde_json::{json, Value};
+use tokio::process::Command;
+
+/// Protocol version the scripted `initialize` answer claims.
+pub const PROTOCOL_VERSION: &str = "2025-06-18";
+
+/// Reads up to the next request and sets `id` to its id, or exits once
+/// stdin is closed.
+const PRELUDE: &str = r#"next() {
+  while IFS= read -r line; do
+    id=$(printf '%s\n' "$line" | awk 'match($0, /"id"[ \t]*:[ \t]*("[^"]*"|-?[0-9]+)/) { s = substr($0, RSTART, RLENGTH); sub(/^"id"[ \t]*:[ \t]*/, "", s); print s }')
+    [ -n "$id" ] && return 0
+  done
+  exit "$code"
+}
+reply() {
+  next
+  printf '{"jsonrpc":"2.0","id":%s,%s}\n' "$id" "$1"
+}
+"#;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    /// Members of the reply besides `jsonrpc` and `id`.
+    Reply(String),
+    /// A whole message sent without waiting for a request.
+    Send(String),
+    Stderr(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptedServer {
+    steps: Vec<Step>,
+    exit_code: i32,
+}
+
+/// `text` as a single quoted `sh` word.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
+
+/// The members of `value`, an object, without its braces.
+fn members(value: &Value) -> String {
+    let text = value.to_string();
+    text[1..text.len() - 1].to_string()
+}
+
+impl ScriptedServer {
+    /// A server that answers nothing and exits when stdin closes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Answers the next request with `result`.
+    pub fn result(mut self, result: Value) -> Self {
+        self.steps
+            .push(Step::Reply(members(&json!({ "result": result }))));
+        self
+    }
+
+    /// Answers the next request with a JSON-RPC error.
+    pub fn error(mut self, code: i64, message: &str) -> Self {
+        self.steps.push(Step::Reply(members(&json!({
+            "error": {"code": code, "message": message}
+        }))));
+        self
+    }
+
+    /// Answers the next request, `initialize`, as a server called `name`
+    /// with tools.
+    pub fn initialize(self, name: &str) -> Self {
+        self.result(json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {"tools": {}},
+            "serverInfo": {"name": name, "version": "0.0.0"}
+        }))
+    }
+
+    /// Sends a notification, before reading the next request.
+    pub fn notify(mut self, method: &str, params: Value) -> Self {
+        self.steps.push(Step::Send(
+            json!({"jsonrpc": "2.0", "method": method, "params": params}).to_string(),
+        ));
+        self
+    }
+
+    /// Writes `line` to stderr, as a crashing server would.
+    pub fn stderr(mut self, line: &str) -> Self {
+        self.steps.push(Step::Stderr(line.to_string()));
+        self
+    }
+
+    /// Exits with `code` once the script is done and stdin closes.
+    pub fn exit_code(mut self, code: i32) -> Self {
+        self.exit_code = code;
+        self
+    }
+
+    /// The `sh` program.
+    pub fn script(&self) -> String {
+        let mut script = format!("code={}\n{}", self.exit_code, PRELUDE);
+        for step in &self.steps {
+            let line = match step {
+                Step::Reply(members) => format!("reply {}", quote(members)),
+                Step::Send(message) => format!("printf '%s\\n' {}", quote(message)),
+                Step::Stderr(line) => format!("printf '%s\\n' {} >&2", quote(line)),
+            };
+            script.push_str(&line);
+            script.push('\n');
+        }
+        script.push_str("while IFS= read -r line; do :; done\nexit \"$code\"\n");
+        script
+    }
+
+    /// The command that runs the server.
+    pub fn command(&self) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(self.script());
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        let server = ScriptedServer::new()
+            .initialize("files")
+            .notify("notifications/tools/list_changed", json!({}))
+            .error(-32601, "Method 'x' not found")
+            .stderr("bye")
+            .exit_code(3);
+        let script = server.script();
+        assert!(script.starts_with("code=3\n"));
+        assert!(script.contains(r#"reply '"result":{"capabilities":{"tools":{}},"protocolVersion":"2025-06-18","serverInfo":{"name":"files","version":"0.0.0"}}'"#));
+        assert!(script.contains(r#"printf '%s\n' '{"jsonrpc":"2.0","method":"notifications/tools/list_changed","params":{}}'"#));
+        assert!(script
+            .contains(r#"reply '"error":{"code":-32601,"message":"Method '\''x'\'' not found"}'"#));
+        assert!(script.contains("printf '%s\\n' 'bye' >&2\n"));
+        assert!(script.ends_with("exit \"$code\"\n"));
+    }
+}
@@ -0,0 +1,95 @@
+//! A minimal MCP client over the stdio of a started server.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+
+/// Talks JSON-RPC to a server started with piped stdio, such as by
+/// [`semcp_common::ContainerExecutor::spawn_piped`].
+pub struct McpSession {
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+    /// Messages the server sent that were not replies, in order.
+    pub notifications: Vec<Value>,
+}
+
+impl McpSession {
+    /// Takes over the stdin and stdout of `child`.
+    pub fn new(child: &mut Child) -> Result<Self> {
+        let stdin = child
+            .stdin
+            .take()
+            .context("The server's stdin is not piped")?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("The server's stdout is not piped")?;
+        Ok(Self {
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+            next_id: 1,
+            notifications: Vec::new(),
+        })
+    }
+
+    async fn send(&mut self, message: &Value) -> Result<()> {
+        let mut line = message.to_string();
+        line.push('\n');
+        self.stdin
+            .write_all(line.as_bytes())
+            .await
+            .context("Failed to write to the server")?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Sends a request and returns the whole reply, `result` or `error`.
+    pub async fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))
+            .await?;
+        loop {
+            let Some(line) = self.stdout.next_line().await? else {
+                bail!("The server closed its stdout before replying to {}", method);
+            };
+            let message: Value = serde_json::from_str(&line)
+                .with_context(|| format!("The server sent a line that is not JSON: {}", line))?;
+            if message.get("id") == Some(&json!(id)) {
+                return Ok(message);
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    pub async fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        self.send(&json!({"jsonrpc": "2.0", "method": method, "params": params}))
+            .await
+    }
+
+    /// Initializes the session as a client would, and returns the reply.
+    pub async fn initialize(&mut self) -> Result<Value> {
+        let reply = self
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": crate::server::PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": {"name": "semcp-testkit", "version": env!("CARGO_PKG_VERSION")}
+                }),
+            )
+            .await?;
+        if reply.get("error").is_some() {
+            bail!("initialize failed: {}", reply["error"]);
+        }
+        self.notify("notifications/initialized", json!({})).await?;
+        Ok(reply)
+    }
+
+    /// Closes the server's stdin, which ends a scripted server.
+    pub fn close(self) {
+        drop(self.stdin);
+    }
+}